@@ -64,7 +64,7 @@ async fn server(endpoint: quinn::Endpoint, opt: Opt) -> Result<()> {
 
     // Handle only the expected amount of clients
     for _ in 0..opt.clients {
-        let handshake = endpoint.accept().await.unwrap();
+        let handshake = endpoint.accept().await.unwrap().accept()?;
         let connection = handshake.await.context("handshake failed")?;
 
         server_tasks.push(tokio::spawn(async move {
@@ -4,7 +4,8 @@ use arbitrary::Arbitrary;
 use libfuzzer_sys::fuzz_target;
 
 extern crate proto;
-use proto::fuzzing::{ConnectionState, ResetStream, Retransmits, StreamsState};
+use proto::fuzzing::{ConnectionState, ResetStream, Retransmits, StreamsState, TimerTable};
+use proto::scheduler::{StreamSchedulerFactory, StrictPriorityConfig};
 use proto::{Dir, Side, StreamId, VarInt};
 use proto::{SendStream, Streams};
 
@@ -32,6 +33,7 @@ enum Operation {
 fuzz_target!(|input: (StreamParams, Vec<Operation>)| {
     let (params, operations) = input;
     let (mut pending, conn_state) = (Retransmits::default(), ConnectionState::Established);
+    let mut timers = TimerTable::default();
     let mut state = StreamsState::new(
         params.side,
         params.max_remote_uni.into(),
@@ -39,6 +41,7 @@ fuzz_target!(|input: (StreamParams, Vec<Operation>)| {
         params.send_window.into(),
         params.receive_window.into(),
         params.stream_receive_window.into(),
+        StrictPriorityConfig::default().build(),
     );
 
     for operation in operations {
@@ -50,7 +53,8 @@ fuzz_target!(|input: (StreamParams, Vec<Operation>)| {
                 Streams::new(&mut state, &conn_state).accept(dir);
             }
             Operation::Finish(id) => {
-                let _ = SendStream::new(id, &mut state, &mut pending, &conn_state).finish();
+                let _ = SendStream::new(id, &mut state, &mut pending, &conn_state, &mut timers)
+                    .finish();
             }
             Operation::ReceivedStopSending(sid, err_code) => {
                 Streams::new(&mut state, &conn_state)
@@ -63,8 +67,8 @@ fuzz_target!(|input: (StreamParams, Vec<Operation>)| {
                     .received_reset(rs);
             }
             Operation::Reset(id) => {
-                let _ =
-                    SendStream::new(id, &mut state, &mut pending, &conn_state).reset(0u32.into());
+                let _ = SendStream::new(id, &mut state, &mut pending, &conn_state, &mut timers)
+                    .reset(0u32.into());
             }
         }
     }
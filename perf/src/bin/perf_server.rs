@@ -111,10 +111,10 @@ async fn run(opt: Opt) -> Result<()> {
 
     let opt = Arc::new(opt);
 
-    while let Some(handshake) = endpoint.accept().await {
+    while let Some(incoming) = endpoint.accept().await {
         let opt = opt.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle(handshake, opt).await {
+            if let Err(e) = handle(incoming, opt).await {
                 error!("connection lost: {:#}", e);
             }
         });
@@ -123,8 +123,8 @@ async fn run(opt: Opt) -> Result<()> {
     Ok(())
 }
 
-async fn handle(handshake: quinn::Connecting, opt: Arc<Opt>) -> Result<()> {
-    let connection = handshake.await.context("handshake failed")?;
+async fn handle(incoming: quinn::Incoming, opt: Arc<Opt>) -> Result<()> {
+    let connection = incoming.accept()?.await.context("handshake failed")?;
     debug!("{} connected", connection.remote_address());
     tokio::try_join!(
         drive_uni(connection.clone()),
@@ -98,6 +98,61 @@ impl CidQueue {
         Some((cid_data.1.unwrap(), orig_offset..self.offset))
     }
 
+    /// Whether a CID other than the active one is available to switch to
+    #[cfg(test)]
+    pub(crate) fn has_next(&self) -> bool {
+        self.iter().nth(1).is_some()
+    }
+
+    /// Number of CIDs other than the active one available to switch to
+    pub(crate) fn spare_count(&self) -> usize {
+        self.iter().count() - 1
+    }
+
+    /// Sequence number of the CID `next` would switch to, if any
+    pub(crate) fn next_seq(&self) -> Option<u64> {
+        let (i, _) = self.iter().nth(1)?;
+        Some(self.offset + i as u64)
+    }
+
+    /// Look up a CID which hasn't been retired by its sequence number
+    pub(crate) fn get(&self, sequence: u64) -> Option<ConnectionId> {
+        let index = self.index(sequence)?;
+        self.buffer[index].map(|(cid, _)| cid)
+    }
+
+    /// Switch to the CID with sequence number `sequence`, if possible, return
+    /// 1) the corresponding ResetToken and 2) a non-empty range preceding it to retire
+    pub(crate) fn switch_to(&mut self, sequence: u64) -> Option<(ResetToken, Range<u64>)> {
+        let index = self.index(sequence).filter(|_| sequence != self.offset)?;
+        let (_, reset_token) = self.buffer[index]?;
+        for i in 0..(sequence - self.offset) as usize {
+            self.buffer[(self.cursor + i) % Self::LEN] = None;
+        }
+
+        let orig_offset = self.offset;
+        self.offset = sequence;
+        self.cursor = index;
+        Some((reset_token.unwrap(), orig_offset..self.offset))
+    }
+
+    /// Discard a CID other than the active one, returning whether it was present
+    pub(crate) fn remove(&mut self, sequence: u64) -> bool {
+        match self.index(sequence).filter(|_| sequence != self.offset) {
+            Some(index) => self.buffer[index].take().is_some(),
+            None => false,
+        }
+    }
+
+    /// Position of `sequence` in `buffer`, if it's within the window
+    fn index(&self, sequence: u64) -> Option<usize> {
+        let step = sequence.checked_sub(self.offset)?;
+        if step >= Self::LEN as u64 {
+            return None;
+        }
+        Some((self.cursor + step as usize) % Self::LEN)
+    }
+
     /// Iterate CIDs in CidQueue that are not `None`, including the active CID
     fn iter(&self) -> impl Iterator<Item = (usize, CidData)> + '_ {
         (0..Self::LEN).filter_map(move |step| {
@@ -153,6 +208,7 @@ mod tests {
     #[test]
     fn next_dense() {
         let mut q = CidQueue::new(initial_cid());
+        assert!(!q.has_next());
         assert!(q.next().is_none());
         assert!(q.next().is_none());
 
@@ -160,10 +216,12 @@ mod tests {
             q.insert(cid(i, 0)).unwrap();
         }
         for i in 1..CidQueue::LEN as u64 {
+            assert!(q.has_next());
             let (_, retire) = q.next().unwrap();
             assert_eq!(q.active_seq(), i);
             assert_eq!(retire.end - retire.start, 1);
         }
+        assert!(!q.has_next());
         assert!(q.next().is_none());
     }
     #[test]
@@ -182,6 +240,30 @@ mod tests {
         assert!(q.next().is_none());
     }
 
+    #[test]
+    fn switch_to_reserved() {
+        let mut q = CidQueue::new(initial_cid());
+        for i in 1..CidQueue::LEN as u64 {
+            q.insert(cid(i, 0)).unwrap();
+        }
+        assert_eq!(q.next_seq(), Some(1));
+        assert_eq!(q.spare_count(), CidQueue::LEN - 1);
+
+        // A discarded CID is skipped
+        assert!(q.remove(1));
+        assert!(!q.remove(1));
+        assert_eq!(q.get(1), None);
+        assert_eq!(q.next_seq(), Some(2));
+
+        let (_, retire) = q.switch_to(3).unwrap();
+        assert_eq!(retire, 0..3);
+        assert_eq!(q.active_seq(), 3);
+        assert_eq!(q.get(2), None);
+        assert!(q.switch_to(3).is_none());
+        assert_eq!(q.next_seq(), Some(4));
+        assert_eq!(q.spare_count(), 1);
+    }
+
     #[test]
     fn wrap() {
         let mut q = CidQueue::new(initial_cid());
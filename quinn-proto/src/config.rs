@@ -1,4 +1,10 @@
-use std::{fmt, num::TryFromIntError, sync::Arc, time::Duration};
+use std::{
+    fmt,
+    net::{SocketAddrV4, SocketAddrV6},
+    num::TryFromIntError,
+    sync::Arc,
+    time::Duration,
+};
 
 use thiserror::Error;
 
@@ -9,6 +15,9 @@ use crate::{
     cid_generator::{ConnectionIdGenerator, RandomConnectionIdGenerator},
     congestion,
     crypto::{self, HandshakeTokenKey, HmacKey},
+    qlog::QlogFactory,
+    scheduler,
+    token::{TokenLog, TokenMemoryCache, TokenMemoryLog, TokenStore},
     VarInt, VarIntBoundsExceeded, DEFAULT_SUPPORTED_VERSIONS, INITIAL_MTU, MAX_UDP_PAYLOAD,
 };
 
@@ -31,6 +40,8 @@ pub struct TransportConfig {
     pub(crate) stream_receive_window: VarInt,
     pub(crate) receive_window: VarInt,
     pub(crate) send_window: u64,
+    pub(crate) max_stream_receive_window: Option<VarInt>,
+    pub(crate) max_receive_window: Option<VarInt>,
 
     pub(crate) max_tlps: u32,
     pub(crate) packet_threshold: u32,
@@ -39,6 +50,8 @@ pub struct TransportConfig {
     pub(crate) initial_mtu: u16,
     pub(crate) min_mtu: u16,
     pub(crate) mtu_discovery_config: Option<MtuDiscoveryConfig>,
+    pub(crate) ack_frequency_config: Option<AckFrequencyConfig>,
+    pub(crate) key_update_config: Option<KeyUpdateConfig>,
 
     pub(crate) persistent_congestion_threshold: u32,
     pub(crate) keep_alive_interval: Option<Duration>,
@@ -46,8 +59,16 @@ pub struct TransportConfig {
     pub(crate) allow_spin: bool,
     pub(crate) datagram_receive_buffer_size: Option<usize>,
     pub(crate) datagram_send_buffer_size: usize,
+    pub(crate) datagram_overflow_policy: DatagramOverflowPolicy,
 
     pub(crate) congestion_controller_factory: Box<dyn congestion::ControllerFactory + Send + Sync>,
+    pub(crate) send_ect1: bool,
+    pub(crate) pacing: bool,
+    pub(crate) max_pacing_rate: Option<u64>,
+    pub(crate) max_send_rate: Option<u64>,
+    pub(crate) stream_scheduler_factory: Box<dyn scheduler::StreamSchedulerFactory + Send + Sync>,
+
+    pub(crate) qlog_factory: Option<Arc<dyn QlogFactory>>,
 }
 
 impl TransportConfig {
@@ -119,6 +140,32 @@ impl TransportConfig {
         self
     }
 
+    /// Size up to which the window of a receive stream may grow automatically
+    ///
+    /// Every receive stream starts out with a window of `stream_receive_window`. When this is set,
+    /// a stream whose data is read at a rate suggesting that the window is smaller than the
+    /// bandwidth-delay product of the path has its window doubled, up to this limit. The
+    /// connection-level window is grown along with it, up to `max_receive_window`, to remain at
+    /// least 1.5 times the stream's window.
+    ///
+    /// This allows `stream_receive_window` to be configured conservatively, limiting memory use for
+    /// slow streams, without impairing throughput on paths with a large bandwidth-delay product.
+    /// `None`, the default, disables auto-tuning of stream windows.
+    pub fn max_stream_receive_window(&mut self, value: Option<VarInt>) -> &mut Self {
+        self.max_stream_receive_window = value;
+        self
+    }
+
+    /// Size up to which the connection-level receive window may grow automatically
+    ///
+    /// Like [`max_stream_receive_window`](Self::max_stream_receive_window), but for the window
+    /// shared by all streams, which starts out at `receive_window`. `None`, the default, disables
+    /// auto-tuning of the connection-level window.
+    pub fn max_receive_window(&mut self, value: Option<VarInt>) -> &mut Self {
+        self.max_receive_window = value;
+        self
+    }
+
     /// Maximum number of bytes to transmit to a peer without acknowledgment
     ///
     /// Provides an upper bound on memory when communicating with peers that issue large amounts of
@@ -218,6 +265,29 @@ impl TransportConfig {
         self
     }
 
+    /// Specifies the ACK frequency config (see [`AckFrequencyConfig`] for details)
+    ///
+    /// The provided configuration will be ignored if the peer does not support the acknowledgement
+    /// frequency QUIC extension.
+    ///
+    /// Defaults to `None`, which disables controlling the peer's acknowledgement frequency. Even
+    /// if set to `None`, the local side still supports the acknowledgement frequency QUIC
+    /// extension and may use it in other ways.
+    pub fn ack_frequency_config(&mut self, value: Option<AckFrequencyConfig>) -> &mut Self {
+        self.ack_frequency_config = value;
+        self
+    }
+
+    /// Specifies when 1-RTT packet protection keys are updated (see [`KeyUpdateConfig`] for
+    /// details)
+    ///
+    /// Defaults to `None`, in which case keys are only updated when approaching the
+    /// confidentiality limit of the negotiated AEAD, or when the peer initiates a key update.
+    pub fn key_update_config(&mut self, value: Option<KeyUpdateConfig>) -> &mut Self {
+        self.key_update_config = value;
+        self
+    }
+
     /// Number of consecutive PTOs after which network is considered to be experiencing persistent congestion.
     pub fn persistent_congestion_threshold(&mut self, value: u32) -> &mut Self {
         self.persistent_congestion_threshold = value;
@@ -266,13 +336,21 @@ impl TransportConfig {
     ///
     /// While datagrams are sent ASAP, it is possible for an application to generate data faster
     /// than the link, or even the underlying hardware, can transmit them. This limits the amount of
-    /// memory that may be consumed in that case. When the send buffer is full and a new datagram is
-    /// sent, older datagrams are dropped until sufficient space is available.
+    /// memory that may be consumed in that case. What happens when the send buffer is full and a
+    /// new datagram is sent is governed by `datagram_overflow_policy`.
     pub fn datagram_send_buffer_size(&mut self, value: usize) -> &mut Self {
         self.datagram_send_buffer_size = value;
         self
     }
 
+    /// What to do when a datagram is sent while the outgoing datagram buffer is full
+    ///
+    /// Defaults to [`DatagramOverflowPolicy::DropOldest`].
+    pub fn datagram_overflow_policy(&mut self, value: DatagramOverflowPolicy) -> &mut Self {
+        self.datagram_overflow_policy = value;
+        self
+    }
+
     /// How to construct new `congestion::Controller`s
     ///
     /// Typically the refcounted configuration of a `congestion::Controller`,
@@ -291,6 +369,81 @@ impl TransportConfig {
         self.congestion_controller_factory = Box::new(factory);
         self
     }
+
+    /// Whether to mark outgoing packets with the ECT(1) rather than the ECT(0) ECN codepoint
+    ///
+    /// ECT(1) identifies packets of L4S flows (RFC 9331), which networks supporting it mark as
+    /// congestion experienced as soon as a shallow queue builds up, rather than once it's full. It
+    /// should only be used along with a congestion controller which reacts to the fraction of
+    /// marked packets, such as `congestion::PragueConfig`, as classic controllers overreact to such
+    /// frequent marks. Defaults to `false`.
+    pub fn send_ect1(&mut self, value: bool) -> &mut Self {
+        self.send_ect1 = value;
+        self
+    }
+
+    /// Whether to spread out the transmission of packets over time
+    ///
+    /// Pacing avoids sending the congestion window in bursts which overflow network buffers. The
+    /// rate is chosen by the congestion controller if it provides one, and derived from the
+    /// congestion window and round-trip time otherwise. Disabling it may be useful when pacing is
+    /// handled elsewhere, such as by the operating system. Defaults to `true`.
+    pub fn pacing(&mut self, value: bool) -> &mut Self {
+        self.pacing = value;
+        self
+    }
+
+    /// Maximum rate at which packets are paced, in bytes per second
+    ///
+    /// Has no effect if [`pacing`](Self::pacing) is disabled. Defaults to `None`, which paces at
+    /// whatever rate the congestion controller calls for.
+    pub fn max_pacing_rate(&mut self, value: Option<u64>) -> &mut Self {
+        self.max_pacing_rate = value;
+        self
+    }
+
+    /// Maximum rate at which data is sent, in bytes per second
+    ///
+    /// Enforced with a token bucket allowing bursts of up to 10ms worth of data, on top of
+    /// congestion control and pacing. Congestion controllers see time spent held back by the limit
+    /// as application-limited, while [`PathStats`](crate::PathStats) reports it separately as
+    /// `rate_limited_time`. A rate of zero stops all transmission until the limit is raised. Can
+    /// be changed for an established connection with
+    /// [`Connection::set_max_send_rate`](crate::Connection::set_max_send_rate). Defaults to
+    /// `None`, meaning no limit.
+    pub fn max_send_rate(&mut self, value: Option<u64>) -> &mut Self {
+        self.max_send_rate = value;
+        self
+    }
+
+    /// How to construct new `scheduler::StreamScheduler`s, which decide the order in which
+    /// streams' data is transmitted
+    ///
+    /// Defaults to strict priority, i.e. a `scheduler::StrictPriorityConfig`.
+    ///
+    /// # Example
+    /// ```
+    /// # use quinn_proto::*;
+    /// let mut config = TransportConfig::default();
+    /// config.stream_scheduler_factory(scheduler::WeightedFairConfig::default());
+    /// ```
+    pub fn stream_scheduler_factory(
+        &mut self,
+        factory: impl scheduler::StreamSchedulerFactory + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.stream_scheduler_factory = Box::new(factory);
+        self
+    }
+
+    /// How to construct writers for the qlog trace of each connection, or `None` to disable qlog
+    ///
+    /// Every connection using this configuration asks the factory for a writer when it is created,
+    /// then logs packets, frames, recovery metrics, key updates and state transitions to it as a
+    /// JSON-SEQ stream. See the [`qlog`](crate::qlog) module for details. Defaults to `None`.
+    pub fn qlog_factory(&mut self, factory: Option<Arc<dyn QlogFactory>>) -> &mut Self {
+        self.qlog_factory = factory;
+        self
+    }
 }
 
 impl Default for TransportConfig {
@@ -308,6 +461,8 @@ impl Default for TransportConfig {
             stream_receive_window: STREAM_RWND.into(),
             receive_window: VarInt::MAX,
             send_window: (8 * STREAM_RWND).into(),
+            max_stream_receive_window: None,
+            max_receive_window: None,
 
             max_tlps: 2,
             packet_threshold: 3,
@@ -316,6 +471,8 @@ impl Default for TransportConfig {
             initial_mtu: INITIAL_MTU,
             min_mtu: INITIAL_MTU,
             mtu_discovery_config: Some(MtuDiscoveryConfig::default()),
+            ack_frequency_config: None,
+            key_update_config: None,
 
             persistent_congestion_threshold: 3,
             keep_alive_interval: None,
@@ -323,8 +480,16 @@ impl Default for TransportConfig {
             allow_spin: true,
             datagram_receive_buffer_size: Some(STREAM_RWND as usize),
             datagram_send_buffer_size: 1024 * 1024,
+            datagram_overflow_policy: DatagramOverflowPolicy::DropOldest,
 
             congestion_controller_factory: Box::new(Arc::new(congestion::CubicConfig::default())),
+            send_ect1: false,
+            pacing: true,
+            max_pacing_rate: None,
+            max_send_rate: None,
+            stream_scheduler_factory: Box::new(scheduler::StrictPriorityConfig::default()),
+
+            qlog_factory: None,
         }
     }
 }
@@ -344,10 +509,14 @@ impl fmt::Debug for TransportConfig {
             .field("stream_receive_window", &self.stream_receive_window)
             .field("receive_window", &self.receive_window)
             .field("send_window", &self.send_window)
+            .field("max_stream_receive_window", &self.max_stream_receive_window)
+            .field("max_receive_window", &self.max_receive_window)
             .field("max_tlps", &self.max_tlps)
             .field("packet_threshold", &self.packet_threshold)
             .field("time_threshold", &self.time_threshold)
             .field("initial_rtt", &self.initial_rtt)
+            .field("ack_frequency_config", &self.ack_frequency_config)
+            .field("key_update_config", &self.key_update_config)
             .field(
                 "persistent_congestion_threshold",
                 &self.persistent_congestion_threshold,
@@ -360,7 +529,17 @@ impl fmt::Debug for TransportConfig {
                 &self.datagram_receive_buffer_size,
             )
             .field("datagram_send_buffer_size", &self.datagram_send_buffer_size)
+            .field("datagram_overflow_policy", &self.datagram_overflow_policy)
             .field("congestion_controller_factory", &"[ opaque ]")
+            .field("send_ect1", &self.send_ect1)
+            .field("pacing", &self.pacing)
+            .field("max_pacing_rate", &self.max_pacing_rate)
+            .field("max_send_rate", &self.max_send_rate)
+            .field("stream_scheduler_factory", &"[ opaque ]")
+            .field(
+                "qlog_factory",
+                &self.qlog_factory.as_ref().map(|_| "[ opaque ]"),
+            )
             .finish()
     }
 }
@@ -472,6 +651,134 @@ impl Default for MtuDiscoveryConfig {
     }
 }
 
+/// Parameters for controlling the peer's acknowledgement frequency
+///
+/// The parameters provided in this config will be sent to the peer at the beginning of the
+/// connection, so it can take them into account when sending acknowledgements (see each parameter's
+/// description for details on how it influences acknowledgement frequency).
+///
+/// Quinn's implementation follows the fourth draft of the
+/// [QUIC Acknowledgement Frequency extension](https://datatracker.ietf.org/doc/html/draft-ietf-quic-ack-frequency-04).
+/// The defaults produce slightly less frequent acknowledgements than the default QUIC behavior,
+/// namely by allowing the peer to wait for up to `max_ack_delay` before acknowledging out-of-order
+/// packets.
+#[derive(Clone, Debug)]
+pub struct AckFrequencyConfig {
+    pub(crate) ack_eliciting_threshold: VarInt,
+    pub(crate) max_ack_delay: Option<Duration>,
+    pub(crate) reordering_threshold: VarInt,
+}
+
+impl AckFrequencyConfig {
+    /// The ack-eliciting threshold we will request the peer to use
+    ///
+    /// This threshold represents the number of ack-eliciting packets an endpoint may receive
+    /// without immediately sending an ACK.
+    ///
+    /// The remote peer should send at least one ACK frame when more than this number of
+    /// ack-eliciting packets have been received. A value of 0 results in a receiver immediately
+    /// acknowledging every ack-eliciting packet.
+    ///
+    /// Defaults to 1, which sends ACK frames for every other ack-eliciting packet.
+    pub fn ack_eliciting_threshold(&mut self, value: VarInt) -> &mut Self {
+        self.ack_eliciting_threshold = value;
+        self
+    }
+
+    /// The `max_ack_delay` we will request the peer to use
+    ///
+    /// This parameter represents the maximum amount of time that an endpoint waits before sending
+    /// an ACK when the ack-eliciting threshold hasn't been reached.
+    ///
+    /// The effective `max_ack_delay` will be clamped to be at least the peer's `min_ack_delay`
+    /// transport parameter, and at most the greater of the current path RTT or 25ms.
+    ///
+    /// Defaults to `None`, in which case the peer's original `max_ack_delay` will be used, as
+    /// obtained from its transport parameters.
+    pub fn max_ack_delay(&mut self, value: Option<Duration>) -> &mut Self {
+        self.max_ack_delay = value;
+        self
+    }
+
+    /// The reordering threshold we will request the peer to use
+    ///
+    /// This threshold represents the amount of out-of-order packets that will trigger an endpoint
+    /// to send an ACK, without waiting for `ack_eliciting_threshold` to be exceeded or for
+    /// `max_ack_delay` to be elapsed.
+    ///
+    /// A value of 0 indicates out-of-order packets do not elicit an immediate ACK. A value of 1
+    /// immediately acknowledges any packets that are received out of order (this is also the
+    /// behavior when the extension is disabled).
+    ///
+    /// It is recommended to set this value to [`TransportConfig::packet_threshold`] minus one.
+    /// Since the default value for [`TransportConfig::packet_threshold`] is 3, this value defaults
+    /// to 2.
+    pub fn reordering_threshold(&mut self, value: VarInt) -> &mut Self {
+        self.reordering_threshold = value;
+        self
+    }
+}
+
+impl Default for AckFrequencyConfig {
+    fn default() -> Self {
+        Self {
+            ack_eliciting_threshold: VarInt(1),
+            max_ack_delay: None,
+            reordering_threshold: VarInt(2),
+        }
+    }
+}
+
+/// Parameters governing proactive updates of 1-RTT packet protection keys
+///
+/// A key update is initiated once the current keys have been in use for `interval`, or have
+/// protected `bytes` of outgoing packets, whichever comes first. Updates only happen when a packet
+/// is sent, and are deferred while the previous key update hasn't been confirmed by the peer, as
+/// required by RFC 9001 §6. Key updates initiated by the peer restart both intervals.
+///
+/// Keys are always updated before reaching the confidentiality limit of the negotiated AEAD,
+/// regardless of this configuration.
+#[derive(Clone, Debug, Default)]
+pub struct KeyUpdateConfig {
+    pub(crate) interval: Option<Duration>,
+    pub(crate) bytes: Option<u64>,
+}
+
+impl KeyUpdateConfig {
+    /// Maximum duration for which the same keys are used to send packets
+    ///
+    /// Defaults to `None`, which doesn't limit how long keys may be used for.
+    pub fn interval(&mut self, value: Option<Duration>) -> &mut Self {
+        self.interval = value;
+        self
+    }
+
+    /// Maximum number of bytes of outgoing packets protected with the same keys
+    ///
+    /// Defaults to `None`, which doesn't limit how much data keys may protect.
+    pub fn bytes(&mut self, value: Option<u64>) -> &mut Self {
+        self.bytes = value;
+        self
+    }
+}
+
+/// How to handle an outgoing datagram that doesn't fit in the send buffer
+///
+/// See [`TransportConfig::datagram_send_buffer_size`]. Datagrams discarded by either drop policy
+/// are counted in [`ConnectionStats::dropped_datagrams`](crate::ConnectionStats::dropped_datagrams).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DatagramOverflowPolicy {
+    /// Drop the oldest buffered datagrams until the new one fits
+    DropOldest,
+    /// Drop the new datagram, keeping those already buffered
+    DropNewest,
+    /// Refuse the new datagram with [`SendDatagramError::Blocked`](crate::SendDatagramError::Blocked)
+    ///
+    /// [`Event::DatagramsUnblocked`](crate::Event::DatagramsUnblocked) is emitted once space is
+    /// freed up.
+    Reject,
+}
+
 /// Global configuration for the endpoint, affecting all connections
 ///
 /// Default values should be suitable for most internet applications.
@@ -618,6 +925,12 @@ pub struct ServerConfig {
     pub(crate) use_retry: bool,
     /// Microseconds after a stateless retry token was issued for which it's considered valid.
     pub(crate) retry_token_lifetime: Duration,
+    /// Duration after a NEW_TOKEN token was issued for which it's considered valid
+    pub(crate) validation_token_lifetime: Duration,
+    /// Detects reuse of NEW_TOKEN tokens
+    pub(crate) validation_token_log: Arc<dyn TokenLog>,
+    /// Number of NEW_TOKEN tokens to issue to each client once the handshake is complete
+    pub(crate) validation_tokens_sent: u32,
 
     /// Maximum number of concurrent connections
     pub(crate) concurrent_connections: u32,
@@ -627,6 +940,14 @@ pub struct ServerConfig {
     /// Improves behavior for clients that move between different internet connections or suffer NAT
     /// rebinding. Enabled by default.
     pub(crate) migration: bool,
+
+    pub(crate) preferred_address_v4: Option<SocketAddrV4>,
+    pub(crate) preferred_address_v6: Option<SocketAddrV6>,
+
+    /// Maximum number of bytes buffered for a single connection attempt awaiting a decision
+    pub(crate) incoming_buffer_size: u64,
+    /// Maximum number of bytes buffered for all connection attempts awaiting a decision
+    pub(crate) incoming_buffer_size_total: u64,
 }
 
 impl ServerConfig {
@@ -642,10 +963,19 @@ impl ServerConfig {
             token_key,
             use_retry: false,
             retry_token_lifetime: Duration::from_secs(15),
+            validation_token_lifetime: Duration::from_secs(24 * 60 * 60),
+            validation_token_log: Arc::new(TokenMemoryLog::default()),
+            validation_tokens_sent: 2,
 
             concurrent_connections: 100_000,
 
             migration: true,
+
+            preferred_address_v4: None,
+            preferred_address_v6: None,
+
+            incoming_buffer_size: 10 << 20,
+            incoming_buffer_size_total: 100 << 20,
         }
     }
 
@@ -664,6 +994,9 @@ impl ServerConfig {
     /// Whether to require clients to prove ownership of an address before committing resources.
     ///
     /// Introduces an additional round-trip to the handshake to make denial of service attacks more difficult.
+    /// Retry packets are sent before the connection attempt is surfaced as an
+    /// [`Incoming`](crate::Incoming); leave this disabled to decide case by case with
+    /// [`Endpoint::retry`](crate::Endpoint::retry) instead.
     pub fn use_retry(&mut self, value: bool) -> &mut Self {
         self.use_retry = value;
         self
@@ -675,6 +1008,36 @@ impl ServerConfig {
         self
     }
 
+    /// Duration after an address validation token sent in a NEW_TOKEN frame was issued for which
+    /// it's considered valid
+    ///
+    /// Clients presenting a valid token skip address validation, including any Retry. Used tokens
+    /// are remembered by the [`validation_token_log`](Self::validation_token_log) until they
+    /// expire, so a longer lifetime requires it to remember more of them. Defaults to one day.
+    pub fn validation_token_lifetime(&mut self, value: Duration) -> &mut Self {
+        self.validation_token_lifetime = value;
+        self
+    }
+
+    /// Log used to accept each address validation token sent in a NEW_TOKEN frame only once
+    ///
+    /// Sharing the log between servers accepting each other's tokens prevents a token from being
+    /// used at each of them. Defaults to a [`TokenMemoryLog`] remembering up to 100,000 tokens.
+    pub fn validation_token_log(&mut self, value: Arc<dyn TokenLog>) -> &mut Self {
+        self.validation_token_log = value;
+        self
+    }
+
+    /// Number of address validation tokens to send to each client in NEW_TOKEN frames once the
+    /// handshake is complete
+    ///
+    /// Clients use each token for at most one future connection. Setting this to zero disables
+    /// sending tokens. Defaults to 2.
+    pub fn validation_tokens_sent(&mut self, value: u32) -> &mut Self {
+        self.validation_tokens_sent = value;
+        self
+    }
+
     /// Maximum number of simultaneous connections to accept.
     ///
     /// New incoming connections are only accepted if the total number of incoming or outgoing
@@ -692,6 +1055,50 @@ impl ServerConfig {
         self.migration = value;
         self
     }
+
+    /// The preferred IPv4 address that will be communicated to clients during handshaking
+    ///
+    /// If the client is able to reach this address, it will switch to it once the handshake is
+    /// confirmed. The address must share the endpoint's port, so that datagrams sent to it are
+    /// received by the same endpoint, e.g. by binding the socket to an unspecified address.
+    /// Ignored if the endpoint uses zero-length connection IDs.
+    pub fn preferred_address_v4(&mut self, address: Option<SocketAddrV4>) -> &mut Self {
+        self.preferred_address_v4 = address;
+        self
+    }
+
+    /// The preferred IPv6 address that will be communicated to clients during handshaking
+    ///
+    /// If the client is able to reach this address, it will switch to it once the handshake is
+    /// confirmed. The address must share the endpoint's port, so that datagrams sent to it are
+    /// received by the same endpoint, e.g. by binding the socket to an unspecified address.
+    /// Ignored if the endpoint uses zero-length connection IDs.
+    pub fn preferred_address_v6(&mut self, address: Option<SocketAddrV6>) -> &mut Self {
+        self.preferred_address_v6 = address;
+        self
+    }
+
+    /// Maximum number of bytes to buffer for each [`Incoming`](crate::Incoming) connection
+    ///
+    /// Datagrams a client sends before the application decides what to do with its connection
+    /// attempt, such as 0-RTT data, are buffered in the meantime and dropped if this limit is
+    /// exceeded. Defaults to 10 MiB.
+    pub fn incoming_buffer_size(&mut self, value: u64) -> &mut Self {
+        self.incoming_buffer_size = value;
+        self
+    }
+
+    /// Maximum number of bytes to buffer for all [`Incoming`](crate::Incoming) connections combined
+    ///
+    /// Defaults to 100 MiB.
+    pub fn incoming_buffer_size_total(&mut self, value: u64) -> &mut Self {
+        self.incoming_buffer_size_total = value;
+        self
+    }
+
+    pub(crate) fn has_preferred_address(&self) -> bool {
+        self.preferred_address_v4.is_some() || self.preferred_address_v6.is_some()
+    }
 }
 
 #[cfg(feature = "rustls")]
@@ -731,8 +1138,18 @@ impl fmt::Debug for ServerConfig {
             .field("token_key", &"[ elided ]")
             .field("use_retry", &self.use_retry)
             .field("retry_token_lifetime", &self.retry_token_lifetime)
+            .field("validation_token_lifetime", &self.validation_token_lifetime)
+            .field("validation_token_log", &"[ elided ]")
+            .field("validation_tokens_sent", &self.validation_tokens_sent)
             .field("concurrent_connections", &self.concurrent_connections)
             .field("migration", &self.migration)
+            .field("preferred_address_v4", &self.preferred_address_v4)
+            .field("preferred_address_v6", &self.preferred_address_v6)
+            .field("incoming_buffer_size", &self.incoming_buffer_size)
+            .field(
+                "incoming_buffer_size_total",
+                &self.incoming_buffer_size_total,
+            )
             .finish()
     }
 }
@@ -751,6 +1168,9 @@ pub struct ClientConfig {
 
     /// QUIC protocol version to use
     pub(crate) version: u32,
+
+    /// Storage for address validation tokens received from servers
+    pub(crate) token_store: Option<Arc<dyn TokenStore>>,
 }
 
 impl ClientConfig {
@@ -760,6 +1180,7 @@ impl ClientConfig {
             transport: Default::default(),
             crypto,
             version: 1,
+            token_store: Some(Arc::new(TokenMemoryCache::default())),
         }
     }
 
@@ -774,6 +1195,15 @@ impl ClientConfig {
         self.version = version;
         self
     }
+
+    /// Set the store for address validation tokens received from servers
+    ///
+    /// Tokens are used when connecting to the same server name again, allowing the server to skip
+    /// address validation. Defaults to a [`TokenMemoryCache`]. `None` disables the use of tokens.
+    pub fn token_store(&mut self, store: Option<Arc<dyn TokenStore>>) -> &mut Self {
+        self.token_store = store;
+        self
+    }
 }
 
 #[cfg(feature = "rustls")]
@@ -810,6 +1240,10 @@ impl fmt::Debug for ClientConfig {
             .field("transport", &self.transport)
             .field("crypto", &"ClientConfig { elided }")
             .field("version", &self.version)
+            .field(
+                "token_store",
+                &self.token_store.as_ref().map(|_| "TokenStore { elided }"),
+            )
             .finish()
     }
 }
@@ -1,16 +1,23 @@
 //! Logic for controlling the rate at which data is sent
 
-use crate::connection::RttEstimator;
+use crate::connection::{RateSample, RttEstimator};
 use std::any::Any;
 use std::time::Instant;
 
 mod bbr;
+mod bbr3;
 mod cubic;
+mod hystart;
+mod ledbat;
 mod new_reno;
+mod prague;
 
 pub use bbr::{Bbr, BbrConfig};
+pub use bbr3::{Bbr3, Bbr3Config};
 pub use cubic::{Cubic, CubicConfig};
+pub use ledbat::{Ledbat, LedbatConfig};
 pub use new_reno::{NewReno, NewRenoConfig};
+pub use prague::{Prague, PragueConfig};
 
 /// Common interface for different congestion controllers
 pub trait Controller: Send {
@@ -33,6 +40,12 @@ pub trait Controller: Send {
     ) {
     }
 
+    /// A delivery rate sample was taken for a batch of acknowledgements
+    ///
+    /// Called after `on_ack` for each of the acknowledged packets, and before `on_end_acks`.
+    #[allow(unused_variables)]
+    fn on_rate_sample(&mut self, now: Instant, sample: &RateSample) {}
+
     /// Packets are acked in batches, all with the same `now` argument. This indicates one of those batches has completed.
     #[allow(unused_variables)]
     fn on_end_acks(
@@ -44,6 +57,15 @@ pub trait Controller: Send {
     ) {
     }
 
+    /// ECN feedback was received for packets which were just acknowledged
+    ///
+    /// `ect` and `ce` are the increases in the peer's counts of packets received with the ECT
+    /// codepoint in use and marked as congestion experienced, respectively. Called after
+    /// `on_end_acks` for the acknowledgements carrying the feedback, and before
+    /// `on_congestion_event` if any packets were marked.
+    #[allow(unused_variables)]
+    fn on_ecn(&mut self, now: Instant, ect: u64, ce: u64) {}
+
     /// Packets were deemed lost or marked congested
     ///
     /// `in_persistent_congestion` indicates whether all packets sent within the persistent
@@ -70,6 +92,21 @@ pub trait Controller: Send {
     /// Initial congestion window
     fn initial_window(&self) -> u64;
 
+    /// Rate at which packets should be sent, in bytes per second
+    ///
+    /// If `None`, packets are paced so that slightly more than one congestion window is sent per
+    /// round trip.
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+
+    /// Number of bytes which may be sent at once when pacing at [`pacing_rate`](Self::pacing_rate)
+    ///
+    /// If `None`, a burst size suitable for the pacing rate is used.
+    fn send_quantum(&self) -> Option<u64> {
+        None
+    }
+
     /// Returns Self for use in down-casting to extract implementation details
     fn into_any(self: Box<Self>) -> Box<dyn Any>;
 }
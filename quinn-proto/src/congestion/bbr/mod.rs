@@ -493,6 +493,11 @@ impl Controller for Bbr {
         self.config.initial_window
     }
 
+    fn pacing_rate(&self) -> Option<u64> {
+        // Until the bandwidth is measured, fall back to the window
+        Some(self.pacing_rate).filter(|&rate| rate != 0)
+    }
+
     fn into_any(self: Box<Self>) -> Box<dyn Any> {
         self
     }
@@ -0,0 +1,1229 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng};
+
+use crate::connection::{RateSample, RttEstimator};
+
+use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+
+/// Experimental! Use at your own risk.
+///
+/// BBR version 3, as specified by <https://datatracker.ietf.org/doc/html/draft-ietf-ccwg-bbr>.
+///
+/// Like [`Bbr`](super::Bbr), builds a model of the path from the delivery rate and the minimum RTT,
+/// and paces data at the estimated bottleneck bandwidth while periodically probing for more. Unlike
+/// it, packet loss and ECN-CE marks are treated as signals that the bandwidth or the amount of data
+/// in flight exceeds what the path can sustain: short-term lower bounds (`bw_lo`, `inflight_lo`)
+/// react to congestion within a round trip, and a long-term upper bound (`inflight_hi`) caps how far
+/// probing may push. This makes it coexist much more fairly with loss-based controllers on paths
+/// with shallow buffers.
+#[derive(Debug, Clone)]
+pub struct Bbr3 {
+    config: Arc<Bbr3Config>,
+    current_mtu: u64,
+    /// Total number of bytes delivered, as of the latest rate sample
+    delivered: u64,
+    /// Total number of bytes declared lost
+    lost: u64,
+    /// Delivery state of the packet the latest rate sample was based on
+    sampled: Option<SampledPacket>,
+    /// While in ProbeRTT, the value of `delivered` below which samples are treated as
+    /// application-limited, and zero otherwise
+    app_limited_until: u64,
+    state: State,
+    ack_phase: AckPhase,
+    pacing_gain: f64,
+    cwnd_gain: f64,
+    pacing_rate: u64,
+    cwnd: u64,
+    /// Congestion window to restore after loss recovery or ProbeRTT
+    prior_cwnd: u64,
+    in_flight: u64,
+    /// Whether sending was limited by the congestion window during the current or previous round
+    cwnd_limited: [bool; 2],
+    /// Latest smoothed RTT estimate
+    srtt: Duration,
+    /// Latest RTT sample
+    latest_rtt: Option<Duration>,
+
+    /// Whether the latest acknowledgements completed a round trip
+    round_start: bool,
+    /// Value of `delivered` at which the current round trip ends
+    next_round_delivered: u64,
+
+    /// Windowed maximum of the delivery rate over the last two ProbeBW cycles
+    max_bw: MaxBwFilter,
+    /// Bandwidth used by the model: `max_bw` bounded by `bw_lo`
+    bw: u64,
+    /// Maximum delivery rate over the current loss round
+    bw_latest: u64,
+    /// Short-term lower bound on the bandwidth, reduced in response to congestion
+    bw_lo: u64,
+    /// Maximum volume of data delivered over a sampling interval during the current loss round
+    inflight_latest: u64,
+    /// Short-term lower bound on the amount of data in flight, reduced in response to congestion
+    inflight_lo: u64,
+    /// Long-term upper bound on the amount of data in flight, found while probing for bandwidth
+    inflight_hi: u64,
+    ack_aggregation: AckAggregation,
+
+    min_rtt: Option<Duration>,
+    min_rtt_stamp: Option<Instant>,
+    /// Minimum RTT over the last `PROBE_RTT_INTERVAL`
+    probe_rtt_min_delay: Option<Duration>,
+    probe_rtt_min_stamp: Option<Instant>,
+    probe_rtt_expired: bool,
+    /// When ProbeRTT may end, once the amount of data in flight was reduced
+    probe_rtt_done_stamp: Option<Instant>,
+    probe_rtt_round_done: bool,
+
+    /// Whether the bottleneck bandwidth was found, through a plateau in the delivery rate or
+    /// congestion
+    filled_pipe: bool,
+    full_bw: u64,
+    full_bw_count: u32,
+    /// Whether the delivery rate plateaued since `full_bw` was last reset
+    full_bw_now: bool,
+
+    /// When the current ProbeBW phase started
+    cycle_stamp: Option<Instant>,
+    /// Time to wait in ProbeBW_DOWN and ProbeBW_CRUISE before probing for bandwidth again
+    bw_probe_wait: Duration,
+    rounds_since_bw_probe: u64,
+    /// Whether the delivery rate samples being received reflect bandwidth probing
+    bw_probe_samples: bool,
+    /// Number of bytes to acknowledge in ProbeBW_UP before growing `inflight_hi` by one MTU
+    probe_up_cnt: u64,
+    bw_probe_up_acks: u64,
+    bw_probe_up_rounds: u32,
+
+    /// Value of `delivered` at which the current loss round ends
+    loss_round_delivered: u64,
+    /// Number of bytes delivered over the previous loss round
+    loss_round_delivered_bytes: u64,
+    loss_round_start: bool,
+    /// Number of bytes lost during the current loss round
+    lost_in_round: u64,
+    /// Estimated number of bytes marked CE during the current loss round
+    ce_in_round: u64,
+    /// Moving average of the fraction of data marked with ECN-CE per round
+    ecn_alpha: f64,
+    /// Number of bytes acknowledged by the latest batch of acknowledgements
+    last_acked: u64,
+
+    /// When the current loss recovery episode started
+    recovery_start: Option<Instant>,
+    /// Whether the congestion window is limited to what was delivered during the first round of
+    /// loss recovery
+    packet_conservation: bool,
+
+    random_number_generator: rand::rngs::StdRng,
+}
+
+impl Bbr3 {
+    /// Construct a state using the given `config`
+    pub fn new(config: Arc<Bbr3Config>, current_mtu: u16) -> Self {
+        let current_mtu = current_mtu as u64;
+        let cwnd = config.initial_window.max(min_pipe_cwnd(current_mtu));
+        Self {
+            config,
+            current_mtu,
+            delivered: 0,
+            lost: 0,
+            sampled: None,
+            app_limited_until: 0,
+            state: State::Startup,
+            ack_phase: AckPhase::Init,
+            pacing_gain: STARTUP_PACING_GAIN,
+            cwnd_gain: STARTUP_CWND_GAIN,
+            pacing_rate: 0,
+            cwnd,
+            prior_cwnd: 0,
+            in_flight: 0,
+            cwnd_limited: [false; 2],
+            srtt: Duration::ZERO,
+            latest_rtt: None,
+            round_start: false,
+            next_round_delivered: 0,
+            max_bw: MaxBwFilter::default(),
+            bw: 0,
+            bw_latest: 0,
+            bw_lo: u64::MAX,
+            inflight_latest: 0,
+            inflight_lo: u64::MAX,
+            inflight_hi: u64::MAX,
+            ack_aggregation: AckAggregation::default(),
+            min_rtt: None,
+            min_rtt_stamp: None,
+            probe_rtt_min_delay: None,
+            probe_rtt_min_stamp: None,
+            probe_rtt_expired: false,
+            probe_rtt_done_stamp: None,
+            probe_rtt_round_done: false,
+            filled_pipe: false,
+            full_bw: 0,
+            full_bw_count: 0,
+            full_bw_now: false,
+            cycle_stamp: None,
+            bw_probe_wait: Duration::ZERO,
+            rounds_since_bw_probe: 0,
+            bw_probe_samples: false,
+            probe_up_cnt: u64::MAX,
+            bw_probe_up_acks: 0,
+            bw_probe_up_rounds: 0,
+            loss_round_delivered: 0,
+            loss_round_delivered_bytes: 0,
+            loss_round_start: false,
+            lost_in_round: 0,
+            ce_in_round: 0,
+            ecn_alpha: 0.0,
+            last_acked: 0,
+            recovery_start: None,
+            packet_conservation: false,
+            random_number_generator: rand::rngs::StdRng::from_entropy(),
+        }
+    }
+
+    fn update_model_and_state(&mut self, now: Instant, rs: &RateSample) {
+        self.update_round(rs);
+        self.update_latest_delivery_signals(rs);
+        self.update_congestion_signals(now, rs);
+        self.ack_aggregation
+            .update(now, self.bw, rs.newly_acked, self.cwnd, self.round_start);
+        self.check_full_bw_reached(rs);
+        if self.state == State::Startup && self.filled_pipe {
+            self.enter_drain();
+        }
+        if self.state == State::Drain && self.in_flight <= self.inflight(self.max_bw.get(), 1.0) {
+            self.start_probe_bw_down(now);
+        }
+        self.update_probe_bw_cycle_phase(now, rs);
+        self.update_min_rtt(now);
+        self.check_probe_rtt(now);
+        if self.loss_round_start {
+            self.bw_latest = rs.delivery_rate.unwrap_or(0);
+            self.inflight_latest = rs.delivered;
+        }
+        self.bw = self.max_bw.get().min(self.bw_lo);
+    }
+
+    fn update_round(&mut self, rs: &RateSample) {
+        self.round_start = rs.prior_delivered >= self.next_round_delivered;
+        if !self.round_start {
+            return;
+        }
+        self.next_round_delivered = self.delivered;
+        self.rounds_since_bw_probe += 1;
+        self.cwnd_limited = [false, self.cwnd_limited[0]];
+        self.packet_conservation = false;
+    }
+
+    fn update_latest_delivery_signals(&mut self, rs: &RateSample) {
+        self.loss_round_start = false;
+        self.bw_latest = self.bw_latest.max(rs.delivery_rate.unwrap_or(0));
+        self.inflight_latest = self.inflight_latest.max(rs.delivered);
+        if rs.prior_delivered >= self.loss_round_delivered {
+            let delivered = self.delivered;
+            self.loss_round_delivered_bytes = delivered - self.loss_round_delivered;
+            self.loss_round_delivered = delivered;
+            self.loss_round_start = true;
+        }
+    }
+
+    fn update_congestion_signals(&mut self, now: Instant, rs: &RateSample) {
+        if let Some(rate) = rs.delivery_rate {
+            if rate >= self.max_bw.get() || !rs.is_app_limited {
+                self.max_bw.update(rate);
+            }
+        }
+        if !self.loss_round_start {
+            return;
+        }
+
+        let ce_ratio = match self.loss_round_delivered_bytes {
+            0 => 0.0,
+            delivered => (self.ce_in_round as f64 / delivered as f64).min(1.0),
+        };
+        if self.ce_in_round != 0 || self.ecn_alpha != 0.0 {
+            self.ecn_alpha = (1.0 - ECN_ALPHA_GAIN) * self.ecn_alpha + ECN_ALPHA_GAIN * ce_ratio;
+        }
+        let ecn_too_high = ce_ratio > ECN_THRESH;
+        let loss_too_high = self.lost_in_round >= STARTUP_FULL_LOSS_COUNT * self.current_mtu
+            && self.lost_in_round as f64
+                > LOSS_THRESH * (self.loss_round_delivered_bytes + self.lost_in_round) as f64;
+
+        if self.state == State::Startup && (ecn_too_high || loss_too_high) {
+            // The bottleneck is already saturated
+            self.filled_pipe = true;
+            self.inflight_hi = self
+                .bdp_multiple(self.max_bw.get(), 1.0)
+                .max(self.inflight_latest);
+        } else if ecn_too_high && self.bw_probe_samples {
+            self.handle_inflight_too_high(now, rs.is_app_limited, rs.tx_in_flight);
+        }
+
+        self.adapt_lower_bounds_from_congestion();
+        self.lost_in_round = 0;
+        self.ce_in_round = 0;
+    }
+
+    fn adapt_lower_bounds_from_congestion(&mut self) {
+        if self.is_probing_bw() {
+            return;
+        }
+        let loss = self.lost_in_round != 0;
+        let ecn = self.ce_in_round != 0;
+        if !loss && !ecn {
+            return;
+        }
+
+        if self.bw_lo == u64::MAX {
+            self.bw_lo = self.max_bw.get();
+        }
+        if self.inflight_lo == u64::MAX {
+            self.inflight_lo = self.cwnd;
+        }
+        let mut inflight_cut = 1.0_f64;
+        if loss {
+            self.bw_lo = self.bw_latest.max((self.bw_lo as f64 * BETA) as u64);
+            inflight_cut = BETA;
+        }
+        if ecn {
+            inflight_cut = inflight_cut.min(1.0 - ECN_FACTOR * self.ecn_alpha);
+        }
+        self.inflight_lo = self
+            .inflight_latest
+            .max((self.inflight_lo as f64 * inflight_cut) as u64);
+    }
+
+    fn check_full_bw_reached(&mut self, rs: &RateSample) {
+        if self.full_bw_now || !self.round_start || rs.is_app_limited {
+            return;
+        }
+        let delivery_rate = rs.delivery_rate.unwrap_or(0);
+        if delivery_rate as f64 >= self.full_bw as f64 * FULL_BW_THRESH {
+            self.full_bw = delivery_rate;
+            self.full_bw_count = 0;
+            return;
+        }
+        self.full_bw_count += 1;
+        self.full_bw_now = self.full_bw_count >= FULL_BW_COUNT;
+        if self.full_bw_now {
+            self.filled_pipe = true;
+        }
+    }
+
+    fn reset_full_bw(&mut self) {
+        self.full_bw = 0;
+        self.full_bw_count = 0;
+        self.full_bw_now = false;
+    }
+
+    fn update_probe_bw_cycle_phase(&mut self, now: Instant, rs: &RateSample) {
+        if !self.filled_pipe {
+            return;
+        }
+        self.adapt_upper_bounds(now, rs);
+        match self.state {
+            State::ProbeBwDown => {
+                if self.check_time_to_probe_bw(now) {
+                    return;
+                }
+                if self.check_time_to_cruise() {
+                    self.start_probe_bw_cruise();
+                }
+            }
+            State::ProbeBwCruise => {
+                self.check_time_to_probe_bw(now);
+            }
+            State::ProbeBwRefill => {
+                // After one round of refilling, start probing
+                if self.round_start {
+                    self.bw_probe_samples = true;
+                    self.start_probe_bw_up(now, rs);
+                }
+            }
+            State::ProbeBwUp => {
+                if self.check_time_to_go_down(rs) {
+                    self.start_probe_bw_down(now);
+                }
+            }
+            State::Startup | State::Drain | State::ProbeRtt => {}
+        }
+    }
+
+    fn adapt_upper_bounds(&mut self, now: Instant, rs: &RateSample) {
+        if self.ack_phase == AckPhase::ProbeStarting && self.round_start {
+            // Starting to get feedback for the data sent while probing
+            self.ack_phase = AckPhase::ProbeFeedback;
+        }
+        if self.ack_phase == AckPhase::ProbeStopping && self.round_start {
+            // End of samples from the bandwidth probing phase
+            self.bw_probe_samples = false;
+            self.ack_phase = AckPhase::Init;
+            if self.is_in_probe_bw_state() && !rs.is_app_limited {
+                self.max_bw.advance();
+            }
+        }
+
+        if self.check_inflight_too_high(now, rs) || self.inflight_hi == u64::MAX {
+            return;
+        }
+        self.inflight_hi = self.inflight_hi.max(rs.tx_in_flight);
+        if self.state == State::ProbeBwUp {
+            self.probe_inflight_hi_upward(rs);
+        }
+    }
+
+    fn check_inflight_too_high(&mut self, now: Instant, rs: &RateSample) -> bool {
+        if !is_inflight_too_high(rs.lost_since_sent, rs.tx_in_flight) {
+            return false;
+        }
+        if self.bw_probe_samples {
+            self.handle_inflight_too_high(now, rs.is_app_limited, rs.tx_in_flight);
+        }
+        true
+    }
+
+    fn handle_inflight_too_high(&mut self, now: Instant, is_app_limited: bool, tx_in_flight: u64) {
+        self.bw_probe_samples = false;
+        if !is_app_limited {
+            self.inflight_hi = tx_in_flight.max((self.target_inflight() as f64 * BETA) as u64);
+        }
+        if self.state == State::ProbeBwUp {
+            self.start_probe_bw_down(now);
+        }
+    }
+
+    fn probe_inflight_hi_upward(&mut self, rs: &RateSample) {
+        if !self.is_cwnd_limited() || self.cwnd < self.inflight_hi {
+            // Not fully using inflight_hi, so don't grow it
+            return;
+        }
+        self.bw_probe_up_acks += rs.newly_acked;
+        if self.bw_probe_up_acks >= self.probe_up_cnt {
+            let delta = self.bw_probe_up_acks / self.probe_up_cnt;
+            self.bw_probe_up_acks -= delta * self.probe_up_cnt;
+            self.inflight_hi += delta * self.current_mtu;
+        }
+        if self.round_start {
+            self.raise_inflight_hi_slope();
+        }
+    }
+
+    /// Doubles the growth of `inflight_hi` in ProbeBW_UP every round
+    fn raise_inflight_hi_slope(&mut self) {
+        self.probe_up_cnt = (self.cwnd >> self.bw_probe_up_rounds).max(1);
+        self.bw_probe_up_rounds = (self.bw_probe_up_rounds + 1).min(30);
+    }
+
+    fn check_time_to_probe_bw(&mut self, now: Instant) -> bool {
+        let elapsed = self.cycle_stamp.map_or(false, |x| {
+            now.saturating_duration_since(x) > self.bw_probe_wait
+        });
+        if !elapsed && !self.is_reno_coexistence_probe_time() {
+            return false;
+        }
+        self.start_probe_bw_refill();
+        true
+    }
+
+    /// Whether to probe for bandwidth as often as Reno would grow its window by the current BDP
+    fn is_reno_coexistence_probe_time(&self) -> bool {
+        let reno_rounds = self.target_inflight() / self.current_mtu;
+        self.rounds_since_bw_probe >= reno_rounds.min(MAX_RENO_ROUNDS)
+    }
+
+    fn check_time_to_cruise(&self) -> bool {
+        if self.in_flight > self.inflight_with_headroom() {
+            // Not enough headroom
+            return false;
+        }
+        self.in_flight <= self.inflight(self.max_bw.get(), 1.0)
+    }
+
+    fn check_time_to_go_down(&mut self, rs: &RateSample) -> bool {
+        if self.is_cwnd_limited() && self.cwnd >= self.inflight_hi {
+            // inflight_hi is limiting probing; wait for the delivery rate to plateau from here
+            self.reset_full_bw();
+            self.full_bw = rs.delivery_rate.unwrap_or(0);
+            return false;
+        }
+        self.full_bw_now
+    }
+
+    fn enter_startup(&mut self) {
+        self.state = State::Startup;
+        self.pacing_gain = STARTUP_PACING_GAIN;
+        self.cwnd_gain = STARTUP_CWND_GAIN;
+    }
+
+    fn enter_drain(&mut self) {
+        self.state = State::Drain;
+        self.pacing_gain = DRAIN_PACING_GAIN;
+        self.cwnd_gain = STARTUP_CWND_GAIN;
+    }
+
+    fn start_probe_bw_down(&mut self, now: Instant) {
+        // Reset congestion signals
+        self.lost_in_round = 0;
+        self.ce_in_round = 0;
+        self.bw_latest = 0;
+        self.inflight_latest = 0;
+
+        self.probe_up_cnt = u64::MAX;
+        self.rounds_since_bw_probe = self.random_number_generator.gen_range(0..2);
+        self.bw_probe_wait = PROBE_BW_MIN_WAIT
+            + Duration::from_millis(self.random_number_generator.gen_range(0..1000));
+        self.cycle_stamp = Some(now);
+        self.ack_phase = AckPhase::ProbeStopping;
+        self.start_round();
+        self.state = State::ProbeBwDown;
+        self.pacing_gain = PROBE_BW_DOWN_PACING_GAIN;
+        self.cwnd_gain = DEFAULT_CWND_GAIN;
+    }
+
+    fn start_probe_bw_cruise(&mut self) {
+        self.state = State::ProbeBwCruise;
+        self.pacing_gain = 1.0;
+        self.cwnd_gain = DEFAULT_CWND_GAIN;
+    }
+
+    fn start_probe_bw_refill(&mut self) {
+        self.reset_lower_bounds();
+        self.bw_probe_up_rounds = 0;
+        self.bw_probe_up_acks = 0;
+        self.ack_phase = AckPhase::Refilling;
+        self.start_round();
+        self.state = State::ProbeBwRefill;
+        self.pacing_gain = 1.0;
+        self.cwnd_gain = DEFAULT_CWND_GAIN;
+    }
+
+    fn start_probe_bw_up(&mut self, now: Instant, rs: &RateSample) {
+        self.ack_phase = AckPhase::ProbeStarting;
+        self.start_round();
+        self.reset_full_bw();
+        self.full_bw = rs.delivery_rate.unwrap_or(0);
+        self.cycle_stamp = Some(now);
+        self.state = State::ProbeBwUp;
+        self.pacing_gain = PROBE_BW_UP_PACING_GAIN;
+        self.cwnd_gain = PROBE_BW_UP_CWND_GAIN;
+        self.raise_inflight_hi_slope();
+    }
+
+    fn update_min_rtt(&mut self, now: Instant) {
+        self.probe_rtt_expired = self.probe_rtt_min_stamp.map_or(false, |x| {
+            now.saturating_duration_since(x) > PROBE_RTT_INTERVAL
+        });
+        let rtt = match self.latest_rtt {
+            Some(x) => x,
+            None => return,
+        };
+        let delay = match self.probe_rtt_min_delay {
+            Some(x) if x <= rtt && !self.probe_rtt_expired => x,
+            _ => {
+                self.probe_rtt_min_delay = Some(rtt);
+                self.probe_rtt_min_stamp = Some(now);
+                rtt
+            }
+        };
+
+        let min_rtt_expired = self.min_rtt_stamp.map_or(true, |x| {
+            now.saturating_duration_since(x) > MIN_RTT_FILTER_LEN
+        });
+        if min_rtt_expired || self.min_rtt.map_or(true, |x| delay < x) {
+            self.min_rtt = self.probe_rtt_min_delay;
+            self.min_rtt_stamp = self.probe_rtt_min_stamp;
+        }
+    }
+
+    fn check_probe_rtt(&mut self, now: Instant) {
+        if self.state != State::ProbeRtt && self.probe_rtt_expired {
+            self.state = State::ProbeRtt;
+            self.pacing_gain = 1.0;
+            self.cwnd_gain = PROBE_RTT_CWND_GAIN;
+            self.save_cwnd();
+            self.probe_rtt_done_stamp = None;
+            self.ack_phase = AckPhase::ProbeStopping;
+            self.start_round();
+        }
+        if self.state != State::ProbeRtt {
+            return;
+        }
+
+        // Ignore low delivery rates caused by ProbeRTT
+        self.app_limited_until = (self.delivered + self.in_flight).max(1);
+        if self.probe_rtt_done_stamp.is_none() && self.in_flight <= self.probe_rtt_cwnd() {
+            // Wait for at least PROBE_RTT_DURATION and one round to elapse
+            self.probe_rtt_done_stamp = Some(now + PROBE_RTT_DURATION);
+            self.probe_rtt_round_done = false;
+            self.start_round();
+        } else if let Some(done) = self.probe_rtt_done_stamp {
+            if self.round_start {
+                self.probe_rtt_round_done = true;
+            }
+            if self.probe_rtt_round_done && now > done {
+                self.probe_rtt_min_stamp = Some(now);
+                self.restore_cwnd();
+                self.exit_probe_rtt(now);
+            }
+        }
+    }
+
+    fn exit_probe_rtt(&mut self, now: Instant) {
+        self.reset_lower_bounds();
+        if self.filled_pipe {
+            self.start_probe_bw_down(now);
+            self.start_probe_bw_cruise();
+        } else {
+            self.enter_startup();
+        }
+    }
+
+    fn set_pacing_rate(&mut self) {
+        let bw = match self.bw {
+            // Until the bandwidth is measured, pace the initial window over a round trip
+            0 => match self.srtt.as_nanos() {
+                0 => return,
+                srtt => (u128::from(self.cwnd) * 1_000_000_000 / srtt) as u64,
+            },
+            bw => bw,
+        };
+        let rate = (self.pacing_gain * bw as f64 * (1.0 - PACING_MARGIN)) as u64;
+        if self.filled_pipe || rate > self.pacing_rate {
+            self.pacing_rate = rate;
+        }
+    }
+
+    fn set_cwnd(&mut self, rs: &RateSample) {
+        let max_inflight = self.quantization_budget(
+            self.bdp_multiple(self.bw, self.cwnd_gain) + self.ack_aggregation.extra_acked(),
+        );
+        if self.packet_conservation {
+            self.cwnd = self.cwnd.max(self.in_flight + rs.newly_acked);
+        } else {
+            if self.filled_pipe {
+                self.cwnd = (self.cwnd + rs.newly_acked).min(max_inflight);
+            } else if self.cwnd < max_inflight || self.delivered < self.config.initial_window {
+                self.cwnd += rs.newly_acked;
+            }
+            self.cwnd = self.cwnd.max(min_pipe_cwnd(self.current_mtu));
+        }
+
+        if self.state == State::ProbeRtt {
+            self.cwnd = self.cwnd.min(self.probe_rtt_cwnd());
+        }
+
+        // Bound the window by the model
+        let mut cap = match self.state {
+            State::ProbeBwDown | State::ProbeBwRefill | State::ProbeBwUp => self.inflight_hi,
+            State::ProbeBwCruise | State::ProbeRtt => self.inflight_with_headroom(),
+            State::Startup | State::Drain => u64::MAX,
+        };
+        cap = cap.min(self.inflight_lo);
+        cap = cap.max(min_pipe_cwnd(self.current_mtu));
+        self.cwnd = self.cwnd.min(cap);
+    }
+
+    fn save_cwnd(&mut self) {
+        self.prior_cwnd = if self.recovery_start.is_none() && self.state != State::ProbeRtt {
+            self.cwnd
+        } else {
+            self.prior_cwnd.max(self.cwnd)
+        };
+    }
+
+    fn restore_cwnd(&mut self) {
+        self.cwnd = self.cwnd.max(self.prior_cwnd);
+    }
+
+    fn reset_lower_bounds(&mut self) {
+        self.bw_lo = u64::MAX;
+        self.inflight_lo = u64::MAX;
+    }
+
+    fn start_round(&mut self) {
+        self.next_round_delivered = self.delivered;
+    }
+
+    /// Amount of data in flight which leaves some room for other flows
+    fn inflight_with_headroom(&self) -> u64 {
+        if self.inflight_hi == u64::MAX {
+            return u64::MAX;
+        }
+        let headroom = ((HEADROOM * self.inflight_hi as f64) as u64).max(self.current_mtu);
+        self.inflight_hi
+            .saturating_sub(headroom)
+            .max(min_pipe_cwnd(self.current_mtu))
+    }
+
+    fn inflight(&self, bw: u64, gain: f64) -> u64 {
+        self.quantization_budget(self.bdp_multiple(bw, gain))
+    }
+
+    fn bdp_multiple(&self, bw: u64, gain: f64) -> u64 {
+        match self.min_rtt {
+            Some(min_rtt) => (gain * bw as f64 * min_rtt.as_secs_f64()) as u64,
+            None => self.config.initial_window,
+        }
+    }
+
+    /// Accounts for the data which may be in flight because of batching and pacing
+    fn quantization_budget(&self, inflight: u64) -> u64 {
+        let mut inflight = inflight
+            .max(3 * self.send_quantum())
+            .max(min_pipe_cwnd(self.current_mtu));
+        if self.state == State::ProbeBwUp {
+            inflight += 2 * self.current_mtu;
+        }
+        inflight
+    }
+
+    fn send_quantum(&self) -> u64 {
+        let floor = match self.pacing_rate < LOW_PACING_RATE {
+            true => self.current_mtu,
+            false => 2 * self.current_mtu,
+        };
+        (self.pacing_rate / 1000).min(MAX_SEND_QUANTUM).max(floor)
+    }
+
+    fn target_inflight(&self) -> u64 {
+        self.bdp_multiple(self.bw, 1.0).min(self.cwnd)
+    }
+
+    fn probe_rtt_cwnd(&self) -> u64 {
+        self.bdp_multiple(self.bw, PROBE_RTT_CWND_GAIN)
+            .max(min_pipe_cwnd(self.current_mtu))
+    }
+
+    fn is_cwnd_limited(&self) -> bool {
+        self.cwnd_limited[0] || self.cwnd_limited[1]
+    }
+
+    fn is_in_probe_bw_state(&self) -> bool {
+        matches!(
+            self.state,
+            State::ProbeBwDown | State::ProbeBwCruise | State::ProbeBwRefill | State::ProbeBwUp
+        )
+    }
+
+    fn is_probing_bw(&self) -> bool {
+        matches!(
+            self.state,
+            State::Startup | State::ProbeBwRefill | State::ProbeBwUp
+        )
+    }
+}
+
+impl Controller for Bbr3 {
+    fn on_sent(&mut self, _now: Instant, bytes: u64, _last_packet_number: u64) {
+        self.in_flight += bytes;
+        if self.in_flight + self.current_mtu > self.cwnd {
+            self.cwnd_limited[0] = true;
+        }
+    }
+
+    fn on_ack(
+        &mut self,
+        _now: Instant,
+        sent: Instant,
+        bytes: u64,
+        _app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        self.in_flight = self.in_flight.saturating_sub(bytes);
+        self.srtt = rtt.get();
+        self.latest_rtt = Some(rtt.latest());
+
+        if self.recovery_start.map_or(false, |start| sent > start) {
+            // A packet sent after the loss was delivered, so recovery is over
+            self.recovery_start = None;
+            self.packet_conservation = false;
+            self.restore_cwnd();
+        }
+    }
+
+    fn on_rate_sample(&mut self, now: Instant, sample: &RateSample) {
+        let mut rs = *sample;
+        if rs.prior_delivered < self.app_limited_until {
+            rs.is_app_limited = true;
+        }
+        self.delivered = rs.prior_delivered + rs.delivered;
+        if self.app_limited_until != 0 && self.delivered > self.app_limited_until {
+            self.app_limited_until = 0;
+        }
+        self.sampled = Some(SampledPacket {
+            tx_in_flight: rs.tx_in_flight,
+            lost: self.lost.saturating_sub(rs.lost_since_sent),
+            is_app_limited: rs.is_app_limited,
+        });
+        self.last_acked = rs.newly_acked;
+
+        self.update_model_and_state(now, &rs);
+        self.set_pacing_rate();
+        self.set_cwnd(&rs);
+    }
+
+    fn on_end_acks(
+        &mut self,
+        _now: Instant,
+        in_flight: u64,
+        _app_limited: bool,
+        _largest_packet_num_acked: Option<u64>,
+    ) {
+        self.in_flight = in_flight;
+    }
+
+    fn on_ecn(&mut self, _now: Instant, ect: u64, ce: u64) {
+        // Marks are counted in packets; attribute them to the data that was just acknowledged
+        if let Some(marked) = (self.last_acked * ce).checked_div(ect + ce) {
+            self.ce_in_round += marked;
+        }
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        is_persistent_congestion: bool,
+        lost_bytes: u64,
+    ) {
+        if lost_bytes == 0 {
+            // CE marks are accounted for in `on_ecn`
+            return;
+        }
+
+        self.lost += lost_bytes;
+        self.in_flight = self.in_flight.saturating_sub(lost_bytes);
+        self.lost_in_round += lost_bytes;
+        // The lost packets' own delivery state isn't available, so judge by that of the packet
+        // the latest rate sample was based on, which was sent around the same time
+        if let Some(packet) = self.sampled.filter(|_| self.bw_probe_samples) {
+            if is_inflight_too_high(self.lost - packet.lost, packet.tx_in_flight) {
+                self.handle_inflight_too_high(now, packet.is_app_limited, packet.tx_in_flight);
+            }
+        }
+
+        let min_cwnd = self.current_mtu;
+        if is_persistent_congestion {
+            self.save_cwnd();
+            self.cwnd = self.in_flight + min_cwnd;
+            self.recovery_start = Some(now);
+            return;
+        }
+        if self.recovery_start.map_or(true, |start| sent > start) {
+            // Start a new recovery episode
+            self.save_cwnd();
+            self.cwnd = self.in_flight + min_cwnd;
+            self.packet_conservation = true;
+            self.recovery_start = Some(now);
+            self.start_round();
+        } else {
+            self.cwnd = self.cwnd.saturating_sub(lost_bytes).max(min_cwnd);
+        }
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.current_mtu = new_mtu as u64;
+        self.cwnd = self.cwnd.max(min_pipe_cwnd(self.current_mtu));
+    }
+
+    fn window(&self) -> u64 {
+        self.cwnd
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config.initial_window
+    }
+
+    fn pacing_rate(&self) -> Option<u64> {
+        // Until the round-trip time is measured, fall back to the window
+        Some(self.pacing_rate).filter(|&rate| rate != 0)
+    }
+
+    fn send_quantum(&self) -> Option<u64> {
+        Some(Self::send_quantum(self))
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Delivery state of a packet when it was sent
+#[derive(Debug, Copy, Clone)]
+struct SampledPacket {
+    /// Number of bytes in flight once the packet was sent, including it
+    tx_in_flight: u64,
+    /// Value of `Bbr3::lost` when the packet was sent
+    lost: u64,
+    is_app_limited: bool,
+}
+
+/// Configuration for the [`Bbr3`] congestion controller
+#[derive(Debug, Clone)]
+pub struct Bbr3Config {
+    initial_window: u64,
+}
+
+impl Bbr3Config {
+    /// Default limit on the amount of outstanding data in bytes.
+    ///
+    /// Recommended value: `min(10 * max_datagram_size, max(2 * max_datagram_size, 14720))`
+    pub fn initial_window(&mut self, value: u64) -> &mut Self {
+        self.initial_window = value;
+        self
+    }
+}
+
+impl Default for Bbr3Config {
+    fn default() -> Self {
+        Self {
+            initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
+        }
+    }
+}
+
+impl ControllerFactory for Arc<Bbr3Config> {
+    fn build(&self, _now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        Box::new(Bbr3::new(self.clone(), current_mtu))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum State {
+    // Rapidly grow the sending rate until the bandwidth stops increasing or congestion is
+    // detected.
+    Startup,
+    // Drain the queue created during Startup.
+    Drain,
+    // Slow down to leave room for other flows and drain any queue.
+    ProbeBwDown,
+    // Send at the estimated bandwidth.
+    ProbeBwCruise,
+    // Refill the pipe up to inflight_hi before probing.
+    ProbeBwRefill,
+    // Probe for more bandwidth by sending faster than the estimate.
+    ProbeBwUp,
+    // Temporarily reduce the amount of data in flight to measure the minimum RTT.
+    ProbeRtt,
+}
+
+/// Which phase of bandwidth probing the acknowledgements being received correspond to
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum AckPhase {
+    Init,
+    Refilling,
+    ProbeStarting,
+    ProbeFeedback,
+    ProbeStopping,
+}
+
+/// Maximum of the delivery rate samples over the current and previous ProbeBW cycles
+#[derive(Debug, Clone, Copy, Default)]
+struct MaxBwFilter {
+    bw: [u64; 2],
+}
+
+impl MaxBwFilter {
+    fn get(&self) -> u64 {
+        self.bw[0].max(self.bw[1])
+    }
+
+    fn update(&mut self, sample: u64) {
+        self.bw[1] = self.bw[1].max(sample);
+    }
+
+    /// Starts a new cycle, forgetting samples from the oldest one
+    fn advance(&mut self) {
+        if self.bw[1] == 0 {
+            return;
+        }
+        self.bw[0] = self.bw[1];
+        self.bw[1] = 0;
+    }
+}
+
+/// Estimates the amount of data acknowledged in excess of the bandwidth, due to aggregation of
+/// acknowledgements
+#[derive(Debug, Clone, Copy, Default)]
+struct AckAggregation {
+    interval_start: Option<Instant>,
+    delivered: u64,
+    /// Maximum excess over the current and previous windows of `EXTRA_ACKED_WINDOW_ROUNDS`
+    max: [u64; 2],
+    rounds: u32,
+}
+
+impl AckAggregation {
+    fn update(&mut self, now: Instant, bw: u64, newly_acked: u64, cwnd: u64, round_start: bool) {
+        if round_start {
+            self.rounds += 1;
+            if self.rounds >= EXTRA_ACKED_WINDOW_ROUNDS {
+                self.rounds = 0;
+                self.max = [self.max[1], 0];
+            }
+        }
+
+        let interval = now.saturating_duration_since(self.interval_start.unwrap_or(now));
+        let mut expected = (bw as f64 * interval.as_secs_f64()) as u64;
+        if self.delivered <= expected {
+            // Acknowledgements aren't arriving faster than expected; start a new interval
+            self.delivered = 0;
+            self.interval_start = Some(now);
+            expected = 0;
+        }
+        self.delivered += newly_acked;
+        let extra = (self.delivered - expected).min(cwnd);
+        self.max[1] = self.max[1].max(extra);
+    }
+
+    fn extra_acked(&self) -> u64 {
+        self.max[0].max(self.max[1])
+    }
+}
+
+fn is_inflight_too_high(lost: u64, tx_in_flight: u64) -> bool {
+    tx_in_flight != 0 && lost as f64 > tx_in_flight as f64 * LOSS_THRESH
+}
+
+fn min_pipe_cwnd(current_mtu: u64) -> u64 {
+    4 * current_mtu
+}
+
+// Pacing gain in Startup, 4 * ln(2), enough to double the sending rate every round.
+const STARTUP_PACING_GAIN: f64 = 2.77;
+const STARTUP_CWND_GAIN: f64 = 2.0;
+const DRAIN_PACING_GAIN: f64 = 0.35;
+const DEFAULT_CWND_GAIN: f64 = 2.0;
+const PROBE_BW_DOWN_PACING_GAIN: f64 = 0.9;
+const PROBE_BW_UP_PACING_GAIN: f64 = 1.25;
+const PROBE_BW_UP_CWND_GAIN: f64 = 2.25;
+const PROBE_RTT_CWND_GAIN: f64 = 0.5;
+// Pace slightly below the estimated bandwidth to avoid building queues.
+const PACING_MARGIN: f64 = 0.01;
+// Maximum fraction of data in flight which may be lost before it's considered too high.
+const LOSS_THRESH: f64 = 0.02;
+// Multiplicative decrease of the short-term bounds in response to loss.
+const BETA: f64 = 0.7;
+// Fraction of inflight_hi left for other flows when not probing.
+const HEADROOM: f64 = 0.15;
+// Fraction of data marked with ECN-CE in a round above which data in flight is too high.
+const ECN_THRESH: f64 = 0.5;
+// Multiplicative decrease of inflight_lo in response to ECN-CE marks, scaled by ecn_alpha.
+const ECN_FACTOR: f64 = 1.0 / 3.0;
+const ECN_ALPHA_GAIN: f64 = 1.0 / 16.0;
+// Growth of the delivery rate per round below which the bandwidth is considered reached.
+const FULL_BW_THRESH: f64 = 1.25;
+const FULL_BW_COUNT: u32 = 3;
+// Number of packets lost in a round after which Startup may end due to loss.
+const STARTUP_FULL_LOSS_COUNT: u64 = 6;
+const MAX_RENO_ROUNDS: u64 = 63;
+const PROBE_BW_MIN_WAIT: Duration = Duration::from_secs(2);
+const PROBE_RTT_INTERVAL: Duration = Duration::from_secs(5);
+const PROBE_RTT_DURATION: Duration = Duration::from_millis(200);
+const MIN_RTT_FILTER_LEN: Duration = Duration::from_secs(10);
+const EXTRA_ACKED_WINDOW_ROUNDS: u32 = 5;
+// 1.2 Mbps in bytes per second, below which a single datagram is sent at a time.
+const LOW_PACING_RATE: u64 = 150_000;
+const MAX_SEND_QUANTUM: u64 = 64 * 1024;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::connection::{DeliveryRateEstimator, DeliveryState};
+
+    const MTU: u64 = 1200;
+
+    /// A path with a single bottleneck, where all the delay is on the return path
+    struct Path {
+        /// Bottleneck bandwidth in bytes per second
+        bandwidth: u64,
+        /// RTT when the bottleneck queue is empty
+        rtt: Duration,
+        /// Capacity of the bottleneck queue in bytes
+        buffer: u64,
+        /// Queue length above which packets are marked with ECN-CE
+        ecn_threshold: Option<u64>,
+    }
+
+    #[derive(Default)]
+    struct Summary {
+        delivered: u64,
+        lost: u64,
+        marked: u64,
+        /// Longest queue observed during the second half of the simulation
+        max_queue: u64,
+    }
+
+    /// Transmits as fast as `bbr` allows over `path` for `duration`
+    fn simulate(bbr: &mut Bbr3, path: &Path, duration: Duration) -> Summary {
+        let start = Instant::now();
+        let mut now = start;
+        let mut rtt = RttEstimator::new(Duration::from_millis(333));
+        let mut rate = DeliveryRateEstimator::new(now);
+        let mut next_send = now;
+        let mut link_free = now;
+        let mut in_flight = 0;
+        // Time at which the sender learns the fate of each packet, with when it was sent, its
+        // delivery state and whether it was lost or marked
+        let mut events = VecDeque::<(Instant, Instant, DeliveryState, Fate)>::new();
+        let mut summary = Summary::default();
+        let serialize = |bytes: u64| Duration::from_secs_f64(bytes as f64 / path.bandwidth as f64);
+
+        while now < start + duration {
+            let can_send = in_flight + MTU <= bbr.window();
+            if can_send && now >= next_send {
+                let state = rate.on_sent(now, in_flight, MTU as u16);
+                bbr.on_sent(now, MTU, 0);
+                in_flight += MTU;
+                let queue = (link_free.saturating_duration_since(now).as_secs_f64()
+                    * path.bandwidth as f64) as u64;
+                if now > start + duration / 2 {
+                    summary.max_queue = summary.max_queue.max(queue);
+                }
+                let fate = if queue + MTU > path.buffer {
+                    // Detected as lost along with the next acknowledgement
+                    Fate::Lost
+                } else {
+                    link_free = link_free.max(now) + serialize(MTU);
+                    match path.ecn_threshold {
+                        Some(threshold) if queue > threshold => Fate::Marked,
+                        _ => Fate::Delivered,
+                    }
+                };
+                events.push_back((link_free + path.rtt, now, state, fate));
+                if let Some(interval) = (MTU * path.bandwidth).checked_div(bbr.pacing_rate) {
+                    next_send = now + serialize(interval);
+                }
+                continue;
+            }
+
+            let mut next = events.front().expect("stalled").0;
+            if can_send {
+                next = next.min(next_send);
+            }
+            now = now.max(next);
+
+            let delivered_before = summary.delivered;
+            let prior_in_flight = in_flight;
+            let mut acked = false;
+            let mut marked = 0;
+            let mut lost = Vec::new();
+            while let Some(&(time, sent, state, fate)) = events.front() {
+                if time > now {
+                    break;
+                }
+                events.pop_front();
+                in_flight -= MTU;
+                if fate == Fate::Lost {
+                    lost.push(sent);
+                    summary.lost += MTU;
+                    continue;
+                }
+                rtt.update(Duration::ZERO, now - sent);
+                bbr.on_ack(now, sent, MTU, false, &rtt);
+                rate.on_ack(now, sent, MTU as u16, state, false);
+                summary.delivered += MTU;
+                acked = true;
+                if fate == Fate::Marked {
+                    marked += 1;
+                    summary.marked += MTU;
+                }
+            }
+            if acked {
+                if let Some(sample) = rate.end_acks(prior_in_flight, rtt.min()) {
+                    bbr.on_rate_sample(now, &sample);
+                }
+                bbr.on_end_acks(now, in_flight, false, None);
+                if path.ecn_threshold.is_some() {
+                    let packets = (summary.delivered - delivered_before) / MTU;
+                    bbr.on_ecn(now, packets - marked, marked);
+                }
+            }
+            if marked != 0 {
+                bbr.on_congestion_event(now, now, false, 0);
+            }
+            for sent in lost {
+                rate.on_lost(MTU);
+                bbr.on_congestion_event(now, sent, false, MTU);
+            }
+        }
+        summary
+    }
+
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    enum Fate {
+        Delivered,
+        Marked,
+        Lost,
+    }
+
+    fn bbr3() -> Bbr3 {
+        Bbr3::new(Arc::new(Bbr3Config::default()), MTU as u16)
+    }
+
+    #[test]
+    fn converges_to_bottleneck() {
+        let mut bbr = bbr3();
+        let path = Path {
+            bandwidth: 1_250_000,
+            rtt: Duration::from_millis(50),
+            buffer: 1_000_000,
+            ecn_threshold: None,
+        };
+        let summary = simulate(&mut bbr, &path, Duration::from_secs(10));
+        assert!(bbr.filled_pipe);
+        // Every sample includes the time to serialize the packet at the bottleneck
+        let min_rtt = bbr.min_rtt.unwrap();
+        assert!(min_rtt >= path.rtt && min_rtt < path.rtt + Duration::from_millis(2));
+        let error = bbr.max_bw.get() as f64 / path.bandwidth as f64 - 1.0;
+        assert!(error.abs() < 0.1, "bandwidth error {error}");
+        assert!(summary.delivered > path.bandwidth * 10 * 8 / 10);
+        assert_eq!(summary.lost, 0);
+        // The queue stays below a BDP once Startup is over
+        let bdp = path.bandwidth / 20;
+        assert!(summary.max_queue < bdp);
+    }
+
+    #[test]
+    fn shallow_buffer_loss() {
+        let mut bbr = bbr3();
+        let path = Path {
+            bandwidth: 1_250_000,
+            rtt: Duration::from_millis(50),
+            buffer: 1_250_000 / 20 / 4,
+            ecn_threshold: None,
+        };
+        let summary = simulate(&mut bbr, &path, Duration::from_secs(10));
+        // Loss bounds the amount of data in flight
+        assert_ne!(bbr.inflight_hi, u64::MAX);
+        assert!(summary.lost < summary.delivered / 20);
+        assert!(summary.delivered > path.bandwidth * 10 * 7 / 10);
+    }
+
+    #[test]
+    fn ecn_marks() {
+        let mut bbr = bbr3();
+        let path = Path {
+            bandwidth: 1_250_000,
+            rtt: Duration::from_millis(50),
+            buffer: 1_000_000,
+            ecn_threshold: Some(10 * MTU),
+        };
+        let summary = simulate(&mut bbr, &path, Duration::from_secs(10));
+        // Marks, rather than loss, bound the amount of data in flight
+        assert_eq!(summary.lost, 0);
+        assert_ne!(bbr.inflight_hi, u64::MAX);
+        assert!(summary.delivered > path.bandwidth * 10 * 7 / 10);
+    }
+}
@@ -2,7 +2,7 @@ use std::any::Any;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+use super::{hystart::HyStart, Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
 use crate::connection::RttEstimator;
 use std::cmp;
 
@@ -70,6 +70,8 @@ pub struct Cubic {
     recovery_start_time: Option<Instant>,
     cubic_state: State,
     current_mtu: u64,
+    /// HyStart++ state, if enabled and still in the initial slow start
+    hystart: Option<HyStart>,
 }
 
 impl Cubic {
@@ -79,6 +81,7 @@ impl Cubic {
             window: config.initial_window,
             ssthresh: u64::MAX,
             recovery_start_time: None,
+            hystart: config.hystart.then(HyStart::default),
             config,
             cubic_state: Default::default(),
             current_mtu: current_mtu as u64,
@@ -91,6 +94,12 @@ impl Cubic {
 }
 
 impl Controller for Cubic {
+    fn on_sent(&mut self, _now: Instant, _bytes: u64, last_packet_number: u64) {
+        if let Some(ref mut hystart) = self.hystart {
+            hystart.on_sent(last_packet_number);
+        }
+    }
+
     fn on_ack(
         &mut self,
         now: Instant,
@@ -110,7 +119,10 @@ impl Controller for Cubic {
 
         if self.window < self.ssthresh {
             // Slow start
-            self.window += bytes;
+            self.window += match self.hystart {
+                Some(ref mut hystart) => hystart.on_ack(bytes, rtt.latest()),
+                None => bytes,
+            };
         } else {
             // Congestion avoidance.
             let ca_start_time;
@@ -162,6 +174,24 @@ impl Controller for Cubic {
         }
     }
 
+    fn on_end_acks(
+        &mut self,
+        _now: Instant,
+        _in_flight: u64,
+        _app_limited: bool,
+        largest_packet_num_acked: Option<u64>,
+    ) {
+        let exit = match self.hystart {
+            Some(ref mut hystart) => hystart.on_end_acks(largest_packet_num_acked),
+            None => false,
+        };
+        if exit {
+            // Conservative slow start is over
+            self.hystart = None;
+            self.ssthresh = self.window;
+        }
+    }
+
     fn on_congestion_event(
         &mut self,
         now: Instant,
@@ -177,6 +207,7 @@ impl Controller for Cubic {
             return;
         }
 
+        self.hystart = None;
         self.recovery_start_time = Some(now);
 
         // Fast convergence
@@ -239,6 +270,7 @@ impl Controller for Cubic {
 #[derive(Debug, Clone)]
 pub struct CubicConfig {
     initial_window: u64,
+    hystart: bool,
 }
 
 impl CubicConfig {
@@ -249,12 +281,21 @@ impl CubicConfig {
         self.initial_window = value;
         self
     }
+
+    /// Whether to use HyStart++ (RFC 9406) during the initial slow start
+    ///
+    /// See [`NewRenoConfig::hystart`](super::NewRenoConfig::hystart).
+    pub fn hystart(&mut self, value: bool) -> &mut Self {
+        self.hystart = value;
+        self
+    }
 }
 
 impl Default for CubicConfig {
     fn default() -> Self {
         Self {
             initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
+            hystart: false,
         }
     }
 }
@@ -0,0 +1,223 @@
+use std::{mem, time::Duration};
+
+/// HyStart++ state, as described in RFC 9406
+///
+/// Slow start is exited early when the RTT increases during a round by more than a fraction of
+/// the previous round's minimum RTT, which indicates that queues along the path are building up.
+/// Rather than immediately switching to congestion avoidance, the window then grows at a reduced
+/// rate for a few rounds of Conservative Slow Start, to verify that the increase wasn't spurious.
+/// The controller resumes regular slow start if the RTT goes back down during that time.
+///
+/// Owned by a controller for the duration of its initial slow start only. The limit on window
+/// growth for unpaced senders doesn't apply, as quinn paces its transmissions.
+#[derive(Debug, Clone, Default)]
+pub(super) struct HyStart {
+    /// Largest packet number sent when the current round started
+    ///
+    /// The round ends once that packet is acknowledged.
+    window_end: Option<u64>,
+    /// Largest packet number sent so far
+    last_sent: u64,
+    /// Minimum RTT sample taken during the previous round
+    last_round_min_rtt: Option<Duration>,
+    /// Minimum RTT sample taken during the current round
+    current_round_min_rtt: Option<Duration>,
+    /// Number of RTT samples taken during the current round
+    rtt_sample_count: u32,
+    /// Whether the RTT sample of the batch of acknowledgements being processed was recorded
+    ///
+    /// An ACK frame yields a single RTT sample, however many packets it acknowledges.
+    batch_sampled: bool,
+    /// Minimum RTT of the round which triggered Conservative Slow Start, if it's ongoing
+    css_baseline_min_rtt: Option<Duration>,
+    /// Number of rounds completed in Conservative Slow Start
+    css_rounds: u32,
+}
+
+impl HyStart {
+    pub(super) fn on_sent(&mut self, last_packet_number: u64) {
+        self.last_sent = last_packet_number;
+    }
+
+    /// Records the acknowledgement of `bytes` with the latest RTT sample `rtt`
+    ///
+    /// Only the first call in each batch of acknowledgements, which ends with `on_end_acks`, takes
+    /// the RTT sample into account. Returns how much the congestion window should grow by.
+    pub(super) fn on_ack(&mut self, bytes: u64, rtt: Duration) -> u64 {
+        if mem::replace(&mut self.batch_sampled, true) {
+            return match self.css_baseline_min_rtt {
+                None => bytes,
+                Some(_) => bytes / CSS_GROWTH_DIVISOR,
+            };
+        }
+
+        let min_rtt = self.current_round_min_rtt.map_or(rtt, |x| x.min(rtt));
+        self.current_round_min_rtt = Some(min_rtt);
+        self.rtt_sample_count += 1;
+        let enough_samples = self.rtt_sample_count >= N_RTT_SAMPLE;
+
+        match self.css_baseline_min_rtt {
+            None => {
+                if let Some(last) = self.last_round_min_rtt {
+                    let threshold = (last / MIN_RTT_DIVISOR).clamp(MIN_RTT_THRESH, MAX_RTT_THRESH);
+                    if enough_samples && min_rtt >= last + threshold {
+                        // Delay is increasing; the window is likely close to the path's capacity
+                        self.css_baseline_min_rtt = Some(min_rtt);
+                        self.css_rounds = 0;
+                    }
+                }
+                bytes
+            }
+            Some(baseline) if enough_samples && min_rtt < baseline => {
+                // The delay increase was spurious
+                self.css_baseline_min_rtt = None;
+                bytes
+            }
+            Some(_) => bytes / CSS_GROWTH_DIVISOR,
+        }
+    }
+
+    /// Records the end of a batch of acknowledgements, up to `largest_acked`
+    ///
+    /// Returns whether slow start should end.
+    pub(super) fn on_end_acks(&mut self, largest_acked: Option<u64>) -> bool {
+        self.batch_sampled = false;
+        match (largest_acked, self.window_end) {
+            (None, _) => return false,
+            (Some(acked), Some(end)) if acked < end => return false,
+            _ => {}
+        }
+
+        // Start a new round
+        self.window_end = Some(self.last_sent);
+        self.last_round_min_rtt = self.current_round_min_rtt.take();
+        self.rtt_sample_count = 0;
+        if self.css_baseline_min_rtt.is_none() {
+            return false;
+        }
+        self.css_rounds += 1;
+        self.css_rounds >= CSS_ROUNDS
+    }
+
+    /// Whether the controller is in Conservative Slow Start
+    #[cfg(test)]
+    fn in_css(&self) -> bool {
+        self.css_baseline_min_rtt.is_some()
+    }
+}
+
+/// Lower bound on the RTT increase which triggers Conservative Slow Start
+const MIN_RTT_THRESH: Duration = Duration::from_millis(4);
+/// Upper bound on the RTT increase which triggers Conservative Slow Start
+const MAX_RTT_THRESH: Duration = Duration::from_millis(16);
+/// Fraction of the previous round's minimum RTT by which the RTT must increase to trigger
+/// Conservative Slow Start
+const MIN_RTT_DIVISOR: u32 = 8;
+/// Number of RTT samples needed in a round before comparing it against the previous one
+const N_RTT_SAMPLE: u32 = 8;
+/// Reduction of window growth during Conservative Slow Start
+const CSS_GROWTH_DIVISOR: u64 = 4;
+/// Number of rounds of Conservative Slow Start before entering congestion avoidance
+const CSS_ROUNDS: u32 = 5;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Simulates a round of 10 packets, each acknowledged separately with the given RTT, returning
+    /// the window growth and whether slow start ended
+    fn round(hystart: &mut HyStart, pn: &mut u64, rtt: Duration) -> (u64, bool) {
+        let mut growth = 0;
+        for _ in 0..9 {
+            growth += hystart.on_ack(1000, rtt);
+            *pn += 1;
+            assert!(!hystart.on_end_acks(Some(*pn)));
+        }
+        growth += hystart.on_ack(1000, rtt);
+        *pn += 1;
+        hystart.on_sent(*pn + 10);
+        (growth, hystart.on_end_acks(Some(*pn)))
+    }
+
+    /// Starts the first round, ending once packet 10 is acknowledged
+    fn start() -> HyStart {
+        let mut hystart = HyStart::default();
+        hystart.on_sent(10);
+        assert!(!hystart.on_end_acks(Some(0)));
+        hystart
+    }
+
+    #[test]
+    fn exits_after_conservative_slow_start() {
+        let mut hystart = start();
+        let mut pn = 0;
+        for _ in 0..3 {
+            assert_eq!(
+                round(&mut hystart, &mut pn, Duration::from_millis(100)),
+                (10_000, false)
+            );
+        }
+
+        // An increase below the threshold of 100ms / 8 is tolerated
+        assert_eq!(
+            round(&mut hystart, &mut pn, Duration::from_millis(110)),
+            (10_000, false)
+        );
+        assert!(!hystart.in_css());
+
+        // Growth is reduced as soon as enough samples were taken to detect the increase
+        assert_eq!(
+            round(&mut hystart, &mut pn, Duration::from_millis(130)),
+            (8 * 1000 + 2 * 250, false)
+        );
+        assert!(hystart.in_css());
+        for _ in 0..3 {
+            assert_eq!(
+                round(&mut hystart, &mut pn, Duration::from_millis(130)),
+                (2_500, false)
+            );
+        }
+        assert_eq!(
+            round(&mut hystart, &mut pn, Duration::from_millis(130)),
+            (2_500, true)
+        );
+    }
+
+    #[test]
+    fn resumes_slow_start_after_spurious_increase() {
+        let mut hystart = start();
+        let mut pn = 0;
+        round(&mut hystart, &mut pn, Duration::from_millis(20));
+        // Increases by at least 4ms are significant, even for short RTTs
+        round(&mut hystart, &mut pn, Duration::from_millis(25));
+        assert!(hystart.in_css());
+        assert_eq!(
+            round(&mut hystart, &mut pn, Duration::from_millis(25)).0,
+            2_500
+        );
+
+        let (growth, exit) = round(&mut hystart, &mut pn, Duration::from_millis(20));
+        assert!(!hystart.in_css());
+        assert!(!exit);
+        // Growth is only reduced until enough samples were taken to detect the decrease
+        assert_eq!(growth, 7 * 250 + 3 * 1000);
+    }
+
+    #[test]
+    fn one_sample_per_ack() {
+        let mut hystart = start();
+        let mut pn = 0;
+        for rtt in [100, 100, 200, 200] {
+            // A single ACK covers the whole round, so only one RTT sample is taken
+            let mut growth = 0;
+            for _ in 0..10 {
+                growth += hystart.on_ack(1000, Duration::from_millis(rtt));
+                pn += 1;
+            }
+            hystart.on_sent(pn + 10);
+            assert!(!hystart.on_end_acks(Some(pn)));
+            assert_eq!(growth, 10_000);
+        }
+        assert!(!hystart.in_css());
+    }
+}
@@ -0,0 +1,541 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+use crate::connection::RttEstimator;
+
+/// Experimental! Use at your own risk.
+///
+/// A less-than-best-effort congestion controller, which yields to any other traffic sharing the
+/// bottleneck. Based on LEDBAT++
+/// <https://datatracker.ietf.org/doc/html/draft-irtf-iccrg-ledbat-plus-plus>, which refines LEDBAT
+/// (RFC 6817).
+///
+/// The queuing delay is estimated by comparing recent RTT samples to the lowest RTT observed over
+/// the last few minutes, and the window grows while it's below a small target and shrinks in
+/// proportion to how much it exceeds it. Since other flows build up queues, this flow backs off as
+/// soon as they compete with it. To stay fair among LEDBAT++ flows and to refresh its view of the
+/// lowest RTT, the window is also periodically reduced to its minimum for a couple of round trips.
+#[derive(Debug, Clone)]
+pub struct Ledbat {
+    config: Arc<LedbatConfig>,
+    current_mtu: u64,
+    /// Maximum number of bytes in flight that may be sent.
+    window: u64,
+    /// Slow start threshold in bytes. When the congestion window is below ssthresh, the mode is
+    /// slow start.
+    ssthresh: u64,
+    /// The time when QUIC first detects a loss, causing it to enter recovery. When a packet sent
+    /// after this time is acknowledged, QUIC exits recovery.
+    recovery_start_time: Instant,
+    /// Bytes which had been acked by the peer since the window was last adjusted in congestion
+    /// avoidance
+    bytes_acked: u64,
+    /// Minimum RTT sample of each of the last `BASE_HISTORY` minutes, most recent last
+    base_history: VecDeque<Duration>,
+    /// When the most recent entry of `base_history` started
+    base_history_start: Option<Instant>,
+    /// Latest RTT samples, whose minimum is the current delay
+    current_delays: VecDeque<Duration>,
+    /// Whether the RTT sample of the batch of acknowledgements being processed was recorded
+    ///
+    /// An ACK frame yields a single RTT sample, however many packets it acknowledges.
+    batch_sampled: bool,
+    /// Smoothed RTT estimate, as of the latest acknowledgement
+    srtt: Duration,
+    slowdown: Slowdown,
+}
+
+impl Ledbat {
+    /// Construct a state using the given `config` and current time `now`
+    pub fn new(config: Arc<LedbatConfig>, now: Instant, current_mtu: u16) -> Self {
+        Self {
+            window: config.initial_window,
+            ssthresh: u64::MAX,
+            recovery_start_time: now,
+            current_mtu: current_mtu as u64,
+            config,
+            bytes_acked: 0,
+            base_history: VecDeque::with_capacity(BASE_HISTORY),
+            base_history_start: None,
+            current_delays: VecDeque::with_capacity(CURRENT_FILTER),
+            batch_sampled: false,
+            srtt: Duration::ZERO,
+            slowdown: Slowdown::Pending,
+        }
+    }
+
+    fn minimum_window(&self) -> u64 {
+        2 * self.current_mtu
+    }
+
+    fn update_delays(&mut self, now: Instant, rtt: &RttEstimator) {
+        let sample = rtt.latest();
+        match self.base_history_start {
+            Some(start) if now.saturating_duration_since(start) < BASE_HISTORY_INTERVAL => {
+                let last = self.base_history.back_mut().unwrap();
+                *last = (*last).min(sample);
+            }
+            Some(_) => {
+                if self.base_history.len() == BASE_HISTORY {
+                    self.base_history.pop_front();
+                }
+                self.base_history.push_back(sample);
+                self.base_history_start = Some(now);
+            }
+            None => {
+                // Samples before the first acknowledgement are accounted for in the estimator's
+                // minimum
+                self.base_history.push_back(rtt.min().min(sample));
+                self.base_history_start = Some(now);
+            }
+        }
+
+        if self.current_delays.len() == CURRENT_FILTER {
+            self.current_delays.pop_front();
+        }
+        self.current_delays.push_back(sample);
+        self.srtt = rtt.get();
+    }
+
+    fn base_delay(&self) -> Duration {
+        self.base_history.iter().copied().min().unwrap_or_default()
+    }
+
+    fn queuing_delay(&self) -> Duration {
+        let current = self
+            .current_delays
+            .iter()
+            .copied()
+            .min()
+            .unwrap_or_default();
+        current.saturating_sub(self.base_delay())
+    }
+
+    /// Fraction of Reno's growth rate to grow the window at
+    ///
+    /// Lower for shorter base delays, which let the window grow more often, so that flows with
+    /// different base delays ramp up at similar rates.
+    fn gain(&self) -> f64 {
+        let base = self.base_delay().as_secs_f64();
+        let ratio = match base > 0.0 {
+            true => (2.0 * self.config.target_delay.as_secs_f64() / base).ceil(),
+            false => MAX_GAIN_DIVISOR,
+        };
+        1.0 / ratio.clamp(1.0, MAX_GAIN_DIVISOR)
+    }
+
+    fn exit_slow_start(&mut self, now: Instant) {
+        self.ssthresh = self.window;
+        self.bytes_acked = 0;
+        self.slowdown = match self.slowdown {
+            // The initial slowdown starts shortly after the initial slow start
+            Slowdown::Pending => Slowdown::Scheduled(now + 2 * self.srtt),
+            Slowdown::Recovering { start } => {
+                // Wait long enough for slowdowns to take at most a tenth of the time
+                Slowdown::Scheduled(now + SLOWDOWN_INTERVAL_FACTOR * (now - start))
+            }
+            x => x,
+        };
+    }
+
+    fn update_slowdown(&mut self, now: Instant) {
+        match self.slowdown {
+            Slowdown::Scheduled(at) if now >= at => {
+                // Let queues drain so that the base delay can be measured again
+                self.ssthresh = self.window;
+                self.window = self.minimum_window();
+                self.bytes_acked = 0;
+                self.slowdown = Slowdown::Frozen {
+                    start: now,
+                    until: now + SLOWDOWN_RTTS * self.srtt,
+                };
+            }
+            Slowdown::Frozen { start, until } if now >= until => {
+                // Ramp back up to the previous window in slow start
+                self.slowdown = Slowdown::Recovering { start };
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Controller for Ledbat {
+    fn on_ack(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        rtt: &RttEstimator,
+    ) {
+        if !mem::replace(&mut self.batch_sampled, true) {
+            self.update_delays(now, rtt);
+        }
+        self.update_slowdown(now);
+        if app_limited
+            || sent <= self.recovery_start_time
+            || matches!(self.slowdown, Slowdown::Frozen { .. })
+        {
+            return;
+        }
+
+        let gain = self.gain();
+        let target = self.config.target_delay;
+        let queuing_delay = self.queuing_delay();
+        if self.window < self.ssthresh {
+            // Slow start, leaving it as soon as a queue builds up
+            if queuing_delay > target * 3 / 4 {
+                self.exit_slow_start(now);
+                return;
+            }
+            self.window += ((bytes as f64 * gain) as u64).max(1);
+            if self.window >= self.ssthresh {
+                self.window = self.ssthresh;
+                self.exit_slow_start(now);
+            }
+            return;
+        }
+
+        // Congestion avoidance, adjusting the window once per window of acknowledged data
+        self.bytes_acked += bytes;
+        if self.bytes_acked < self.window {
+            return;
+        }
+        self.bytes_acked -= self.window;
+        let window = self.window as f64;
+        let excess = queuing_delay.as_secs_f64() / target.as_secs_f64() - 1.0;
+        let change = match excess > 0.0 {
+            // Decrease in proportion to the excess delay, by at most half of the window
+            true => (gain * self.current_mtu as f64 - window * excess).max(-window / 2.0),
+            false => gain * self.current_mtu as f64,
+        };
+        self.window = ((window + change) as u64).max(self.minimum_window());
+    }
+
+    fn on_end_acks(
+        &mut self,
+        _now: Instant,
+        _in_flight: u64,
+        _app_limited: bool,
+        _largest_packet_num_acked: Option<u64>,
+    ) {
+        self.batch_sampled = false;
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        is_persistent_congestion: bool,
+        _lost_bytes: u64,
+    ) {
+        if sent <= self.recovery_start_time {
+            return;
+        }
+
+        self.recovery_start_time = now;
+        self.window = (self.window / 2).max(self.minimum_window());
+        if self.window < self.ssthresh || self.ssthresh == u64::MAX {
+            self.exit_slow_start(now);
+        }
+        self.ssthresh = self.window;
+
+        if is_persistent_congestion {
+            self.window = self.minimum_window();
+        }
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.current_mtu = new_mtu as u64;
+        self.window = self.window.max(self.minimum_window());
+    }
+
+    fn window(&self) -> u64 {
+        self.window
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config.initial_window
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Configuration for the [`Ledbat`] congestion controller
+#[derive(Debug, Clone)]
+pub struct LedbatConfig {
+    initial_window: u64,
+    target_delay: Duration,
+}
+
+impl LedbatConfig {
+    /// Default limit on the amount of outstanding data in bytes.
+    ///
+    /// Recommended value: `min(10 * max_datagram_size, max(2 * max_datagram_size, 14720))`
+    pub fn initial_window(&mut self, value: u64) -> &mut Self {
+        self.initial_window = value;
+        self
+    }
+
+    /// Queuing delay the controller aims for
+    ///
+    /// The window shrinks whenever the RTT exceeds the base delay by more than this. Lower values
+    /// yield to other traffic sooner, but may underutilize paths with noisy delays. Defaults to
+    /// 60ms.
+    pub fn target_delay(&mut self, value: Duration) -> &mut Self {
+        self.target_delay = value;
+        self
+    }
+}
+
+impl Default for LedbatConfig {
+    fn default() -> Self {
+        Self {
+            initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
+            target_delay: Duration::from_millis(60),
+        }
+    }
+}
+
+impl ControllerFactory for Arc<LedbatConfig> {
+    fn build(&self, now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        Box::new(Ledbat::new(self.clone(), now, current_mtu))
+    }
+}
+
+/// State of the periodic slowdown
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Slowdown {
+    /// Waiting for the initial slow start to end
+    Pending,
+    /// The next slowdown starts at the given time
+    Scheduled(Instant),
+    /// The window is held at its minimum
+    Frozen { start: Instant, until: Instant },
+    /// The window is growing back to its previous size in slow start
+    Recovering { start: Instant },
+}
+
+/// Number of minutes whose minimum RTT is remembered to compute the base delay
+const BASE_HISTORY: usize = 10;
+const BASE_HISTORY_INTERVAL: Duration = Duration::from_secs(60);
+/// Number of RTT samples whose minimum is the current delay
+const CURRENT_FILTER: usize = 4;
+/// Upper bound on the reduction of the growth rate relative to Reno
+const MAX_GAIN_DIVISOR: f64 = 16.0;
+/// Number of round trips to hold the window at its minimum for during a slowdown
+const SLOWDOWN_RTTS: u32 = 2;
+/// Time between slowdowns, relative to the duration of the previous one
+const SLOWDOWN_INTERVAL_FACTOR: u32 = 9;
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use super::*;
+
+    const MTU: u16 = 1200;
+
+    /// Acknowledges a full window sent one RTT of `rtt` ago, one packet per ACK
+    fn round(ledbat: &mut Ledbat, now: &mut Instant, estimator: &mut RttEstimator, rtt: Duration) {
+        let sent = *now;
+        *now += rtt;
+        estimator.update(Duration::ZERO, rtt);
+        let mut remaining = ledbat.window();
+        while remaining > 0 {
+            let bytes = remaining.min(MTU.into());
+            ledbat.on_ack(*now, sent, bytes, false, estimator);
+            ledbat.on_end_acks(*now, 0, false, None);
+            remaining -= bytes;
+        }
+    }
+
+    fn ledbat(now: Instant) -> (Ledbat, RttEstimator) {
+        (
+            Ledbat::new(Arc::new(LedbatConfig::default()), now, MTU),
+            RttEstimator::new(Duration::from_millis(333)),
+        )
+    }
+
+    #[test]
+    fn slow_start_ends_with_queuing_delay() {
+        let mut now = Instant::now();
+        let (mut ledbat, mut estimator) = ledbat(now);
+        let base = Duration::from_millis(20);
+        now += Duration::from_millis(1);
+        round(&mut ledbat, &mut now, &mut estimator, base);
+        // Growth is reduced for short base delays: 1 / ceil(2 * 60ms / 20ms)
+        assert_eq!(ledbat.window(), 12_000 + 12_000 / 6);
+        assert_eq!(ledbat.ssthresh, u64::MAX);
+
+        // Queuing delay beyond 3/4 of the target ends slow start
+        round(
+            &mut ledbat,
+            &mut now,
+            &mut estimator,
+            base + Duration::from_millis(50),
+        );
+        assert_eq!(ledbat.ssthresh, ledbat.window());
+        assert_matches!(ledbat.slowdown, Slowdown::Scheduled(_));
+    }
+
+    #[test]
+    fn yields_to_queuing_delay() {
+        let mut now = Instant::now();
+        let (mut ledbat, mut estimator) = ledbat(now);
+        let base = Duration::from_millis(40);
+        now += Duration::from_millis(1);
+        for _ in 0..3 {
+            round(&mut ledbat, &mut now, &mut estimator, base);
+        }
+        round(
+            &mut ledbat,
+            &mut now,
+            &mut estimator,
+            base + Duration::from_millis(50),
+        );
+        // Skip the initial slowdown
+        ledbat.slowdown = Slowdown::Scheduled(now + Duration::from_secs(3600));
+
+        // Below the target, the window grows slower than Reno's
+        let window = ledbat.window();
+        round(
+            &mut ledbat,
+            &mut now,
+            &mut estimator,
+            base + Duration::from_millis(30),
+        );
+        assert_eq!(ledbat.window(), window + 1200 / 3);
+
+        // Above it, it shrinks in proportion to the excess delay
+        let window = ledbat.window();
+        round(
+            &mut ledbat,
+            &mut now,
+            &mut estimator,
+            base + Duration::from_millis(75),
+        );
+        round(
+            &mut ledbat,
+            &mut now,
+            &mut estimator,
+            base + Duration::from_millis(75),
+        );
+        assert!(ledbat.window() < window * 3 / 4, "{}", ledbat.window());
+
+        // ...by at most half per round trip
+        let window = ledbat.window();
+        round(
+            &mut ledbat,
+            &mut now,
+            &mut estimator,
+            base + Duration::from_secs(1),
+        );
+        assert_eq!(ledbat.window(), window / 2);
+    }
+
+    #[test]
+    fn periodic_slowdown() {
+        let mut now = Instant::now();
+        let (mut ledbat, mut estimator) = ledbat(now);
+        let base = Duration::from_millis(50);
+        now += Duration::from_millis(1);
+        for _ in 0..5 {
+            round(&mut ledbat, &mut now, &mut estimator, base);
+        }
+        round(&mut ledbat, &mut now, &mut estimator, base * 2);
+        assert_matches!(ledbat.slowdown, Slowdown::Scheduled(_));
+
+        // Two round trips after slow start, the window drops to its minimum
+        let mut window = ledbat.window();
+        let mut rounds = 0;
+        while !matches!(ledbat.slowdown, Slowdown::Frozen { .. }) {
+            window = ledbat.window();
+            round(&mut ledbat, &mut now, &mut estimator, base);
+            rounds += 1;
+        }
+        assert_eq!(rounds, 3);
+        let start = now;
+
+        // ...where it's held for two round trips
+        rounds = 0;
+        while matches!(ledbat.slowdown, Slowdown::Frozen { .. }) {
+            assert_eq!(ledbat.window(), 2400);
+            round(&mut ledbat, &mut now, &mut estimator, base);
+            rounds += 1;
+        }
+        assert_eq!(rounds, 3);
+        assert_matches!(ledbat.slowdown, Slowdown::Recovering { .. });
+
+        // ...then grows back to its previous size in slow start
+        while ledbat.window() < window {
+            round(&mut ledbat, &mut now, &mut estimator, base);
+        }
+        let duration = now - start;
+        assert_eq!(ledbat.slowdown, Slowdown::Scheduled(now + 9 * duration));
+    }
+
+    #[test]
+    fn base_delay_history() {
+        let mut now = Instant::now();
+        let (mut ledbat, mut estimator) = ledbat(now);
+        now += Duration::from_millis(1);
+        round(
+            &mut ledbat,
+            &mut now,
+            &mut estimator,
+            Duration::from_millis(20),
+        );
+        assert_eq!(ledbat.base_delay(), Duration::from_millis(20));
+
+        // After a route change, the longer delay becomes the base delay once the older samples
+        // were forgotten
+        let start = now;
+        while now - start < BASE_HISTORY_INTERVAL * BASE_HISTORY as u32 {
+            assert_eq!(ledbat.base_delay(), Duration::from_millis(20));
+            round(
+                &mut ledbat,
+                &mut now,
+                &mut estimator,
+                Duration::from_secs(1),
+            );
+        }
+        round(
+            &mut ledbat,
+            &mut now,
+            &mut estimator,
+            Duration::from_secs(1),
+        );
+        assert_eq!(ledbat.base_delay(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn one_sample_per_ack() {
+        let mut now = Instant::now();
+        let (mut ledbat, mut estimator) = ledbat(now);
+        let base = Duration::from_millis(20);
+        now += Duration::from_millis(1);
+        round(&mut ledbat, &mut now, &mut estimator, base);
+
+        // A single ACK of many packets with a higher RTT doesn't displace the earlier samples
+        let sent = now;
+        now += base * 3;
+        estimator.update(Duration::ZERO, base * 3);
+        for _ in 0..10 {
+            ledbat.on_ack(now, sent, MTU.into(), false, &estimator);
+        }
+        ledbat.on_end_acks(now, 0, false, None);
+        assert_eq!(ledbat.current_delays.back(), Some(&(base * 3)));
+        assert_eq!(ledbat.queuing_delay(), Duration::ZERO);
+    }
+}
@@ -2,7 +2,7 @@ use std::any::Any;
 use std::sync::Arc;
 use std::time::Instant;
 
-use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+use super::{hystart::HyStart, Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
 use crate::connection::RttEstimator;
 
 /// A simple, standard congestion controller
@@ -20,6 +20,8 @@ pub struct NewReno {
     recovery_start_time: Instant,
     /// Bytes which had been acked by the peer since leaving slow start
     bytes_acked: u64,
+    /// HyStart++ state, if enabled and still in the initial slow start
+    hystart: Option<HyStart>,
 }
 
 impl NewReno {
@@ -30,6 +32,7 @@ impl NewReno {
             ssthresh: u64::max_value(),
             recovery_start_time: now,
             current_mtu: current_mtu as u64,
+            hystart: config.hystart.then(HyStart::default),
             config,
             bytes_acked: 0,
         }
@@ -41,13 +44,19 @@ impl NewReno {
 }
 
 impl Controller for NewReno {
+    fn on_sent(&mut self, _now: Instant, _bytes: u64, last_packet_number: u64) {
+        if let Some(ref mut hystart) = self.hystart {
+            hystart.on_sent(last_packet_number);
+        }
+    }
+
     fn on_ack(
         &mut self,
         _now: Instant,
         sent: Instant,
         bytes: u64,
         app_limited: bool,
-        _rtt: &RttEstimator,
+        rtt: &RttEstimator,
     ) {
         if app_limited || sent <= self.recovery_start_time {
             return;
@@ -55,7 +64,10 @@ impl Controller for NewReno {
 
         if self.window < self.ssthresh {
             // Slow start
-            self.window += bytes;
+            self.window += match self.hystart {
+                Some(ref mut hystart) => hystart.on_ack(bytes, rtt.latest()),
+                None => bytes,
+            };
 
             if self.window >= self.ssthresh {
                 // Exiting slow start
@@ -82,6 +94,25 @@ impl Controller for NewReno {
         }
     }
 
+    fn on_end_acks(
+        &mut self,
+        _now: Instant,
+        _in_flight: u64,
+        _app_limited: bool,
+        largest_packet_num_acked: Option<u64>,
+    ) {
+        let exit = match self.hystart {
+            Some(ref mut hystart) => hystart.on_end_acks(largest_packet_num_acked),
+            None => false,
+        };
+        if exit {
+            // Conservative slow start is over
+            self.hystart = None;
+            self.ssthresh = self.window;
+            self.bytes_acked = 0;
+        }
+    }
+
     fn on_congestion_event(
         &mut self,
         now: Instant,
@@ -93,6 +124,7 @@ impl Controller for NewReno {
             return;
         }
 
+        self.hystart = None;
         self.recovery_start_time = now;
         self.window = (self.window as f32 * self.config.loss_reduction_factor) as u64;
         self.window = self.window.max(self.minimum_window());
@@ -130,6 +162,7 @@ impl Controller for NewReno {
 pub struct NewRenoConfig {
     initial_window: u64,
     loss_reduction_factor: f32,
+    hystart: bool,
 }
 
 impl NewRenoConfig {
@@ -146,6 +179,16 @@ impl NewRenoConfig {
         self.loss_reduction_factor = value;
         self
     }
+
+    /// Whether to use HyStart++ (RFC 9406) during the initial slow start
+    ///
+    /// HyStart++ leaves slow start as soon as increasing round-trip times indicate that queues are
+    /// building up along the path, rather than waiting for packet loss. This avoids large bursts of
+    /// loss on paths with deep buffers. Defaults to `false`.
+    pub fn hystart(&mut self, value: bool) -> &mut Self {
+        self.hystart = value;
+        self
+    }
 }
 
 impl Default for NewRenoConfig {
@@ -153,6 +196,7 @@ impl Default for NewRenoConfig {
         Self {
             initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
             loss_reduction_factor: 0.5,
+            hystart: false,
         }
     }
 }
@@ -0,0 +1,305 @@
+use std::any::Any;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::{Controller, ControllerFactory, BASE_DATAGRAM_SIZE};
+use crate::connection::RttEstimator;
+
+/// Experimental! Use at your own risk.
+///
+/// A scalable congestion controller for L4S (RFC 9330), modeled after TCP Prague
+/// <https://datatracker.ietf.org/doc/html/draft-briscoe-iccrg-prague-congestion-control>.
+///
+/// Rather than treating any ECN-CE mark like a loss, the window is reduced in proportion to the
+/// fraction of packets being marked: at most once per round trip, by half of a moving average of
+/// that fraction. Along with networks which mark L4S packets as soon as a shallow queue builds up,
+/// this keeps queuing delay low while still using all of the available capacity. Packet loss is
+/// responded to like NewReno does.
+///
+/// Meant to be used with [`TransportConfig::send_ect1`](crate::TransportConfig::send_ect1).
+#[derive(Debug, Clone)]
+pub struct Prague {
+    config: Arc<PragueConfig>,
+    current_mtu: u64,
+    /// Maximum number of bytes in flight that may be sent.
+    window: u64,
+    /// Slow start threshold in bytes. When the congestion window is below ssthresh, the mode is
+    /// slow start and the window grows by the number of bytes acknowledged.
+    ssthresh: u64,
+    /// The time when QUIC first detects a loss, causing it to enter recovery. When a packet sent
+    /// after this time is acknowledged, QUIC exits recovery.
+    recovery_start_time: Instant,
+    /// Bytes which had been acked by the peer since the window last grew in congestion avoidance
+    bytes_acked: u64,
+    /// Moving average of the fraction of packets marked CE per round trip
+    alpha: f64,
+    /// Number of packets ECN feedback was received for during the current round
+    round_packets: u64,
+    /// Number of packets marked CE during the current round
+    round_marked: u64,
+    /// Largest packet number sent when the current round started
+    ///
+    /// The round ends once that packet is acknowledged.
+    round_end: Option<u64>,
+    /// Largest packet number sent so far
+    last_sent: u64,
+    /// Whether the window was reduced in response to CE marks during the current round
+    reduced_in_round: bool,
+}
+
+impl Prague {
+    /// Construct a state using the given `config` and current time `now`
+    pub fn new(config: Arc<PragueConfig>, now: Instant, current_mtu: u16) -> Self {
+        Self {
+            window: config.initial_window,
+            ssthresh: u64::MAX,
+            recovery_start_time: now,
+            current_mtu: current_mtu as u64,
+            config,
+            bytes_acked: 0,
+            alpha: 1.0,
+            round_packets: 0,
+            round_marked: 0,
+            round_end: None,
+            last_sent: 0,
+            reduced_in_round: false,
+        }
+    }
+
+    fn minimum_window(&self) -> u64 {
+        2 * self.current_mtu
+    }
+}
+
+impl Controller for Prague {
+    fn on_sent(&mut self, _now: Instant, _bytes: u64, last_packet_number: u64) {
+        self.last_sent = last_packet_number;
+    }
+
+    fn on_ack(
+        &mut self,
+        _now: Instant,
+        sent: Instant,
+        bytes: u64,
+        app_limited: bool,
+        _rtt: &RttEstimator,
+    ) {
+        if app_limited || sent <= self.recovery_start_time {
+            return;
+        }
+
+        if self.window < self.ssthresh {
+            // Slow start
+            self.window += bytes;
+        } else {
+            // Congestion avoidance, growing the window by one datagram per round trip
+            self.bytes_acked += bytes;
+            if self.bytes_acked >= self.window {
+                self.bytes_acked -= self.window;
+                self.window += self.current_mtu;
+            }
+        }
+    }
+
+    fn on_end_acks(
+        &mut self,
+        _now: Instant,
+        _in_flight: u64,
+        _app_limited: bool,
+        largest_packet_num_acked: Option<u64>,
+    ) {
+        match (largest_packet_num_acked, self.round_end) {
+            (None, _) => return,
+            (Some(acked), Some(end)) if acked < end => return,
+            _ => {}
+        }
+
+        // Start a new round
+        if self.round_packets != 0 {
+            let fraction = self.round_marked as f64 / self.round_packets as f64;
+            self.alpha = (1.0 - self.config.alpha_gain) * self.alpha
+                + self.config.alpha_gain * fraction.min(1.0);
+        }
+        self.round_packets = 0;
+        self.round_marked = 0;
+        self.reduced_in_round = false;
+        self.round_end = Some(self.last_sent);
+    }
+
+    fn on_ecn(&mut self, _now: Instant, ect: u64, ce: u64) {
+        self.round_packets += ect + ce;
+        self.round_marked += ce;
+        if ce == 0 || self.reduced_in_round {
+            return;
+        }
+
+        self.reduced_in_round = true;
+        let reduction = (self.window as f64 * self.alpha / 2.0) as u64;
+        self.window = self
+            .window
+            .saturating_sub(reduction)
+            .max(self.minimum_window());
+        self.ssthresh = self.window;
+        self.bytes_acked = 0;
+    }
+
+    fn on_congestion_event(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        is_persistent_congestion: bool,
+        lost_bytes: u64,
+    ) {
+        if lost_bytes == 0 {
+            // CE marks are handled in `on_ecn`
+            return;
+        }
+        if sent <= self.recovery_start_time {
+            return;
+        }
+
+        self.recovery_start_time = now;
+        self.window = (self.window / 2).max(self.minimum_window());
+        self.ssthresh = self.window;
+        self.bytes_acked = 0;
+
+        if is_persistent_congestion {
+            self.window = self.minimum_window();
+        }
+    }
+
+    fn on_mtu_update(&mut self, new_mtu: u16) {
+        self.current_mtu = new_mtu as u64;
+        self.window = self.window.max(self.minimum_window());
+    }
+
+    fn window(&self) -> u64 {
+        self.window
+    }
+
+    fn clone_box(&self) -> Box<dyn Controller> {
+        Box::new(self.clone())
+    }
+
+    fn initial_window(&self) -> u64 {
+        self.config.initial_window
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// Configuration for the [`Prague`] congestion controller
+#[derive(Debug, Clone)]
+pub struct PragueConfig {
+    initial_window: u64,
+    alpha_gain: f64,
+}
+
+impl PragueConfig {
+    /// Default limit on the amount of outstanding data in bytes.
+    ///
+    /// Recommended value: `min(10 * max_datagram_size, max(2 * max_datagram_size, 14720))`
+    pub fn initial_window(&mut self, value: u64) -> &mut Self {
+        self.initial_window = value;
+        self
+    }
+
+    /// Weight given to each round trip's fraction of CE-marked packets in the moving average which
+    /// determines window reductions
+    ///
+    /// Higher values react faster to changes in congestion, at the cost of stability. Defaults to
+    /// 1/16.
+    pub fn alpha_gain(&mut self, value: f64) -> &mut Self {
+        self.alpha_gain = value;
+        self
+    }
+}
+
+impl Default for PragueConfig {
+    fn default() -> Self {
+        Self {
+            initial_window: 14720.clamp(2 * BASE_DATAGRAM_SIZE, 10 * BASE_DATAGRAM_SIZE),
+            alpha_gain: 1.0 / 16.0,
+        }
+    }
+}
+
+impl ControllerFactory for Arc<PragueConfig> {
+    fn build(&self, now: Instant, current_mtu: u16) -> Box<dyn Controller> {
+        Box::new(Prague::new(self.clone(), now, current_mtu))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Simulates a round of 10 packets, of which `marked` were marked CE
+    fn round(prague: &mut Prague, now: &mut Instant, pn: &mut u64, marked: u64) {
+        let rtt = RttEstimator::new(Duration::from_millis(10));
+        *now += Duration::from_millis(1);
+        let sent = *now;
+        for _ in 0..10 {
+            *pn += 1;
+            prague.on_sent(sent, 1200, *pn);
+        }
+        *now += Duration::from_millis(10);
+        for _ in 0..10 {
+            prague.on_ack(*now, sent, 1200, false, &rtt);
+        }
+        prague.on_end_acks(*now, 0, false, Some(*pn));
+        prague.on_ecn(*now, 10 - marked, marked);
+    }
+
+    #[test]
+    fn reduction_proportional_to_marks() {
+        let mut now = Instant::now();
+        let mut prague = Prague::new(Arc::new(PragueConfig::default()), now, 1200);
+        let mut pn = 0;
+        round(&mut prague, &mut now, &mut pn, 0);
+        assert_eq!(prague.window(), 12_000 + 12_000);
+
+        // The first mark ends slow start, reducing the window almost like a loss would
+        round(&mut prague, &mut now, &mut pn, 1);
+        assert_eq!(prague.alpha, 15.0 / 16.0);
+        assert_eq!(prague.window(), 36_000 - 36_000 * 15 / 32);
+
+        // As rounds go by with few marks, reductions get smaller
+        for _ in 0..50 {
+            round(&mut prague, &mut now, &mut pn, 1);
+        }
+        assert!(prague.alpha < 0.15, "alpha {}", prague.alpha);
+        let before = prague.window();
+        round(&mut prague, &mut now, &mut pn, 1);
+        let reduction = before - prague.window();
+        assert!(reduction < before / 10, "reduced by {reduction}");
+    }
+
+    #[test]
+    fn single_reduction_per_round() {
+        let mut now = Instant::now();
+        let mut prague = Prague::new(Arc::new(PragueConfig::default()), now, 1200);
+        let rtt = RttEstimator::new(Duration::from_millis(10));
+        prague.on_sent(now, 12_000, 10);
+        now += Duration::from_millis(10);
+        prague.on_ack(now, now, 1200, false, &rtt);
+        prague.on_end_acks(now, 10_800, false, Some(1));
+        prague.on_ecn(now, 0, 1);
+        let window = prague.window();
+        assert_eq!(window, 13_200 / 2);
+
+        // Further marks in the same round don't reduce the window again
+        prague.on_end_acks(now, 9_600, false, Some(2));
+        prague.on_ecn(now, 0, 1);
+        assert_eq!(prague.window(), window);
+
+        // ...but once the round ends, they do
+        prague.on_end_acks(now, 0, false, Some(10));
+        prague.on_ecn(now, 0, 1);
+        assert!(prague.window() < window);
+    }
+}
@@ -209,6 +209,39 @@ impl Assembler {
         self.bytes_read
     }
 
+    /// Whether all data before `offset` has been received and read
+    pub(super) fn is_read_up_to(&self, offset: u64) -> bool {
+        match self.state {
+            State::Ordered => self.bytes_read >= offset,
+            State::Unordered { ref recvd } => {
+                self.data.is_empty()
+                    && recvd
+                        .iter()
+                        .next()
+                        .map_or(offset == 0, |x| x.start == 0 && x.end >= offset)
+            }
+        }
+    }
+
+    /// Discard buffered data at or beyond `offset`
+    pub(super) fn truncate(&mut self, offset: u64) {
+        let data = mem::take(&mut self.data).into_vec();
+        self.buffered = 0;
+        self.allocated = 0;
+        for mut buffer in data {
+            if buffer.offset >= offset {
+                continue;
+            }
+            buffer.bytes.truncate((offset - buffer.offset) as usize);
+            if buffer.defragmented {
+                buffer.allocation_size = buffer.bytes.len();
+            }
+            self.buffered += buffer.bytes.len();
+            self.allocated += buffer.allocation_size;
+            self.data.push(buffer);
+        }
+    }
+
     /// Discard all buffered data
     pub(super) fn clear(&mut self) {
         self.data.clear();
@@ -28,21 +28,28 @@ pub(super) struct CidState {
 }
 
 impl CidState {
-    pub(crate) fn new(cid_len: usize, cid_lifetime: Option<Duration>, now: Instant) -> Self {
+    /// `issued` is the number of CIDs supplied during handshaking, i.e. the initial CID and, if
+    /// any, the one carried in the preferred address transport parameter
+    pub(crate) fn new(
+        cid_len: usize,
+        cid_lifetime: Option<Duration>,
+        now: Instant,
+        issued: u64,
+    ) -> Self {
         let mut active_seq = FxHashSet::default();
-        // Add sequence number of CID used in handshaking into tracking set
-        active_seq.insert(0);
+        // Add sequence numbers of CIDs used in handshaking into tracking set
+        active_seq.extend(0..issued);
         let mut this = Self {
             retire_timestamp: VecDeque::new(),
-            issued: 1, // One CID is already supplied during handshaking
+            issued,
             active_seq,
             prev_retire_seq: 0,
             retire_seq: 0,
             cid_len,
             cid_lifetime,
         };
-        // Track lifetime of cid used in handshaking
-        this.track_lifetime(0, now);
+        // Track lifetime of cids used in handshaking
+        this.track_lifetime(issued - 1, now);
         this
     }
 
@@ -172,6 +179,11 @@ impl CidState {
         Ok(limit > self.active_seq.len() as u64)
     }
 
+    /// Number of local connection IDs issued so far
+    pub(crate) fn issued(&self) -> u64 {
+        self.issued
+    }
+
     /// Length of local Connection IDs
     pub(crate) fn cid_len(&self) -> usize {
         self.cid_len
@@ -1,14 +1,18 @@
-use std::collections::VecDeque;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    mem,
+    time::Instant,
+};
 
 use bytes::{Bytes, BytesMut};
 use thiserror::Error;
 use tracing::{debug, trace};
 
-use super::Connection;
+use super::{spaces::DatagramIds, Connection, ConnectionStats};
 use crate::{
     frame::{Datagram, FrameStruct},
     packet::SpaceId,
-    TransportError,
+    DatagramOverflowPolicy, TransportError,
 };
 
 /// API to control datagram traffic
@@ -19,29 +23,109 @@ pub struct Datagrams<'a> {
 impl<'a> Datagrams<'a> {
     /// Queue an unreliable, unordered datagram for immediate transmission
     ///
+    /// If the send buffer is full, the configured
+    /// [`DatagramOverflowPolicy`](crate::DatagramOverflowPolicy) applies.
+    ///
     /// Returns `Err` iff a `len`-byte datagram cannot currently be sent
     pub fn send(&mut self, data: Bytes) -> Result<(), SendDatagramError> {
+        self.send_with_options(data, &DatagramOptions::default())
+    }
+
+    /// Queue an unreliable, unordered datagram, tracking whether it's delivered
+    ///
+    /// Once the packet carrying the datagram is acknowledged or declared lost, an
+    /// [`Event::DatagramAcked`] or [`Event::DatagramLost`] carrying `id` is emitted. The datagram
+    /// is also reported lost if it must be discarded after it was sent, e.g. because the peer
+    /// rejected 0-RTT data. No event is emitted if the datagram is dropped from the send buffer
+    /// before being sent, or if the connection is closed first.
+    ///
+    /// `id` is not interpreted; the same value may be used for several datagrams.
+    ///
+    /// [`Event::DatagramAcked`]: crate::Event::DatagramAcked
+    /// [`Event::DatagramLost`]: crate::Event::DatagramLost
+    pub fn send_with_id(&mut self, data: Bytes, id: u64) -> Result<(), SendDatagramError> {
+        self.send_with_options(data, DatagramOptions::default().id(id))
+    }
+
+    /// Queue an unreliable, unordered datagram with an ID, priority and/or deadline
+    ///
+    /// Otherwise behaves like [`send`](Self::send).
+    pub fn send_with_options(
+        &mut self,
+        data: Bytes,
+        options: &DatagramOptions,
+    ) -> Result<(), SendDatagramError> {
+        let policy = self.conn.config.datagram_overflow_policy;
+        self.send_inner(data, options, policy)
+    }
+
+    /// Queue a datagram like [`send`](Self::send), but never drop one to make room
+    ///
+    /// Regardless of the configured overflow policy, fails with [`SendDatagramError::Blocked`]
+    /// if the send buffer is full, after which [`Event::DatagramsUnblocked`] is emitted once
+    /// space is freed up.
+    ///
+    /// [`Event::DatagramsUnblocked`]: crate::Event::DatagramsUnblocked
+    pub fn try_send(&mut self, data: Bytes) -> Result<(), SendDatagramError> {
+        self.send_inner(
+            data,
+            &DatagramOptions::default(),
+            DatagramOverflowPolicy::Reject,
+        )
+    }
+
+    fn send_inner(
+        &mut self,
+        data: Bytes,
+        options: &DatagramOptions,
+        policy: DatagramOverflowPolicy,
+    ) -> Result<(), SendDatagramError> {
         if self.conn.config.datagram_receive_buffer_size.is_none() {
             return Err(SendDatagramError::Disabled);
         }
         let max = self
             .max_size()
             .ok_or(SendDatagramError::UnsupportedByPeer)?;
-        while self.conn.datagrams.outgoing_total > self.conn.config.datagram_send_buffer_size {
-            let prev = self
-                .conn
-                .datagrams
-                .outgoing
-                .pop_front()
-                .expect("datagrams.outgoing_total desynchronized");
-            trace!(len = prev.data.len(), "dropping outgoing datagram");
-            self.conn.datagrams.outgoing_total -= prev.data.len();
-        }
         if data.len() > max {
             return Err(SendDatagramError::TooLarge);
         }
-        self.conn.datagrams.outgoing_total += data.len();
-        self.conn.datagrams.outgoing.push_back(Datagram { data });
+        let window = self.conn.config.datagram_send_buffer_size;
+        let state = &mut self.conn.datagrams;
+        // A datagram larger than the whole buffer is still accepted when nothing else is queued,
+        // lest it never be sendable at all
+        if state.outgoing_total + data.len() > window && !state.outgoing.is_empty() {
+            match policy {
+                DatagramOverflowPolicy::DropOldest => {
+                    while state.outgoing_total + data.len() > window {
+                        let prev = match state.pop_oldest() {
+                            Some(x) => x,
+                            None => break,
+                        };
+                        trace!(len = prev.datagram.data.len(), "dropping outgoing datagram");
+                        self.conn.stats.dropped_datagrams += 1;
+                    }
+                }
+                DatagramOverflowPolicy::DropNewest => {
+                    trace!(len = data.len(), "dropping outgoing datagram");
+                    self.conn.stats.dropped_datagrams += 1;
+                    return Ok(());
+                }
+                DatagramOverflowPolicy::Reject => {
+                    state.send_blocked = true;
+                    return Err(SendDatagramError::Blocked(data));
+                }
+            }
+        }
+        state.push(
+            OutgoingDatagram {
+                datagram: Datagram { data },
+                id: options.id,
+                deadline: options.deadline,
+                seq: 0,
+            },
+            options.priority,
+        );
+        self.conn.set_datagram_deadline_timer();
         Ok(())
     }
 
@@ -77,8 +161,8 @@ impl<'a> Datagrams<'a> {
 
     /// Bytes available in the outgoing datagram buffer
     ///
-    /// When greater than zero, [`send`](Self::send)ing a datagram of at most this size is
-    /// guaranteed not to cause older datagrams to be dropped.
+    /// [`send`](Self::send)ing a datagram of at most this size is guaranteed not to cause any
+    /// datagram to be dropped or refused.
     pub fn send_buffer_space(&self) -> usize {
         self.conn
             .config
@@ -87,14 +171,81 @@ impl<'a> Datagrams<'a> {
     }
 }
 
+/// Per-datagram transmission parameters
+///
+/// Passed to [`Datagrams::send_with_options`].
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DatagramOptions {
+    pub(crate) id: Option<u64>,
+    pub(crate) priority: i32,
+    pub(crate) deadline: Option<Instant>,
+}
+
+impl DatagramOptions {
+    /// Report whether the datagram was delivered under `id`
+    ///
+    /// See [`Datagrams::send_with_id`].
+    pub fn id(&mut self, id: u64) -> &mut Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Priority of the datagram relative to other datagrams and to stream data
+    ///
+    /// Datagrams are sent in decreasing order of priority, and in the order they were queued
+    /// within a priority. A datagram is sent before stream data of the same or lower
+    /// [priority](crate::SendStream::set_priority), and after stream data of a higher one. How
+    /// stream priorities compare with it depends on the stream scheduler; see
+    /// [`StreamScheduler::max_priority`](crate::scheduler::StreamScheduler::max_priority).
+    ///
+    /// Defaults to 0.
+    pub fn priority(&mut self, priority: i32) -> &mut Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Discard the datagram if it hasn't been sent by `deadline`
+    ///
+    /// Expired datagrams are discarded once `deadline` is passed to
+    /// [`Connection::handle_timeout`](crate::Connection::handle_timeout) or
+    /// [`Connection::poll_transmit`](crate::Connection::poll_transmit), and take up send buffer
+    /// space until then. They're counted in
+    /// [`ConnectionStats::expired_datagrams`](crate::ConnectionStats::expired_datagrams), and
+    /// aren't reported by [`Event::DatagramLost`](crate::Event::DatagramLost).
+    pub fn deadline(&mut self, deadline: Instant) -> &mut Self {
+        self.deadline = Some(deadline);
+        self
+    }
+}
+
 #[derive(Default)]
 pub(super) struct DatagramState {
     /// Number of bytes of datagrams that have been received by the local transport but not
     /// delivered to the application
     pub(super) recv_buffered: usize,
     pub(super) incoming: VecDeque<Datagram>,
-    pub(super) outgoing: VecDeque<Datagram>,
+    /// Datagrams awaiting transmission, by priority
+    ///
+    /// Levels are removed as soon as they're empty.
+    pub(super) outgoing: BTreeMap<i32, VecDeque<OutgoingDatagram>>,
     pub(super) outgoing_total: usize,
+    /// No outgoing datagram expires before this time
+    next_deadline: Option<Instant>,
+    /// Sequence number to assign to the next outgoing datagram
+    next_seq: u64,
+    /// Whether a datagram was refused for lack of buffer space since the buffer last drained
+    pub(super) send_blocked: bool,
+}
+
+/// A datagram queued for transmission
+pub(super) struct OutgoingDatagram {
+    pub(super) datagram: Datagram,
+    /// Identifies the datagram in delivery notifications, if the application asked for them
+    pub(super) id: Option<u64>,
+    /// When the datagram should be discarded if it still hasn't been sent
+    deadline: Option<Instant>,
+    /// Order in which the datagram was queued, across all priorities
+    seq: u64,
 }
 
 impl DatagramState {
@@ -127,21 +278,114 @@ impl DatagramState {
         Ok(was_empty)
     }
 
-    pub(super) fn write(&mut self, buf: &mut BytesMut, max_size: usize) -> bool {
-        let datagram = match self.outgoing.pop_front() {
+    fn push(&mut self, mut datagram: OutgoingDatagram, priority: i32) {
+        datagram.seq = self.next_seq;
+        self.next_seq += 1;
+        self.outgoing_total += datagram.datagram.data.len();
+        if let Some(deadline) = datagram.deadline {
+            self.next_deadline = Some(self.next_deadline.map_or(deadline, |x| x.min(deadline)));
+        }
+        self.outgoing
+            .entry(priority)
+            .or_default()
+            .push_back(datagram);
+    }
+
+    /// Remove the datagram that was queued first, regardless of priority
+    fn pop_oldest(&mut self) -> Option<OutgoingDatagram> {
+        let priority = *self
+            .outgoing
+            .iter()
+            .min_by_key(|(_, queue)| queue.front().map(|x| x.seq))?
+            .0;
+        self.pop(priority)
+    }
+
+    fn pop(&mut self, priority: i32) -> Option<OutgoingDatagram> {
+        let queue = self.outgoing.get_mut(&priority)?;
+        let datagram = queue.pop_front()?;
+        if queue.is_empty() {
+            self.outgoing.remove(&priority);
+        }
+        self.outgoing_total -= datagram.datagram.data.len();
+        Some(datagram)
+    }
+
+    /// Priority of the next datagram to send, if any
+    ///
+    /// Discards expired datagrams first.
+    pub(super) fn next_priority(
+        &mut self,
+        now: Instant,
+        stats: &mut ConnectionStats,
+    ) -> Option<i32> {
+        let _ = self.expire(now, stats);
+        self.outgoing.keys().next_back().copied()
+    }
+
+    /// Discard datagrams of any priority whose deadline has passed
+    ///
+    /// Returns whether space was freed after a datagram was refused for lack of it.
+    pub(super) fn expire(&mut self, now: Instant, stats: &mut ConnectionStats) -> bool {
+        if self.next_deadline.map_or(true, |x| x > now) {
+            return false;
+        }
+        let mut next_deadline = None;
+        let mut expired_bytes = 0;
+        self.outgoing.retain(|_, queue| {
+            queue.retain(|x| match x.deadline {
+                Some(deadline) if deadline <= now => {
+                    trace!(len = x.datagram.data.len(), "discarding expired datagram");
+                    expired_bytes += x.datagram.data.len();
+                    stats.expired_datagrams += 1;
+                    false
+                }
+                Some(deadline) => {
+                    next_deadline =
+                        Some(next_deadline.map_or(deadline, |x: Instant| x.min(deadline)));
+                    true
+                }
+                None => true,
+            });
+            !queue.is_empty()
+        });
+        self.outgoing_total -= expired_bytes;
+        self.next_deadline = next_deadline;
+        expired_bytes != 0 && mem::take(&mut self.send_blocked)
+    }
+
+    /// No outgoing datagram expires before this time
+    pub(super) fn next_deadline(&self) -> Option<Instant> {
+        self.next_deadline
+    }
+
+    /// Write the next queued datagram, if it fits, recording its ID in `sent_ids` if it has one
+    ///
+    /// Should be called only after [`next_priority`](Self::next_priority) returned `Some`.
+    pub(super) fn write(
+        &mut self,
+        buf: &mut BytesMut,
+        max_size: usize,
+        sent_ids: &mut DatagramIds,
+    ) -> bool {
+        let (&priority, queue) = match self.outgoing.iter().next_back() {
             Some(x) => x,
             None => return false,
         };
-
-        if buf.len() + datagram.size(true) > max_size {
+        let size = queue
+            .front()
+            .expect("empty datagram priority level")
+            .datagram
+            .size(true);
+        if buf.len() + size > max_size {
             // Future work: we could be more clever about cramming small datagrams into
             // mostly-full packets when a larger one is queued first
-            self.outgoing.push_front(datagram);
             return false;
         }
 
-        self.outgoing_total -= datagram.data.len();
-        datagram.encode(true, buf);
+        let outgoing = self.pop(priority).unwrap();
+        outgoing.datagram.encode(true, buf);
+        sent_ids.extend(outgoing.id);
         true
     }
 
@@ -167,4 +411,9 @@ pub enum SendDatagramError {
     /// exceeded.
     #[error("datagram too large")]
     TooLarge,
+    /// The send buffer is full
+    ///
+    /// Returned under [`DatagramOverflowPolicy::Reject`], carrying back the refused datagram.
+    #[error("datagram send buffer full")]
+    Blocked(Bytes),
 }
@@ -0,0 +1,332 @@
+use std::time::{Duration, Instant};
+
+/// Estimates the rate at which data is delivered to the peer
+///
+/// Follows <https://datatracker.ietf.org/doc/html/draft-cheng-iccrg-delivery-rate-estimation>: the
+/// connection's delivery progress is recorded in every packet sent, and compared to its progress
+/// once the packet is acknowledged.
+#[derive(Debug, Clone)]
+pub(crate) struct DeliveryRateEstimator {
+    /// Total number of bytes acknowledged
+    delivered: u64,
+    /// When `delivered` last increased, or when sending restarted after an idle period
+    delivered_time: Instant,
+    /// Send time of the most recently acknowledged packet, or when sending restarted after an idle
+    /// period
+    first_sent_time: Instant,
+    /// While the connection is application-limited, the value of `delivered` at which samples stop
+    /// being affected by it, and zero otherwise
+    app_limited_until: u64,
+    /// Number of bytes acknowledged since the last sample was taken
+    newly_acked: u64,
+    /// Total number of bytes declared lost
+    lost: u64,
+    /// Number of bytes declared lost since the last sample was taken
+    newly_lost: u64,
+    /// Send time, delivery state and application-limitedness of the acknowledged packet the next
+    /// sample will be based on
+    newest_acked: Option<(Instant, DeliveryState, bool)>,
+}
+
+impl DeliveryRateEstimator {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self {
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            app_limited_until: 0,
+            newly_acked: 0,
+            lost: 0,
+            newly_lost: 0,
+            newest_acked: None,
+        }
+    }
+
+    /// Snapshots the delivery progress for a packet about to be sent
+    ///
+    /// `in_flight` is the number of bytes in flight before the packet, of `size` bytes, is sent.
+    pub(crate) fn on_sent(&mut self, now: Instant, in_flight: u64, size: u16) -> DeliveryState {
+        if in_flight == 0 {
+            self.first_sent_time = now;
+            self.delivered_time = now;
+        }
+        DeliveryState {
+            delivered: self.delivered as u32,
+            delivered_time: micros_before(now, self.delivered_time),
+            first_sent_time: micros_before(now, self.first_sent_time),
+            tx_in_flight: (in_flight + u64::from(size)).try_into().unwrap_or(u32::MAX),
+            lost: self.lost as u32,
+        }
+    }
+
+    /// Whether packets sent now are limited by the application rather than the network
+    pub(crate) fn is_app_limited(&self) -> bool {
+        self.app_limited_until != 0
+    }
+
+    /// Accounts for the acknowledgement of a packet sent at `sent`, with the delivery state and
+    /// application-limitedness recorded then
+    pub(crate) fn on_ack(
+        &mut self,
+        now: Instant,
+        sent: Instant,
+        size: u16,
+        state: DeliveryState,
+        app_limited: bool,
+    ) {
+        self.delivered += u64::from(size);
+        self.delivered_time = now;
+        self.newly_acked += u64::from(size);
+
+        // Base the sample on the most recently sent packet, which reflects the latest deliveries
+        if let Some((newest_sent, newest, _)) = self.newest_acked {
+            if (self.delivered_when_sent(&state), sent)
+                < (self.delivered_when_sent(&newest), newest_sent)
+            {
+                return;
+            }
+        }
+        self.newest_acked = Some((sent, state, app_limited));
+        self.first_sent_time = sent;
+    }
+
+    pub(crate) fn on_lost(&mut self, bytes: u64) {
+        self.lost += bytes;
+        self.newly_lost += bytes;
+    }
+
+    /// Marks the connection as application-limited, given the number of bytes in flight
+    pub(crate) fn on_app_limited(&mut self, in_flight: u64) {
+        self.app_limited_until = (self.delivered + in_flight).max(1);
+    }
+
+    /// Completes the sample for the acknowledgements processed since the last call
+    ///
+    /// `prior_in_flight` is the number of bytes which were in flight before those
+    /// acknowledgements. Samples spanning less than `min_rtt` don't yield a delivery rate, as it is
+    /// likely to be inflated by acknowledgements having been compressed in time.
+    pub(crate) fn end_acks(
+        &mut self,
+        prior_in_flight: u64,
+        min_rtt: Duration,
+    ) -> Option<RateSample> {
+        if self.app_limited_until != 0 && self.delivered > self.app_limited_until {
+            self.app_limited_until = 0;
+        }
+
+        let newly_acked = std::mem::take(&mut self.newly_acked);
+        let lost = std::mem::take(&mut self.newly_lost);
+        let (sent, state, is_app_limited) = self.newest_acked.take()?;
+        // Use the longer of the send and ack phases, as either can be compressed in time
+        let send_elapsed = Duration::from_micros(state.first_sent_time.into());
+        let ack_elapsed = self.delivered_time.saturating_duration_since(
+            sent.checked_sub(Duration::from_micros(state.delivered_time.into()))
+                .unwrap_or(sent),
+        );
+        let interval = send_elapsed.max(ack_elapsed);
+        let prior_delivered = self.delivered_when_sent(&state);
+        let delivered = self.delivered.saturating_sub(prior_delivered);
+        let delivery_rate = match interval.is_zero() || interval < min_rtt {
+            true => None,
+            false => Some(
+                (u128::from(delivered) * 1_000_000_000 / interval.as_nanos())
+                    .try_into()
+                    .unwrap_or(u64::MAX),
+            ),
+        };
+
+        Some(RateSample {
+            delivery_rate,
+            interval,
+            delivered,
+            prior_delivered,
+            newly_acked,
+            lost,
+            lost_since_sent: u64::try_from((self.lost as u32).wrapping_sub(state.lost) as i32)
+                .unwrap_or(0),
+            tx_in_flight: state.tx_in_flight.into(),
+            prior_in_flight,
+            is_app_limited,
+        })
+    }
+
+    /// Total number of bytes acknowledged when a packet was sent, given its delivery state
+    fn delivered_when_sent(&self, state: &DeliveryState) -> u64 {
+        // Packets sent on a previous path may be ahead of this estimator
+        let behind = (self.delivered as u32).wrapping_sub(state.delivered) as i32;
+        match u64::try_from(behind) {
+            Ok(behind) => self.delivered.saturating_sub(behind),
+            Err(_) => self.delivered + u64::from(behind.unsigned_abs()),
+        }
+    }
+}
+
+/// The delivery progress of a connection when a packet was sent
+///
+/// Byte counts are stored modulo 2^32, and times as microseconds before the packet was sent, to
+/// keep `SentPacket` small.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct DeliveryState {
+    /// Total number of bytes acknowledged
+    delivered: u32,
+    /// When `delivered` last increased
+    delivered_time: u32,
+    /// Send time of the most recently acknowledged packet
+    first_sent_time: u32,
+    /// Number of bytes in flight once the packet was sent, including it
+    tx_in_flight: u32,
+    /// Total number of bytes declared lost
+    lost: u32,
+}
+
+fn micros_before(now: Instant, time: Instant) -> u32 {
+    now.saturating_duration_since(time)
+        .as_micros()
+        .try_into()
+        .unwrap_or(u32::MAX)
+}
+
+/// Delivery rate sample taken when acknowledgements are received
+///
+/// Passed to [`Controller::on_rate_sample`](crate::congestion::Controller::on_rate_sample). The
+/// sample is based on the most recently sent of the packets just acknowledged, and measures how
+/// much data was delivered between when it was sent and when it was acknowledged.
+#[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
+pub struct RateSample {
+    /// Delivery rate in bytes per second
+    ///
+    /// `None` if the interval was too short for a meaningful estimate, i.e. shorter than the
+    /// minimum RTT.
+    pub delivery_rate: Option<u64>,
+    /// Duration over which `delivered` was measured
+    pub interval: Duration,
+    /// Number of bytes delivered over `interval`
+    pub delivered: u64,
+    /// Total number of bytes delivered on the path when the packet the sample is based on was sent
+    ///
+    /// Useful to count round trips, which end once a packet sent after the previous one ended is
+    /// acknowledged.
+    pub prior_delivered: u64,
+    /// Number of bytes newly acknowledged
+    pub newly_acked: u64,
+    /// Number of bytes declared lost since the previous sample
+    pub lost: u64,
+    /// Number of bytes declared lost since the packet the sample is based on was sent
+    pub lost_since_sent: u64,
+    /// Number of bytes in flight once the packet the sample is based on was sent, including it
+    ///
+    /// Together with `lost_since_sent`, indicates whether that much data in flight caused
+    /// excessive loss.
+    pub tx_in_flight: u64,
+    /// Number of bytes in flight before the acknowledgements were received
+    pub prior_in_flight: u64,
+    /// Whether the connection was application-limited when the packet the sample is based on was
+    /// sent, in which case the delivery rate is likely below what the path supports
+    pub is_app_limited: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    const MSS: u16 = 1200;
+
+    /// Sends a packet every millisecond and acknowledges each a round trip later, returning the
+    /// sample taken for the last one
+    fn steady_flow(
+        rate: &mut DeliveryRateEstimator,
+        start: Instant,
+        packets: u32,
+        rtt: Duration,
+    ) -> RateSample {
+        let mut in_flight = 0;
+        let mut sent = VecDeque::new();
+        let mut sample = None;
+        for i in 0..packets {
+            let now = start + Duration::from_millis(i.into());
+            while let Some(&(time, state, app_limited)) = sent.front() {
+                if time + rtt > now {
+                    break;
+                }
+                sent.pop_front();
+                in_flight -= u64::from(MSS);
+                rate.on_ack(now, time, MSS, state, app_limited);
+                sample = rate.end_acks(in_flight + u64::from(MSS), rtt);
+            }
+            sent.push_back((
+                now,
+                rate.on_sent(now, in_flight, MSS),
+                rate.is_app_limited(),
+            ));
+            in_flight += u64::from(MSS);
+        }
+        sample.unwrap()
+    }
+
+    #[test]
+    fn measures_steady_rate() {
+        let now = Instant::now();
+        let mut rate = DeliveryRateEstimator::new(now);
+        let sample = steady_flow(&mut rate, now, 50, Duration::from_millis(10));
+        assert_eq!(sample.delivery_rate, Some(u64::from(MSS) * 1000));
+        assert_eq!(sample.interval, Duration::from_millis(10));
+        assert_eq!(sample.newly_acked, u64::from(MSS));
+        assert_eq!(sample.prior_in_flight, 10 * u64::from(MSS));
+        assert!(!sample.is_app_limited);
+    }
+
+    #[test]
+    fn short_interval() {
+        let now = Instant::now();
+        let mut rate = DeliveryRateEstimator::new(now);
+        let state = rate.on_sent(now, 0, MSS);
+        let later = now + Duration::from_millis(10);
+        rate.on_ack(later, now, MSS, state, false);
+
+        // The interval is shorter than the minimum RTT
+        let sample = rate
+            .end_acks(u64::from(MSS), Duration::from_millis(20))
+            .unwrap();
+        assert_eq!(sample.delivery_rate, None);
+        assert_eq!(sample.delivered, u64::from(MSS));
+
+        // Nothing was acknowledged since
+        assert!(rate.end_acks(0, Duration::from_millis(20)).is_none());
+    }
+
+    #[test]
+    fn loss_since_sent() {
+        let now = Instant::now();
+        let mut rate = DeliveryRateEstimator::new(now);
+        rate.on_lost(u64::from(MSS));
+        let state = rate.on_sent(now, 2 * u64::from(MSS), MSS);
+        rate.on_lost(2 * u64::from(MSS));
+        rate.on_ack(now + Duration::from_millis(10), now, MSS, state, false);
+
+        let sample = rate
+            .end_acks(3 * u64::from(MSS), Duration::from_millis(10))
+            .unwrap();
+        assert_eq!(sample.tx_in_flight, 3 * u64::from(MSS));
+        assert_eq!(sample.lost, 3 * u64::from(MSS));
+        assert_eq!(sample.lost_since_sent, 2 * u64::from(MSS));
+    }
+
+    #[test]
+    fn app_limited() {
+        let now = Instant::now();
+        let rtt = Duration::from_millis(10);
+
+        // Packets sent before any data sent afterwards was acknowledged are marked
+        let mut rate = DeliveryRateEstimator::new(now);
+        rate.on_app_limited(0);
+        assert!(steady_flow(&mut rate, now, 15, rtt).is_app_limited);
+
+        // ...but not later ones
+        let mut rate = DeliveryRateEstimator::new(now);
+        rate.on_app_limited(0);
+        assert!(!steady_flow(&mut rate, now, 30, rtt).is_app_limited);
+    }
+}
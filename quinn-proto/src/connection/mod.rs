@@ -3,14 +3,14 @@ use std::{
     collections::VecDeque,
     convert::TryFrom,
     fmt, io, mem,
-    net::{IpAddr, SocketAddr},
+    net::{IpAddr, SocketAddr, SocketAddrV6},
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime},
 };
 
-use bytes::{Bytes, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use frame::StreamMetaVec;
-use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
 use thiserror::Error;
 use tracing::{debug, error, trace, trace_span, warn};
 
@@ -23,17 +23,22 @@ use crate::{
     frame,
     frame::{Close, Datagram, FrameStruct},
     packet::{Header, LongType, Packet, PartialDecode, SpaceId},
+    qlog::QlogStream,
     range_set::ArrayRangeSet,
     shared::{
         ConnectionEvent, ConnectionEventInner, ConnectionId, EcnCodepoint, EndpointEvent,
         EndpointEventInner,
     },
-    token::ResetToken,
-    transport_parameters::TransportParameters,
-    Dir, EndpointConfig, Frame, Side, StreamId, Transmit, TransportError, TransportErrorCode,
-    VarInt, MAX_STREAM_COUNT, MIN_INITIAL_SIZE, RESET_TOKEN_SIZE, TIMER_GRANULARITY,
+    token::{ResetToken, TokenStore, ValidationToken},
+    transport_parameters::{TransportParameters, VersionInformation, ACK_DELAY_EXPONENT},
+    Dir, EndpointConfig, Frame, RetryToken, Side, StreamId, Transmit, TransportError,
+    TransportErrorCode, VarInt, MAX_STREAM_COUNT, MIN_INITIAL_SIZE, RESET_TOKEN_SIZE,
+    TIMER_GRANULARITY,
 };
 
+mod ack_frequency;
+use ack_frequency::AckFrequencyState;
+
 mod assembler;
 pub use assembler::Chunk;
 
@@ -42,7 +47,12 @@ use cid_state::CidState;
 
 mod datagrams;
 use datagrams::DatagramState;
-pub use datagrams::{Datagrams, SendDatagramError};
+pub use datagrams::{DatagramOptions, Datagrams, SendDatagramError};
+
+mod delivery_rate;
+pub use delivery_rate::RateSample;
+#[cfg(test)]
+pub(crate) use delivery_rate::{DeliveryRateEstimator, DeliveryState};
 
 mod mtud;
 mod pacing;
@@ -52,16 +62,20 @@ use packet_builder::PacketBuilder;
 
 mod paths;
 use paths::PathData;
-pub use paths::RttEstimator;
+pub use paths::{PathEvent, RttEstimator};
 
 mod send_buffer;
 
+mod send_rate;
+use send_rate::SendRateLimit;
+pub(crate) use send_rate::SharedSendRateLimit;
+
 mod spaces;
 #[cfg(fuzzing)]
 pub use spaces::Retransmits;
 #[cfg(not(fuzzing))]
 use spaces::Retransmits;
-use spaces::{PacketSpace, SendableFrames, SentPacket, ThinRetransmits};
+use spaces::{DatagramIds, PacketSpace, SendableFrames, SentPacket, ThinRetransmits};
 
 mod stats;
 pub use stats::{ConnectionStats, FrameStats, PathStats, UdpStats};
@@ -73,13 +87,17 @@ pub use streams::StreamsState;
 use streams::StreamsState;
 //pub(crate) use streams::{ByteSlice, BytesArray};
 pub use streams::{
-    BytesSource, Chunks, FinishError, ReadError, ReadableError, RecvStream, SendStream,
-    StreamEvent, Streams, UnknownStream, WriteError, Written,
+    BytesSource, Chunks, FinishError, ReadError, ReadableError, RecvStream, ResetAtError,
+    SendStream, StreamEvent, Streams, UnknownStream, WriteError, Written,
 };
 
 mod timer;
 use crate::congestion::Controller;
-use timer::{Timer, TimerTable};
+use timer::Timer;
+#[cfg(fuzzing)]
+pub use timer::TimerTable;
+#[cfg(not(fuzzing))]
+use timer::TimerTable;
 
 /// Protocol state and logic for a single QUIC connection
 ///
@@ -130,11 +148,26 @@ pub struct Connection {
     handshake_cid: ConnectionId,
     /// The CID the peer initially chose, for use during the handshake
     rem_handshake_cid: ConnectionId,
-    /// The "real" local IP address which was was used to receive the initial packet.
-    /// This is only populated for the server case, and if known
+    /// The "real" local IP address which was was used to receive the initial packet, or the most
+    /// recent non-probing packet if the peer switched to another one of our addresses.
+    /// This is only populated for the server case, and if known, or for clients that migrated to
+    /// an explicitly chosen address
     local_ip: Option<IpAddr>,
     path: PathData,
     prev_path: Option<PathData>,
+    /// A path being validated before switching to it, e.g. the server's preferred address
+    pending_path: Option<PathData>,
+    /// The local IP address `pending_path` is probed from
+    pending_local_ip: Option<IpAddr>,
+    /// Sequence number of the remote CID reserved for `pending_path`, if CIDs are in use
+    ///
+    /// Distinct from the active path's, so that the paths can't be linked.
+    pending_rem_cid_seq: Option<u64>,
+    /// Whether to switch to `pending_path` as soon as it's validated, rather than waiting for
+    /// `migrate` to be called
+    switch_to_pending: bool,
+    /// The path MTU last reported to the application
+    reported_mtu: u16,
     state: State,
     side: Side,
     /// Whether or not 0-RTT was enabled during the handshake. Does not imply acceptance.
@@ -142,6 +175,14 @@ pub struct Connection {
     /// Set if 0-RTT is supported, then cleared when no longer needed.
     zero_rtt_crypto: Option<ZeroRttCrypto>,
     key_phase: bool,
+    /// Number of 1-RTT key updates so far
+    key_generation: u64,
+    /// When the current 1-RTT keys were first used to send a packet
+    key_phase_start: Option<Instant>,
+    /// Total size of the packets sent with the current 1-RTT keys
+    key_phase_bytes: u64,
+    /// Whether a key update was requested through `initiate_key_update`
+    key_update_requested: bool,
     /// Transport parameters set by the peer
     peer_params: TransportParameters,
     /// Source ConnectionId of the first packet received from the peer
@@ -151,6 +192,11 @@ pub struct Connection {
     /// The value that the server included in the Source Connection ID field of a Retry packet, if
     /// one was received
     retry_src_cid: Option<ConnectionId>,
+    /// What a client needs to restart the handshake with another version, until a Version
+    /// Negotiation packet has been processed
+    handshake_restart: Option<HandshakeRestart>,
+    /// The version the client first attempted, if it switched versions due to Version Negotiation
+    original_version: Option<u32>,
     /// Total number of outgoing packets that have been deemed lost
     lost_packets: u64,
     events: VecDeque<Event>,
@@ -171,6 +217,8 @@ pub struct Connection {
     /// spoofing key updates.
     next_crypto: Option<KeyPair<Box<dyn PacketKey>>>,
     accepted_0rtt: bool,
+    /// Number of the first 1-RTT packet we sent; Data space packets sent before it were 0-RTT
+    first_1rtt_packet: Option<u64>,
     /// Whether the idle timer should be reset the next time an ack-eliciting packet is transmitted.
     permit_idle_reset: bool,
     /// Negotiated idle timeout
@@ -180,9 +228,11 @@ pub struct Connection {
     authentication_failures: u64,
     /// Why the connection was lost, if it has been
     error: Option<ConnectionError>,
-    /// Sent in every outgoing Initial packet. Always empty for servers and after Initial keys are
-    /// discarded.
+    /// Sent in every outgoing Initial packet, either received in a Retry packet or stored from a
+    /// previous connection. Always empty for servers and after Initial keys are discarded.
     retry_token: Bytes,
+    /// Where to store address validation tokens received from the server, and the server's name
+    token_store: Option<(Arc<dyn TokenStore>, String)>,
 
     //
     // Queued non-retransmittable 1-RTT data
@@ -206,8 +256,14 @@ pub struct Connection {
     /// Number of packets authenticated
     total_authed_packets: u64,
     /// Whether the last `poll_transmit` call yielded no data because there was
-    /// no outgoing application data.
+    /// no outgoing application data, or was held back by a send rate limit.
     app_limited: bool,
+    /// When `app_limited` was last set, if it's true, and whether a send rate limit was the cause
+    app_limited_since: Option<(Instant, bool)>,
+    /// Limit on the rate at which this connection sends data
+    send_rate: SendRateLimit,
+    /// Limit on the rate at which all of the endpoint's connections send data
+    endpoint_send_rate: Arc<SharedSendRateLimit>,
 
     streams: StreamsState,
     /// Surplus remote CIDs for future use on new paths
@@ -220,15 +276,22 @@ pub struct Connection {
     stats: ConnectionStats,
     /// QUIC version used for the connection.
     version: u32,
+    /// Structured event log, if enabled
+    qlog: Option<QlogStream>,
+    /// State of the acknowledgement frequency extension
+    ack_frequency: AckFrequencyState,
 }
 
 impl Connection {
     pub(crate) fn new(
         endpoint_config: Arc<EndpointConfig>,
         server_config: Option<Arc<ServerConfig>>,
+        handshake_restart: Option<HandshakeRestart>,
+        path_validated: bool,
         config: Arc<TransportConfig>,
         init_cid: ConnectionId,
         loc_cid: ConnectionId,
+        pref_addr_cid: Option<ConnectionId>,
         rem_cid: ConnectionId,
         remote: SocketAddr,
         local_ip: Option<IpAddr>,
@@ -237,6 +300,7 @@ impl Connection {
         now: Instant,
         version: u32,
         allow_mtud: bool,
+        endpoint_send_rate: Arc<SharedSendRateLimit>,
     ) -> Self {
         let side = if server_config.is_some() {
             Side::Server
@@ -253,14 +317,23 @@ impl Connection {
             client_hello: None,
         });
         let mut rng = StdRng::from_entropy();
-        let path_validated = server_config.as_ref().map_or(true, |c| c.use_retry);
+        let qlog = config
+            .qlog_factory
+            .as_ref()
+            .and_then(|factory| factory.create(side, &init_cid))
+            .and_then(|writer| QlogStream::new(writer, side, &init_cid, now));
         let mut this = Self {
             endpoint_config,
             server_config,
             crypto,
             handshake_cid: loc_cid,
             rem_handshake_cid: rem_cid,
-            local_cid_state: CidState::new(cid_gen.cid_len(), cid_gen.cid_lifetime(), now),
+            local_cid_state: CidState::new(
+                cid_gen.cid_len(),
+                cid_gen.cid_lifetime(),
+                now,
+                1 + pref_addr_cid.is_some() as u64,
+            ),
             path: PathData::new(
                 remote,
                 config.initial_rtt,
@@ -279,15 +352,26 @@ impl Connection {
             ),
             local_ip,
             prev_path: None,
+            pending_path: None,
+            pending_local_ip: None,
+            pending_rem_cid_seq: None,
+            switch_to_pending: false,
+            reported_mtu: config.get_initial_mtu(),
             side,
             state,
             zero_rtt_enabled: false,
             zero_rtt_crypto: None,
             key_phase: false,
+            key_generation: 0,
+            key_phase_start: None,
+            key_phase_bytes: 0,
+            key_update_requested: false,
             peer_params: TransportParameters::default(),
             orig_rem_cid: rem_cid,
             initial_dst_cid: init_cid,
             retry_src_cid: None,
+            handshake_restart,
+            original_version: None,
             lost_packets: 0,
             events: VecDeque::new(),
             endpoint_events: VecDeque::new(),
@@ -298,12 +382,14 @@ impl Connection {
             prev_crypto: None,
             next_crypto: None,
             accepted_0rtt: false,
+            first_1rtt_packet: None,
             permit_idle_reset: true,
             idle_timeout: config.max_idle_timeout,
             timers: TimerTable::default(),
             authentication_failures: 0,
             error: None,
             retry_token: Bytes::new(),
+            token_store: None,
 
             path_response: None,
             close: false,
@@ -311,6 +397,9 @@ impl Connection {
             pto_count: 0,
 
             app_limited: false,
+            app_limited_since: None,
+            send_rate: SendRateLimit::new(config.max_send_rate),
+            endpoint_send_rate,
             in_flight: InFlight::new(),
             receiving_ecn: false,
             total_authed_packets: 0,
@@ -322,6 +411,7 @@ impl Connection {
                 config.send_window,
                 config.receive_window,
                 config.stream_receive_window,
+                config.stream_scheduler_factory.build(),
             ),
             datagrams: DatagramState::default(),
             config,
@@ -329,7 +419,15 @@ impl Connection {
             rng,
             stats: ConnectionStats::default(),
             version,
+            qlog,
+            ack_frequency: AckFrequencyState::new(Duration::from_millis(
+                TransportParameters::default().max_ack_delay.into_inner(),
+            )),
         };
+        this.streams.set_receive_window_limits(
+            this.config.max_receive_window,
+            this.config.max_stream_receive_window,
+        );
         if side.is_client() {
             // Kick off the connection
             this.write_crypto();
@@ -338,6 +436,17 @@ impl Connection {
         this
     }
 
+    /// Use a token stored from a previous connection to the same server, and store the ones the
+    /// server sends us
+    pub(crate) fn set_token_store(&mut self, store: Arc<dyn TokenStore>, server_name: &str) {
+        debug_assert!(self.side.is_client());
+        if let Some(token) = store.take(server_name) {
+            trace!("using stored address validation token");
+            self.retry_token = token;
+        }
+        self.token_store = Some((store, server_name.into()));
+    }
+
     /// Returns the next time at which `handle_timeout` should be called
     ///
     /// The value returned may change after:
@@ -357,6 +466,14 @@ impl Connection {
     /// - a call was made to `handle_timeout`
     #[must_use]
     pub fn poll(&mut self) -> Option<Event> {
+        // The MTU changes in many places, including whenever we switch paths, so check it lazily
+        let mtu = self.path.current_mtu();
+        if mtu != self.reported_mtu {
+            self.reported_mtu = mtu;
+            self.events
+                .push_back(Event::Path(PathEvent::MtuChanged { mtu }));
+        }
+
         if let Some(x) = self.events.pop_front() {
             return Some(x);
         }
@@ -407,6 +524,7 @@ impl Connection {
             state: &mut self.streams,
             pending: &mut self.spaces[SpaceId::Data].pending,
             conn_state: &self.state,
+            timers: &mut self.timers,
         }
     }
 
@@ -434,46 +552,60 @@ impl Connection {
                     .challenge
                     .expect("previous path challenge pending without token");
                 let destination = prev_path.remote;
-                debug_assert_eq!(
-                    self.highest_space,
-                    SpaceId::Data,
-                    "PATH_CHALLENGE queued without 1-RTT keys"
+                trace!("validating previous path with PATH_CHALLENGE {:08x}", token);
+                return self.send_path_frame(
+                    now,
+                    frame::Type::PATH_CHALLENGE,
+                    token,
+                    destination,
+                    self.local_ip,
+                    self.rem_cids.active(),
                 );
-                let mut buf = BytesMut::with_capacity(self.path.current_mtu() as usize);
-                let buf_capacity = self.path.current_mtu() as usize;
+            }
+        }
 
-                let mut builder = PacketBuilder::new(
+        // Send PATH_CHALLENGE for a path we intend to switch to if necessary
+        if let Some(ref mut pending_path) = self.pending_path {
+            if pending_path.challenge_pending {
+                pending_path.challenge_pending = false;
+                let token = pending_path
+                    .challenge
+                    .expect("pending path challenge pending without token");
+                let destination = pending_path.remote;
+                trace!("validating new path with PATH_CHALLENGE {:08x}", token);
+                // Nothing else is sent on the path to elicit an acknowledgement, so the challenge is
+                // resent if no response arrives within a PTO (RFC 9000 §8.2.1)
+                let pto = self.pending_path_pto();
+                self.timers.set(Timer::PendingPathChallenge, now + pto);
+                let dst_cid = self.pending_rem_cid();
+                return self.send_path_frame(
                     now,
-                    SpaceId::Data,
-                    &mut buf,
-                    buf_capacity,
-                    0,
-                    false,
-                    self,
-                    self.version,
-                )?;
-                trace!("validating previous path with PATH_CHALLENGE {:08x}", token);
-                buf.write(frame::Type::PATH_CHALLENGE);
-                buf.write(token);
-                self.stats.frame_tx.path_challenge += 1;
-
-                // An endpoint MUST expand datagrams that contain a PATH_CHALLENGE frame
-                // to at least the smallest allowed maximum datagram size of 1200 bytes,
-                // unless the anti-amplification limit for the path does not permit
-                // sending a datagram of this size
-                builder.pad_to(MIN_INITIAL_SIZE);
-
-                builder.finish(self, &mut buf);
-                self.stats.udp_tx.datagrams += 1;
-                self.stats.udp_tx.transmits += 1;
-                self.stats.udp_tx.bytes += buf.len() as u64;
-                return Some(Transmit {
+                    frame::Type::PATH_CHALLENGE,
+                    token,
                     destination,
-                    contents: buf.freeze(),
-                    ecn: None,
-                    segment_size: None,
-                    src_ip: self.local_ip,
-                });
+                    self.pending_local_ip,
+                    dst_cid,
+                );
+            }
+        }
+
+        // PATH_RESPONSE must be sent on the path the PATH_CHALLENGE was received on
+        if let Some(ref response) = self.path_response {
+            if response.remote != self.path.remote || response.local_ip != self.local_ip {
+                let response = self.path_response.take().unwrap();
+                trace!(
+                    remote = %response.remote,
+                    "responding on another path with PATH_RESPONSE {:08x}",
+                    response.token
+                );
+                return self.send_path_frame(
+                    now,
+                    frame::Type::PATH_RESPONSE,
+                    response.token,
+                    response.remote,
+                    response.local_ip,
+                    self.rem_cids.active(),
+                );
             }
         }
 
@@ -511,6 +643,7 @@ impl Connection {
         let mut sent_frames = None;
         let mut pad_datagram = false;
         let mut congestion_blocked = false;
+        let mut rate_limited = false;
 
         // Iterate over all spaces and find data to send
         let mut space_idx = 0;
@@ -535,7 +668,8 @@ impl Connection {
             }
 
             let mut ack_eliciting = !self.spaces[space_id].pending.is_empty(&self.streams)
-                || self.spaces[space_id].ping_pending;
+                || self.spaces[space_id].ping_pending
+                || self.spaces[space_id].immediate_ack_pending;
             if space_id == SpaceId::Data {
                 ack_eliciting |= self.can_send_1rtt();
             }
@@ -592,20 +726,21 @@ impl Connection {
                     }
 
                     // Check whether the next datagram is blocked by pacing
-                    let smoothed_rtt = self.path.rtt.get();
-                    if let Some(delay) = self.path.pacing.delay(
-                        smoothed_rtt,
-                        bytes_to_send,
-                        self.path.current_mtu(),
-                        self.path.congestion.window(),
-                        now,
-                    ) {
+                    if let Some(delay) = self.pacing_delay(bytes_to_send, now) {
                         self.timers.set(Timer::Pacing, delay);
                         congestion_blocked = true;
                         // Loss probes should be subject to pacing, even though
                         // they are not congestion controlled.
                         break;
                     }
+
+                    // Check whether the next datagram is blocked by a send rate limit, which the
+                    // congestion controller should see as the application limiting the rate
+                    if let Some(delay) = self.send_rate_delay(bytes_to_send, now) {
+                        self.timers.set(Timer::Pacing, delay);
+                        rate_limited = true;
+                        break;
+                    }
                 }
 
                 // Finish current packet
@@ -673,6 +808,7 @@ impl Connection {
             let builder = builder.get_or_insert(PacketBuilder::new(
                 now,
                 space_id,
+                self.rem_cids.active(),
                 &mut buf,
                 buf_capacity,
                 (num_datagrams - 1) * (self.path.current_mtu() as usize),
@@ -694,6 +830,7 @@ impl Connection {
                 // have gotten any other ACK for the data earlier on.
                 if !self.spaces[space_id].pending_acks.ranges().is_empty() {
                     Self::populate_acks(
+                        now,
                         self.receiving_ecn,
                         &mut SentFrames::default(),
                         &mut self.spaces[space_id],
@@ -740,7 +877,13 @@ impl Connection {
                 break;
             }
 
-            let sent = self.populate_packet(space_id, &mut buf, buf_capacity - builder.tag_len);
+            let sent = self.populate_packet(
+                now,
+                space_id,
+                &mut buf,
+                buf_capacity - builder.tag_len,
+                builder.exact_number,
+            );
 
             // ACK-only packets should only be sent when explicitly allowed. If we write them due
             // to any other reason, there is a bug which leads to one component announcing write
@@ -758,6 +901,9 @@ impl Connection {
 
             if sent.largest_acked.is_some() {
                 self.spaces[space_id].pending_acks.acks_sent();
+                if space_id == SpaceId::Data {
+                    self.timers.stop(Timer::MaxAckDelay);
+                }
             }
 
             // Keep information about the packet around until it gets finalized
@@ -779,7 +925,10 @@ impl Connection {
                 .on_sent(now, buf.len() as u64, last_packet_number);
         }
 
-        self.app_limited = buf.is_empty() && !congestion_blocked;
+        self.set_app_limited(now, buf.is_empty() && !congestion_blocked, rate_limited);
+        if self.app_limited {
+            self.path.delivery_rate.on_app_limited(self.in_flight.bytes);
+        }
 
         // Send MTU probe if necessary
         if buf.is_empty() && self.state.is_established() {
@@ -799,6 +948,7 @@ impl Connection {
             let mut builder = PacketBuilder::new(
                 now,
                 space_id,
+                self.rem_cids.active(),
                 &mut buf,
                 buf_capacity,
                 0,
@@ -838,7 +988,7 @@ impl Connection {
             destination: self.path.remote,
             contents: buf.freeze(),
             ecn: if self.path.sending_ecn {
-                Some(EcnCodepoint::Ect0)
+                Some(self.ecn_codepoint())
             } else {
                 None
             },
@@ -892,14 +1042,19 @@ impl Connection {
             Datagram {
                 now,
                 remote,
+                local_ip,
                 ecn,
                 first_decode,
                 remaining,
             } => {
                 // If this packet could initiate a migration and we're a client or a server that
-                // forbids migration, drop the datagram. This could be relaxed to heuristically
-                // permit NAT-rebinding-like migration.
+                // forbids migration, drop the datagram, unless it's from a path we're validating.
+                // This could be relaxed to heuristically permit NAT-rebinding-like migration.
                 if remote != self.path.remote
+                    && self
+                        .pending_path
+                        .as_ref()
+                        .map_or(true, |x| x.remote != remote)
                     && self.server_config.as_ref().map_or(true, |x| !x.migration)
                 {
                     trace!("discarding packet from unrecognized peer {}", remote);
@@ -912,7 +1067,7 @@ impl Connection {
                 self.stats.udp_rx.bytes += first_decode.len() as u64;
                 let data_len = first_decode.len();
 
-                self.handle_decode(now, remote, ecn, first_decode);
+                self.handle_decode(now, remote, local_ip, ecn, first_decode);
                 // The current `path` might have changed inside `handle_decode`,
                 // since the packet could have triggered a migration. Make sure
                 // the data received is accounted for the most recent path by accessing
@@ -921,7 +1076,7 @@ impl Connection {
 
                 if let Some(data) = remaining {
                     self.stats.udp_rx.bytes += data.len() as u64;
-                    self.handle_coalesced(now, remote, ecn, data);
+                    self.handle_coalesced(now, remote, local_ip, ecn, data);
                 }
 
                 if was_anti_amplification_blocked {
@@ -930,6 +1085,7 @@ impl Connection {
                     // the server's first flight is lost.
                     self.set_loss_detection_timer(now);
                 }
+                self.qlog_state(now);
             }
             NewIdentifiers(ids, now) => {
                 self.local_cid_state.new_cids(&ids, now);
@@ -983,15 +1139,56 @@ impl Connection {
                     self.zero_rtt_crypto = None;
                     self.prev_crypto = None;
                 }
+                Timer::PendingPathValidation => {
+                    if let Some(path) = self.clear_pending_path() {
+                        // Keep using the active path
+                        debug!(remote = %path.remote, "path validation failed");
+                        self.events
+                            .push_back(Event::Path(PathEvent::ValidationFailed {
+                                remote: path.remote,
+                            }));
+                    }
+                }
+                Timer::PendingPathChallenge => {
+                    if let Some(ref mut path) = self.pending_path {
+                        if path.challenge.is_some() {
+                            trace!(remote = %path.remote, "resending PATH_CHALLENGE");
+                            path.challenge_pending = true;
+                        }
+                    }
+                }
                 Timer::PathValidation => {
                     debug!("path validation failed");
+                    self.events
+                        .push_back(Event::Path(PathEvent::ValidationFailed {
+                            remote: self.path.remote,
+                        }));
                     if let Some(prev) = self.prev_path.take() {
-                        self.path = prev;
+                        let old = mem::replace(&mut self.path, prev);
+                        self.remote_address_changed(old.remote);
                     }
                     self.path.challenge = None;
                     self.path.challenge_pending = false;
                 }
                 Timer::Pacing => trace!("pacing timer expired"),
+                Timer::MaxAckDelay => {
+                    trace!("max ack delay reached");
+                    // This timer is only armed in the Data space
+                    self.spaces[SpaceId::Data]
+                        .pending_acks
+                        .on_max_ack_delay_timeout()
+                }
+                Timer::StreamDeadline => {
+                    self.streams
+                        .expire_writes(now, &mut self.spaces[SpaceId::Data].pending);
+                    self.streams.set_write_deadline_timer(&mut self.timers);
+                }
+                Timer::DatagramDeadline => {
+                    if self.datagrams.expire(now, &mut self.stats) {
+                        self.events.push_back(Event::DatagramsUnblocked);
+                    }
+                    self.set_datagram_deadline_timer();
+                }
                 Timer::PushNewCid => {
                     // Update `retire_prior_to` field in NEW_CONNECTION_ID frame
                     let num_new_cid = self.local_cid_state.on_cid_timeout().into();
@@ -1006,6 +1203,7 @@ impl Connection {
                 }
             }
         }
+        self.qlog_state(now);
     }
 
     /// Close a connection immediately
@@ -1033,6 +1231,7 @@ impl Connection {
             self.set_close_timer(now);
             self.close = true;
             self.state = State::Closed(state::Closed { reason });
+            self.qlog_state(now);
         }
     }
 
@@ -1057,9 +1256,231 @@ impl Connection {
         self.spaces[self.highest_space].ping_pending = true;
     }
 
+    /// Ask the remote endpoint to acknowledge all packets received so far without delay
+    ///
+    /// Sends an IMMEDIATE_ACK frame, overriding any acknowledgement delay requested through
+    /// [`AckFrequencyConfig`](crate::AckFrequencyConfig). Has no effect until the handshake has
+    /// completed, or if the peer does not support the acknowledgement frequency extension.
+    pub fn immediate_ack(&mut self) {
+        if self.spaces[SpaceId::Data].crypto.is_some() && self.peer_params.min_ack_delay.is_some() {
+            self.spaces[SpaceId::Data].immediate_ack_pending = true;
+        }
+    }
+
+    /// Check whether a new network path to `remote`, sent from `local_ip`, is usable
+    ///
+    /// Sends a PATH_CHALLENGE on the new path while traffic continues on the current one. Once
+    /// the peer responds, [`migrate`](Self::migrate) can switch to the path without further
+    /// delay; if it doesn't respond within a few PTOs, the probe is abandoned. Replaces any
+    /// previously probed path.
+    ///
+    /// `local_ip` sets the source address of datagrams sent on the new path, as in
+    /// [`Transmit::src_ip`]. Only clients may probe new paths.
+    pub fn probe_path(
+        &mut self,
+        now: Instant,
+        remote: SocketAddr,
+        local_ip: Option<IpAddr>,
+    ) -> Result<(), MigrateError> {
+        self.check_migration(true)?;
+        self.clear_pending_path();
+        // Don't use the same connection ID from multiple local addresses
+        self.pending_rem_cid_seq = match self.rem_cids.active().is_empty() {
+            true => None,
+            false => Some(
+                self.rem_cids
+                    .next_seq()
+                    .ok_or(MigrateError::NoConnectionId)?,
+            ),
+        };
+        trace!(%remote, ?local_ip, "probing new path");
+        self.start_pending_path(now, remote);
+        self.pending_local_ip = local_ip;
+        self.switch_to_pending = false;
+        Ok(())
+    }
+
+    /// Move the connection to a new network path to `remote`, sent from `local_ip`
+    ///
+    /// If the path was successfully validated by [`probe_path`](Self::probe_path), the switch
+    /// takes effect immediately. Otherwise, traffic moves to the new path at once and the path is
+    /// validated with a PATH_CHALLENGE as it's used. In either case, congestion control and RTT
+    /// estimation start over.
+    ///
+    /// The peer follows the connection to the new path when it receives non-probing packets on
+    /// it. Only clients may migrate, and only once the handshake is confirmed.
+    pub fn migrate(
+        &mut self,
+        now: Instant,
+        remote: SocketAddr,
+        local_ip: Option<IpAddr>,
+    ) -> Result<(), MigrateError> {
+        let probed = self.pending_path.as_ref().map_or(false, |x| {
+            x.challenge.is_none() && x.remote == remote && self.pending_local_ip == local_ip
+        });
+        self.check_migration(!probed)?;
+        let old_remote = self.path.remote;
+        if probed {
+            trace!(%remote, ?local_ip, "migrating to probed path");
+            self.switch_to_pending_path();
+        } else {
+            trace!(%remote, ?local_ip, "migrating to new path");
+            self.clear_pending_path();
+            self.path = self.new_path(now, remote, true);
+            self.path.challenge = Some(self.rng.gen());
+            self.path.challenge_pending = true;
+            self.timers.set(
+                Timer::PathValidation,
+                now + 3 * cmp::max(
+                    self.pto(SpaceId::Data),
+                    self.path.rtt.pto_base() + self.max_ack_delay(),
+                ),
+            );
+            // Break linkability, if possible
+            self.update_rem_cid();
+        }
+        self.remote_address_changed(old_remote);
+        self.local_ip = local_ip;
+        self.prev_path = None;
+        self.spin = false;
+        // Stateless resets are only recognized from the address they're registered for
+        if let Some(reset_token) = self.peer_params.stateless_reset_token {
+            self.set_reset_token(reset_token);
+        }
+        // Let the peer know about the new path promptly
+        self.ping();
+        Ok(())
+    }
+
+    /// Check whether the client may start using a new path
+    ///
+    /// `new_cid` indicates whether the path needs a remote CID not yet used on any path, rather
+    /// than the one reserved for `pending_path`.
+    fn check_migration(&self, new_cid: bool) -> Result<(), MigrateError> {
+        if self.side.is_server() {
+            return Err(MigrateError::NotClient);
+        }
+        if self.state.is_closed() {
+            return Err(MigrateError::Closed);
+        }
+        if !self.state.is_established() || self.spaces[SpaceId::Handshake].crypto.is_some() {
+            return Err(MigrateError::HandshakeNotConfirmed);
+        }
+        if self.peer_params.disable_active_migration {
+            return Err(MigrateError::Disabled);
+        }
+        // A CID reserved for a pending path which is being replaced can't be reused either
+        let reserved = usize::from(new_cid && self.pending_rem_cid_seq.is_some());
+        if !self.rem_cids.active().is_empty() && self.rem_cids.spare_count() <= reserved {
+            return Err(MigrateError::NoConnectionId);
+        }
+        Ok(())
+    }
+
+    /// The remote CID to send datagrams on `pending_path` with
+    fn pending_rem_cid(&self) -> ConnectionId {
+        self.pending_rem_cid_seq
+            .and_then(|seq| self.rem_cids.get(seq))
+            .unwrap_or_else(|| self.rem_cids.active())
+    }
+
+    /// Start validating a fresh path to `remote` as `pending_path`
+    ///
+    /// Validation is abandoned if the peer doesn't respond within a few PTOs, independently of any
+    /// validation of the active path.
+    fn start_pending_path(&mut self, now: Instant, remote: SocketAddr) {
+        let mut path = self.new_path(now, remote, true);
+        path.challenge = Some(self.rng.gen());
+        path.challenge_pending = true;
+        self.pending_path = Some(path);
+        self.timers.set(
+            Timer::PendingPathValidation,
+            now + 3 * self.pending_path_pto(),
+        );
+    }
+
+    /// Probe timeout on `pending_path`, which has no RTT samples of its own
+    fn pending_path_pto(&self) -> Duration {
+        let path = self.pending_path.as_ref().unwrap();
+        cmp::max(
+            self.pto(SpaceId::Data),
+            path.rtt.pto_base() + self.max_ack_delay(),
+        )
+    }
+
+    /// Stop validating `pending_path`, discarding the remote CID reserved for it
+    ///
+    /// The CID isn't retired right away, since the peer would then issue a new one beyond the
+    /// window of CIDs we track. It's retired along with those preceding the next CID we switch to.
+    fn clear_pending_path(&mut self) -> Option<PathData> {
+        if let Some(seq) = self.pending_rem_cid_seq.take() {
+            self.rem_cids.remove(seq);
+        }
+        self.stop_pending_path_timers();
+        self.pending_path.take()
+    }
+
+    fn stop_pending_path_timers(&mut self) {
+        self.timers.stop(Timer::PendingPathValidation);
+        self.timers.stop(Timer::PendingPathChallenge);
+    }
+
+    /// Make `pending_path` the active path, along with the remote CID reserved for it
+    ///
+    /// Returns the previously active path.
+    fn switch_to_pending_path(&mut self) -> PathData {
+        let old = mem::replace(&mut self.path, self.pending_path.take().unwrap());
+        self.stop_pending_path_timers();
+        if let Some(seq) = self.pending_rem_cid_seq.take() {
+            match self.rem_cids.switch_to(seq) {
+                Some((reset_token, retired)) => {
+                    self.spaces[SpaceId::Data]
+                        .pending
+                        .retire_cids
+                        .extend(retired);
+                    self.set_reset_token(reset_token);
+                }
+                None => self.update_rem_cid(),
+            }
+        }
+        old
+    }
+
+    /// Update the 1-RTT keys before sending the next packet
+    ///
+    /// The update doesn't happen immediately, but when the next 1-RTT packet is built, like updates
+    /// initiated according to [`TransportConfig::key_update_config`]. Call
+    /// [`poll_transmit`](Self::poll_transmit) for it to take effect.
     #[doc(hidden)]
     pub fn initiate_key_update(&mut self) {
-        self.update_keys(None, false);
+        self.key_update_requested = true;
+    }
+
+    /// Whether a key update should be initiated before sending a 1-RTT packet
+    fn key_update_due(&mut self, now: Instant) -> bool {
+        // Keys mustn't be updated before the handshake is confirmed
+        if self.spaces[SpaceId::Data].crypto.is_none()
+            || self.spaces[SpaceId::Handshake].crypto.is_some()
+        {
+            return false;
+        }
+        let start = *self.key_phase_start.get_or_insert(now);
+        if self.key_update_requested {
+            return true;
+        }
+        let config = match self.config.key_update_config {
+            Some(ref x) => x,
+            None => return false,
+        };
+        let due = config
+            .interval
+            .map_or(false, |x| now.saturating_duration_since(start) >= x)
+            || config.bytes.map_or(false, |x| self.key_phase_bytes >= x);
+        // A new key update can't be initiated until the previous one has been confirmed
+        due && self
+            .prev_crypto
+            .as_ref()
+            .map_or(true, |x| x.end_packet.is_some() && !x.update_unacked)
     }
 
     /// Get a session reference
@@ -1127,7 +1548,7 @@ impl Connection {
     /// This can be different from the address the endpoint is bound to, in case
     /// the endpoint is bound to a wildcard address like `0.0.0.0` or `::`.
     ///
-    /// This will return `None` for clients.
+    /// This will return `None` for clients, unless one was supplied to [`migrate`](Self::migrate).
     ///
     /// Retrieving the local IP address is currently supported on the following
     /// platforms:
@@ -1166,6 +1587,11 @@ impl Connection {
         self.streams.max_concurrent(dir)
     }
 
+    /// See [`TransportConfig::max_send_rate()`]
+    pub fn set_max_send_rate(&mut self, rate: Option<u64>) {
+        self.send_rate.set_rate(rate);
+    }
+
     /// See [`TransportConfig::receive_window()`]
     pub fn set_receive_window(&mut self, receive_window: VarInt) {
         if self.streams.set_receive_window(receive_window) {
@@ -1213,7 +1639,32 @@ impl Connection {
             return Ok(());
         }
 
-        let mut ack_eliciting_acked = false;
+        // Take the RTT sample before processing the acknowledged packets, so that congestion
+        // control sees it
+        let ack_eliciting_acked = newly_acked.elts().any(|packet| {
+            self.spaces[space]
+                .sent_packets
+                .get(&packet)
+                .map_or(false, |info| info.ack_eliciting)
+        });
+        if new_largest && ack_eliciting_acked {
+            let ack_delay = if space != SpaceId::Data {
+                Duration::from_micros(0)
+            } else {
+                cmp::min(
+                    self.max_ack_delay(),
+                    Duration::from_micros(ack.delay << self.peer_params.ack_delay_exponent.0),
+                )
+            };
+            let rtt = instant_saturating_sub(now, self.spaces[space].largest_acked_packet_sent);
+            self.path.rtt.update(ack_delay, rtt);
+            if self.path.first_packet_after_rtt_sample.is_none() {
+                self.path.first_packet_after_rtt_sample =
+                    Some((space, self.spaces[space].next_packet_number));
+            }
+        }
+
+        let prior_in_flight = self.in_flight.bytes;
         for packet in newly_acked.elts() {
             if let Some(info) = self.spaces[space].sent_packets.remove(&packet) {
                 if let Some(acked) = info.largest_acked {
@@ -1224,7 +1675,6 @@ impl Connection {
                     // https://www.rfc-editor.org/rfc/rfc9000.html#name-limiting-ranges-by-tracking
                     self.spaces[space].pending_acks.subtract_below(acked);
                 }
-                ack_eliciting_acked |= info.ack_eliciting;
 
                 // Notify MTU discovery that a packet was acked, because it might be an MTU probe
                 let mtu_updated = self.path.mtud.on_acked(space, packet, info.size);
@@ -1234,10 +1684,17 @@ impl Connection {
                         .on_mtu_update(self.path.mtud.current_mtu());
                 }
 
-                self.on_packet_acked(now, space, info);
+                self.on_packet_acked(now, space, packet, info);
             }
         }
 
+        if let Some(sample) = self
+            .path
+            .delivery_rate
+            .end_acks(prior_in_flight, self.path.rtt.min())
+        {
+            self.path.congestion.on_rate_sample(now, &sample);
+        }
         self.path.congestion.on_end_acks(
             now,
             self.in_flight.bytes,
@@ -1245,23 +1702,6 @@ impl Connection {
             self.spaces[space].largest_acked_packet,
         );
 
-        if new_largest && ack_eliciting_acked {
-            let ack_delay = if space != SpaceId::Data {
-                Duration::from_micros(0)
-            } else {
-                cmp::min(
-                    self.max_ack_delay(),
-                    Duration::from_micros(ack.delay << self.peer_params.ack_delay_exponent.0),
-                )
-            };
-            let rtt = instant_saturating_sub(now, self.spaces[space].largest_acked_packet_sent);
-            self.path.rtt.update(ack_delay, rtt);
-            if self.path.first_packet_after_rtt_sample.is_none() {
-                self.path.first_packet_after_rtt_sample =
-                    Some((space, self.spaces[space].next_packet_number));
-            }
-        }
-
         // Must be called before crypto/pto_count are clobbered
         self.detect_lost_packets(now, space, true);
 
@@ -1288,6 +1728,7 @@ impl Connection {
         }
 
         self.set_loss_detection_timer(now);
+        self.qlog_metrics(now);
         Ok(())
     }
 
@@ -1300,7 +1741,8 @@ impl Connection {
         ecn: frame::EcnCounts,
         largest_sent_time: Instant,
     ) {
-        match self.spaces[space].detect_ecn(newly_acked, ecn) {
+        let codepoint = self.ecn_codepoint();
+        match self.spaces[space].detect_ecn(newly_acked, ecn, codepoint) {
             Err(e) => {
                 debug!("halting ECN due to verification failure: {}", e);
                 self.path.sending_ecn = false;
@@ -1308,19 +1750,77 @@ impl Connection {
                 // future attempts to use ECN on new paths.
                 self.spaces[space].ecn_feedback = frame::EcnCounts::ZERO;
             }
-            Ok(false) => {}
-            Ok(true) => {
-                self.stats.path.congestion_events += 1;
-                self.path
-                    .congestion
-                    .on_congestion_event(now, largest_sent_time, false, 0);
+            Ok((ect, ce)) => {
+                self.path.congestion.on_ecn(now, ect, ce);
+                if ce != 0 {
+                    self.stats.path.congestion_events += 1;
+                    self.path
+                        .congestion
+                        .on_congestion_event(now, largest_sent_time, false, 0);
+                }
             }
         }
     }
 
+    /// The ECN codepoint outgoing packets are marked with, when ECN is in use
+    fn ecn_codepoint(&self) -> EcnCodepoint {
+        match self.config.send_ect1 {
+            true => EcnCodepoint::Ect1,
+            false => EcnCodepoint::Ect0,
+        }
+    }
+
+    /// Returns when pacing will next allow `bytes_to_send` to be sent, if it doesn't right away
+    fn pacing_delay(&mut self, bytes_to_send: u64, now: Instant) -> Option<Instant> {
+        if !self.config.pacing {
+            return None;
+        }
+        // Pacing at the send rate limit avoids sending in bursts whenever it permits
+        let max_rate = match (self.config.max_pacing_rate, self.send_rate.rate()) {
+            (Some(x), Some(y)) => Some(x.min(y)),
+            (x, y) => x.or(y),
+        };
+        let congestion = &self.path.congestion;
+        self.path.pacing.delay(
+            self.path.rtt.get(),
+            bytes_to_send,
+            self.path.mtud.current_mtu(),
+            congestion.window(),
+            congestion.pacing_rate(),
+            congestion.send_quantum(),
+            max_rate,
+            now,
+        )
+    }
+
+    /// Returns when the send rate limits will next allow `bytes_to_send` to be sent, if they
+    /// don't right away
+    fn send_rate_delay(&mut self, bytes_to_send: u64, now: Instant) -> Option<Instant> {
+        let mtu = self.path.current_mtu();
+        let own = self.send_rate.delay(bytes_to_send, mtu, now);
+        let endpoint = self.endpoint_send_rate.delay(bytes_to_send, mtu, now);
+        own.max(endpoint)
+    }
+
+    /// Being held back by a send rate limit counts as being application-limited for congestion
+    /// control, but is accounted for separately in the stats
+    fn set_app_limited(&mut self, now: Instant, app_limited: bool, rate_limited: bool) {
+        if let Some((since, rate_limited)) = self.app_limited_since.take() {
+            let time = now.saturating_duration_since(since);
+            match rate_limited {
+                true => self.stats.path.rate_limited_time += time,
+                false => self.stats.path.app_limited_time += time,
+            }
+        }
+        self.app_limited = app_limited || rate_limited;
+        if self.app_limited {
+            self.app_limited_since = Some((now, rate_limited));
+        }
+    }
+
     // Not timing-aware, so it's safe to call this for inferred acks, such as arise from
     // high-latency handshakes
-    fn on_packet_acked(&mut self, now: Instant, space: SpaceId, info: SentPacket) {
+    fn on_packet_acked(&mut self, now: Instant, space: SpaceId, number: u64, info: SentPacket) {
         self.remove_in_flight(space, &info);
         if info.ack_eliciting && self.path.challenge.is_none() {
             // Only pass ACKs to the congestion controller if we are not validating the current
@@ -1332,6 +1832,13 @@ impl Connection {
                 self.app_limited,
                 &self.path.rtt,
             );
+            self.path.delivery_rate.on_ack(
+                now,
+                info.time_sent,
+                info.size,
+                info.delivery,
+                info.app_limited,
+            );
         }
 
         // Update state for confirmed delivery of frames
@@ -1339,10 +1846,19 @@ impl Connection {
             for (id, _) in retransmits.reset_stream.iter() {
                 self.streams.reset_acked(*id);
             }
+            if retransmits.ack_frequency {
+                self.ack_frequency.on_acked(number);
+            }
         }
 
-        for frame in info.stream_frames {
-            self.streams.received_ack_of(frame);
+        if !info.stream_frames.is_empty() {
+            for frame in info.stream_frames {
+                self.streams.received_ack_of(frame);
+            }
+            self.streams.set_write_deadline_timer(&mut self.timers);
+        }
+        for id in info.datagram_ids {
+            self.events.push_back(Event::DatagramAcked(id));
         }
     }
 
@@ -1367,6 +1883,7 @@ impl Connection {
             // Time threshold loss Detection
             self.detect_lost_packets(now, pn_space, false);
             self.set_loss_detection_timer(now);
+            self.qlog_metrics(now);
             return;
         }
 
@@ -1478,6 +1995,7 @@ impl Connection {
             self.lost_packets += lost_packets.len() as u64;
             self.stats.path.lost_packets += lost_packets.len() as u64;
             self.stats.path.lost_bytes += size_of_lost_packets;
+            self.path.delivery_rate.on_lost(size_of_lost_packets);
             trace!(
                 "packets lost: {:?}, bytes lost: {}",
                 lost_packets,
@@ -1485,6 +2003,11 @@ impl Connection {
             );
 
             for packet in &lost_packets {
+                if let Some(ref mut qlog) = self.qlog {
+                    let zero_rtt = pn_space == SpaceId::Data
+                        && self.first_1rtt_packet.map_or(true, |x| *packet < x);
+                    qlog.packet_lost(now, pn_space, zero_rtt, *packet);
+                }
                 let info = self.spaces[pn_space].sent_packets.remove(packet).unwrap(); // safe: lost_packets is populated just above
                 self.remove_in_flight(pn_space, &info);
                 for frame in info.stream_frames {
@@ -1492,6 +2015,7 @@ impl Connection {
                 }
                 self.spaces[pn_space].pending |= info.retransmits;
                 self.path.mtud.on_non_probe_lost(*packet, info.size);
+                self.datagrams_lost(info.datagram_ids);
             }
 
             if self.path.mtud.black_hole_detected(now) {
@@ -1705,6 +2229,7 @@ impl Connection {
         let span = trace_span!("first recv");
         let _guard = span.enter();
         debug_assert!(self.side.is_server());
+        let local_ip = self.local_ip;
         let len = packet.header_data.len() + packet.payload.len();
         self.path.total_recvd = len as u64;
 
@@ -1726,9 +2251,9 @@ impl Connection {
             false,
             false,
         );
-        self.process_decrypted_packet(now, remote, Some(packet_number), packet)?;
+        self.process_decrypted_packet(now, remote, local_ip, Some(packet_number), packet)?;
         if let Some(data) = remaining {
-            self.handle_coalesced(now, remote, ecn, data);
+            self.handle_coalesced(now, remote, local_ip, ecn, data);
         }
         Ok(())
     }
@@ -1903,6 +2428,7 @@ impl Connection {
         &mut self,
         now: Instant,
         remote: SocketAddr,
+        local_ip: Option<IpAddr>,
         ecn: Option<EcnCodepoint>,
         data: BytesMut,
     ) {
@@ -1917,7 +2443,7 @@ impl Connection {
             ) {
                 Ok((partial_decode, rest)) => {
                     remaining = rest;
-                    self.handle_decode(now, remote, ecn, partial_decode);
+                    self.handle_decode(now, remote, local_ip, ecn, partial_decode);
                 }
                 Err(e) => {
                     trace!("malformed header: {}", e);
@@ -1931,6 +2457,7 @@ impl Connection {
         &mut self,
         now: Instant,
         remote: SocketAddr,
+        local_ip: Option<IpAddr>,
         ecn: Option<EcnCodepoint>,
         partial_decode: PartialDecode,
     ) {
@@ -1963,8 +2490,10 @@ impl Connection {
                 == Some(&packet[packet.len() - RESET_TOKEN_SIZE..]);
 
         match partial_decode.finish(header_crypto) {
-            Ok(packet) => self.handle_packet(now, remote, ecn, Some(packet), stateless_reset),
-            Err(_) if stateless_reset => self.handle_packet(now, remote, ecn, None, true),
+            Ok(packet) => {
+                self.handle_packet(now, remote, local_ip, ecn, Some(packet), stateless_reset)
+            }
+            Err(_) if stateless_reset => self.handle_packet(now, remote, local_ip, ecn, None, true),
             Err(e) => {
                 trace!("unable to complete packet decoding: {}", e);
             }
@@ -1975,6 +2504,7 @@ impl Connection {
         &mut self,
         now: Instant,
         remote: SocketAddr,
+        local_ip: Option<IpAddr>,
         ecn: Option<EcnCodepoint>,
         packet: Option<Packet>,
         stateless_reset: bool,
@@ -2061,6 +2591,18 @@ impl Connection {
                         }
                     }
 
+                    if let (Some(qlog), Some(number)) = (self.qlog.as_mut(), number) {
+                        qlog.packet_received(
+                            now,
+                            packet.header.space(),
+                            packet.header.is_short(),
+                            number,
+                            packet.payload.len() + packet.header_data.len(),
+                            Bytes::copy_from_slice(&packet.payload),
+                            self.peer_params.ack_delay_exponent.into_inner(),
+                        );
+                    }
+
                     if !self.state.is_closed() {
                         let spin = match packet.header {
                             Header::Short { spin, .. } => spin,
@@ -2075,7 +2617,7 @@ impl Connection {
                             packet.header.is_1rtt(),
                         );
                     }
-                    self.process_decrypted_packet(now, remote, number, packet)
+                    self.process_decrypted_packet(now, remote, local_ip, number, packet)
                 }
             }
         };
@@ -2128,16 +2670,21 @@ impl Connection {
         &mut self,
         now: Instant,
         remote: SocketAddr,
+        local_ip: Option<IpAddr>,
         number: Option<u64>,
         packet: Packet,
     ) -> Result<(), ConnectionError> {
         let state = match self.state {
             State::Established => {
                 match packet.header.space() {
-                    SpaceId::Data => {
-                        self.process_payload(now, remote, number.unwrap(), packet.payload.freeze())?
-                    }
-                    _ => self.process_early_payload(now, packet)?,
+                    SpaceId::Data => self.process_payload(
+                        now,
+                        remote,
+                        local_ip,
+                        number.unwrap(),
+                        packet.payload.freeze(),
+                    )?,
+                    _ => self.process_early_payload(now, number.unwrap(), packet)?,
                 }
                 return Ok(());
             }
@@ -2196,7 +2743,7 @@ impl Connection {
 
                 let space = &mut self.spaces[SpaceId::Initial];
                 if let Some(info) = space.sent_packets.remove(&0) {
-                    self.on_packet_acked(now, SpaceId::Initial, info);
+                    self.on_packet_acked(now, SpaceId::Initial, 0, info);
                 };
 
                 self.discard_space(now, SpaceId::Initial); // Make sure we clean up after any retransmitted Initials
@@ -2219,6 +2766,7 @@ impl Connection {
                 for (_, info) in zero_rtt {
                     self.remove_in_flight(SpaceId::Data, &info);
                     self.spaces[SpaceId::Data].pending |= info.retransmits;
+                    self.datagrams_lost(info.datagram_ids);
                 }
                 self.streams.retransmit_all_for_0rtt();
 
@@ -2245,7 +2793,7 @@ impl Connection {
                 }
                 self.path.validated = true;
 
-                self.process_early_payload(now, packet)?;
+                self.process_early_payload(now, number.unwrap(), packet)?;
                 if self.state.is_closed() {
                     return Ok(());
                 }
@@ -2281,6 +2829,7 @@ impl Connection {
                                 mem::take(&mut self.spaces[SpaceId::Data].sent_packets);
                             for (_, packet) in sent_packets {
                                 self.remove_in_flight(SpaceId::Data, &packet);
+                                self.datagrams_lost(packet.datagram_ids);
                             }
                         } else {
                             self.accepted_0rtt = true;
@@ -2296,9 +2845,24 @@ impl Connection {
                 } else {
                     // Server-only
                     self.spaces[SpaceId::Data].pending.handshake_done = true;
+                    let tokens = self.server_config.as_ref().unwrap().validation_tokens_sent;
+                    for _ in 0..tokens {
+                        self.spaces[SpaceId::Data]
+                            .pending
+                            .new_tokens
+                            .push(self.path.remote);
+                    }
                     self.discard_space(now, SpaceId::Handshake);
                 }
 
+                if self.config.ack_frequency_config.is_some()
+                    && self
+                        .ack_frequency
+                        .should_send_ack_frequency(&self.peer_params)
+                {
+                    self.spaces[SpaceId::Data].pending.ack_frequency = true;
+                }
+
                 self.events.push_back(Event::Connected);
                 self.state = State::Established;
                 trace!("established");
@@ -2324,7 +2888,7 @@ impl Connection {
                 }
 
                 let starting_space = self.highest_space;
-                self.process_early_payload(now, packet)?;
+                self.process_early_payload(now, number.unwrap(), packet)?;
 
                 if self.side.is_server()
                     && starting_space == SpaceId::Initial
@@ -2348,7 +2912,13 @@ impl Connection {
                 ty: LongType::ZeroRtt,
                 ..
             } => {
-                self.process_payload(now, remote, number.unwrap(), packet.payload.freeze())?;
+                self.process_payload(
+                    now,
+                    remote,
+                    local_ip,
+                    number.unwrap(),
+                    packet.payload.freeze(),
+                )?;
                 Ok(())
             }
             Header::VersionNegotiate { .. } => {
@@ -2358,15 +2928,26 @@ impl Connection {
                 let supported = packet
                     .payload
                     .chunks(4)
-                    .any(|x| match <[u8; 4]>::try_from(x) {
-                        Ok(version) => self.version == u32::from_be_bytes(version),
-                        Err(_) => false,
-                    });
-                if supported {
+                    .filter_map(|x| <[u8; 4]>::try_from(x).ok().map(u32::from_be_bytes))
+                    .collect::<Vec<_>>();
+                if supported.contains(&self.version) {
                     return Ok(());
                 }
-                debug!("remote doesn't support our version");
-                Err(ConnectionError::VersionMismatch)
+                let restart = match self.handshake_restart.take() {
+                    Some(x) => x,
+                    None => {
+                        // Clients act upon at most one Version Negotiation packet
+                        trace!("ignoring subsequent version negotiation");
+                        return Ok(());
+                    }
+                };
+                match self.select_version(&supported) {
+                    Some(version) => self.restart_handshake(now, version, restart),
+                    None => {
+                        debug!("remote doesn't support our version");
+                        Err(ConnectionError::VersionMismatch)
+                    }
+                }
             }
             Header::Short { .. } => unreachable!(
                 "short packets received during handshake are discarded in handle_packet"
@@ -2374,10 +2955,80 @@ impl Connection {
         }
     }
 
+    /// Pick our most preferred version among those supported by the peer
+    fn select_version(&self, peer_versions: &[u32]) -> Option<u32> {
+        self.endpoint_config
+            .supported_versions
+            .iter()
+            .copied()
+            .find(|x| peer_versions.contains(x))
+    }
+
+    /// Start over with a new version after receiving a Version Negotiation packet
+    ///
+    /// Discards everything sent so far, including 0-RTT packets, whose data is retransmitted using
+    /// the new version.
+    fn restart_handshake(
+        &mut self,
+        now: Instant,
+        version: u32,
+        restart: HandshakeRestart,
+    ) -> Result<(), ConnectionError> {
+        debug!(
+            from = format_args!("{:#x}", self.version),
+            to = format_args!("{:#x}", version),
+            "restarting handshake after version negotiation"
+        );
+        let params = TransportParameters {
+            version_information: Some(VersionInformation {
+                chosen_version: version,
+                available_versions: self.endpoint_config.supported_versions.clone(),
+            }),
+            ..restart.params
+        };
+        self.crypto = restart
+            .crypto
+            .start_session(version, &restart.server_name, &params)
+            .map_err(|e| {
+                debug!("failed to restart handshake: {}", e);
+                ConnectionError::VersionMismatch
+            })?;
+        self.original_version = Some(self.version);
+        self.version = version;
+
+        self.discard_space(now, SpaceId::Initial);
+        self.spaces[SpaceId::Initial] = PacketSpace {
+            crypto: Some(self.crypto.initial_keys(&self.initial_dst_cid, self.side)),
+            next_packet_number: self.spaces[SpaceId::Initial].next_packet_number,
+            ..PacketSpace::new(now)
+        };
+
+        // 0-RTT packets of the abandoned attempt can't be accepted, so retransmit all their data
+        self.zero_rtt_enabled = false;
+        self.zero_rtt_crypto = None;
+        let zero_rtt = mem::take(&mut self.spaces[SpaceId::Data].sent_packets);
+        for (_, info) in zero_rtt {
+            self.remove_in_flight(SpaceId::Data, &info);
+            self.spaces[SpaceId::Data].pending |= info.retransmits;
+            self.datagrams_lost(info.datagram_ids);
+        }
+        self.streams.retransmit_all_for_0rtt();
+
+        self.state = State::Handshake(state::Handshake {
+            expected_token: Bytes::new(),
+            rem_cid_set: false,
+            client_hello: None,
+        });
+        self.write_crypto();
+        self.init_0rtt();
+        Ok(())
+    }
+
     /// Process an Initial or Handshake packet payload
     fn process_early_payload(
         &mut self,
         now: Instant,
+        number: u64,
         packet: Packet,
     ) -> Result<(), TransportError> {
         debug_assert_ne!(packet.header.space(), SpaceId::Data);
@@ -2421,9 +3072,10 @@ impl Connection {
                 }
             }
         }
+        // Only the Data space uses delayed acknowledgements, so the returned timer hint is unused
         self.spaces[packet.header.space()]
             .pending_acks
-            .packet_received(ack_eliciting);
+            .packet_received(now, number, ack_eliciting);
 
         self.write_crypto();
         Ok(())
@@ -2433,6 +3085,7 @@ impl Connection {
         &mut self,
         now: Instant,
         remote: SocketAddr,
+        local_ip: Option<IpAddr>,
         number: u64,
         payload: Bytes,
     ) -> Result<(), TransportError> {
@@ -2518,9 +3171,11 @@ impl Connection {
                         self.path_response = Some(PathResponse {
                             packet: number,
                             token,
+                            remote,
+                            local_ip,
                         });
                     }
-                    if remote == self.path.remote {
+                    if remote == self.path.remote && local_ip == self.local_ip {
                         // PATH_CHALLENGE on active path, possible off-path packet forwarding
                         // attack. Send a non-probing packet to recover the active path.
                         self.ping();
@@ -2529,6 +3184,8 @@ impl Connection {
                 Frame::PathResponse(token) => {
                     if self.path.challenge == Some(token) && remote == self.path.remote {
                         trace!("new path validated");
+                        self.events
+                            .push_back(Event::Path(PathEvent::Validated { remote }));
                         self.timers.stop(Timer::PathValidation);
                         self.path.challenge = None;
                         self.path.validated = true;
@@ -2536,6 +3193,31 @@ impl Connection {
                             prev_path.challenge = None;
                             prev_path.challenge_pending = false;
                         }
+                    } else if self
+                        .pending_path
+                        .as_ref()
+                        .map_or(false, |x| x.challenge == Some(token) && x.remote == remote)
+                    {
+                        self.stop_pending_path_timers();
+                        self.events
+                            .push_back(Event::Path(PathEvent::Validated { remote }));
+                        let path = self.pending_path.as_mut().unwrap();
+                        path.challenge = None;
+                        path.challenge_pending = false;
+                        if self.switch_to_pending {
+                            trace!(%remote, "new path validated, switching to it");
+                            let old = self.switch_to_pending_path();
+                            self.remote_address_changed(old.remote);
+                            self.local_ip = self.pending_local_ip;
+                            // Stateless resets are only recognized from the address they're
+                            // registered for
+                            if let Some(reset_token) = self.peer_params.stateless_reset_token {
+                                self.set_reset_token(reset_token);
+                            }
+                        } else {
+                            // Wait for the application to migrate
+                            trace!(%remote, "new path validated");
+                        }
                     } else {
                         debug!(token, "ignoring invalid PATH_RESPONSE");
                     }
@@ -2554,6 +3236,11 @@ impl Connection {
                         self.spaces[SpaceId::Data].pending.max_data = true;
                     }
                 }
+                Frame::ResetStreamAt(frame) => {
+                    if self.streams.received_reset_at(frame)?.should_transmit() {
+                        self.spaces[SpaceId::Data].pending.max_data = true;
+                    }
+                }
                 Frame::DataBlocked { offset } => {
                     debug!(offset, "peer claims to be blocked at connection level");
                 }
@@ -2632,6 +3319,7 @@ impl Connection {
                                 .retire_cids
                                 .extend(retired);
                             self.set_reset_token(reset_token);
+                            self.check_pending_rem_cid();
                         }
                         Err(InsertError::ExceedsLimit) => {
                             return Err(TransportError::CONNECTION_ID_LIMIT_ERROR(""));
@@ -2663,7 +3351,9 @@ impl Connection {
                         return Err(TransportError::FRAME_ENCODING_ERROR("empty token"));
                     }
                     trace!("got new token");
-                    // TODO: Cache, or perhaps forward to user?
+                    if let Some((ref store, ref server_name)) = self.token_store {
+                        store.insert(server_name, token);
+                    }
                 }
                 Frame::Datagram(datagram) => {
                     if self
@@ -2673,6 +3363,29 @@ impl Connection {
                         self.events.push_back(Event::DatagramReceived);
                     }
                 }
+                Frame::AckFrequency(ack_frequency) => {
+                    // This frame can only be sent in the Data space
+                    if !self.ack_frequency.ack_frequency_received(&ack_frequency)? {
+                        // The AckFrequency frame is stale (we have already received a more recent
+                        // one)
+                        continue;
+                    }
+
+                    // Our `max_ack_delay` has been updated, so we may need to adjust its associated
+                    // timeout
+                    let pending_acks = &mut self.spaces[SpaceId::Data].pending_acks;
+                    pending_acks.set_ack_frequency_params(&ack_frequency);
+                    match pending_acks.max_ack_delay_timeout(self.ack_frequency.max_ack_delay) {
+                        Some(timeout) => self.timers.set(Timer::MaxAckDelay, timeout),
+                        None => self.timers.stop(Timer::MaxAckDelay),
+                    }
+                }
+                Frame::ImmediateAck => {
+                    // This frame can only be sent in the Data space
+                    self.spaces[SpaceId::Data]
+                        .pending_acks
+                        .set_immediate_ack_required();
+                }
                 Frame::HandshakeDone => {
                     if self.side.is_server() {
                         return Err(TransportError::PROTOCOL_VIOLATION(
@@ -2681,14 +3394,20 @@ impl Connection {
                     }
                     if self.spaces[SpaceId::Handshake].crypto.is_some() {
                         self.discard_space(now, SpaceId::Handshake);
+                        // The handshake is now confirmed
+                        self.validate_preferred_address(now);
                     }
                 }
             }
         }
 
-        self.spaces[SpaceId::Data]
+        if self.spaces[SpaceId::Data]
             .pending_acks
-            .packet_received(ack_eliciting);
+            .packet_received(now, number, ack_eliciting)
+        {
+            self.timers
+                .set(Timer::MaxAckDelay, now + self.ack_frequency.max_ack_delay);
+        }
 
         // Issue stream ID credit due to ACKs of outgoing finish/resets and incoming finish/resets
         // on stopped streams. Incoming finishes/resets on open streams are not handled here as they
@@ -2707,9 +3426,12 @@ impl Connection {
             self.close = true;
         }
 
+        // Clients never follow the server to a new address: they only receive packets from other
+        // addresses while validating a path themselves, and switch to it once that succeeds
         if remote != self.path.remote
             && !is_probing_packet
             && number == self.spaces[SpaceId::Data].rx_packet
+            && self.side.is_server()
         {
             debug_assert!(
                 self.server_config
@@ -2718,16 +3440,26 @@ impl Connection {
                     .migration,
                 "migration-initiating packets should have been dropped immediately"
             );
-            self.migrate(now, remote);
+            self.on_peer_migrated(now, remote);
             // Break linkability, if possible
             self.update_rem_cid();
             self.spin = false;
         }
 
+        if local_ip != self.local_ip
+            && !is_probing_packet
+            && number == self.spaces[SpaceId::Data].rx_packet
+            && self.side.is_server()
+        {
+            // The client switched to another one of our addresses, e.g. our preferred address
+            trace!(?local_ip, "local address changed");
+            self.local_ip = local_ip;
+        }
+
         Ok(())
     }
 
-    fn migrate(&mut self, now: Instant, remote: SocketAddr) {
+    fn on_peer_migrated(&mut self, now: Instant, remote: SocketAddr) {
         trace!(%remote, "migration initiated");
         // Reset rtt/congestion state for new path unless it looks like a NAT rebinding.
         // Note that the congestion window will not grow until validation terminates. Helps mitigate
@@ -2735,28 +3467,14 @@ impl Connection {
         let mut new_path = if remote.is_ipv4() && remote.ip() == self.path.remote.ip() {
             PathData::from_previous(remote, &self.path, now)
         } else {
-            let peer_max_udp_payload_size =
-                u16::try_from(self.peer_params.max_udp_payload_size.into_inner())
-                    .unwrap_or(u16::MAX);
-            PathData::new(
-                remote,
-                self.config.initial_rtt,
-                self.config
-                    .congestion_controller_factory
-                    .build(now, self.config.get_initial_mtu()),
-                self.config.get_initial_mtu(),
-                self.config.min_mtu,
-                Some(peer_max_udp_payload_size),
-                self.config.mtu_discovery_config.clone(),
-                now,
-                false,
-            )
+            self.new_path(now, remote, false)
         };
         new_path.challenge = Some(self.rng.gen());
         new_path.challenge_pending = true;
         let prev_pto = self.pto(SpaceId::Data);
 
         let mut prev = mem::replace(&mut self.path, new_path);
+        self.remote_address_changed(prev.remote);
         // Don't clobber the original path if the previous one hasn't been validated yet
         if prev.challenge.is_none() {
             prev.challenge = Some(self.rng.gen());
@@ -2770,6 +3488,101 @@ impl Connection {
         );
     }
 
+    /// Start validating the server's preferred address, if any, to switch to it if that succeeds
+    fn validate_preferred_address(&mut self, now: Instant) {
+        let address = match self.peer_params.preferred_address {
+            Some(ref x) => x,
+            None => return,
+        };
+        let remote = match self.path.remote {
+            SocketAddr::V4(_) => address.address_v4.map(SocketAddr::V4),
+            SocketAddr::V6(current) => match current.ip().to_ipv4_mapped() {
+                // IPv4 server reached through a dual-stack socket
+                Some(_) => address.address_v4.map(|x| {
+                    SocketAddr::V6(SocketAddrV6::new(x.ip().to_ipv6_mapped(), x.port(), 0, 0))
+                }),
+                None => address.address_v6.map(SocketAddr::V6),
+            },
+        };
+        let remote = match remote {
+            Some(x) if x != self.path.remote => x,
+            _ => {
+                trace!("no preferred address to switch to");
+                return;
+            }
+        };
+        if self.local_cid_state.cid_len() == 0 {
+            // The endpoint could only route datagrams from the new address to us by CID
+            debug!("not using preferred address without local connection IDs");
+            return;
+        }
+
+        // Use the CID supplied alongside the preferred address, leaving the active path's as is
+        let seq = match self.rem_cids.next_seq() {
+            Some(x) => x,
+            None => {
+                debug!("not using preferred address without a spare remote connection ID");
+                return;
+            }
+        };
+        self.clear_pending_path();
+        self.pending_rem_cid_seq = Some(seq);
+        trace!(%remote, "validating preferred address");
+        self.start_pending_path(now, remote);
+        self.pending_local_ip = self.local_ip;
+        self.switch_to_pending = true;
+    }
+
+    /// Let the application know if the active path's remote address differs from `old`
+    fn remote_address_changed(&mut self, old: SocketAddr) {
+        let new = self.path.remote;
+        if old != new {
+            self.events
+                .push_back(Event::Path(PathEvent::RemoteAddressChanged { old, new }));
+        }
+    }
+
+    /// Fresh state for a path to `remote`, sharing nothing with the current path
+    fn new_path(&self, now: Instant, remote: SocketAddr, validated: bool) -> PathData {
+        let peer_max_udp_payload_size =
+            u16::try_from(self.peer_params.max_udp_payload_size.into_inner()).unwrap_or(u16::MAX);
+        PathData::new(
+            remote,
+            self.config.initial_rtt,
+            self.config
+                .congestion_controller_factory
+                .build(now, self.config.get_initial_mtu()),
+            self.config.get_initial_mtu(),
+            self.config.min_mtu,
+            Some(peer_max_udp_payload_size),
+            self.config.mtu_discovery_config.clone(),
+            now,
+            validated,
+        )
+    }
+
+    /// Reserve another remote CID for `pending_path` if the peer retired the one it was using
+    fn check_pending_rem_cid(&mut self) {
+        let seq = match self.pending_rem_cid_seq {
+            Some(x) => x,
+            None => return,
+        };
+        if self.rem_cids.get(seq).is_some() && seq != self.rem_cids.active_seq() {
+            return;
+        }
+        self.pending_rem_cid_seq = self.rem_cids.next_seq();
+        if self.pending_rem_cid_seq.is_none() {
+            debug!("abandoning path validation without a spare remote connection ID");
+            if let Some(path) = self.pending_path.take() {
+                self.events
+                    .push_back(Event::Path(PathEvent::ValidationFailed {
+                        remote: path.remote,
+                    }));
+            }
+            self.stop_pending_path_timers();
+        }
+    }
+
     /// Switch to a previously unused remote connection ID, if possible
     fn update_rem_cid(&mut self) {
         let (reset_token, retired) = match self.rem_cids.next() {
@@ -2800,17 +3613,22 @@ impl Connection {
             return;
         }
 
-        // Subtract 1 to account for the CID we supplied while handshaking
-        let n = self.peer_params.issue_cids_limit() - 1;
+        // Account for the CIDs we supplied while handshaking
+        let n = self
+            .peer_params
+            .issue_cids_limit()
+            .saturating_sub(self.local_cid_state.issued());
         self.endpoint_events
             .push_back(EndpointEventInner::NeedIdentifiers(now, n));
     }
 
     fn populate_packet(
         &mut self,
+        now: Instant,
         space_id: SpaceId,
         buf: &mut BytesMut,
         max_size: usize,
+        pn: u64,
     ) -> SentFrames {
         let mut sent = SentFrames::default();
         let space = &mut self.spaces[space_id];
@@ -2833,10 +3651,53 @@ impl Connection {
             self.stats.frame_tx.ping += 1;
         }
 
+        // IMMEDIATE_ACK
+        if mem::replace(&mut space.immediate_ack_pending, false) {
+            trace!("IMMEDIATE_ACK");
+            buf.write(frame::Type::IMMEDIATE_ACK);
+            sent.non_retransmits = true;
+            self.stats.frame_tx.immediate_ack += 1;
+        }
+
         // ACK
-        if space.pending_acks.can_send() {
+        // Delayed ACKs are bundled into any packet we are sending anyway
+        if space.pending_acks.can_send() || (!is_0rtt && space.pending_acks.can_piggyback()) {
             debug_assert!(!space.pending_acks.ranges().is_empty());
-            Self::populate_acks(self.receiving_ecn, &mut sent, space, buf, &mut self.stats);
+            Self::populate_acks(
+                now,
+                self.receiving_ecn,
+                &mut sent,
+                space,
+                buf,
+                &mut self.stats,
+            );
+        }
+
+        // ACK_FREQUENCY
+        if space_id == SpaceId::Data
+            && !is_0rtt
+            && buf.len() + frame::AckFrequency::SIZE_BOUND < max_size
+            && mem::replace(&mut space.pending.ack_frequency, false)
+        {
+            if let Some(config) = &self.config.ack_frequency_config {
+                let max_ack_delay = self.ack_frequency.candidate_max_ack_delay(
+                    self.path.rtt.get(),
+                    config,
+                    &self.peer_params,
+                );
+                let frame = frame::AckFrequency {
+                    sequence: self.ack_frequency.next_sequence_number(),
+                    ack_eliciting_threshold: config.ack_eliciting_threshold,
+                    request_max_ack_delay: VarInt::from_u64(max_ack_delay.as_micros() as u64)
+                        .unwrap_or(VarInt::MAX),
+                    reordering_threshold: config.reordering_threshold,
+                };
+                trace!(?frame, "ACK_FREQUENCY");
+                frame.encode(buf);
+                sent.retransmits.get_or_create().ack_frequency = true;
+                self.ack_frequency.ack_frequency_sent(pn, max_ack_delay);
+                self.stats.frame_tx.ack_frequency += 1;
+            }
         }
 
         // PATH_CHALLENGE
@@ -2915,6 +3776,8 @@ impl Connection {
                 &mut sent.retransmits,
                 &mut self.stats.frame_tx,
                 max_size,
+                now,
+                self.path.rtt.get(),
             );
         }
 
@@ -2953,21 +3816,72 @@ impl Connection {
             self.stats.frame_tx.retire_connection_id += 1;
         }
 
-        // DATAGRAM
-        while buf.len() + Datagram::SIZE_BOUND < max_size && space_id == SpaceId::Data {
-            match self.datagrams.write(buf, max_size) {
-                true => {
-                    sent.non_retransmits = true;
-                    self.stats.frame_tx.datagram += 1;
-                }
-                false => break,
+        // NEW_TOKEN
+        while buf.len() < max_size && space_id == SpaceId::Data {
+            let remote = match self.spaces[space_id].pending.new_tokens.pop() {
+                Some(x) => x,
+                None => break,
+            };
+            if remote != self.path.remote {
+                // The token would be bound to an address the client no longer uses
+                continue;
             }
+            let server_config = self
+                .server_config
+                .as_ref()
+                .expect("NEW_TOKEN queued by client");
+            let mut random_bytes = [0; RetryToken::RANDOM_BYTES_LEN];
+            self.rng.fill_bytes(&mut random_bytes);
+            let token = ValidationToken {
+                issued: SystemTime::now(),
+                random_bytes: &random_bytes,
+            }
+            .encode(&*server_config.token_key, &remote.ip());
+            if buf.len() + 1 + VarInt::from_u32(token.len() as u32).size() + token.len() >= max_size
+            {
+                self.spaces[space_id].pending.new_tokens.push(remote);
+                break;
+            }
+            trace!("NEW_TOKEN");
+            buf.write(frame::Type::NEW_TOKEN);
+            buf.write_var(token.len() as u64);
+            buf.put_slice(&token);
+            sent.retransmits.get_or_create().new_tokens.push(remote);
+            self.stats.frame_tx.new_token += 1;
         }
 
-        // STREAM
+        // DATAGRAM and STREAM, most urgent first. Datagrams go ahead of stream data of equal
+        // priority.
         if space_id == SpaceId::Data {
-            sent.stream_frames = self.streams.write_stream_frames(buf, max_size);
-            self.stats.frame_tx.stream += sent.stream_frames.len() as u64;
+            let buffered = self.datagrams.outgoing_total;
+            let mut datagram_fits = true;
+            loop {
+                let priority = match datagram_fits {
+                    true => self.datagrams.next_priority(now, &mut self.stats),
+                    false => None,
+                };
+                let frames = self.streams.write_stream_frames(buf, max_size, priority);
+                self.stats.frame_tx.stream += frames.len() as u64;
+                sent.stream_frames.extend(frames);
+                if priority.is_none() {
+                    break;
+                }
+                if buf.len() + Datagram::SIZE_BOUND >= max_size
+                    || !self.datagrams.write(buf, max_size, &mut sent.datagram_ids)
+                {
+                    // Fill the rest of the packet with stream data
+                    datagram_fits = false;
+                    continue;
+                }
+                sent.non_retransmits = true;
+                self.stats.frame_tx.datagram += 1;
+            }
+            if self.datagrams.outgoing_total < buffered
+                && mem::take(&mut self.datagrams.send_blocked)
+            {
+                self.events.push_back(Event::DatagramsUnblocked);
+            }
+            self.set_datagram_deadline_timer();
         }
 
         sent
@@ -2978,6 +3892,7 @@ impl Connection {
     /// This method assumes ACKs are pending, and should only be called if
     /// `!PendingAcks::ranges().is_empty()` returns `true`.
     fn populate_acks(
+        now: Instant,
         receiving_ecn: bool,
         sent: &mut SentFrames,
         space: &mut PacketSpace,
@@ -2995,11 +3910,9 @@ impl Connection {
         };
         sent.largest_acked = space.pending_acks.ranges().max();
 
-        let delay_micros = space.pending_acks.ack_delay().as_micros() as u64;
+        let delay_micros = space.pending_acks.ack_delay(now).as_micros() as u64;
 
-        // TODO: This should come from `TransportConfig` if that gets configurable.
-        let ack_delay_exp = TransportParameters::default().ack_delay_exponent;
-        let delay = delay_micros >> ack_delay_exp.into_inner();
+        let delay = delay_micros >> ACK_DELAY_EXPONENT;
 
         trace!("ACK {:?}, Delay = {}us", space.pending_acks.ranges(), delay);
 
@@ -3007,6 +3920,13 @@ impl Connection {
         stats.frame_tx.acks += 1;
     }
 
+    fn set_datagram_deadline_timer(&mut self) {
+        match self.datagrams.next_deadline() {
+            Some(deadline) => self.timers.set(Timer::DatagramDeadline, deadline),
+            None => self.timers.stop(Timer::DatagramDeadline),
+        }
+    }
+
     fn close_common(&mut self) {
         trace!("connection closed");
         for &timer in &Timer::VALUES {
@@ -3030,11 +3950,46 @@ impl Connection {
                 "CID authentication failure",
             ));
         }
+        self.validate_version_information(&params)?;
 
         self.set_peer_params(params);
         Ok(())
     }
 
+    /// Guard against version downgrade attacks, as described in RFC 9368
+    fn validate_version_information(
+        &self,
+        params: &TransportParameters,
+    ) -> Result<(), TransportError> {
+        let info = match params.version_information {
+            Some(ref info) => info,
+            // Version negotiation can only be authenticated if the server supports it
+            None if self.original_version.is_some() => {
+                return Err(TransportError::VERSION_NEGOTIATION_ERROR(
+                    "missing version information after version negotiation",
+                ));
+            }
+            None => return Ok(()),
+        };
+        if info.chosen_version != self.version {
+            return Err(TransportError::VERSION_NEGOTIATION_ERROR(
+                "chosen version mismatch",
+            ));
+        }
+        if let Some(original_version) = self.original_version {
+            // Had the Version Negotiation packet been genuine, we would have picked the same
+            // version from the server's authenticated list of versions
+            if info.available_versions.contains(&original_version)
+                || self.select_version(&info.available_versions) != Some(self.version)
+            {
+                return Err(TransportError::VERSION_NEGOTIATION_ERROR(
+                    "version downgrade detected",
+                ));
+            }
+        }
+        Ok(())
+    }
+
     fn set_peer_params(&mut self, params: TransportParameters) {
         self.streams.set_params(&params);
         self.idle_timeout = match (self.config.max_idle_timeout, params.max_idle_timeout) {
@@ -3051,6 +4006,8 @@ impl Connection {
                 retire_prior_to: 0,
             }).expect("preferred address CID is the first received, and hence is guaranteed to be legal");
         }
+        self.ack_frequency.peer_max_ack_delay =
+            Duration::from_millis(params.max_ack_delay.into_inner());
         self.peer_params = params;
         self.path.mtud.on_peer_max_udp_payload_size_received(
             u16::try_from(self.peer_params.max_udp_payload_size.into_inner()).unwrap_or(u16::MAX),
@@ -3129,14 +4086,14 @@ impl Connection {
                 return Err(Some(TransportError::KEY_UPDATE_ERROR("")));
             }
             trace!("key update authenticated");
-            self.update_keys(Some((number, now)), true);
+            self.update_keys(now, Some((number, now)), true);
             self.set_key_discard_timer(now, space);
         }
 
         Ok(Some(number))
     }
 
-    fn update_keys(&mut self, end_packet: Option<(u64, Instant)>, remote: bool) {
+    fn update_keys(&mut self, now: Instant, end_packet: Option<(u64, Instant)>, remote: bool) {
         // Generate keys for the key phase after the one we're switching to, store them in
         // `next_crypto`, make the contents of `next_crypto` current, and move the current keys into
         // `prev_crypto`.
@@ -3159,6 +4116,33 @@ impl Connection {
             update_unacked: remote,
         });
         self.key_phase = !self.key_phase;
+        if let Some(ref mut qlog) = self.qlog {
+            qlog.key_updated(now, remote);
+        }
+
+        let previous_duration = self
+            .key_phase_start
+            .map_or(Duration::ZERO, |x| now.saturating_duration_since(x));
+        self.key_generation += 1;
+        self.key_phase_start = Some(now);
+        self.key_phase_bytes = 0;
+        self.key_update_requested = false;
+        let initiator = match remote {
+            true => {
+                self.stats.peer_key_updates += 1;
+                !self.side
+            }
+            false => {
+                self.stats.local_key_updates += 1;
+                self.side
+            }
+        };
+        trace!(generation = self.key_generation, ?initiator, "keys updated");
+        self.events.push_back(Event::KeysUpdated {
+            key_phase: self.key_generation,
+            initiator,
+            previous_duration,
+        });
     }
 
     /// The number of bytes of packets containing retransmittable frames that have not been
@@ -3232,8 +4216,84 @@ impl Connection {
         self.path.current_mtu()
     }
 
+    /// Log recovery metrics to qlog if they changed
+    fn qlog_metrics(&mut self, now: Instant) {
+        if let Some(ref mut qlog) = self.qlog {
+            qlog.metrics_updated(
+                now,
+                &self.path.rtt,
+                self.path.congestion.window(),
+                self.in_flight.bytes,
+            );
+        }
+    }
+
+    /// Log a connection state transition to qlog, if one occurred
+    fn qlog_state(&mut self, now: Instant) {
+        if let Some(ref mut qlog) = self.qlog {
+            qlog.state_updated(now, self.state.qlog_name());
+        }
+    }
+
+    /// The peer's `max_ack_delay`, accounting for any ACK_FREQUENCY frame we sent it
     fn max_ack_delay(&self) -> Duration {
-        Duration::from_micros(self.peer_params.max_ack_delay.0 * 1000)
+        self.ack_frequency.max_ack_delay_for_pto()
+    }
+
+    /// Build a datagram carrying only a PATH_CHALLENGE or PATH_RESPONSE frame for a path other
+    /// than the active one
+    fn send_path_frame(
+        &mut self,
+        now: Instant,
+        ty: frame::Type,
+        token: u64,
+        destination: SocketAddr,
+        src_ip: Option<IpAddr>,
+        dst_cid: ConnectionId,
+    ) -> Option<Transmit> {
+        debug_assert_eq!(
+            self.highest_space,
+            SpaceId::Data,
+            "path validation frame queued without 1-RTT keys"
+        );
+        let mut buf = BytesMut::with_capacity(self.path.current_mtu() as usize);
+        let buf_capacity = self.path.current_mtu() as usize;
+
+        let mut builder = PacketBuilder::new(
+            now,
+            SpaceId::Data,
+            dst_cid,
+            &mut buf,
+            buf_capacity,
+            0,
+            false,
+            self,
+            self.version,
+        )?;
+        buf.write(ty);
+        buf.write(token);
+        if ty == frame::Type::PATH_CHALLENGE {
+            self.stats.frame_tx.path_challenge += 1;
+        } else {
+            self.stats.frame_tx.path_response += 1;
+        }
+
+        // An endpoint MUST expand datagrams that contain a PATH_CHALLENGE or PATH_RESPONSE frame
+        // to at least the smallest allowed maximum datagram size of 1200 bytes, unless the
+        // anti-amplification limit for the path does not permit sending a datagram of this size
+        builder.pad_to(MIN_INITIAL_SIZE);
+
+        builder.finish(now, self, &mut buf);
+        self.stats.udp_tx.datagrams += 1;
+        self.stats.udp_tx.transmits += 1;
+        self.stats.udp_tx.bytes += buf.len() as u64;
+        Some(Transmit {
+            destination,
+            contents: buf.freeze(),
+            ecn: None,
+            segment_size: None,
+            src_ip,
+        })
     }
 
     /// Whether we have 1-RTT data to send
@@ -3246,10 +4306,21 @@ impl Connection {
                 .prev_path
                 .as_ref()
                 .map_or(false, |x| x.challenge_pending)
+            || self
+                .pending_path
+                .as_ref()
+                .map_or(false, |x| x.challenge_pending)
             || self.path_response.is_some()
             || !self.datagrams.outgoing.is_empty()
     }
 
+    /// Let the application know that tracked datagrams won't be delivered
+    fn datagrams_lost(&mut self, ids: DatagramIds) {
+        for id in ids {
+            self.events.push_back(Event::DatagramLost(id));
+        }
+    }
+
     /// Update counters to account for a packet becoming acknowledged, lost, or abandoned
     fn remove_in_flight(&mut self, space: SpaceId, packet: &SentPacket) {
         self.in_flight.bytes -= u64::from(packet.size);
@@ -3327,6 +4398,26 @@ impl From<ConnectionError> for io::Error {
     }
 }
 
+/// Reasons why a connection couldn't be moved to a new path
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum MigrateError {
+    /// Only clients may initiate migration
+    #[error("only clients may initiate migration")]
+    NotClient,
+    /// The handshake hasn't been confirmed yet
+    #[error("handshake not yet confirmed")]
+    HandshakeNotConfirmed,
+    /// The connection is closed
+    #[error("connection closed")]
+    Closed,
+    /// The peer doesn't support active migration
+    #[error("peer disabled active migration")]
+    Disabled,
+    /// The peer hasn't supplied a connection ID that hasn't been used on another path yet
+    #[error("no unused connection ID available")]
+    NoConnectionId,
+}
+
 #[allow(unreachable_pub)] // fuzzing only
 #[derive(Clone)]
 pub enum State {
@@ -3360,6 +4451,17 @@ impl State {
     fn is_drained(&self) -> bool {
         matches!(*self, Self::Drained)
     }
+
+    /// Name of the closest corresponding qlog connection state
+    fn qlog_name(&self) -> &'static str {
+        match *self {
+            Self::Handshake(_) => "handshake_started",
+            Self::Established => "handshake_complete",
+            Self::Closed(_) => "closing",
+            Self::Draining => "draining",
+            Self::Drained => "closed",
+        }
+    }
 }
 
 mod state {
@@ -3450,12 +4552,50 @@ pub enum Event {
     Stream(StreamEvent),
     /// One or more application datagrams have been received
     DatagramReceived,
+    /// The packet carrying the datagram sent with this ID was acknowledged by the peer
+    ///
+    /// See [`Datagrams::send_with_id`].
+    DatagramAcked(u64),
+    /// The datagram sent with this ID was lost, and will not be retransmitted
+    ///
+    /// See [`Datagrams::send_with_id`].
+    DatagramLost(u64),
+    /// Space became available in the outgoing datagram buffer after a datagram was refused
+    ///
+    /// See [`DatagramOverflowPolicy::Reject`](crate::DatagramOverflowPolicy::Reject).
+    DatagramsUnblocked,
+    /// Network path events
+    Path(PathEvent),
+    /// The keys protecting 1-RTT packets were updated
+    ///
+    /// See [`TransportConfig::key_update_config`](crate::TransportConfig::key_update_config).
+    KeysUpdated {
+        /// Number of key updates so far, the first 1-RTT keys being key phase 0
+        ///
+        /// The key phase bit in packet headers is the least significant bit of this value.
+        key_phase: u64,
+        /// The side which initiated the key update
+        initiator: Side,
+        /// How long the previous keys were in use for
+        previous_duration: Duration,
+    },
+}
+
+/// What a client needs to restart its handshake using a different version
+pub(crate) struct HandshakeRestart {
+    pub(crate) crypto: Arc<dyn crypto::ClientConfig>,
+    pub(crate) server_name: String,
+    pub(crate) params: TransportParameters,
 }
 
 struct PathResponse {
     /// The packet number the corresponding PATH_CHALLENGE was received in
     packet: u64,
     token: u64,
+    /// The address the corresponding PATH_CHALLENGE was received from
+    remote: SocketAddr,
+    /// The local address the corresponding PATH_CHALLENGE was received on
+    local_ip: Option<IpAddr>,
 }
 
 fn instant_saturating_sub(x: Instant, y: Instant) -> Duration {
@@ -3487,6 +4627,7 @@ struct SentFrames {
     retransmits: ThinRetransmits,
     largest_acked: Option<u64>,
     stream_frames: StreamMetaVec,
+    datagram_ids: DatagramIds,
     /// Whether the packet contains non-retransmittable frames (like datagrams)
     non_retransmits: bool,
     requires_padding: bool,
@@ -9,13 +9,11 @@ use tracing::warn;
 /// The pacer's capacity is derived on a fraction of the congestion window
 /// which can be sent in regular intervals
 /// Once the bucket is empty, further transmission is blocked.
-/// The bucket refills at a rate slightly faster
-/// than one congestion window per RTT, as recommended in
-/// <https://tools.ietf.org/html/draft-ietf-quic-recovery-34#section-7.7>
+/// Unless the congestion controller provides a pacing rate, the bucket
+/// refills at a rate slightly faster than one congestion window per RTT, as
+/// recommended in <https://tools.ietf.org/html/draft-ietf-quic-recovery-34#section-7.7>
 pub(super) struct Pacer {
     capacity: u64,
-    last_window: u64,
-    last_mtu: u16,
     tokens: u64,
     prev: Instant,
 }
@@ -23,11 +21,9 @@ pub(super) struct Pacer {
 impl Pacer {
     /// Obtains a new [`Pacer`].
     pub(super) fn new(smoothed_rtt: Duration, window: u64, mtu: u16, now: Instant) -> Self {
-        let capacity = optimal_capacity(smoothed_rtt, window, mtu);
+        let capacity = optimal_capacity(Rate::from_window(window, smoothed_rtt), mtu);
         Self {
             capacity,
-            last_window: window,
-            last_mtu: mtu,
             tokens: capacity,
             prev: now,
         }
@@ -43,14 +39,19 @@ impl Pacer {
     /// If we can send a packet right away, this returns `None`. Otherwise, returns `Some(d)`,
     /// where `d` is the time before this function should be called again.
     ///
-    /// The 5/4 ratio used here comes from the suggestion that N = 1.25 in the draft IETF RFC for
-    /// QUIC.
+    /// `pacing_rate` and `send_quantum` are the rate in bytes per second and the burst size
+    /// requested by the congestion controller, if any. Otherwise, the rate is derived from the
+    /// congestion window: the 5/4 ratio used then comes from the suggestion that N = 1.25 in the
+    /// draft IETF RFC for QUIC. Either way, the rate never exceeds `max_rate`.
     pub(super) fn delay(
         &mut self,
         smoothed_rtt: Duration,
         bytes_to_send: u64,
         mtu: u16,
         window: u64,
+        pacing_rate: Option<u64>,
+        send_quantum: Option<u64>,
+        max_rate: Option<u64>,
         now: Instant,
     ) -> Option<Instant> {
         debug_assert_ne!(
@@ -58,13 +59,23 @@ impl Pacer {
             "zero-sized congestion control window is nonsense"
         );
 
-        if window != self.last_window || mtu != self.last_mtu {
-            self.capacity = optimal_capacity(smoothed_rtt, window, mtu);
+        let (rate, capacity) = match pacing_rate {
+            Some(rate) => (Rate::per_second(rate), send_quantum),
+            None => (Rate::from_window(window, smoothed_rtt), None),
+        };
+        let (rate, capacity) = match max_rate {
+            Some(max) if rate.bytes_per_second() > u128::from(max) => (Rate::per_second(max), None),
+            _ => (rate, capacity),
+        };
+        // Bursts must fit at least one datagram, with room for a partially written one
+        let capacity = capacity
+            .unwrap_or_else(|| optimal_capacity(rate, mtu))
+            .max(2 * mtu as u64);
+        if capacity != self.capacity {
+            self.capacity = capacity;
 
             // Clamp the tokens
             self.tokens = self.capacity.min(self.tokens);
-            self.last_window = window;
-            self.last_mtu = mtu;
         }
 
         // if we can already send a packet, there is no need for delay
@@ -72,24 +83,17 @@ impl Pacer {
             return None;
         }
 
-        // we disable pacing for extremely large windows
-        if window > u32::max_value().into() {
-            return None;
-        }
-
-        let window = window as u32;
-
         let time_elapsed = now.checked_duration_since(self.prev).unwrap_or_else(|| {
             warn!("received a timestamp early than a previous recorded time, ignoring");
             Default::default()
         });
 
-        if smoothed_rtt.as_nanos() == 0 {
+        if rate.bytes == 0 || rate.interval.as_nanos() == 0 {
             return None;
         }
 
-        let elapsed_rtts = time_elapsed.as_secs_f64() / smoothed_rtt.as_secs_f64();
-        let new_tokens = window as f64 * 1.25 * elapsed_rtts;
+        let new_tokens =
+            rate.bytes as f64 * time_elapsed.as_secs_f64() / rate.interval.as_secs_f64();
         self.tokens = self
             .tokens
             .saturating_add(new_tokens as _)
@@ -102,18 +106,42 @@ impl Pacer {
             return None;
         }
 
-        let unscaled_delay = smoothed_rtt
-            .checked_mul((bytes_to_send.max(self.capacity) - self.tokens) as _)
-            .unwrap_or_else(|| Duration::new(u64::max_value(), 999_999_999))
-            / window;
+        // this is the time at which the pacing window becomes full again
+        let deficit = bytes_to_send.max(self.capacity) - self.tokens;
+        let delay = rate.interval.as_nanos() * u128::from(deficit) / u128::from(rate.bytes);
+        Some(self.prev + Duration::from_nanos(delay.try_into().unwrap_or(u64::MAX)))
+    }
+}
+
+/// Rate at which the pacer's tokens are replenished: `bytes` per `interval`
+#[derive(Debug, Copy, Clone)]
+struct Rate {
+    bytes: u64,
+    interval: Duration,
+}
+
+impl Rate {
+    /// Slightly more than one congestion window per RTT
+    fn from_window(window: u64, smoothed_rtt: Duration) -> Self {
+        Self {
+            bytes: window,
+            interval: smoothed_rtt * 4 / 5,
+        }
+    }
+
+    fn per_second(bytes: u64) -> Self {
+        Self {
+            bytes,
+            interval: Duration::from_secs(1),
+        }
+    }
 
-        // divisions come before multiplications to prevent overflow
-        // this is the time at which the pacing window becomes empty
-        Some(self.prev + (unscaled_delay / 5) * 4)
+    fn bytes_per_second(&self) -> u128 {
+        u128::from(self.bytes) * 1_000_000_000 / self.interval.as_nanos().max(1)
     }
 }
 
-/// Calculates a pacer capacity for a certain window and RTT
+/// Calculates a pacer capacity for a certain rate
 ///
 /// The goal is to emit a burst (of size `capacity`) in timer intervals
 /// which compromise between
@@ -126,10 +154,11 @@ impl Pacer {
 /// tokens for the extra-elapsed time can be stored.
 ///
 /// Too long burst intervals make pacing less effective.
-fn optimal_capacity(smoothed_rtt: Duration, window: u64, mtu: u16) -> u64 {
-    let rtt = smoothed_rtt.as_nanos().max(1);
+fn optimal_capacity(rate: Rate, mtu: u16) -> u64 {
+    let interval = rate.interval.as_nanos().max(1);
 
-    let capacity = ((window as u128 * BURST_INTERVAL_NANOS) / rtt) as u64;
+    // The capacity is refilled in 4/5 of the burst interval
+    let capacity = ((rate.bytes as u128 * BURST_INTERVAL_NANOS * 4 / 5) / interval) as u64;
 
     // Small bursts are less efficient (no GSO), could increase latency and don't effectively
     // use the channel's buffer capacity. Large bursts might block the connection on sending.
@@ -161,13 +190,40 @@ mod tests {
         let rtt = Duration::from_micros(400);
 
         assert!(Pacer::new(rtt, 30000, 1500, new_instant)
-            .delay(Duration::from_micros(0), 0, 1500, 1, old_instant)
+            .delay(
+                Duration::from_micros(0),
+                0,
+                1500,
+                1,
+                None,
+                None,
+                None,
+                old_instant
+            )
             .is_none());
         assert!(Pacer::new(rtt, 30000, 1500, new_instant)
-            .delay(Duration::from_micros(0), 1600, 1500, 1, old_instant)
+            .delay(
+                Duration::from_micros(0),
+                1600,
+                1500,
+                1,
+                None,
+                None,
+                None,
+                old_instant
+            )
             .is_none());
         assert!(Pacer::new(rtt, 30000, 1500, new_instant)
-            .delay(Duration::from_micros(0), 1500, 1500, 3000, old_instant)
+            .delay(
+                Duration::from_micros(0),
+                1500,
+                1500,
+                3000,
+                None,
+                None,
+                None,
+                old_instant
+            )
             .is_none());
     }
 
@@ -209,27 +265,27 @@ mod tests {
         assert_eq!(pacer.tokens, pacer.capacity);
         let initial_tokens = pacer.tokens;
 
-        pacer.delay(rtt, mtu as u64, mtu, window * 2, now);
+        pacer.delay(rtt, mtu as u64, mtu, window * 2, None, None, None, now);
         assert_eq!(
             pacer.capacity,
             (2 * window as u128 * BURST_INTERVAL_NANOS / rtt.as_nanos()) as u64
         );
         assert_eq!(pacer.tokens, initial_tokens);
 
-        pacer.delay(rtt, mtu as u64, mtu, window / 2, now);
+        pacer.delay(rtt, mtu as u64, mtu, window / 2, None, None, None, now);
         assert_eq!(
             pacer.capacity,
             (window as u128 / 2 * BURST_INTERVAL_NANOS / rtt.as_nanos()) as u64
         );
         assert_eq!(pacer.tokens, initial_tokens / 2);
 
-        pacer.delay(rtt, mtu as u64, mtu * 2, window, now);
+        pacer.delay(rtt, mtu as u64, mtu * 2, window, None, None, None, now);
         assert_eq!(
             pacer.capacity,
             (window as u128 * BURST_INTERVAL_NANOS / rtt.as_nanos()) as u64
         );
 
-        pacer.delay(rtt, mtu as u64, 20_000, window, now);
+        pacer.delay(rtt, mtu as u64, 20_000, window, None, None, None, now);
         assert_eq!(pacer.capacity, 20_000_u64 * MIN_BURST_SIZE);
     }
 
@@ -245,7 +301,7 @@ mod tests {
 
         for _ in 0..packet_capacity {
             assert_eq!(
-                pacer.delay(rtt, mtu as u64, mtu, window, old_instant),
+                pacer.delay(rtt, mtu as u64, mtu, window, None, None, None, old_instant),
                 None,
                 "When capacity is available packets should be sent immediately"
             );
@@ -257,7 +313,7 @@ mod tests {
 
         assert_eq!(
             pacer
-                .delay(rtt, mtu as u64, mtu, window, old_instant)
+                .delay(rtt, mtu as u64, mtu, window, None, None, None, old_instant)
                 .expect("Send must be delayed")
                 .duration_since(old_instant),
             pace_duration
@@ -270,6 +326,9 @@ mod tests {
                 mtu as u64,
                 mtu,
                 window,
+                None,
+                None,
+                None,
                 old_instant + pace_duration / 2
             ),
             None
@@ -278,7 +337,7 @@ mod tests {
 
         for _ in 0..packet_capacity / 2 {
             assert_eq!(
-                pacer.delay(rtt, mtu as u64, mtu, window, old_instant),
+                pacer.delay(rtt, mtu as u64, mtu, window, None, None, None, old_instant),
                 None,
                 "When capacity is available packets should be sent immediately"
             );
@@ -293,10 +352,107 @@ mod tests {
                 mtu as u64,
                 mtu,
                 window,
+                None,
+                None,
+                None,
                 old_instant + pace_duration * 3 / 2
             ),
             None
         );
         assert_eq!(pacer.tokens, pacer.capacity);
     }
+
+    #[test]
+    fn follows_controller_rate() {
+        let window = 2_000_000u64;
+        let mtu = 1000;
+        let rtt = Duration::from_millis(50);
+        let rate = 1_000_000;
+        let quantum = 10_000;
+        let now = Instant::now();
+
+        let mut pacer = Pacer::new(rtt, window, mtu, now);
+        let delay = pacer.delay(
+            rtt,
+            mtu as u64,
+            mtu,
+            window,
+            Some(rate),
+            Some(quantum),
+            None,
+            now,
+        );
+        assert_eq!(delay, None);
+        assert_eq!(pacer.capacity, quantum);
+
+        for _ in 0..quantum / mtu as u64 {
+            pacer.on_transmit(mtu);
+        }
+
+        // The bucket refills at the requested rate, rather than based on the window
+        let delay = pacer
+            .delay(
+                rtt,
+                mtu as u64,
+                mtu,
+                window,
+                Some(rate),
+                Some(quantum),
+                None,
+                now,
+            )
+            .expect("Send must be delayed");
+        assert_eq!(delay.duration_since(now), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn caps_rate() {
+        let window = 2_000_000u64;
+        let mtu = 1000;
+        let rtt = Duration::from_millis(50);
+        let max_rate = 1_000_000;
+        let now = Instant::now();
+
+        let mut pacer = Pacer::new(rtt, window, mtu, now);
+        let capacity = MIN_BURST_SIZE * mtu as u64;
+        for rate in [None, Some(max_rate * 2)] {
+            pacer.tokens = 0;
+            let delay = pacer
+                .delay(
+                    rtt,
+                    mtu as u64,
+                    mtu,
+                    window,
+                    rate,
+                    None,
+                    Some(max_rate),
+                    now,
+                )
+                .expect("Send must be delayed");
+            assert_eq!(pacer.capacity, capacity);
+            assert_eq!(
+                delay.duration_since(now),
+                Duration::from_secs(1) * capacity as u32 / max_rate as u32
+            );
+        }
+
+        // Slower rates are unaffected
+        pacer.tokens = 0;
+        let delay = pacer
+            .delay(
+                rtt,
+                mtu as u64,
+                mtu,
+                window,
+                Some(max_rate / 2),
+                None,
+                Some(max_rate),
+                now,
+            )
+            .expect("Send must be delayed");
+        assert_eq!(
+            delay.duration_since(now),
+            Duration::from_secs(1) * capacity as u32 / (max_rate / 2) as u32
+        );
+    }
 }
@@ -8,7 +8,8 @@ use super::{spaces::SentPacket, Connection, SentFrames};
 use crate::{
     frame::{self, Close},
     packet::{Header, LongType, PacketNumber, PartialEncode, SpaceId, FIXED_BIT},
-    TransportError, TransportErrorCode,
+    transport_parameters::ACK_DELAY_EXPONENT,
+    ConnectionId, TransportError, TransportErrorCode,
 };
 
 pub(super) struct PacketBuilder {
@@ -32,6 +33,7 @@ impl PacketBuilder {
     pub(super) fn new(
         now: Instant,
         space_id: SpaceId,
+        dst_cid: ConnectionId,
         buffer: &mut BytesMut,
         buffer_capacity: usize,
         datagram_start: usize,
@@ -50,8 +52,10 @@ impl PacketBuilder {
             .confidentiality_limit();
         let sent_with_keys = conn.spaces[space_id].sent_with_keys;
         if space_id == SpaceId::Data {
-            if sent_with_keys.saturating_add(KEY_UPDATE_MARGIN) >= confidentiality_limit {
-                conn.initiate_key_update();
+            if sent_with_keys.saturating_add(KEY_UPDATE_MARGIN) >= confidentiality_limit
+                || conn.key_update_due(now)
+            {
+                conn.update_keys(now, None, false);
             }
         } else if sent_with_keys.saturating_add(1) == confidentiality_limit {
             // We still have time to attempt a graceful close
@@ -80,7 +84,7 @@ impl PacketBuilder {
         let number = PacketNumber::new(exact_number, space.largest_acked_packet.unwrap_or(0));
         let header = match space_id {
             SpaceId::Data if space.crypto.is_some() => Header::Short {
-                dst_cid: conn.rem_cids.active(),
+                dst_cid,
                 number,
                 spin: if conn.spin_enabled {
                     conn.spin
@@ -92,20 +96,20 @@ impl PacketBuilder {
             SpaceId::Data => Header::Long {
                 ty: LongType::ZeroRtt,
                 src_cid: conn.handshake_cid,
-                dst_cid: conn.rem_cids.active(),
+                dst_cid,
                 number,
                 version,
             },
             SpaceId::Handshake => Header::Long {
                 ty: LongType::Handshake,
                 src_cid: conn.handshake_cid,
-                dst_cid: conn.rem_cids.active(),
+                dst_cid,
                 number,
                 version,
             },
             SpaceId::Initial => Header::Initial {
                 src_cid: conn.handshake_cid,
-                dst_cid: conn.rem_cids.active(),
+                dst_cid,
                 token: conn.retry_token.clone(),
                 number,
                 version,
@@ -138,7 +142,7 @@ impl PacketBuilder {
         // payload_len >= sample_size + 4 - pn_len - tag_len
         let min_size = Ord::max(
             buffer.len() + (sample_size + 4).saturating_sub(number.len() + tag_len),
-            partial_encode.start + conn.rem_cids.active().len() + 6,
+            partial_encode.start + dst_cid.len() + 6,
         );
         let max_size = buffer_capacity - partial_encode.start - partial_encode.header_len - tag_len;
 
@@ -172,7 +176,7 @@ impl PacketBuilder {
         let ack_eliciting = self.ack_eliciting;
         let exact_number = self.exact_number;
         let space_id = self.space;
-        let (size, padded) = self.finish(conn, buffer);
+        let (size, padded) = self.finish(now, conn, buffer);
         let sent = match sent {
             Some(sent) => sent,
             None => return,
@@ -190,6 +194,12 @@ impl PacketBuilder {
             ack_eliciting,
             retransmits: sent.retransmits,
             stream_frames: sent.stream_frames,
+            datagram_ids: sent.datagram_ids,
+            delivery: conn
+                .path
+                .delivery_rate
+                .on_sent(now, conn.in_flight.bytes, size),
+            app_limited: conn.path.delivery_rate.is_app_limited(),
         };
 
         conn.in_flight.insert(&packet);
@@ -206,17 +216,43 @@ impl PacketBuilder {
             }
             conn.set_loss_detection_timer(now);
             conn.path.pacing.on_transmit(size);
+            conn.send_rate.on_transmit(size);
+            conn.endpoint_send_rate.on_transmit(size);
         }
     }
 
     /// Encrypt packet, returning the length of the packet and whether padding was added
-    pub(super) fn finish(self, conn: &mut Connection, buffer: &mut BytesMut) -> (usize, bool) {
+    pub(super) fn finish(
+        self,
+        now: Instant,
+        conn: &mut Connection,
+        buffer: &mut BytesMut,
+    ) -> (usize, bool) {
         let pad = buffer.len() < self.min_size;
         if pad {
             trace!("PADDING * {}", self.min_size - buffer.len());
             buffer.resize(self.min_size, 0);
         }
 
+        let encode_start = self.partial_encode.start;
+        if let Some(ref mut qlog) = conn.qlog {
+            let payload_start = encode_start + self.partial_encode.header_len;
+            qlog.packet_sent(
+                now,
+                self.space,
+                self.short_header,
+                self.exact_number,
+                buffer.len() - encode_start + self.tag_len,
+                Bytes::copy_from_slice(&buffer[payload_start..]),
+                ACK_DELAY_EXPONENT.into(),
+            );
+        }
+
+        if self.short_header {
+            conn.key_phase_bytes += (buffer.len() - encode_start + self.tag_len) as u64;
+            conn.first_1rtt_packet.get_or_insert(self.exact_number);
+        }
+
         let space = &conn.spaces[self.space];
         let (header_crypto, packet_crypto) = if let Some(ref crypto) = space.crypto {
             (&*crypto.header.local, &*crypto.packet.local)
@@ -234,7 +270,6 @@ impl PacketBuilder {
         );
 
         buffer.resize(buffer.len() + packet_crypto.tag_len(), 0);
-        let encode_start = self.partial_encode.start;
         let packet_buf = &mut buffer[encode_start..];
         self.partial_encode.finish(
             packet_buf,
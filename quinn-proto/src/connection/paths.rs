@@ -1,6 +1,6 @@
 use std::{cmp, net::SocketAddr, time::Duration, time::Instant};
 
-use super::{mtud::MtuDiscovery, pacing::Pacer};
+use super::{delivery_rate::DeliveryRateEstimator, mtud::MtuDiscovery, pacing::Pacer};
 use crate::{config::MtuDiscoveryConfig, congestion, packet::SpaceId, TIMER_GRANULARITY};
 
 /// Description of a particular network path
@@ -13,6 +13,8 @@ pub(super) struct PathData {
     pub(super) congestion: Box<dyn congestion::Controller>,
     /// Pacing state
     pub(super) pacing: Pacer,
+    /// Delivery rate sampling state
+    pub(super) delivery_rate: DeliveryRateEstimator,
     pub(super) challenge: Option<u64>,
     pub(super) challenge_pending: bool,
     /// Whether we're certain the peer can both send and receive on this address
@@ -49,6 +51,7 @@ impl PathData {
             rtt: RttEstimator::new(initial_rtt),
             sending_ecn: true,
             pacing: Pacer::new(initial_rtt, congestion.initial_window(), initial_mtu, now),
+            delivery_rate: DeliveryRateEstimator::new(now),
             congestion,
             challenge: None,
             challenge_pending: false,
@@ -69,6 +72,7 @@ impl PathData {
             remote,
             rtt: prev.rtt,
             pacing: Pacer::new(smoothed_rtt, congestion.window(), prev.current_mtu(), now),
+            delivery_rate: prev.delivery_rate.clone(),
             sending_ecn: true,
             congestion,
             challenge: None,
@@ -93,6 +97,38 @@ impl PathData {
     }
 }
 
+/// Application events about the network paths of a connection
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PathEvent {
+    /// The peer proved it can receive on a new path, in response to a PATH_CHALLENGE
+    Validated {
+        /// The peer's address on the validated path
+        remote: SocketAddr,
+    },
+    /// The peer did not respond on a new path in time
+    ///
+    /// The connection keeps using, or returns to, its previous path.
+    ValidationFailed {
+        /// The peer's address on the failed path
+        remote: SocketAddr,
+    },
+    /// Traffic to the peer moved to another address
+    ///
+    /// Emitted when the peer migrates or its NAT rebinds, and when the connection switches paths
+    /// itself, e.g. to the server's preferred address.
+    RemoteAddressChanged {
+        /// The address the peer was using before
+        old: SocketAddr,
+        /// The address the peer is using now
+        new: SocketAddr,
+    },
+    /// The maximum size of UDP payloads on the current path changed
+    MtuChanged {
+        /// The new path MTU
+        mtu: u16,
+    },
+}
+
 /// RTT estimation for a particular network path
 #[derive(Copy, Clone)]
 pub struct RttEstimator {
@@ -107,7 +143,7 @@ pub struct RttEstimator {
 }
 
 impl RttEstimator {
-    fn new(initial_rtt: Duration) -> Self {
+    pub(crate) fn new(initial_rtt: Duration) -> Self {
         Self {
             latest: initial_rtt,
             smoothed: None,
@@ -134,6 +170,16 @@ impl RttEstimator {
         self.min
     }
 
+    /// The most recent RTT sample, not adjusted for ack delay
+    pub fn latest(&self) -> Duration {
+        self.latest
+    }
+
+    /// The RTT variance, computed as described in RFC6298
+    pub(crate) fn var(&self) -> Duration {
+        self.var
+    }
+
     // PTO computed as described in RFC9002#6.2.1
     pub(crate) fn pto_base(&self) -> Duration {
         self.get() + cmp::max(4 * self.var, TIMER_GRANULARITY)
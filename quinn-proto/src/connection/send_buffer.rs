@@ -161,6 +161,61 @@ impl SendBuffer {
         self.retransmits.insert(range);
     }
 
+    /// Discard data at or beyond `end`, which becomes the end of the stream
+    ///
+    /// Data which has already been acknowledged is unaffected.
+    pub(super) fn truncate(&mut self, end: u64) {
+        let base_offset = self.offset - self.unacked_len as u64;
+        let end = end.clamp(base_offset, self.offset);
+        let mut to_discard = (self.offset - end) as usize;
+        self.unacked_len -= to_discard;
+        while to_discard > 0 {
+            let back = self
+                .unacked_segments
+                .back_mut()
+                .expect("Expected buffered data");
+            if back.len() <= to_discard {
+                to_discard -= back.len();
+                self.unacked_segments.pop_back();
+            } else {
+                back.truncate(back.len() - to_discard);
+                to_discard = 0;
+            }
+        }
+        self.offset = end;
+        self.unsent = self.unsent.min(end);
+        self.acks.remove(end..u64::MAX);
+        self.retransmits.remove(end..u64::MAX);
+    }
+
+    /// Discard a range of data which hasn't been sent yet, moving any data after it back to
+    /// `range.start`
+    pub(super) fn discard_unsent(&mut self, range: Range<u64>) {
+        debug_assert!(self.unsent <= range.start && range.end <= self.offset);
+        let base_offset = self.offset - self.unacked_len as u64;
+        let mut segment_offset = base_offset;
+        let mut segments = VecDeque::with_capacity(self.unacked_segments.len() + 1);
+        for mut segment in self.unacked_segments.drain(..) {
+            let start = segment_offset;
+            let end = start + segment.len() as u64;
+            segment_offset = end;
+            if end <= range.start || start >= range.end {
+                segments.push_back(segment);
+                continue;
+            }
+            if start < range.start {
+                segments.push_back(segment.split_to((range.start - start) as usize));
+            }
+            if end > range.end {
+                segments.push_back(segment.split_off(segment.len() - (end - range.end) as usize));
+            }
+        }
+        self.unacked_segments = segments;
+        let len = range.end - range.start;
+        self.unacked_len -= len as usize;
+        self.offset -= len;
+    }
+
     pub(super) fn retransmit_all_for_0rtt(&mut self) {
         debug_assert_eq!(self.offset, self.unacked_len as u64);
         self.unsent = 0;
@@ -172,6 +227,26 @@ impl SendBuffer {
         self.offset
     }
 
+    /// First stream offset which hasn't been acknowledged along with all data before it
+    pub(super) fn acked(&self) -> u64 {
+        self.offset - self.unacked_len as u64
+    }
+
+    /// First stream offset which hasn't been sent yet
+    pub(super) fn unsent(&self) -> u64 {
+        self.unsent
+    }
+
+    /// Whether all of `range` has been acknowledged
+    pub(super) fn is_acked(&self, range: Range<u64>) -> bool {
+        let base_offset = self.offset - self.unacked_len as u64;
+        range.end <= base_offset
+            || self
+                .acks
+                .iter()
+                .any(|x| x.start <= range.start.max(base_offset) && range.end <= x.end)
+    }
+
     /// Whether all sent data has been acknowledged
     pub(super) fn is_fully_acked(&self) -> bool {
         self.unacked_len == 0
@@ -384,6 +459,67 @@ mod tests {
         assert!(buf.acks.is_empty());
     }
 
+    #[test]
+    fn is_acked() {
+        let mut buf = SendBuffer::new();
+        const MSG: &[u8] = b"Hello, world with extra data!";
+        buf.write(MSG.into());
+        assert_eq!(buf.poll_transmit(16), (0..16, false));
+        assert_eq!(buf.poll_transmit(16), (16..23, true));
+        buf.ack(16..23);
+        assert!(buf.is_acked(16..20));
+        assert!(!buf.is_acked(8..20));
+        assert!(!buf.is_acked(16..24));
+        buf.ack(0..16);
+        assert!(buf.is_acked(0..23));
+        assert!(!buf.is_acked(0..24));
+    }
+
+    #[test]
+    fn truncate() {
+        let mut buf = SendBuffer::new();
+        buf.write(b"Hello, "[..].into());
+        buf.write(b"world with extra data!"[..].into());
+        assert_eq!(buf.poll_transmit(16), (0..16, false));
+        buf.ack(0..4);
+        buf.ack(12..16);
+        buf.retransmit(4..12);
+        buf.truncate(10);
+        assert_eq!(buf.offset(), 10);
+        assert_eq!(aggregate_unacked(&buf), b"o, wor");
+        assert_eq!(buf.unacked(), 6);
+        assert_eq!(buf.poll_transmit(16), (4..10, true));
+        assert!(!buf.has_unsent_data());
+        buf.ack(4..10);
+        assert!(buf.is_fully_acked());
+        // Acknowledged data can't be discarded
+        buf.truncate(2);
+        assert_eq!(buf.offset(), 10);
+    }
+
+    #[test]
+    fn discard_unsent() {
+        let mut buf = SendBuffer::new();
+        buf.write(b"Hello, "[..].into());
+        buf.write(b"world"[..].into());
+        buf.write(b"!"[..].into());
+        assert_eq!(buf.poll_transmit(32), (0..13, true));
+        buf.write(b"stale"[..].into());
+        buf.write(b" fresh"[..].into());
+        buf.discard_unsent(13..18);
+        assert_eq!(buf.offset(), 19);
+        assert_eq!(aggregate_unacked(&buf), b"Hello, world! fresh");
+
+        // Ranges spanning and splitting segments
+        buf.write(b"abc"[..].into());
+        buf.discard_unsent(15..20);
+        assert_eq!(buf.offset(), 17);
+        assert_eq!(aggregate_unacked(&buf), b"Hello, world! fbc");
+        assert_eq!(buf.poll_transmit(32), (13..17, true));
+        assert_eq!(buf.get(13..17), b" f");
+        assert_eq!(buf.get(15..17), b"bc");
+    }
+
     fn aggregate_unacked(buf: &SendBuffer) -> Vec<u8> {
         let mut result = Vec::new();
         for segment in buf.unacked_segments.iter() {
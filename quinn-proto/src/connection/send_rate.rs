@@ -0,0 +1,205 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+/// A token bucket enforcing a maximum send rate
+///
+/// Tokens accumulate at the configured rate, up to a burst of [`BURST_DURATION`] worth of data,
+/// and are consumed by transmitted packets.
+#[derive(Debug)]
+pub(crate) struct SendRateLimit {
+    /// Maximum rate in bytes per second, if any
+    rate: Option<u64>,
+    tokens: u64,
+    /// When tokens were last added, if ever
+    prev: Option<Instant>,
+}
+
+impl SendRateLimit {
+    pub(crate) fn new(rate: Option<u64>) -> Self {
+        Self {
+            rate,
+            // Clamped to the capacity once it's known
+            tokens: u64::MAX,
+            prev: None,
+        }
+    }
+
+    pub(crate) fn rate(&self) -> Option<u64> {
+        self.rate
+    }
+
+    pub(crate) fn set_rate(&mut self, rate: Option<u64>) {
+        self.rate = rate;
+    }
+
+    /// Record that a packet has been transmitted
+    pub(crate) fn on_transmit(&mut self, packet_length: u16) {
+        self.tokens = self.tokens.saturating_sub(packet_length.into());
+    }
+
+    /// Return when `bytes_to_send` may be sent, or `None` if it may be sent right away
+    pub(crate) fn delay(&mut self, bytes_to_send: u64, mtu: u16, now: Instant) -> Option<Instant> {
+        let rate = self.rate?;
+        let capacity = match rate {
+            // Nothing may be sent, not even the initial burst
+            0 => 0,
+            // Bursts must fit at least one datagram, with room for a partially written one
+            _ => ((u128::from(rate) * BURST_DURATION.as_nanos() / 1_000_000_000) as u64)
+                .max(2 * u64::from(mtu)),
+        };
+
+        let prev = *self.prev.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(prev);
+        let new_tokens = u128::from(rate) * elapsed.as_nanos() / 1_000_000_000;
+        if self.tokens.saturating_add(new_tokens as u64) >= capacity {
+            self.tokens = capacity;
+            self.prev = Some(now);
+        } else if rate != 0 {
+            self.tokens += new_tokens as u64;
+            // Only account for the time the new tokens took to accumulate, so that frequent calls
+            // don't lose fractions of tokens
+            self.prev = Some(
+                prev + Duration::from_nanos((new_tokens * 1_000_000_000 / u128::from(rate)) as u64),
+            );
+        }
+
+        if self.tokens >= bytes_to_send {
+            return None;
+        }
+        if rate == 0 {
+            // Nothing may be sent until the limit is lifted
+            return Some(now + Duration::from_secs(1));
+        }
+        let deficit = u128::from(bytes_to_send - self.tokens);
+        let wait = (deficit * 1_000_000_000 + u128::from(rate) - 1) / u128::from(rate);
+        Some(self.prev.unwrap_or(now) + Duration::from_nanos(wait as u64))
+    }
+}
+
+/// A [`SendRateLimit`] shared by all of an endpoint's connections
+#[derive(Debug)]
+pub(crate) struct SharedSendRateLimit {
+    /// Whether a rate is set, so that connections needn't lock `limit` for every datagram otherwise
+    limited: AtomicBool,
+    limit: Mutex<SendRateLimit>,
+}
+
+impl SharedSendRateLimit {
+    pub(crate) fn new() -> Self {
+        Self {
+            limited: AtomicBool::new(false),
+            limit: Mutex::new(SendRateLimit::new(None)),
+        }
+    }
+
+    pub(crate) fn set_rate(&self, rate: Option<u64>) {
+        let mut limit = self.lock();
+        limit.set_rate(rate);
+        self.limited.store(rate.is_some(), Ordering::Relaxed);
+    }
+
+    /// Record that a packet has been transmitted
+    pub(crate) fn on_transmit(&self, packet_length: u16) {
+        if self.limited.load(Ordering::Relaxed) {
+            self.lock().on_transmit(packet_length);
+        }
+    }
+
+    /// Return when `bytes_to_send` may be sent, or `None` if it may be sent right away
+    pub(crate) fn delay(&self, bytes_to_send: u64, mtu: u16, now: Instant) -> Option<Instant> {
+        if !self.limited.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.lock().delay(bytes_to_send, mtu, now)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SendRateLimit> {
+        // The token bucket is consistent after any panic, so carry on regardless
+        self.limit.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Amount of time worth of data which may be sent in a single burst
+const BURST_DURATION: Duration = Duration::from_millis(10);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited() {
+        let mut limit = SendRateLimit::new(None);
+        let now = Instant::now();
+        for _ in 0..1000 {
+            assert_eq!(limit.delay(1200, 1200, now), None);
+            limit.on_transmit(1200);
+        }
+    }
+
+    #[test]
+    fn limits_rate() {
+        let rate = 1_000_000;
+        let mtu = 1000;
+        let mut limit = SendRateLimit::new(Some(rate));
+        let start = Instant::now();
+
+        // An initial burst is allowed
+        let mut sent = 0;
+        while limit.delay(mtu as u64, mtu, start).is_none() {
+            limit.on_transmit(mtu);
+            sent += u64::from(mtu);
+        }
+        assert_eq!(sent, rate / 100);
+
+        // Afterwards, data is sent at the configured rate
+        let mut now = start;
+        for _ in 0..1000 {
+            if let Some(delay) = limit.delay(mtu as u64, mtu, now) {
+                now = delay;
+                assert_eq!(limit.delay(mtu as u64, mtu, now), None);
+            }
+            limit.on_transmit(mtu);
+            sent += u64::from(mtu);
+        }
+        assert_eq!(now.duration_since(start), Duration::from_secs(1));
+        assert_eq!(sent, rate + rate / 100);
+    }
+
+    #[test]
+    fn frequent_calls() {
+        let rate = 1_000_000;
+        let mut limit = SendRateLimit::new(Some(rate));
+        let start = Instant::now();
+        limit.delay(1000, 1000, start);
+        limit.on_transmit(u16::MAX);
+        limit.on_transmit(u16::MAX);
+
+        // Checking every 100ns, when less than a byte accumulates in between, doesn't starve
+        let mut now = start;
+        while limit.delay(1000, 1000, now).is_some() {
+            now += Duration::from_nanos(100);
+        }
+        assert_eq!(now.duration_since(start), Duration::from_millis(1));
+    }
+
+    #[test]
+    fn zero_rate() {
+        let mut limit = SendRateLimit::new(Some(0));
+        let start = Instant::now();
+        assert!(limit.delay(1, 1200, start).is_some());
+
+        // Tokens start accumulating once the limit is raised, without an initial burst
+        let now = start + Duration::from_secs(1);
+        assert!(limit.delay(1200, 1200, now).is_some());
+        limit.set_rate(Some(1_000_000));
+        assert_eq!(
+            limit.delay(1000, 1200, now),
+            Some(now + Duration::from_millis(1))
+        );
+    }
+}
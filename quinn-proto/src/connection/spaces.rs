@@ -2,16 +2,18 @@ use std::{
     cmp,
     collections::{BTreeMap, VecDeque},
     mem,
+    net::SocketAddr,
     ops::{Index, IndexMut},
     time::{Duration, Instant},
 };
 
 use rustc_hash::FxHashSet;
+use tinyvec::TinyVec;
 
-use super::assembler::Assembler;
+use super::{assembler::Assembler, delivery_rate::DeliveryState};
 use crate::{
     connection::StreamsState, crypto::Keys, frame, packet::SpaceId, range_set::ArrayRangeSet,
-    shared::IssuedCid, Dir, StreamId, VarInt,
+    shared::IssuedCid, Dir, EcnCodepoint, StreamId, VarInt,
 };
 
 pub(super) struct PacketSpace {
@@ -57,6 +59,8 @@ pub(super) struct PacketSpace {
     /// Number of tail loss probes to send
     pub(super) loss_probes: u32,
     pub(super) ping_pending: bool,
+    /// Whether an IMMEDIATE_ACK frame should be sent
+    pub(super) immediate_ack_pending: bool,
     /// Number of congestion control "in flight" bytes
     pub(super) in_flight: u64,
     /// Number of packets sent in the current key phase
@@ -87,6 +91,7 @@ impl PacketSpace {
             loss_time: None,
             loss_probes: 0,
             ping_pending: false,
+            immediate_ack_pending: false,
             in_flight: 0,
             sent_with_keys: 0,
         }
@@ -141,17 +146,22 @@ impl PacketSpace {
 
     pub(super) fn can_send(&self, streams: &StreamsState) -> SendableFrames {
         let acks = self.pending_acks.can_send();
-        let other = !self.pending.is_empty(streams) || self.ping_pending;
+        let other =
+            !self.pending.is_empty(streams) || self.ping_pending || self.immediate_ack_pending;
 
         SendableFrames { acks, other }
     }
 
-    /// Verifies sanity of an ECN block and returns whether congestion was encountered.
+    /// Verifies sanity of an ECN block for packets sent with `codepoint`
+    ///
+    /// Returns the number of newly reported packets which were received with that codepoint and
+    /// which were marked as congestion experienced.
     pub(super) fn detect_ecn(
         &mut self,
         newly_acked: u64,
         ecn: frame::EcnCounts,
-    ) -> Result<bool, &'static str> {
+        codepoint: EcnCodepoint,
+    ) -> Result<(u64, u64), &'static str> {
         let ect0_increase = ecn
             .ect0
             .checked_sub(self.ecn_feedback.ect0)
@@ -168,7 +178,11 @@ impl PacketSpace {
         if total_increase < newly_acked {
             return Err("ECN bleaching");
         }
-        if (ect0_increase + ce_increase) < newly_acked || ect1_increase != 0 {
+        let (ect_increase, other_increase) = match codepoint {
+            EcnCodepoint::Ect1 => (ect1_increase, ect0_increase),
+            _ => (ect0_increase, ect1_increase),
+        };
+        if (ect_increase + ce_increase) < newly_acked || other_increase != 0 {
             return Err("ECN corruption");
         }
         // If total_increase > newly_acked (which happens when ACKs are lost), this is required by
@@ -176,7 +190,7 @@ impl PacketSpace {
         // to count CE packets as CE or ECT0. Recording them as CE is more consistent and keeps the
         // congestion check obvious.
         self.ecn_feedback = ecn;
-        Ok(ce_increase != 0)
+        Ok((ect_increase, ce_increase))
     }
 
     pub(super) fn sent(&mut self, number: u64, packet: SentPacket) {
@@ -219,6 +233,14 @@ pub(super) struct SentPacket {
     ///
     /// The actual application data is stored with the stream state.
     pub(super) stream_frames: frame::StreamMetaVec,
+    /// IDs of the datagrams in the packet whose delivery the application is tracking
+    ///
+    /// Only datagrams sent with an ID are tracked, and packets rarely carry more than one.
+    pub(super) datagram_ids: DatagramIds,
+    /// The connection's delivery progress when the packet was sent, for delivery rate sampling
+    pub(super) delivery: DeliveryState,
+    /// Whether the connection was application-limited when the packet was sent
+    pub(super) app_limited: bool,
 }
 
 /// Retransmittable data queue
@@ -234,6 +256,9 @@ pub struct Retransmits {
     pub(super) new_cids: Vec<IssuedCid>,
     pub(super) retire_cids: Vec<u64>,
     pub(super) handshake_done: bool,
+    pub(super) ack_frequency: bool,
+    /// Remote addresses to send NEW_TOKEN frames with tokens bound to
+    pub(super) new_tokens: Vec<SocketAddr>,
 }
 
 impl Retransmits {
@@ -250,6 +275,8 @@ impl Retransmits {
             && self.new_cids.is_empty()
             && self.retire_cids.is_empty()
             && !self.handshake_done
+            && !self.ack_frequency
+            && self.new_tokens.is_empty()
     }
 }
 
@@ -270,6 +297,8 @@ impl ::std::ops::BitOrAssign for Retransmits {
         self.new_cids.extend(&rhs.new_cids);
         self.retire_cids.extend(rhs.retire_cids);
         self.handshake_done |= rhs.handshake_done;
+        self.ack_frequency |= rhs.ack_frequency;
+        self.new_tokens.extend_from_slice(&rhs.new_tokens);
     }
 }
 
@@ -348,6 +377,9 @@ pub(super) struct Dedup {
 /// packets that are reordered but still delivered in a timely manner.
 type Window = u128;
 
+/// IDs of tracked datagrams in a packet, stored inline for the common case
+pub(super) type DatagramIds = TinyVec<[u64; 1]>;
+
 /// Number of packets tracked by `Dedup`.
 const WINDOW_SIZE: u64 = 1 + mem::size_of::<Window>() as u64 * 8;
 
@@ -416,30 +448,136 @@ impl SendableFrames {
 
 #[derive(Debug, Default)]
 pub(super) struct PendingAcks {
-    permit_ack_only: bool,
-    ranges: ArrayRangeSet,
-    /// This value will be used for calculating ACK delay once it is implemented
+    /// Whether we should send an ACK immediately, even if that means sending an ACK-only packet
+    ///
+    /// Otherwise, ACKs are only sent alongside other ack-eliciting frames, or once the
+    /// `MaxAckDelay` timer expires.
+    immediate_ack_required: bool,
+    /// The number of ack-eliciting packets received since the last ACK frame was sent
+    ack_eliciting_since_last_ack_sent: u64,
+    /// The number of ack-eliciting packets that may be received without immediately sending an
+    /// ACK, as requested by the peer through an ACK_FREQUENCY frame
     ///
-    /// ACK delay will be the delay between when a packet arrived (`latest_incoming`)
-    /// and between it will be allowed to be acknowledged (`can_send() == true`).
-    latest_incoming: Option<Instant>,
-    ack_delay: Duration,
+    /// Zero, the default, acknowledges every ack-eliciting packet immediately.
+    ack_eliciting_threshold: u64,
+    /// How far out of order ack-eliciting packets may arrive before an ACK is sent immediately,
+    /// or zero to not react to reordering at all
+    reordering_threshold: u64,
+    /// Receipt time of the earliest ack-eliciting packet since the last ACK was sent, which
+    /// determines when `max_ack_delay` elapses
+    earliest_ack_eliciting_since_last_ack_sent: Option<Instant>,
+    /// The largest ack-eliciting packet number received so far
+    largest_ack_eliciting_packet: Option<u64>,
+    /// The largest packet number acknowledged by the most recently sent ACK frame
+    largest_acked: Option<u64>,
+    ranges: ArrayRangeSet,
+    /// The largest packet number received so far, and the time it arrived, used to compute the
+    /// ACK delay
+    largest_packet: Option<(u64, Instant)>,
 }
 
 impl PendingAcks {
     /// Whether any ACK frames can be sent
     pub(super) fn can_send(&self) -> bool {
-        self.permit_ack_only && !self.ranges.is_empty()
+        self.immediate_ack_required && !self.ranges.is_empty()
+    }
+
+    /// Whether ack-eliciting packets are awaiting an ACK that could be bundled with other frames
+    pub(super) fn can_piggyback(&self) -> bool {
+        self.ack_eliciting_since_last_ack_sent != 0 && !self.ranges.is_empty()
     }
 
-    /// Returns the duration the acknowledgement of the latest incoming packet has been delayed
-    pub(super) fn ack_delay(&self) -> Duration {
-        self.ack_delay
+    /// Returns the delay between the receipt of the largest packet number to be acknowledged and
+    /// `now`
+    pub(super) fn ack_delay(&self, now: Instant) -> Duration {
+        self.largest_packet
+            .map_or(Duration::default(), |(_, received)| {
+                now.saturating_duration_since(received)
+            })
     }
 
     /// Handle receipt of a new packet
-    pub(super) fn packet_received(&mut self, ack_eliciting: bool) {
-        self.permit_ack_only |= ack_eliciting;
+    ///
+    /// Returns whether the `MaxAckDelay` timer should be armed.
+    pub(super) fn packet_received(
+        &mut self,
+        now: Instant,
+        packet_number: u64,
+        ack_eliciting: bool,
+    ) -> bool {
+        if !ack_eliciting {
+            return false;
+        }
+
+        let prev_largest_ack_eliciting = self.largest_ack_eliciting_packet;
+        self.largest_ack_eliciting_packet =
+            self.largest_ack_eliciting_packet.max(Some(packet_number));
+
+        self.ack_eliciting_since_last_ack_sent += 1;
+        self.immediate_ack_required |=
+            self.ack_eliciting_since_last_ack_sent > self.ack_eliciting_threshold;
+        self.immediate_ack_required |=
+            self.is_out_of_order(packet_number, prev_largest_ack_eliciting);
+
+        if self.earliest_ack_eliciting_since_last_ack_sent.is_none() && !self.can_send() {
+            self.earliest_ack_eliciting_since_last_ack_sent = Some(now);
+            return true;
+        }
+        false
+    }
+
+    /// Whether the arrival of `packet_number` should trigger an immediate ACK due to reordering
+    ///
+    /// See draft-ietf-quic-ack-frequency, section 6.2.
+    fn is_out_of_order(&self, packet_number: u64, prev_largest: Option<u64>) -> bool {
+        let prev_largest = match prev_largest {
+            Some(x) => x,
+            None => return false,
+        };
+        match self.reordering_threshold {
+            0 => false,
+            // Any packet arriving out of order is acknowledged immediately
+            1 => packet_number < prev_largest || packet_number > prev_largest + 1,
+            threshold => {
+                // Acknowledge immediately once the largest missing packet is newer than what our
+                // last ACK covered, and at least `threshold` packets were received after it
+                let mut ranges = self.ranges.iter().rev();
+                let (newest, below) = match (ranges.next(), ranges.next()) {
+                    (Some(newest), Some(below)) => (newest, below),
+                    _ => return false,
+                };
+                debug_assert!(below.end < newest.start);
+                let largest_missing = newest.start - 1;
+                self.largest_acked.map_or(true, |x| largest_missing >= x)
+                    && newest.end - newest.start >= threshold
+            }
+        }
+    }
+
+    /// Apply the parameters of an ACK_FREQUENCY frame sent by the peer
+    pub(super) fn set_ack_frequency_params(&mut self, frame: &frame::AckFrequency) {
+        self.ack_eliciting_threshold = frame.ack_eliciting_threshold.into_inner();
+        self.reordering_threshold = frame.reordering_threshold.into_inner();
+    }
+
+    /// Require that an ACK be sent as soon as possible, e.g. due to an IMMEDIATE_ACK frame
+    pub(super) fn set_immediate_ack_required(&mut self) {
+        self.immediate_ack_required = true;
+    }
+
+    /// Returns when the `MaxAckDelay` timer should expire, if it should be running
+    pub(super) fn max_ack_delay_timeout(&self, max_ack_delay: Duration) -> Option<Instant> {
+        match self.immediate_ack_required {
+            true => None,
+            false => self
+                .earliest_ack_eliciting_since_last_ack_sent
+                .map(|earliest| earliest + max_ack_delay),
+        }
+    }
+
+    /// Handle expiry of the `MaxAckDelay` timer
+    pub(super) fn on_max_ack_delay_timeout(&mut self) {
+        self.immediate_ack_required = self.ack_eliciting_since_last_ack_sent != 0;
     }
 
     /// Should be called whenever ACKs have been sent
@@ -452,13 +590,18 @@ impl PendingAcks {
         // This reset needs to happen before we check whether more data
         // is available in this space - because otherwise it would return
         // `true` purely due to the ACKs
-        self.permit_ack_only = false;
+        self.immediate_ack_required = false;
+        self.ack_eliciting_since_last_ack_sent = 0;
+        self.earliest_ack_eliciting_since_last_ack_sent = None;
+        self.largest_acked = self.ranges.max();
     }
 
     /// Insert one packet that needs to be acknowledged
     pub(super) fn insert_one(&mut self, packet: u64, now: Instant) {
         self.ranges.insert_one(packet);
-        self.latest_incoming = Some(now);
+        if self.largest_packet.map_or(true, |(pn, _)| packet > pn) {
+            self.largest_packet = Some((packet, now));
+        }
 
         if self.ranges.len() > MAX_ACK_BLOCKS {
             self.ranges.pop_min();
@@ -539,6 +682,80 @@ mod test {
         assert_eq!(dedup.window, 1 << (WINDOW_SIZE - 2));
     }
 
+    #[test]
+    fn pending_acks_threshold() {
+        let now = Instant::now();
+        let mut acks = PendingAcks::default();
+        acks.set_ack_frequency_params(&frame::AckFrequency {
+            sequence: VarInt(0),
+            ack_eliciting_threshold: VarInt(1),
+            request_max_ack_delay: VarInt(25_000),
+            reordering_threshold: VarInt(0),
+        });
+
+        // The first ack-eliciting packet arms the timer instead of being acknowledged immediately
+        acks.insert_one(0, now);
+        assert!(acks.packet_received(now, 0, true));
+        assert!(!acks.can_send());
+        assert!(acks.can_piggyback());
+        assert_eq!(
+            acks.max_ack_delay_timeout(Duration::from_millis(25)),
+            Some(now + Duration::from_millis(25))
+        );
+
+        // Non-ack-eliciting packets don't count towards the threshold
+        acks.insert_one(1, now);
+        assert!(!acks.packet_received(now, 1, false));
+        assert!(!acks.can_send());
+
+        // Exceeding the threshold requires an immediate ACK
+        acks.insert_one(2, now);
+        assert!(!acks.packet_received(now, 2, true));
+        assert!(acks.can_send());
+        assert_eq!(acks.max_ack_delay_timeout(Duration::from_millis(25)), None);
+
+        acks.acks_sent();
+        assert!(!acks.can_send());
+        assert!(!acks.can_piggyback());
+
+        // Expiry of the timer releases the delayed ACK
+        acks.insert_one(3, now);
+        assert!(acks.packet_received(now, 3, true));
+        acks.on_max_ack_delay_timeout();
+        assert!(acks.can_send());
+        assert_eq!(
+            acks.ack_delay(now + Duration::from_millis(3)),
+            Duration::from_millis(3)
+        );
+    }
+
+    #[test]
+    fn pending_acks_reordering() {
+        let now = Instant::now();
+        let mut acks = PendingAcks::default();
+        acks.set_ack_frequency_params(&frame::AckFrequency {
+            sequence: VarInt(0),
+            ack_eliciting_threshold: VarInt(10),
+            request_max_ack_delay: VarInt(25_000),
+            reordering_threshold: VarInt(1),
+        });
+
+        acks.insert_one(0, now);
+        acks.packet_received(now, 0, true);
+        assert!(!acks.can_send());
+
+        // Skipping a packet number is reported immediately
+        acks.insert_one(2, now);
+        acks.packet_received(now, 2, true);
+        assert!(acks.can_send());
+        acks.acks_sent();
+
+        // So is filling the gap
+        acks.insert_one(1, now);
+        acks.packet_received(now, 1, true);
+        assert!(acks.can_send());
+    }
+
     #[test]
     fn sent_packet_size() {
         // The tracking state of sent packets should be minimal, and not grow
@@ -24,11 +24,13 @@ pub struct UdpStats {
 #[allow(missing_docs)]
 pub struct FrameStats {
     pub acks: u64,
+    pub ack_frequency: u64,
     pub crypto: u64,
     pub connection_close: u64,
     pub data_blocked: u64,
     pub datagram: u64,
     pub handshake_done: u8,
+    pub immediate_ack: u64,
     pub max_data: u64,
     pub max_stream_data: u64,
     pub max_streams_bidi: u64,
@@ -39,6 +41,7 @@ pub struct FrameStats {
     pub path_response: u64,
     pub ping: u64,
     pub reset_stream: u64,
+    pub reset_stream_at: u64,
     pub retire_connection_id: u64,
     pub stream_data_blocked: u64,
     pub streams_blocked_bidi: u64,
@@ -53,7 +56,10 @@ impl FrameStats {
             Frame::Padding => {}
             Frame::Ping => self.ping += 1,
             Frame::Ack(_) => self.acks += 1,
+            Frame::AckFrequency(_) => self.ack_frequency += 1,
+            Frame::ImmediateAck => self.immediate_ack += 1,
             Frame::ResetStream(_) => self.reset_stream += 1,
+            Frame::ResetStreamAt(_) => self.reset_stream_at += 1,
             Frame::StopSending(_) => self.stop_sending += 1,
             Frame::Crypto(_) => self.crypto += 1,
             Frame::Datagram(_) => self.datagram += 1,
@@ -92,11 +98,13 @@ impl std::fmt::Debug for FrameStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("FrameStats")
             .field("ACK", &self.acks)
+            .field("ACK_FREQUENCY", &self.ack_frequency)
             .field("CONNECTION_CLOSE", &self.connection_close)
             .field("CRYPTO", &self.crypto)
             .field("DATA_BLOCKED", &self.data_blocked)
             .field("DATAGRAM", &self.datagram)
             .field("HANDSHAKE_DONE", &self.handshake_done)
+            .field("IMMEDIATE_ACK", &self.immediate_ack)
             .field("MAX_DATA", &self.max_data)
             .field("MAX_STREAM_DATA", &self.max_stream_data)
             .field("MAX_STREAMS_BIDI", &self.max_streams_bidi)
@@ -107,6 +115,7 @@ impl std::fmt::Debug for FrameStats {
             .field("PATH_RESPONSE", &self.path_response)
             .field("PING", &self.ping)
             .field("RESET_STREAM", &self.reset_stream)
+            .field("RESET_STREAM_AT", &self.reset_stream_at)
             .field("RETIRE_CONNECTION_ID", &self.retire_connection_id)
             .field("STREAM_DATA_BLOCKED", &self.stream_data_blocked)
             .field("STREAMS_BLOCKED_BIDI", &self.streams_blocked_bidi)
@@ -140,6 +149,12 @@ pub struct PathStats {
     pub lost_plpmtud_probes: u64,
     /// The number of times a black hole was detected in the path
     pub black_holes_detected: u64,
+    /// Time spent application-limited, i.e. with nothing to send despite the congestion window
+    /// allowing it
+    pub app_limited_time: Duration,
+    /// Time spent held back by a send rate limit despite the congestion window allowing more data
+    /// to be sent
+    pub rate_limited_time: Duration,
 }
 
 /// Connection statistics
@@ -156,4 +171,12 @@ pub struct ConnectionStats {
     pub frame_rx: FrameStats,
     /// Statistics related to the current transmission path
     pub path: PathStats,
+    /// The amount of application datagrams discarded because the send buffer was full
+    pub dropped_datagrams: u64,
+    /// The amount of application datagrams discarded unsent because their deadline passed
+    pub expired_datagrams: u64,
+    /// The amount of 1-RTT key updates initiated locally
+    pub local_key_updates: u64,
+    /// The amount of 1-RTT key updates initiated by the peer
+    pub peer_key_updates: u64,
 }
@@ -1,25 +1,26 @@
-use std::{
-    cell::RefCell,
-    collections::{hash_map, BinaryHeap, VecDeque},
-};
+use std::{collections::hash_map, time::Instant};
 
 use bytes::Bytes;
 use thiserror::Error;
 use tracing::trace;
 
-use super::spaces::{Retransmits, ThinRetransmits};
+use super::{
+    spaces::{Retransmits, ThinRetransmits},
+    timer::TimerTable,
+};
 use crate::{frame, Dir, StreamId, VarInt};
 
 mod recv;
-use recv::Recv;
 pub use recv::{Chunks, ReadError, ReadableError};
+use recv::{Recv, WindowTuner};
 
 mod send;
 pub(crate) use send::{ByteSlice, BytesArray};
-pub use send::{BytesSource, FinishError, WriteError, Written};
-use send::{Send, SendState};
+pub use send::{BytesSource, FinishError, ResetAtError, WriteError, Written};
+use send::{Send, SendState, WriteDeadline};
 
 mod state;
+use state::track_write_deadline;
 #[allow(unreachable_pub)] // fuzzing only
 pub use state::StreamsState;
 
@@ -165,6 +166,7 @@ pub struct SendStream<'a> {
     pub(super) state: &'a mut StreamsState,
     pub(super) pending: &'a mut Retransmits,
     pub(super) conn_state: &'a super::State,
+    pub(super) timers: &'a mut TimerTable,
 }
 
 impl<'a> SendStream<'a> {
@@ -174,12 +176,14 @@ impl<'a> SendStream<'a> {
         state: &'a mut StreamsState,
         pending: &'a mut Retransmits,
         conn_state: &'a super::State,
+        timers: &'a mut TimerTable,
     ) -> Self {
         Self {
             id,
             state,
             pending,
             conn_state,
+            timers,
         }
     }
 
@@ -187,7 +191,95 @@ impl<'a> SendStream<'a> {
     ///
     /// Returns the number of bytes successfully written.
     pub fn write(&mut self, data: &[u8]) -> Result<usize, WriteError> {
-        Ok(self.write_source(&mut ByteSlice::from_slice(data))?.bytes)
+        Ok(self
+            .write_source(&mut ByteSlice::from_slice(data), 1)?
+            .bytes)
+    }
+
+    /// Send data on the given stream, giving up on it if it isn't acknowledged by `deadline`
+    ///
+    /// Behaves like [`write`](Self::write), but if the peer hasn't acknowledged all of the written
+    /// data by `deadline`, the stream is reset with `error_code` and a [`StreamEvent::Expired`]
+    /// event is emitted. Once reset, none of the stream's data is retransmitted, and further
+    /// writes fail with [`WriteError::Expired`] until the peer acknowledges the reset.
+    ///
+    /// Returns the number of bytes successfully written.
+    pub fn write_with_deadline(
+        &mut self,
+        data: &[u8],
+        deadline: Instant,
+        error_code: VarInt,
+    ) -> Result<usize, WriteError> {
+        let written = self
+            .write_source(&mut ByteSlice::from_slice(data), 1)?
+            .bytes;
+        self.add_deadline(written, deadline, error_code, false);
+        Ok(written)
+    }
+
+    /// Send a message on the given stream, skipping it if it isn't sent by `deadline`
+    ///
+    /// Unlike [`write_with_deadline`](Self::write_with_deadline), either all of `data` is written
+    /// or, if flow control doesn't leave room for it, none of it and [`WriteError::Blocked`] is
+    /// returned. A message which wouldn't fit even once all data written before it is consumed,
+    /// because it's larger than the configured send window or than the flow control windows the
+    /// peer grants, fails with [`WriteError::TooLarge`] instead. If none of the message has been transmitted when `deadline` passes, it's removed
+    /// from the stream and never sent: the data written after it moves back to take its place, so
+    /// the peer continues reading at the next message. A message that was already partly or
+    /// entirely transmitted can't be skipped without tearing it, so if it isn't acknowledged by
+    /// `deadline` the stream is reset with `error_code` as by `write_with_deadline`.
+    pub fn write_message_with_deadline(
+        &mut self,
+        data: &[u8],
+        deadline: Instant,
+        error_code: VarInt,
+    ) -> Result<(), WriteError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let len = data.len() as u64;
+        let written = match self.write_source(&mut ByteSlice::from_slice(data), len) {
+            Err(WriteError::Blocked) if self.exceeds_windows(len) => {
+                return Err(WriteError::TooLarge);
+            }
+            result => result?,
+        };
+        debug_assert_eq!(written.bytes, data.len());
+        self.add_deadline(written.bytes, deadline, error_code, true);
+        Ok(())
+    }
+
+    /// Whether `len` bytes can't be written at once even if all data written so far is consumed
+    fn exceeds_windows(&self, len: u64) -> bool {
+        if self.conn_state.is_closed() {
+            return false;
+        }
+        // The peer can't have consumed more data than it acknowledged, so the windows it grants
+        // are at most the flow control credit beyond that
+        let acked = self.state.data_sent.saturating_sub(self.state.unacked_data);
+        let stream_window = self
+            .state
+            .send
+            .get(&self.id)
+            .map_or(u64::MAX, |stream| stream.max_data - stream.pending.acked());
+        len > self.state.send_window || len > self.state.max_data - acked || len > stream_window
+    }
+
+    /// Track the `len` bytes just written as data which must be acknowledged by `deadline`
+    fn add_deadline(&mut self, len: usize, deadline: Instant, error_code: VarInt, message: bool) {
+        if len == 0 {
+            return;
+        }
+        let stream = self.state.send.get_mut(&self.id).unwrap();
+        let end = stream.offset();
+        stream.deadlines.push_back(WriteDeadline {
+            range: end - len as u64..end,
+            deadline,
+            error_code,
+            message,
+        });
+        track_write_deadline(&mut self.state.write_deadlines, self.id, stream);
+        self.state.set_write_deadline_timer(self.timers);
     }
 
     /// Send data on the given stream
@@ -197,10 +289,15 @@ impl<'a> SendStream<'a> {
     /// [`Written::chunks`] will not count this chunk as fully written. However
     /// the chunk will be advanced and contain only non-written data after the call.
     pub fn write_chunks(&mut self, data: &mut [Bytes]) -> Result<Written, WriteError> {
-        self.write_source(&mut BytesArray::from_chunks(data))
+        self.write_source(&mut BytesArray::from_chunks(data), 1)
     }
 
-    fn write_source<B: BytesSource>(&mut self, source: &mut B) -> Result<Written, WriteError> {
+    /// Write data from `source`, unless fewer than `required` bytes would fit
+    fn write_source<B: BytesSource>(
+        &mut self,
+        source: &mut B,
+        required: u64,
+    ) -> Result<Written, WriteError> {
         if self.conn_state.is_closed() {
             trace!(%self.id, "write blocked; connection draining");
             return Err(WriteError::Blocked);
@@ -212,7 +309,7 @@ impl<'a> SendStream<'a> {
             .send
             .get_mut(&self.id)
             .ok_or(WriteError::UnknownStream)?;
-        if limit == 0 {
+        if limit < required {
             trace!(
                 stream = %self.id, max_data = self.state.max_data, data_sent = self.state.data_sent,
                 "write blocked by connection-level flow control or send window"
@@ -225,12 +322,14 @@ impl<'a> SendStream<'a> {
         }
 
         let was_pending = stream.is_pending();
-        let written = stream.write(source, limit)?;
+        let written = stream.write(source, limit, required)?;
         self.state.data_sent += written.bytes as u64;
         self.state.unacked_data += written.bytes as u64;
         trace!(stream = %self.id, "wrote {} bytes", written.bytes);
         if !was_pending {
-            push_pending(&mut self.state.pending, self.id, stream.priority);
+            self.state
+                .scheduler
+                .push(self.id, stream.priority, stream.incremental);
         }
         Ok(written)
     }
@@ -258,7 +357,9 @@ impl<'a> SendStream<'a> {
         let was_pending = stream.is_pending();
         stream.finish()?;
         if !was_pending {
-            push_pending(&mut self.state.pending, self.id, stream.priority);
+            self.state
+                .scheduler
+                .push(self.id, stream.priority, stream.incremental);
         }
 
         Ok(())
@@ -275,7 +376,7 @@ impl<'a> SendStream<'a> {
             .get_mut(&self.id)
             .ok_or(UnknownStream { _private: () })?;
 
-        if matches!(stream.state, SendState::ResetSent) {
+        if stream.is_reset() {
             // Redundant reset call
             return Err(UnknownStream { _private: () });
         }
@@ -285,12 +386,59 @@ impl<'a> SendStream<'a> {
         // credit based on the final offset communicated in the RESET_STREAM frame we send.
         self.state.unacked_data -= stream.pending.unacked();
         stream.reset();
+        track_write_deadline(&mut self.state.write_deadlines, self.id, stream);
+        self.state.set_write_deadline_timer(self.timers);
+        self.state.scheduler.remove(self.id);
         self.pending.reset_stream.push((self.id, error_code));
 
         // Don't reopen an already-closed stream we haven't forgotten yet
         Ok(())
     }
 
+    /// Abandon transmitting data on a stream, except for its first `reliable_size` bytes
+    ///
+    /// Unlike [`reset`](Self::reset), data written before `reliable_size` is still transmitted
+    /// and retransmitted as necessary, and the peer can read it before it learns of the reset.
+    /// This uses the RESET_STREAM_AT frame of draft-ietf-quic-reliable-stream-reset, which the
+    /// peer must support unless `reliable_size` is zero, in which case this is equivalent to
+    /// `reset`.
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn reset_at(&mut self, error_code: VarInt, reliable_size: u64) -> Result<(), ResetAtError> {
+        if reliable_size == 0 {
+            return self
+                .reset(error_code)
+                .map_err(|_| ResetAtError::UnknownStream);
+        }
+        let stream = self
+            .state
+            .send
+            .get_mut(&self.id)
+            .ok_or(ResetAtError::UnknownStream)?;
+
+        if stream.is_reset() {
+            return Err(ResetAtError::UnknownStream);
+        }
+        if !self.state.peer_supports_reset_stream_at {
+            return Err(ResetAtError::Unsupported);
+        }
+        if reliable_size > stream.offset() {
+            return Err(ResetAtError::ReliableSizeTooLarge);
+        }
+
+        // As in `reset`, but data below the reliable size still occupies the send window until
+        // it's acknowledged
+        self.state.unacked_data -= stream.reset_at(reliable_size);
+        track_write_deadline(&mut self.state.write_deadlines, self.id, stream);
+        if !stream.is_pending() {
+            self.state.scheduler.remove(self.id);
+        }
+        self.state.set_write_deadline_timer(self.timers);
+        self.pending.reset_stream.push((self.id, error_code));
+        Ok(())
+    }
+
     /// Set the priority of a stream
     ///
     /// # Panics
@@ -306,74 +454,52 @@ impl<'a> SendStream<'a> {
         Ok(())
     }
 
-    /// Get the priority of a stream
+    /// Set whether a stream's data may be interleaved with that of other streams
+    ///
+    /// Only affects schedulers which distinguish incremental streams, such as
+    /// [`ExtensiblePriorities`](crate::scheduler::ExtensiblePriorities). Streams aren't
+    /// incremental by default. As with priorities, a change may only take effect after the stream
+    /// next transmits.
     ///
     /// # Panics
     /// - when applied to a receive stream
-    pub fn priority(&self) -> Result<i32, UnknownStream> {
+    pub fn set_incremental(&mut self, incremental: bool) -> Result<(), UnknownStream> {
         let stream = self
             .state
             .send
-            .get(&self.id)
+            .get_mut(&self.id)
             .ok_or(UnknownStream { _private: () })?;
 
-        Ok(stream.priority)
-    }
-}
-
-fn push_pending(pending: &mut BinaryHeap<PendingLevel>, id: StreamId, priority: i32) {
-    for level in pending.iter() {
-        if priority == level.priority {
-            level.queue.borrow_mut().push_back(id);
-            return;
-        }
-    }
-
-    // If there is only a single level and it's empty, repurpose it for the
-    // required priority
-    if pending.len() == 1 {
-        if let Some(mut first) = pending.peek_mut() {
-            let mut queue = first.queue.borrow_mut();
-            if queue.is_empty() {
-                queue.push_back(id);
-                drop(queue);
-                first.priority = priority;
-                return;
-            }
-        }
+        stream.incremental = incremental;
+        Ok(())
     }
 
-    let mut queue = VecDeque::new();
-    queue.push_back(id);
-    pending.push(PendingLevel {
-        queue: RefCell::new(queue),
-        priority,
-    });
-}
-
-struct PendingLevel {
-    // RefCell is needed because BinaryHeap doesn't have an iter_mut()
-    queue: RefCell<VecDeque<StreamId>>,
-    priority: i32,
-}
-
-impl PartialEq for PendingLevel {
-    fn eq(&self, other: &Self) -> bool {
-        self.priority.eq(&other.priority)
-    }
-}
+    /// Get whether a stream is incremental
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn incremental(&self) -> Result<bool, UnknownStream> {
+        let stream = self
+            .state
+            .send
+            .get(&self.id)
+            .ok_or(UnknownStream { _private: () })?;
 
-impl PartialOrd for PendingLevel {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+        Ok(stream.incremental)
     }
-}
 
-impl Eq for PendingLevel {}
+    /// Get the priority of a stream
+    ///
+    /// # Panics
+    /// - when applied to a receive stream
+    pub fn priority(&self) -> Result<i32, UnknownStream> {
+        let stream = self
+            .state
+            .send
+            .get(&self.id)
+            .ok_or(UnknownStream { _private: () })?;
 
-impl Ord for PendingLevel {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.priority.cmp(&other.priority)
+        Ok(stream.priority)
     }
 }
 
@@ -409,6 +535,16 @@ pub enum StreamEvent {
         /// Error code supplied by the peer
         error_code: VarInt,
     },
+    /// Data written to an outgoing stream wasn't acknowledged by its deadline, so the stream was
+    /// reset
+    ///
+    /// See [`SendStream::write_with_deadline`].
+    Expired {
+        /// Which stream has been reset
+        id: StreamId,
+        /// Error code the stream was reset with
+        error_code: VarInt,
+    },
     /// At least one new stream of a certain directionality may be opened
     Available {
         /// Directionality for which streams are newly available
@@ -1,5 +1,6 @@
 use std::collections::hash_map::Entry;
 use std::mem;
+use std::time::{Duration, Instant};
 
 use thiserror::Error;
 use tracing::debug;
@@ -13,6 +14,9 @@ pub(super) struct Recv {
     state: RecvState,
     pub(super) assembler: Assembler,
     sent_max_stream_data: u64,
+    /// Flow control credit to issue beyond the data read by the application
+    pub(super) window: u64,
+    tuner: WindowTuner,
     pub(super) end: u64,
     pub(super) stopped: bool,
 }
@@ -23,6 +27,8 @@ impl Recv {
             state: RecvState::default(),
             assembler: Assembler::new(),
             sent_max_stream_data: initial_max_data,
+            window: initial_max_data,
+            tuner: WindowTuner::default(),
             end: 0,
             stopped: false,
         }
@@ -52,6 +58,17 @@ impl Recv {
             }
         }
 
+        if let RecvState::ResetRecvd { reliable_size, .. } = self.state {
+            // Credit for the whole stream was consumed by the reset; only data the peer still
+            // delivers is of interest
+            if frame.offset < reliable_size {
+                let mut data = frame.data;
+                data.truncate((reliable_size - frame.offset) as usize);
+                self.assembler.insert(frame.offset, data, payload_len);
+            }
+            return Ok((0, false));
+        }
+
         let new_bytes = self.credit_consumed_by(end, received, max_data)?;
 
         // Stopped streams don't need to wait for the actual data, they just need to know
@@ -95,21 +112,34 @@ impl Recv {
     /// transmission of the value is recommended. If the boolean value is
     /// `false` the new window should only be transmitted if a previous transmission
     /// had failed.
-    pub(super) fn max_stream_data(&mut self, stream_receive_window: u64) -> (u64, ShouldTransmit) {
-        let max_stream_data = self.assembler.bytes_read() + stream_receive_window;
+    pub(super) fn max_stream_data(&mut self) -> (u64, ShouldTransmit) {
+        let max_stream_data = self.assembler.bytes_read() + self.window;
 
         // Only announce a window update if it's significant enough
         // to make it worthwhile sending a MAX_STREAM_DATA frame.
         // We use here a fraction of the configured stream receive window to make
         // the decision, and accommodate for streams using bigger windows requiring
         // less updates. A fixed size would also work - but it would need to be
-        // smaller than `stream_receive_window` in order to make sure the stream
+        // smaller than the window in order to make sure the stream
         // does not get stuck.
         let diff = max_stream_data - self.sent_max_stream_data;
-        let transmit = self.receiving_unknown_size() && diff >= (stream_receive_window / 8);
+        let transmit = self.receiving_unknown_size() && diff >= (self.window / 8);
         (max_stream_data, ShouldTransmit(transmit))
     }
 
+    /// Grows the window, up to `limit`, if it's too small for the rate at which data is read
+    ///
+    /// Returns whether the window grew.
+    pub(super) fn tune_window(&mut self, now: Instant, rtt: Duration, limit: u64) -> bool {
+        let read = self.assembler.bytes_read();
+        let grow = self.tuner.sample(now, rtt, self.window, read);
+        if !grow || self.window >= limit {
+            return false;
+        }
+        self.window = self.window.saturating_mul(2).min(limit);
+        true
+    }
+
     /// Records that a `MAX_STREAM_DATA` announcing a certain window was sent
     ///
     /// This will suppress enqueuing further `MAX_STREAM_DATA` frames unless
@@ -130,6 +160,20 @@ impl Recv {
         matches!(self.state, RecvState::Recv { .. })
     }
 
+    /// Whether the stream was reset, but data the peer still delivers hasn't all been read
+    pub(super) fn is_awaiting_reliable_data(&self) -> bool {
+        match self.state {
+            RecvState::ResetRecvd { reliable_size, .. } => {
+                !self.assembler.is_read_up_to(reliable_size)
+            }
+            RecvState::Recv { .. } => false,
+        }
+    }
+
+    pub(super) fn is_reset(&self) -> bool {
+        matches!(self.state, RecvState::ResetRecvd { .. })
+    }
+
     fn final_offset(&self) -> Option<u64> {
         match self.state {
             RecvState::Recv { size } => size,
@@ -138,10 +182,14 @@ impl Recv {
     }
 
     /// Returns `false` iff the reset was redundant
+    ///
+    /// Data below `reliable_size` remains readable. A repeated reset may lower the reliable size,
+    /// but not raise it.
     pub(super) fn reset(
         &mut self,
         error_code: VarInt,
         final_offset: VarInt,
+        reliable_size: u64,
         received: u64,
         max_data: u64,
     ) -> Result<bool, TransportError> {
@@ -157,18 +205,35 @@ impl Recv {
         }
         self.credit_consumed_by(final_offset.into(), received, max_data)?;
 
-        if matches!(self.state, RecvState::ResetRecvd { .. }) {
-            return Ok(false);
+        if let RecvState::ResetRecvd {
+            reliable_size: ref mut prev,
+            ..
+        } = self.state
+        {
+            if reliable_size > *prev {
+                return Err(TransportError::STREAM_STATE_ERROR(
+                    "reliable size increased",
+                ));
+            }
+            if reliable_size == *prev {
+                return Ok(false);
+            }
+            *prev = reliable_size;
+        } else {
+            self.state = RecvState::ResetRecvd {
+                size: final_offset.into(),
+                error_code,
+                reliable_size,
+            };
+        }
+        // Nuke buffers so that future reads fail immediately, unless the peer committed to
+        // delivering some of the data. Reads of reset streams don't issue flow control credit,
+        // since that was issued for the whole stream upon reset.
+        if reliable_size == 0 {
+            self.assembler.clear();
+        } else {
+            self.assembler.truncate(reliable_size);
         }
-        self.state = RecvState::ResetRecvd {
-            size: final_offset.into(),
-            error_code,
-        };
-        // Nuke buffers so that future reads fail immediately, which ensures future reads don't
-        // issue flow control credit redundant to that already issued. We could instead special-case
-        // reset streams during read, but it's unclear if there's any benefit to retaining data for
-        // reset streams.
-        self.assembler.clear();
         Ok(true)
     }
 
@@ -257,8 +322,15 @@ impl<'a> Chunks<'a> {
         }
 
         match rs.state {
-            RecvState::ResetRecvd { error_code, .. } => {
-                debug_assert_eq!(self.read, 0, "reset streams have empty buffers");
+            RecvState::ResetRecvd {
+                error_code,
+                reliable_size,
+                ..
+            } => {
+                if !rs.assembler.is_read_up_to(reliable_size) {
+                    // Data the peer still delivers is outstanding
+                    return Err(ReadError::Blocked);
+                }
                 self.streams.stream_freed(self.id, StreamHalf::Recv);
                 self.state = ChunksState::Reset(error_code);
                 Err(ReadError::Reset(error_code))
@@ -295,6 +367,11 @@ impl<'a> Chunks<'a> {
             return ShouldTransmit(false);
         }
 
+        let reset = match state {
+            ChunksState::Reset(_) => true,
+            ChunksState::Readable(ref rs) => rs.is_reset(),
+            _ => false,
+        };
         let mut should_transmit = false;
         // We issue additional stream ID credit after the application is notified that a previously
         // open stream has finished or been reset and we've therefore disposed of its state.
@@ -307,7 +384,7 @@ impl<'a> Chunks<'a> {
 
         // If the stream hasn't finished, we may need to issue stream-level flow control credit
         if let ChunksState::Readable(mut rs) = state {
-            let (_, max_stream_data) = rs.max_stream_data(self.streams.stream_receive_window);
+            let (_, max_stream_data) = rs.max_stream_data();
             should_transmit |= max_stream_data.0;
             if max_stream_data.0 {
                 self.pending.max_stream_data.insert(self.id);
@@ -316,7 +393,11 @@ impl<'a> Chunks<'a> {
             self.streams.recv.insert(self.id, rs);
         }
 
-        // Issue connection-level flow control credit for any data we read regardless of state
+        // Issue connection-level flow control credit for any data we read, unless it was already
+        // issued upon reset
+        if reset {
+            return ShouldTransmit(should_transmit);
+        }
         let max_data = self.streams.add_read_credits(self.read);
         self.pending.max_data |= max_data.0;
         should_transmit |= max_data.0;
@@ -337,6 +418,48 @@ enum ChunksState {
     Finalized,
 }
 
+/// Detects flow control windows which are too small for the bandwidth-delay product of the path
+///
+/// Window updates are only issued as the application reads data, so the peer becomes blocked
+/// whenever it could send more than the window in the round trip it takes an update to reach it.
+/// Like Chromium's flow controller, we consider a window too small when the rate at which data is
+/// read suggests a bandwidth-delay product of more than a quarter of the window.
+#[derive(Debug, Default)]
+pub(super) struct WindowTuner {
+    /// Time and amount of data consumed at the start of the current measurement
+    epoch: Option<(Instant, u64)>,
+}
+
+impl WindowTuner {
+    /// Records that `consumed` bytes have been consumed in total, returning whether `window`
+    /// should grow
+    ///
+    /// Measurements span at least half a window, so that the rate isn't skewed by the granularity
+    /// of reads.
+    pub(super) fn sample(
+        &mut self,
+        now: Instant,
+        rtt: Duration,
+        window: u64,
+        consumed: u64,
+    ) -> bool {
+        let (start, offset) = match self.epoch {
+            Some(x) => x,
+            None => {
+                self.epoch = Some((now, consumed));
+                return false;
+            }
+        };
+        let delta = consumed.saturating_sub(offset);
+        if delta < window / 2 {
+            return false;
+        }
+        self.epoch = Some((now, consumed));
+        let elapsed = now.saturating_duration_since(start);
+        u128::from(delta) * 4 * rtt.as_nanos() > u128::from(window) * elapsed.as_nanos()
+    }
+}
+
 /// Errors triggered when reading from a recv stream
 #[derive(Debug, Error, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum ReadError {
@@ -375,8 +498,15 @@ impl From<IllegalOrderedRead> for ReadableError {
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum RecvState {
-    Recv { size: Option<u64> },
-    ResetRecvd { size: u64, error_code: VarInt },
+    Recv {
+        size: Option<u64>,
+    },
+    ResetRecvd {
+        size: u64,
+        error_code: VarInt,
+        /// Amount of data the peer still delivers, from the start of the stream
+        reliable_size: u64,
+    },
 }
 
 impl Default for RecvState {
@@ -1,3 +1,5 @@
+use std::{collections::VecDeque, mem, ops::Range, time::Instant};
+
 use bytes::Bytes;
 use thiserror::Error;
 
@@ -9,12 +11,22 @@ pub(super) struct Send {
     pub(super) state: SendState,
     pub(super) pending: SendBuffer,
     pub(super) priority: i32,
+    /// Whether the stream's data may be interleaved with that of other streams
+    pub(super) incremental: bool,
     /// Whether a frame containing a FIN bit must be transmitted, even if we don't have any new data
     pub(super) fin_pending: bool,
     /// Whether this stream is in the `connection_blocked` list of `Streams`
     pub(super) connection_blocked: bool,
+    /// Whether a write failed because it didn't fit in the stream-level flow control window
+    pub(super) write_blocked: bool,
     /// The reason the peer wants us to stop, if `STOP_SENDING` was received
     pub(super) stop_reason: Option<VarInt>,
+    /// Data written with a deadline that may not have been acknowledged yet, in stream order
+    pub(super) deadlines: VecDeque<WriteDeadline>,
+    /// The deadline this stream is tracked under in `StreamsState::write_deadlines`
+    pub(super) next_deadline: Option<Instant>,
+    /// The error code the stream was reset with because a write deadline passed
+    pub(super) expired: Option<VarInt>,
 }
 
 impl Send {
@@ -24,9 +36,14 @@ impl Send {
             state: SendState::Ready,
             pending: SendBuffer::new(),
             priority: 0,
+            incremental: false,
             fin_pending: false,
             connection_blocked: false,
+            write_blocked: false,
             stop_reason: None,
+            deadlines: VecDeque::new(),
+            next_deadline: None,
+            expired: None,
         }
     }
 
@@ -35,6 +52,11 @@ impl Send {
         matches!(self.state, SendState::ResetSent { .. })
     }
 
+    /// Whether the stream has been reset without any data left to deliver
+    pub(super) fn is_abandoned(&self) -> bool {
+        matches!(self.state, SendState::ResetSent { reliable: None })
+    }
+
     pub(super) fn finish(&mut self) -> Result<(), FinishError> {
         if let Some(error_code) = self.stop_reason {
             Err(FinishError::Stopped(error_code))
@@ -53,7 +75,11 @@ impl Send {
         &mut self,
         source: &mut S,
         limit: u64,
+        required: u64,
     ) -> Result<Written, WriteError> {
+        if let Some(error_code) = self.expired {
+            return Err(WriteError::Expired(error_code));
+        }
         if !self.is_writable() {
             return Err(WriteError::UnknownStream);
         }
@@ -61,7 +87,8 @@ impl Send {
             return Err(WriteError::Stopped(error_code));
         }
         let budget = self.max_data - self.pending.offset();
-        if budget == 0 {
+        if budget < required {
+            self.write_blocked = true;
             return Err(WriteError::Blocked);
         }
         let mut limit = limit.min(budget) as usize;
@@ -87,7 +114,44 @@ impl Send {
     pub(super) fn reset(&mut self) {
         use SendState::*;
         if let DataSent { .. } | Ready = self.state {
-            self.state = ResetSent;
+            self.state = ResetSent { reliable: None };
+        }
+        self.deadlines.clear();
+    }
+
+    /// Update stream state due to a reset which still delivers data up to `reliable_size`
+    ///
+    /// Returns the amount of unacknowledged data that no longer needs to be delivered.
+    pub(super) fn reset_at(&mut self, reliable_size: u64) -> u64 {
+        let final_size = self.pending.offset();
+        let unacked = self.pending.unacked();
+        self.pending.truncate(reliable_size);
+        self.fin_pending = false;
+        self.deadlines.clear();
+        self.state = SendState::ResetSent {
+            reliable: Some(ReliableReset {
+                final_size,
+                reliable_size,
+                acked: false,
+            }),
+        };
+        unacked - self.pending.unacked()
+    }
+
+    /// Handle acknowledgement of a reset
+    ///
+    /// Returns whether the stream is done, i.e. all data which had to be delivered has been
+    /// acknowledged too.
+    pub(super) fn reset_acked(&mut self) -> bool {
+        match self.state {
+            SendState::ResetSent { reliable: None } => true,
+            SendState::ResetSent {
+                reliable: Some(ref mut reliable),
+            } => {
+                reliable.acked = true;
+                self.pending.is_fully_acked()
+            }
+            _ => false,
         }
     }
 
@@ -114,10 +178,51 @@ impl Send {
                 *finish_acked |= frame.fin;
                 *finish_acked && self.pending.is_fully_acked()
             }
+            SendState::ResetSent {
+                reliable: Some(reliable),
+            } => reliable.acked && self.pending.is_fully_acked(),
             _ => false,
         }
     }
 
+    /// Forget the deadlines of fully acknowledged writes, returning the earliest remaining one
+    pub(super) fn forget_acked_deadlines(&mut self) -> Option<Instant> {
+        let pending = &self.pending;
+        self.deadlines
+            .retain(|x| !pending.is_acked(x.range.clone()));
+        self.deadlines.iter().map(|x| x.deadline).min()
+    }
+
+    /// Discard messages whose deadline passed before any of their data was sent
+    ///
+    /// Returns the number of bytes discarded, and the error code to reset the stream with if
+    /// other data wasn't acknowledged in time.
+    pub(super) fn expire_deadlines(&mut self, now: Instant) -> (u64, Option<VarInt>) {
+        let mut discarded = 0;
+        let mut i = 0;
+        while let Some(x) = self.deadlines.get(i) {
+            if x.deadline > now {
+                i += 1;
+                continue;
+            }
+            if !x.message || x.range.start < self.pending.unsent() {
+                // Skipping data the peer may have started reading would tear it
+                return (discarded, Some(x.error_code));
+            }
+            let range = x.range.clone();
+            let len = range.end - range.start;
+            self.pending.discard_unsent(range);
+            self.deadlines.remove(i);
+            // Everything written after the message moves back to take its place
+            for later in self.deadlines.range_mut(i..) {
+                later.range.start -= len;
+                later.range.end -= len;
+            }
+            discarded += len;
+        }
+        (discarded, None)
+    }
+
     /// Handle increase to stream-level flow control limit
     ///
     /// Returns whether the stream was unblocked
@@ -125,7 +230,8 @@ impl Send {
         if offset <= self.max_data || self.state != SendState::Ready {
             return false;
         }
-        let was_blocked = self.pending.offset() == self.max_data;
+        let was_blocked =
+            self.pending.offset() == self.max_data || mem::take(&mut self.write_blocked);
         self.max_data = offset;
         was_blocked
     }
@@ -143,6 +249,17 @@ impl Send {
     }
 }
 
+/// A range of stream data which must be acknowledged by a certain time
+#[derive(Debug)]
+pub(super) struct WriteDeadline {
+    pub(super) range: Range<u64>,
+    pub(super) deadline: Instant,
+    /// Error code to reset the stream with if the deadline passes
+    pub(super) error_code: VarInt,
+    /// Whether the range is a message which may be skipped rather than resetting the stream
+    pub(super) message: bool,
+}
+
 /// A [`BytesSource`] implementation for `&'a mut [Bytes]`
 ///
 /// The type allows to dequeue [`Bytes`] chunks from an array of chunks, up to
@@ -273,6 +390,21 @@ pub enum WriteError {
     /// [`StreamEvent::Finished`]: crate::StreamEvent::Finished
     #[error("stopped by peer: code {0}")]
     Stopped(VarInt),
+    /// Data written with a deadline wasn't acknowledged in time, so the stream has been reset.
+    /// The stream cannot be finished or further written to.
+    ///
+    /// Carries the error code supplied to [`SendStream::write_with_deadline`].
+    ///
+    /// [`SendStream::write_with_deadline`]: crate::SendStream::write_with_deadline
+    #[error("write deadline expired: code {0}")]
+    Expired(VarInt),
+    /// A message written with [`SendStream::write_message_with_deadline`] is larger than the
+    /// configured send window or the flow control windows granted by the peer, so it can't be
+    /// written at once
+    ///
+    /// [`SendStream::write_message_with_deadline`]: crate::SendStream::write_message_with_deadline
+    #[error("message too large")]
+    TooLarge,
     /// The stream has not been opened or has already been finished or reset
     #[error("unknown stream")]
     UnknownStream,
@@ -284,8 +416,19 @@ pub(super) enum SendState {
     Ready,
     /// Stream was finished; now sending retransmits only
     DataSent { finish_acked: bool },
-    /// Sent RESET
-    ResetSent,
+    /// Sent RESET_STREAM, or RESET_STREAM_AT if some data must still be delivered
+    ResetSent { reliable: Option<ReliableReset> },
+}
+
+/// Progress of a reset which still delivers a prefix of the stream
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) struct ReliableReset {
+    /// The final size of the stream, which may exceed the data still buffered
+    pub(super) final_size: u64,
+    /// Amount of data, from the start of the stream, which is still delivered
+    pub(super) reliable_size: u64,
+    /// Whether the peer acknowledged the RESET_STREAM_AT frame
+    pub(super) acked: bool,
 }
 
 /// Reasons why attempting to finish a stream might fail
@@ -304,6 +447,20 @@ pub enum FinishError {
     UnknownStream,
 }
 
+/// Reasons why attempting to reset a stream with a reliable size might fail
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum ResetAtError {
+    /// The peer doesn't support reliable stream resets, so data can't be delivered after a reset
+    #[error("reliable stream resets unsupported by peer")]
+    Unsupported,
+    /// The reliable size lies beyond the data written to the stream
+    #[error("reliable size exceeds data written")]
+    ReliableSizeTooLarge,
+    /// The stream has not been opened or was already reset
+    #[error("unknown stream")]
+    UnknownStream,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,7 +1,8 @@
 use std::{
-    collections::{binary_heap::PeekMut, hash_map, BinaryHeap, VecDeque},
+    collections::{hash_map, BTreeSet, VecDeque},
     convert::TryFrom,
     mem,
+    time::{Duration, Instant},
 };
 
 use bytes::{BufMut, BytesMut};
@@ -9,13 +10,17 @@ use rustc_hash::FxHashMap;
 use tracing::{debug, trace};
 
 use super::{
-    push_pending, PendingLevel, Recv, Retransmits, Send, SendState, ShouldTransmit, StreamEvent,
-    StreamHalf, ThinRetransmits,
+    Recv, Retransmits, Send, SendState, ShouldTransmit, StreamEvent, StreamHalf, ThinRetransmits,
+    WindowTuner,
 };
 use crate::{
     coding::BufMutExt,
-    connection::stats::FrameStats,
+    connection::{
+        stats::FrameStats,
+        timer::{Timer, TimerTable},
+    },
     frame::{self, FrameStruct, StreamMetaVec},
+    scheduler::StreamScheduler,
     transport_parameters::TransportParameters,
     Dir, Side, StreamId, TransportError, VarInt, MAX_STREAM_COUNT,
 };
@@ -53,7 +58,9 @@ pub struct StreamsState {
     /// permitted to open but which have not yet been opened.
     pub(super) send_streams: usize,
     /// Streams with outgoing data queued
-    pub(super) pending: BinaryHeap<PendingLevel>,
+    pub(super) scheduler: Box<dyn StreamScheduler>,
+    /// Earliest deadline of each stream's unacknowledged writes, see `track_write_deadline`
+    pub(super) write_deadlines: BTreeSet<(Instant, StreamId)>,
 
     events: VecDeque<StreamEvent>,
     /// Streams blocked on connection-level flow control or stream window space
@@ -86,9 +93,16 @@ pub struct StreamsState {
     initial_max_stream_data_uni: VarInt,
     initial_max_stream_data_bidi_local: VarInt,
     initial_max_stream_data_bidi_remote: VarInt,
+    /// Whether the peer supports RESET_STREAM_AT
+    pub(super) peer_supports_reset_stream_at: bool,
 
     /// The shrink to be applied to local_max_data when receive_window is shrunk
     receive_window_shrink_debt: u64,
+    /// Size up to which `receive_window` may be grown automatically
+    receive_window_limit: u64,
+    /// Size up to which the window of each receive stream may be grown automatically
+    stream_receive_window_limit: u64,
+    receive_window_tuner: WindowTuner,
 }
 
 impl StreamsState {
@@ -100,6 +114,7 @@ impl StreamsState {
         send_window: u64,
         receive_window: VarInt,
         stream_receive_window: VarInt,
+        scheduler: Box<dyn StreamScheduler>,
     ) -> Self {
         let mut this = Self {
             side,
@@ -115,7 +130,8 @@ impl StreamsState {
             opened: [false, false],
             next_reported_remote: [0, 0],
             send_streams: 0,
-            pending: BinaryHeap::new(),
+            scheduler,
+            write_deadlines: BTreeSet::new(),
             events: VecDeque::new(),
             connection_blocked: Vec::new(),
             max_data: 0,
@@ -131,7 +147,11 @@ impl StreamsState {
             initial_max_stream_data_uni: 0u32.into(),
             initial_max_stream_data_bidi_local: 0u32.into(),
             initial_max_stream_data_bidi_remote: 0u32.into(),
+            peer_supports_reset_stream_at: false,
             receive_window_shrink_debt: 0,
+            receive_window_limit: receive_window.into(),
+            stream_receive_window_limit: stream_receive_window.into(),
+            receive_window_tuner: WindowTuner::default(),
         };
 
         for dir in Dir::iter() {
@@ -147,6 +167,7 @@ impl StreamsState {
         self.initial_max_stream_data_uni = params.initial_max_stream_data_uni;
         self.initial_max_stream_data_bidi_local = params.initial_max_stream_data_bidi_local;
         self.initial_max_stream_data_bidi_remote = params.initial_max_stream_data_bidi_remote;
+        self.peer_supports_reset_stream_at = params.reset_stream_at;
         self.max[Dir::Bi as usize] = params.initial_max_streams_bidi.into();
         self.max[Dir::Uni as usize] = params.initial_max_streams_uni.into();
         self.received_max_data(params.initial_max_data);
@@ -191,7 +212,8 @@ impl StreamsState {
             }
         }
 
-        self.pending.clear();
+        self.scheduler.clear();
+        self.write_deadlines.clear();
         self.send_streams = 0;
         self.data_sent = 0;
         self.connection_blocked.clear();
@@ -219,7 +241,7 @@ impl StreamsState {
             }
         };
 
-        if !rs.is_receiving() {
+        if !rs.is_receiving() && !rs.is_awaiting_reliable_data() {
             trace!("dropping frame for finished stream");
             return Ok(ShouldTransmit(false));
         }
@@ -260,7 +282,41 @@ impl StreamsState {
             debug!("received illegal RESET_STREAM frame");
             e
         })?;
+        self.on_reset(id, error_code, final_offset, 0)
+    }
 
+    /// Process incoming RESET_STREAM_AT frame
+    ///
+    /// If successful, returns whether a `MAX_DATA` frame needs to be transmitted
+    pub(crate) fn received_reset_at(
+        &mut self,
+        frame: frame::ResetStreamAt,
+    ) -> Result<ShouldTransmit, TransportError> {
+        let frame::ResetStreamAt {
+            id,
+            error_code,
+            final_offset,
+            reliable_size,
+        } = frame;
+        self.validate_receive_id(id).map_err(|e| {
+            debug!("received illegal RESET_STREAM_AT frame");
+            e
+        })?;
+        if reliable_size > final_offset {
+            return Err(TransportError::FRAME_ENCODING_ERROR(
+                "reliable size exceeds final size",
+            ));
+        }
+        self.on_reset(id, error_code, final_offset, reliable_size.into())
+    }
+
+    fn on_reset(
+        &mut self,
+        id: StreamId,
+        error_code: VarInt,
+        final_offset: VarInt,
+        reliable_size: u64,
+    ) -> Result<ShouldTransmit, TransportError> {
         let rs = match self.recv.get_mut(&id) {
             Some(stream) => stream,
             None => {
@@ -270,15 +326,23 @@ impl StreamsState {
         };
 
         // State transition
+        let was_reset = rs.is_reset();
         if !rs.reset(
             error_code,
             final_offset,
+            reliable_size,
             self.data_recvd,
             self.local_max_data,
         )? {
             // Redundant reset
             return Ok(ShouldTransmit(false));
         }
+        if was_reset {
+            // Only the reliable size was lowered, which may let a blocked read complete. Flow
+            // control was settled by the first reset.
+            self.on_stream_frame(true, id);
+            return Ok(ShouldTransmit(false));
+        }
         let bytes_read = rs.assembler.bytes_read();
         let stopped = rs.stopped;
         let end = rs.end;
@@ -289,7 +353,8 @@ impl StreamsState {
         }
         self.on_stream_frame(!stopped, id);
 
-        // Update flow control
+        // Update flow control. Credit for data below the reliable size is issued here too, rather
+        // than as the application reads it.
         Ok(if bytes_read != final_offset.into_inner() {
             // bytes_read is always <= end, so this won't underflow.
             self.data_recvd = self
@@ -319,8 +384,8 @@ impl StreamsState {
     pub(crate) fn reset_acked(&mut self, id: StreamId) {
         match self.send.entry(id) {
             hash_map::Entry::Vacant(_) => {}
-            hash_map::Entry::Occupied(e) => {
-                if let SendState::ResetSent = e.get().state {
+            hash_map::Entry::Occupied(mut e) => {
+                if e.get_mut().reset_acked() {
                     e.remove_entry();
                     self.stream_freed(id, StreamHalf::Send);
                 }
@@ -330,14 +395,7 @@ impl StreamsState {
 
     /// Whether any stream data is queued, regardless of control frames
     pub(crate) fn can_send_stream_data(&self) -> bool {
-        // Reset streams may linger in the pending stream list, but will never produce stream frames
-        self.pending.iter().any(|level| {
-            level
-                .queue
-                .borrow()
-                .iter()
-                .any(|id| self.send.get(id).map_or(false, |s| !s.is_reset()))
-        })
+        !self.scheduler.is_empty()
     }
 
     /// Whether MAX_STREAM_DATA frames could be sent for stream `id`
@@ -354,9 +412,11 @@ impl StreamsState {
         retransmits: &mut ThinRetransmits,
         stats: &mut FrameStats,
         max_size: usize,
+        now: Instant,
+        rtt: Duration,
     ) {
-        // RESET_STREAM
-        while buf.len() + frame::ResetStream::SIZE_BOUND < max_size {
+        // RESET_STREAM, RESET_STREAM_AT
+        while buf.len() + frame::ResetStreamAt::SIZE_BOUND < max_size {
             let (id, error_code) = match pending.reset_stream.pop() {
                 Some(x) => x,
                 None => break,
@@ -365,18 +425,36 @@ impl StreamsState {
                 Some(x) => x,
                 None => continue,
             };
-            trace!(stream = %id, "RESET_STREAM");
             retransmits
                 .get_or_create()
                 .reset_stream
                 .push((id, error_code));
-            frame::ResetStream {
-                id,
-                error_code,
-                final_offset: VarInt::try_from(stream.offset()).expect("impossibly large offset"),
+            if let SendState::ResetSent {
+                reliable: Some(reliable),
+            } = stream.state
+            {
+                trace!(stream = %id, reliable_size = reliable.reliable_size, "RESET_STREAM_AT");
+                frame::ResetStreamAt {
+                    id,
+                    error_code,
+                    final_offset: VarInt::try_from(reliable.final_size)
+                        .expect("impossibly large offset"),
+                    reliable_size: VarInt::try_from(reliable.reliable_size)
+                        .expect("impossibly large offset"),
+                }
+                .encode(buf);
+                stats.reset_stream_at += 1;
+            } else {
+                trace!(stream = %id, "RESET_STREAM");
+                frame::ResetStream {
+                    id,
+                    error_code,
+                    final_offset: VarInt::try_from(stream.offset())
+                        .expect("impossibly large offset"),
+                }
+                .encode(buf);
+                stats.reset_stream += 1;
             }
-            .encode(buf);
-            stats.reset_stream += 1;
         }
 
         // STOP_SENDING
@@ -401,6 +479,7 @@ impl StreamsState {
         // MAX_DATA
         if pending.max_data && buf.len() + 9 < max_size {
             pending.max_data = false;
+            self.tune_receive_window(now, rtt);
 
             // `local_max_data` can grow bigger than `VarInt`.
             // For transmission inside QUIC frames we need to clamp it to the
@@ -437,7 +516,10 @@ impl StreamsState {
             }
             retransmits.get_or_create().max_stream_data.insert(id);
 
-            let (max, _) = rs.max_stream_data(self.stream_receive_window);
+            let grown = rs
+                .tune_window(now, rtt, self.stream_receive_window_limit)
+                .then_some(rs.window);
+            let (max, _) = rs.max_stream_data();
             rs.record_sent_max_stream_data(max);
 
             trace!(stream = %id, max = max, "MAX_STREAM_DATA");
@@ -445,6 +527,15 @@ impl StreamsState {
             buf.write(id);
             buf.write_var(max);
             stats.max_stream_data += 1;
+
+            // Keep the connection-level window large enough for the grown stream to make use of
+            if let Some(window) = grown {
+                trace!(stream = %id, window, "stream receive window grown");
+                let target = window.saturating_add(window / 2);
+                if self.grow_receive_window(target.min(self.receive_window_limit)) {
+                    pending.max_data = true;
+                }
+            }
         }
 
         // MAX_STREAMS
@@ -473,10 +564,59 @@ impl StreamsState {
         }
     }
 
+    /// Arm the stream deadline timer for the earliest write deadline, if any
+    pub(crate) fn set_write_deadline_timer(&self, timers: &mut TimerTable) {
+        match self.write_deadlines.iter().next() {
+            Some(&(deadline, _)) => timers.set(Timer::StreamDeadline, deadline),
+            None => timers.stop(Timer::StreamDeadline),
+        }
+    }
+
+    /// Skip or reset data which wasn't acknowledged by its deadline
+    pub(crate) fn expire_writes(&mut self, now: Instant, pending: &mut Retransmits) {
+        while let Some(&(deadline, id)) = self.write_deadlines.iter().next() {
+            if deadline > now {
+                break;
+            }
+            let stream = match self.send.get_mut(&id) {
+                Some(x) => x,
+                None => {
+                    self.write_deadlines.remove(&(deadline, id));
+                    continue;
+                }
+            };
+            stream.forget_acked_deadlines();
+            let (discarded, reset) = stream.expire_deadlines(now);
+            if discarded > 0 {
+                debug!(%id, discarded, "write deadline expired, skipping unsent messages");
+                // The skipped data will never be sent, so it no longer counts against flow control
+                self.data_sent -= discarded;
+                self.unacked_data -= discarded;
+            }
+            if let Some(error_code) = reset {
+                debug!(%id, "write deadline expired, resetting stream");
+                // As in `SendStream::reset`
+                self.unacked_data -= stream.pending.unacked();
+                stream.reset();
+                self.scheduler.remove(id);
+                stream.expired = Some(error_code);
+                pending.reset_stream.push((id, error_code));
+                self.events
+                    .push_back(StreamEvent::Expired { id, error_code });
+            }
+            track_write_deadline(&mut self.write_deadlines, id, stream);
+        }
+    }
+
+    /// Write STREAM frames for pending streams, most urgent first
+    ///
+    /// If `above` is set, stream data is only written while the scheduler's
+    /// [`max_priority`](crate::scheduler::StreamScheduler::max_priority) exceeds it.
     pub(crate) fn write_stream_frames(
         &mut self,
         buf: &mut BytesMut,
         max_buf_size: usize,
+        above: Option<i32>,
     ) -> StreamMetaVec {
         let mut stream_frames = StreamMetaVec::new();
         while buf.len() + frame::Stream::SIZE_BOUND < max_buf_size {
@@ -487,36 +627,28 @@ impl StreamsState {
                 break;
             }
 
-            let num_levels = self.pending.len();
-            let mut level = match self.pending.peek_mut() {
+            if above.map_or(false, |x| {
+                self.scheduler.max_priority().map_or(true, |p| p <= x)
+            }) {
+                break;
+            }
+            let id = match self.scheduler.peek() {
                 Some(x) => x,
                 None => break,
             };
-            // Poppping data from the front of the queue, storing as much data
-            // as possible in a single frame, and enqueing sending further
-            // remaining data at the end of the queue helps with fairness.
-            // Other streams will have a chance to write data before we touch
-            // this stream again.
-            let id = match level.queue.get_mut().pop_front() {
-                Some(x) => x,
-                None => {
-                    debug_assert!(
-                        num_levels == 1,
-                        "An empty queue is only allowed for a single level"
-                    );
-                    break;
-                }
-            };
             let stream = match self.send.get_mut(&id) {
                 Some(s) => s,
-                // Stream was reset with pending data and the reset was acknowledged
-                None => continue,
+                // Stream state was discarded while it was queued
+                None => {
+                    self.scheduler.pop();
+                    continue;
+                }
             };
+            self.scheduler.pop();
 
-            // Reset streams aren't removed from the pending list and still exist while the peer
-            // hasn't acknowledged the reset, but should not generate STREAM frames, so we need to
-            // check for them explicitly.
-            if stream.is_reset() {
+            // Streams leave the scheduler when they're reset, but check explicitly in case a
+            // stream ended up queued without anything to send.
+            if stream.is_abandoned() || !stream.is_pending() {
                 continue;
             }
 
@@ -530,25 +662,11 @@ impl StreamsState {
                 stream.fin_pending = false;
             }
 
+            // Taking as much data as fits in a single frame and queuing the stream again behind
+            // others lets the scheduler decide how capacity is shared.
+            self.scheduler.on_transmit(id, offsets.end - offsets.start);
             if stream.is_pending() {
-                if level.priority == stream.priority {
-                    // Enqueue for the same level
-                    level.queue.get_mut().push_back(id);
-                } else {
-                    // Enqueue for a different level. If the current level is empty, drop it
-                    if level.queue.borrow().is_empty() && num_levels != 1 {
-                        // We keep the last level around even in empty form so that
-                        // the next insert doesn't have to reallocate the queue
-                        PeekMut::pop(level);
-                    } else {
-                        drop(level);
-                    }
-                    push_pending(&mut self.pending, id, stream.priority);
-                }
-            } else if level.queue.borrow().is_empty() && num_levels != 1 {
-                // We keep the last level around even in empty form so that
-                // the next insert doesn't have to reallocate the queue
-                PeekMut::pop(level);
+                self.scheduler.push(id, stream.priority, stream.incremental);
             }
 
             let meta = frame::StreamMeta { id, offsets, fin };
@@ -594,20 +712,33 @@ impl StreamsState {
             hash_map::Entry::Occupied(e) => e,
         };
         let stream = entry.get_mut();
-        if stream.is_reset() {
+        if stream.is_abandoned() {
             // We account for outstanding data on reset streams at time of reset
             return;
         }
         let id = frame.id;
-        self.unacked_data -= frame.offsets.end - frame.offsets.start;
-        if !stream.ack(frame) {
+        let reset = stream.is_reset();
+        // Data beyond the reliable size of a reset stream was accounted for at time of reset
+        let end = match reset {
+            true => frame.offsets.end.min(stream.offset()),
+            false => frame.offsets.end,
+        };
+        self.unacked_data -= end.saturating_sub(frame.offsets.start);
+        let finished = stream.ack(frame);
+        if stream.next_deadline.is_some() {
+            // Acknowledged writes no longer need to meet their deadlines
+            track_write_deadline(&mut self.write_deadlines, id, stream);
+        }
+        if !finished {
             // The stream is unfinished or may still need retransmits
             return;
         }
 
         entry.remove_entry();
         self.stream_freed(id, StreamHalf::Send);
-        self.events.push_back(StreamEvent::Finished { id });
+        if !reset {
+            self.events.push_back(StreamEvent::Finished { id });
+        }
     }
 
     pub(crate) fn retransmit(&mut self, frame: frame::StreamMeta) {
@@ -616,8 +747,22 @@ impl StreamsState {
             None => return,
             Some(x) => x,
         };
+        if stream.is_abandoned() {
+            // Nothing is retransmitted on a reset stream
+            return;
+        }
+        let mut frame = frame;
+        if stream.is_reset() {
+            // Only data below the reliable size is still delivered
+            frame.offsets.end = frame.offsets.end.min(stream.offset());
+            frame.fin = false;
+            if frame.offsets.is_empty() {
+                return;
+            }
+        }
         if !stream.is_pending() {
-            push_pending(&mut self.pending, frame.id, stream.priority);
+            self.scheduler
+                .push(frame.id, stream.priority, stream.incremental);
         }
         stream.fin_pending |= frame.fin;
         stream.pending.retransmit(frame.offsets);
@@ -634,7 +779,7 @@ impl StreamsState {
                     continue;
                 }
                 if !stream.is_pending() {
-                    push_pending(&mut self.pending, id, stream.priority);
+                    self.scheduler.push(id, stream.priority, stream.incremental);
                 }
                 stream.pending.retransmit_all_for_0rtt();
             }
@@ -797,6 +942,45 @@ impl StreamsState {
         expanded
     }
 
+    /// Set the sizes up to which the connection and stream receive windows may be grown
+    /// automatically, or `None` to leave them at their configured values
+    pub(crate) fn set_receive_window_limits(
+        &mut self,
+        receive_window: Option<VarInt>,
+        stream_receive_window: Option<VarInt>,
+    ) {
+        self.receive_window_limit = receive_window.map_or(0, u64::from);
+        self.stream_receive_window_limit = stream_receive_window
+            .map_or(self.stream_receive_window, u64::from)
+            .max(self.stream_receive_window);
+    }
+
+    /// Grows the receive window, up to its limit, if it's too small for the rate at which data is
+    /// read
+    fn tune_receive_window(&mut self, now: Instant, rtt: Duration) {
+        // Credit is issued as data is read, so `local_max_data` tracks the data read
+        let consumed = self.local_max_data.saturating_sub(self.receive_window);
+        if self
+            .receive_window_tuner
+            .sample(now, rtt, self.receive_window, consumed)
+        {
+            let target = self.receive_window.saturating_mul(2);
+            if self.grow_receive_window(target.min(self.receive_window_limit)) {
+                trace!(window = self.receive_window, "receive window grown");
+            }
+        }
+    }
+
+    /// Increases the receive window to `size` if it's currently smaller
+    ///
+    /// Returns whether the window grew.
+    fn grow_receive_window(&mut self, size: u64) -> bool {
+        if size <= self.receive_window {
+            return false;
+        }
+        self.set_receive_window(VarInt::from_u64(size).unwrap_or(VarInt::MAX))
+    }
+
     pub(super) fn insert(&mut self, remote: bool, id: StreamId) {
         let bi = id.dir() == Dir::Bi;
         if bi || !remote {
@@ -867,12 +1051,32 @@ impl StreamsState {
     }
 }
 
+/// Track `stream` under the earliest deadline of its writes which haven't been acknowledged yet
+pub(super) fn track_write_deadline(
+    deadlines: &mut BTreeSet<(Instant, StreamId)>,
+    id: StreamId,
+    stream: &mut Send,
+) {
+    let next = stream.forget_acked_deadlines();
+    if next == stream.next_deadline {
+        return;
+    }
+    if let Some(old) = mem::replace(&mut stream.next_deadline, next) {
+        deadlines.remove(&(old, id));
+    }
+    if let Some(x) = next {
+        deadlines.insert((x, id));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        connection::State as ConnState, connection::Streams, ReadableError, RecvStream, SendStream,
-        TransportErrorCode, WriteError,
+        connection::State as ConnState,
+        connection::Streams,
+        scheduler::{ExtensiblePrioritiesConfig, StreamSchedulerFactory, StrictPriorityConfig},
+        ReadableError, RecvStream, SendStream, TransportErrorCode, WriteError,
     };
     use bytes::{Bytes, BytesMut};
 
@@ -884,6 +1088,7 @@ mod tests {
             1024 * 1024,
             (1024 * 1024u32).into(),
             (1024 * 1024u32).into(),
+            StrictPriorityConfig::default().build(),
         )
     }
 
@@ -992,6 +1197,173 @@ mod tests {
         assert_eq!(client.local_max_data - initial_max, 4096);
     }
 
+    #[test]
+    fn reset_at_flow_control() {
+        let mut client = make(Side::Client);
+        let id = StreamId::new(Side::Server, Dir::Uni, 0);
+        let initial_max = client.local_max_data;
+        let _ = client
+            .received(
+                frame::Stream {
+                    id,
+                    offset: 0,
+                    fin: false,
+                    data: Bytes::from_static(&[0; 2048]),
+                },
+                2048,
+            )
+            .unwrap();
+        assert_eq!(
+            client
+                .received_reset_at(frame::ResetStreamAt {
+                    id,
+                    error_code: 0u32.into(),
+                    final_offset: 4096u32.into(),
+                    reliable_size: 3072u32.into(),
+                })
+                .unwrap(),
+            ShouldTransmit(false)
+        );
+        assert_eq!(client.data_recvd, 4096);
+        assert_eq!(client.local_max_data - initial_max, 4096);
+
+        // Data up to the reliable size is still delivered, without redundant credit
+        let _ = client
+            .received(
+                frame::Stream {
+                    id,
+                    offset: 2048,
+                    fin: false,
+                    data: Bytes::from_static(&[0; 2048]),
+                },
+                2048,
+            )
+            .unwrap();
+        let mut pending = Retransmits::default();
+        let mut recv = RecvStream {
+            id,
+            state: &mut client,
+            pending: &mut pending,
+        };
+        let mut chunks = recv.read(true).unwrap();
+        assert_eq!(chunks.next(usize::MAX).unwrap().unwrap().bytes.len(), 2048);
+        assert_eq!(chunks.next(usize::MAX).unwrap().unwrap().bytes.len(), 1024);
+        assert_eq!(
+            chunks.next(usize::MAX).unwrap_err(),
+            crate::ReadError::Reset(0u32.into())
+        );
+        let _ = chunks.finalize();
+        assert_eq!(client.data_recvd, 4096);
+        assert_eq!(client.local_max_data - initial_max, 4096);
+    }
+
+    #[test]
+    fn reset_at_exceeding_final_size() {
+        let mut client = make(Side::Client);
+        let id = StreamId::new(Side::Server, Dir::Uni, 0);
+        assert!(client
+            .received_reset_at(frame::ResetStreamAt {
+                id,
+                error_code: 0u32.into(),
+                final_offset: 1024u32.into(),
+                reliable_size: 2048u32.into(),
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn reset_at_lowered() {
+        let mut client = make(Side::Client);
+        let id = StreamId::new(Side::Server, Dir::Uni, 0);
+        let initial_max = client.local_max_data;
+        let reset_at = |reliable_size: u32| frame::ResetStreamAt {
+            id,
+            error_code: 0u32.into(),
+            final_offset: 4096u32.into(),
+            reliable_size: reliable_size.into(),
+        };
+        let _ = client
+            .received(
+                frame::Stream {
+                    id,
+                    offset: 0,
+                    fin: false,
+                    data: Bytes::from_static(&[0; 4096]),
+                },
+                4096,
+            )
+            .unwrap();
+        let _ = client.received_reset_at(reset_at(3072)).unwrap();
+        assert!(client.received_reset_at(reset_at(3584)).is_err());
+        assert_eq!(
+            client.received_reset_at(reset_at(1024)).unwrap(),
+            ShouldTransmit(false)
+        );
+        // Flow control is only settled once
+        assert_eq!(client.data_recvd, 4096);
+        assert_eq!(client.local_max_data - initial_max, 4096);
+
+        let mut pending = Retransmits::default();
+        let mut recv = RecvStream {
+            id,
+            state: &mut client,
+            pending: &mut pending,
+        };
+        let mut chunks = recv.read(true).unwrap();
+        assert_eq!(chunks.next(usize::MAX).unwrap().unwrap().bytes.len(), 1024);
+        assert_eq!(
+            chunks.next(usize::MAX).unwrap_err(),
+            crate::ReadError::Reset(0u32.into())
+        );
+        let _ = chunks.finalize();
+    }
+
+    #[test]
+    fn reset_after_reset_at() {
+        let mut client = make(Side::Client);
+        let id = StreamId::new(Side::Server, Dir::Uni, 0);
+        let _ = client
+            .received(
+                frame::Stream {
+                    id,
+                    offset: 0,
+                    fin: false,
+                    data: Bytes::from_static(&[0; 2048]),
+                },
+                2048,
+            )
+            .unwrap();
+        let _ = client
+            .received_reset_at(frame::ResetStreamAt {
+                id,
+                error_code: 0u32.into(),
+                final_offset: 4096u32.into(),
+                reliable_size: 3072u32.into(),
+            })
+            .unwrap();
+        let _ = client
+            .received_reset(frame::ResetStream {
+                id,
+                error_code: 0u32.into(),
+                final_offset: 4096u32.into(),
+            })
+            .unwrap();
+
+        // The peer no longer delivers anything
+        let mut pending = Retransmits::default();
+        let mut recv = RecvStream {
+            id,
+            state: &mut client,
+            pending: &mut pending,
+        };
+        let mut chunks = recv.read(true).unwrap();
+        assert_eq!(
+            chunks.next(usize::MAX).unwrap_err(),
+            crate::ReadError::Reset(0u32.into())
+        );
+        let _ = chunks.finalize();
+    }
+
     #[test]
     fn reset_after_empty_frame_flow_control() {
         let mut client = make(Side::Client);
@@ -1172,6 +1544,7 @@ mod tests {
         });
 
         let (mut pending, state) = (Retransmits::default(), ConnState::Established);
+        let mut timers = TimerTable::default();
         let id = Streams {
             state: &mut server,
             conn_state: &state,
@@ -1184,6 +1557,7 @@ mod tests {
             state: &mut server,
             pending: &mut pending,
             conn_state: &state,
+            timers: &mut timers,
         };
 
         let error_code = 0u32.into();
@@ -1231,6 +1605,7 @@ mod tests {
         });
 
         let (mut pending, state) = (Retransmits::default(), ConnState::Established);
+        let mut timers = TimerTable::default();
         let mut streams = Streams {
             state: &mut server,
             conn_state: &state,
@@ -1245,6 +1620,7 @@ mod tests {
             state: &mut server,
             pending: &mut pending,
             conn_state: &state,
+            timers: &mut timers,
         };
         mid.write(b"mid").unwrap();
 
@@ -1253,6 +1629,7 @@ mod tests {
             state: &mut server,
             pending: &mut pending,
             conn_state: &state,
+            timers: &mut timers,
         };
         low.set_priority(-1).unwrap();
         low.write(b"low").unwrap();
@@ -1262,18 +1639,18 @@ mod tests {
             state: &mut server,
             pending: &mut pending,
             conn_state: &state,
+            timers: &mut timers,
         };
         high.set_priority(1).unwrap();
         high.write(b"high").unwrap();
 
         let mut buf = BytesMut::with_capacity(40);
-        let meta = server.write_stream_frames(&mut buf, 40);
+        let meta = server.write_stream_frames(&mut buf, 40, None);
         assert_eq!(meta[0].id, id_high);
         assert_eq!(meta[1].id, id_mid);
         assert_eq!(meta[2].id, id_low);
 
         assert!(!server.can_send_stream_data());
-        assert_eq!(server.pending.len(), 1);
     }
 
     #[test]
@@ -1287,6 +1664,7 @@ mod tests {
         });
 
         let (mut pending, state) = (Retransmits::default(), ConnState::Established);
+        let mut timers = TimerTable::default();
         let mut streams = Streams {
             state: &mut server,
             conn_state: &state,
@@ -1300,19 +1678,19 @@ mod tests {
             state: &mut server,
             pending: &mut pending,
             conn_state: &state,
+            timers: &mut timers,
         };
         assert_eq!(mid.write(b"mid").unwrap(), 3);
-        assert_eq!(server.pending.len(), 1);
 
         let mut high = SendStream {
             id: id_high,
             state: &mut server,
             pending: &mut pending,
             conn_state: &state,
+            timers: &mut timers,
         };
         high.set_priority(1).unwrap();
         assert_eq!(high.write(&[0; 200]).unwrap(), 200);
-        assert_eq!(server.pending.len(), 2);
 
         // Requeue the high priority stream to lowest priority. The initial send
         // still uses high priority since it's queued that way. After that it will
@@ -1322,25 +1700,69 @@ mod tests {
             state: &mut server,
             pending: &mut pending,
             conn_state: &state,
+            timers: &mut timers,
         };
         high.set_priority(-1).unwrap();
 
         let mut buf = BytesMut::with_capacity(1000);
-        let meta = server.write_stream_frames(&mut buf, 40);
+        let meta = server.write_stream_frames(&mut buf, 40, None);
         assert_eq!(meta.len(), 1);
         assert_eq!(meta[0].id, id_high);
 
-        // After requeuing we should end up with 2 priorities - not 3
-        assert_eq!(server.pending.len(), 2);
-
         // Send the remaining data. The initial mid priority one should go first now
-        let meta = server.write_stream_frames(&mut buf, 1000);
+        let meta = server.write_stream_frames(&mut buf, 1000, None);
         assert_eq!(meta.len(), 2);
         assert_eq!(meta[0].id, id_mid);
         assert_eq!(meta[1].id, id_high);
 
         assert!(!server.can_send_stream_data());
-        assert_eq!(server.pending.len(), 1);
+    }
+
+    #[test]
+    fn incremental_streams() {
+        let mut server = make(Side::Server);
+        server.scheduler = ExtensiblePrioritiesConfig::default().build();
+        server.set_params(&TransportParameters {
+            initial_max_streams_bidi: 3u32.into(),
+            initial_max_data: 1000u32.into(),
+            initial_max_stream_data_bidi_remote: 1000u32.into(),
+            ..Default::default()
+        });
+
+        let (mut pending, state) = (Retransmits::default(), ConnState::Established);
+        let mut timers = TimerTable::default();
+        let mut streams = Streams {
+            state: &mut server,
+            conn_state: &state,
+        };
+        let ids = [
+            streams.open(Dir::Bi).unwrap(),
+            streams.open(Dir::Bi).unwrap(),
+            streams.open(Dir::Bi).unwrap(),
+        ];
+        for (i, &id) in ids.iter().enumerate().rev() {
+            let mut stream = SendStream {
+                id,
+                state: &mut server,
+                pending: &mut pending,
+                conn_state: &state,
+                timers: &mut timers,
+            };
+            stream.set_incremental(i == 2).unwrap();
+            stream.write(&[0; 100]).unwrap();
+        }
+
+        // Non-incremental streams are sent one after another, in order of stream ID
+        let mut buf = BytesMut::with_capacity(1000);
+        let meta = server.write_stream_frames(&mut buf, 60, None);
+        assert_eq!(meta.len(), 1);
+        assert_eq!(meta[0].id, ids[0]);
+        let meta = server.write_stream_frames(&mut buf, 1000, None);
+        assert_eq!(
+            meta.iter().map(|x| x.id).collect::<Vec<_>>(),
+            [ids[0], ids[1], ids[2]]
+        );
+        assert!(!server.can_send_stream_data());
     }
 
     #[test]
@@ -1380,6 +1802,7 @@ mod tests {
             ..Default::default()
         });
         let (mut pending, state) = (Retransmits::default(), ConnState::Established);
+        let mut timers = TimerTable::default();
         let mut streams = Streams {
             state: &mut server,
             conn_state: &state,
@@ -1391,6 +1814,7 @@ mod tests {
             state: &mut server,
             pending: &mut pending,
             conn_state: &state,
+            timers: &mut timers,
         };
         stream.write(b"hello").unwrap();
         stream.reset(0u32.into()).unwrap();
@@ -10,7 +10,7 @@ pub(crate) enum Timer {
     Close = 2,
     /// When keys are discarded because they should not be needed anymore
     KeyDiscard = 3,
-    /// When to give up on validating a new path to the peer
+    /// When to give up on validating a new active path to the peer
     PathValidation = 4,
     /// When to send a `PING` frame to keep the connection alive
     KeepAlive = 5,
@@ -18,10 +18,20 @@ pub(crate) enum Timer {
     Pacing = 6,
     /// When to invalidate old CID and proactively push new one via NEW_CONNECTION_ID frame
     PushNewCid = 7,
+    /// When to send an ACK that was delayed because of the peer's ACK_FREQUENCY request
+    MaxAckDelay = 8,
+    /// When data written with a deadline must have been acknowledged
+    StreamDeadline = 9,
+    /// When an outgoing datagram expires if it still hasn't been sent
+    DatagramDeadline = 10,
+    /// When to give up on validating a path probed before switching to it
+    PendingPathValidation = 11,
+    /// When to resend a PATH_CHALLENGE on a probed path, assuming the previous one was lost
+    PendingPathChallenge = 12,
 }
 
 impl Timer {
-    pub(crate) const VALUES: [Self; 8] = [
+    pub(crate) const VALUES: [Self; 13] = [
         Self::LossDetection,
         Self::Idle,
         Self::Close,
@@ -30,13 +40,19 @@ impl Timer {
         Self::KeepAlive,
         Self::Pacing,
         Self::PushNewCid,
+        Self::MaxAckDelay,
+        Self::StreamDeadline,
+        Self::DatagramDeadline,
+        Self::PendingPathValidation,
+        Self::PendingPathChallenge,
     ];
 }
 
 /// A table of data associated with each distinct kind of `Timer`
+#[allow(unreachable_pub)] // fuzzing only
 #[derive(Debug, Copy, Clone, Default)]
-pub(crate) struct TimerTable {
-    data: [Option<Instant>; 8],
+pub struct TimerTable {
+    data: [Option<Instant>; 13],
 }
 
 impl TimerTable {
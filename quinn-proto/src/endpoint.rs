@@ -1,7 +1,7 @@
 use std::{
     collections::{hash_map, HashMap},
     convert::TryFrom,
-    fmt, iter,
+    fmt, iter, mem,
     net::{IpAddr, SocketAddr},
     ops::{Index, IndexMut},
     sync::Arc,
@@ -19,7 +19,7 @@ use crate::{
     cid_generator::{ConnectionIdGenerator, RandomConnectionIdGenerator},
     coding::BufMutExt,
     config::{ClientConfig, EndpointConfig, ServerConfig},
-    connection::{Connection, ConnectionError},
+    connection::{Connection, ConnectionError, HandshakeRestart, SharedSendRateLimit},
     crypto::{self, Keys, UnsupportedVersion},
     frame,
     packet::{Header, Packet, PacketDecodeError, PacketNumber, PartialDecode},
@@ -27,7 +27,8 @@ use crate::{
         ConnectionEvent, ConnectionEventInner, ConnectionId, EcnCodepoint, EndpointEvent,
         EndpointEventInner, IssuedCid,
     },
-    transport_parameters::TransportParameters,
+    token::{TokenKind, ValidationToken},
+    transport_parameters::{PreferredAddress, TransportParameters},
     ResetToken, RetryToken, Side, Transmit, TransportConfig, TransportError, INITIAL_MTU,
     MAX_CID_SIZE, MIN_INITIAL_SIZE, RESET_TOKEN_SIZE,
 };
@@ -45,6 +46,11 @@ pub struct Endpoint {
     server_config: Option<Arc<ServerConfig>>,
     /// Whether the underlying UDP socket promises not to fragment packets
     allow_mtud: bool,
+    /// Buffered datagrams for each connection attempt awaiting a decision from the application
+    incoming_buffers: Slab<IncomingBuffer>,
+    all_incoming_buffers_total_bytes: u64,
+    /// Limit on the rate at which all connections together send data
+    send_rate: Arc<SharedSendRateLimit>,
 }
 
 impl Endpoint {
@@ -66,6 +72,9 @@ impl Endpoint {
             config,
             server_config,
             allow_mtud,
+            incoming_buffers: Slab::new(),
+            all_incoming_buffers_total_bytes: 0,
+            send_rate: Arc::new(SharedSendRateLimit::new()),
         }
     }
 
@@ -74,6 +83,16 @@ impl Endpoint {
         self.server_config = server_config;
     }
 
+    /// Limit the rate at which all of the endpoint's connections together send data, in bytes per
+    /// second
+    ///
+    /// Takes effect immediately, including for existing connections. Works like
+    /// [`TransportConfig::max_send_rate`](crate::TransportConfig::max_send_rate), which limits
+    /// individual connections.
+    pub fn set_max_send_rate(&mut self, rate: Option<u64>) {
+        self.send_rate.set_rate(rate);
+    }
+
     /// Process `EndpointEvent`s emitted from related `Connection`s
     ///
     /// In turn, processing this event may return a `ConnectionEvent` for the same `Connection`.
@@ -175,17 +194,37 @@ impl Endpoint {
         //
 
         let addresses = FourTuple { remote, local_ip };
-        if let Some(ch) = self.index.get(&addresses, &first_decode) {
-            return Some(DatagramEvent::ConnectionEvent(
-                ch,
-                ConnectionEvent(ConnectionEventInner::Datagram {
-                    now,
-                    remote: addresses.remote,
-                    ecn,
-                    first_decode,
-                    remaining,
-                }),
-            ));
+        if let Some(route) = self.index.get(&addresses, &first_decode) {
+            let event = ConnectionEvent(ConnectionEventInner::Datagram {
+                now,
+                remote: addresses.remote,
+                local_ip: addresses.local_ip,
+                ecn,
+                first_decode,
+                remaining,
+            });
+            match route {
+                RouteDatagramTo::Incoming(incoming_idx) => {
+                    let incoming_buffer = &mut self.incoming_buffers[incoming_idx];
+                    let fits = self.server_config.as_ref().map_or(false, |config| {
+                        incoming_buffer.total_bytes + datagram_len as u64
+                            <= config.incoming_buffer_size
+                            && self.all_incoming_buffers_total_bytes + datagram_len as u64
+                                <= config.incoming_buffer_size_total
+                    });
+                    if fits {
+                        incoming_buffer.datagrams.push(event);
+                        incoming_buffer.total_bytes += datagram_len as u64;
+                        self.all_incoming_buffers_total_bytes += datagram_len as u64;
+                    } else {
+                        debug!("dropping datagram for connection awaiting acceptance");
+                    }
+                    return None;
+                }
+                RouteDatagramTo::Connection(ch) => {
+                    return Some(DatagramEvent::ConnectionEvent(ch, event));
+                }
+            }
         }
 
         //
@@ -226,7 +265,7 @@ impl Endpoint {
             };
             return match first_decode.finish(Some(&*crypto.header.remote)) {
                 Ok(packet) => {
-                    self.handle_first_packet(now, addresses, ecn, packet, remaining, &crypto)
+                    self.handle_first_packet(now, addresses, ecn, packet, remaining, crypto)
                 }
                 Err(e) => {
                     trace!("unable to decode initial packet: {}", e);
@@ -331,16 +370,19 @@ impl Endpoint {
             self.local_cid_generator.as_ref(),
             loc_cid,
             None,
+            config.version,
         );
         let tls = config
             .crypto
+            .clone()
             .start_session(config.version, server_name, &params)?;
 
-        let conn = self.add_connection(
+        let mut conn = self.add_connection(
             ch,
             config.version,
             remote_id,
             loc_cid,
+            None,
             remote_id,
             FourTuple {
                 remote,
@@ -349,8 +391,17 @@ impl Endpoint {
             Instant::now(),
             tls,
             None,
+            Some(HandshakeRestart {
+                crypto: config.crypto,
+                server_name: server_name.into(),
+                params,
+            }),
+            true,
             config.transport,
         );
+        if let Some(store) = config.token_store {
+            conn.set_token_store(store, server_name);
+        }
         Ok((ch, conn))
     }
 
@@ -395,7 +446,7 @@ impl Endpoint {
         ecn: Option<EcnCodepoint>,
         mut packet: Packet,
         rest: Option<BytesMut>,
-        crypto: &Keys,
+        crypto: Keys,
     ) -> Option<DatagramEvent> {
         let (src_cid, dst_cid, token, packet_number, version) = match packet.header {
             Header::Initial {
@@ -433,14 +484,16 @@ impl Endpoint {
             return Some(DatagramEvent::Response(self.initial_close(
                 version,
                 addresses,
-                crypto,
+                &crypto,
                 &src_cid,
                 TransportError::CONNECTION_REFUSED(""),
             )));
         }
 
+        let token_kind = TokenKind::from_token(&token);
         if dst_cid.len() < 8
-            && (!server_config.use_retry || dst_cid.len() != self.local_cid_generator.cid_len())
+            && (token_kind != Some(TokenKind::Retry)
+                || dst_cid.len() != self.local_cid_generator.cid_len())
         {
             debug!(
                 "rejecting connection due to invalid DCID length {}",
@@ -449,52 +502,45 @@ impl Endpoint {
             return Some(DatagramEvent::Response(self.initial_close(
                 version,
                 addresses,
-                crypto,
+                &crypto,
                 &src_cid,
                 TransportError::PROTOCOL_VIOLATION("invalid destination CID length"),
             )));
         }
 
-        let (retry_src_cid, orig_dst_cid) = if server_config.use_retry {
-            if token.is_empty() {
-                // First Initial
-                let mut random_bytes = vec![0u8; RetryToken::RANDOM_BYTES_LEN];
-                self.rng.fill_bytes(&mut random_bytes);
-                // The peer will use this as the DCID of its following Initials. Initial DCIDs are
-                // looked up separately from Handshake/Data DCIDs, so there is no risk of collision
-                // with established connections. In the unlikely event that a collision occurs
-                // between two connections in the initial phase, both will fail fast and may be
-                // retried by the application layer.
-                let loc_cid = self.local_cid_generator.generate_cid();
-
-                let token = RetryToken {
-                    orig_dst_cid: dst_cid,
-                    issued: SystemTime::now(),
-                    random_bytes: &random_bytes,
+        // A token from a NEW_TOKEN frame proves the client's address just like a Retry token. Unlike
+        // an invalid Retry token, an invalid one is simply ignored, since it may just have expired.
+        // Each token is accepted only once, so that observing one doesn't allow spoofing the
+        // client's address.
+        let address_validated = token_kind == Some(TokenKind::Validation)
+            && match ValidationToken::from_bytes(
+                &*server_config.token_key,
+                &addresses.remote.ip(),
+                &token,
+            ) {
+                Ok(token)
+                    if token.issued + server_config.validation_token_lifetime
+                        > SystemTime::now() =>
+                {
+                    match server_config.validation_token_log.check_and_insert(
+                        token.nonce(),
+                        token.issued,
+                        server_config.validation_token_lifetime,
+                    ) {
+                        Ok(()) => true,
+                        Err(e) => {
+                            debug!("ignoring validation token: {}", e);
+                            false
+                        }
+                    }
                 }
-                .encode(&*server_config.token_key, &addresses.remote, &loc_cid);
-
-                let header = Header::Retry {
-                    src_cid: loc_cid,
-                    dst_cid: src_cid,
-                    version,
-                };
-
-                let mut buf = BytesMut::new();
-                let encode = header.encode(&mut buf);
-                buf.put_slice(&token);
-                buf.extend_from_slice(&server_config.crypto.retry_tag(version, &dst_cid, &buf));
-                encode.finish(&mut buf, &*crypto.header.local, None);
-
-                return Some(DatagramEvent::Response(Transmit {
-                    destination: addresses.remote,
-                    ecn: None,
-                    contents: buf.freeze(),
-                    segment_size: None,
-                    src_ip: addresses.local_ip,
-                }));
-            }
+                _ => {
+                    debug!("ignoring invalid validation token");
+                    false
+                }
+            };
 
+        let (retry_src_cid, orig_dst_cid) = if token_kind == Some(TokenKind::Retry) {
             match RetryToken::from_bytes(
                 &*server_config.token_key,
                 &addresses.remote,
@@ -511,7 +557,7 @@ impl Endpoint {
                     return Some(DatagramEvent::Response(self.initial_close(
                         version,
                         addresses,
-                        crypto,
+                        &crypto,
                         &src_cid,
                         TransportError::INVALID_TOKEN(""),
                     )));
@@ -521,6 +567,81 @@ impl Endpoint {
             (None, dst_cid)
         };
 
+        if server_config.use_retry && retry_src_cid.is_none() && !address_validated {
+            return Some(DatagramEvent::Response(self.retry_packet(
+                &server_config,
+                addresses,
+                version,
+                src_cid,
+                dst_cid,
+                &crypto,
+            )));
+        }
+
+        let incoming_idx = self.incoming_buffers.insert(IncomingBuffer::default());
+        self.index.insert_initial_incoming(dst_cid, incoming_idx);
+        Some(DatagramEvent::NewConnection(Incoming {
+            received_at: now,
+            addresses,
+            ecn,
+            packet,
+            packet_number,
+            rest,
+            crypto,
+            src_cid,
+            dst_cid,
+            version,
+            retry_src_cid,
+            orig_dst_cid,
+            address_validated: retry_src_cid.is_some() || address_validated,
+            server_config,
+            incoming_idx,
+            improper_drop_warner: IncomingImproperDropWarner,
+        }))
+    }
+
+    /// Attempt to accept this incoming connection (an error may still occur)
+    #[allow(clippy::result_large_err)] // Only returned when a connection fails to start
+    pub fn accept(
+        &mut self,
+        incoming: Incoming,
+        now: Instant,
+    ) -> Result<(ConnectionHandle, Connection), AcceptError> {
+        let incoming_buffer = self.clean_up_incoming(&incoming);
+        incoming.improper_drop_warner.dismiss();
+        let Incoming {
+            received_at,
+            addresses,
+            ecn,
+            packet,
+            packet_number,
+            rest,
+            crypto,
+            src_cid,
+            dst_cid,
+            version,
+            retry_src_cid,
+            orig_dst_cid,
+            address_validated,
+            server_config,
+            ..
+        } = incoming;
+
+        if self.connections.len() >= server_config.concurrent_connections as usize || self.is_full()
+        {
+            debug!("refusing connection");
+            return Err(AcceptError {
+                cause: TransportError::CONNECTION_REFUSED("").into(),
+                response: Some(self.initial_close(
+                    version,
+                    addresses,
+                    &crypto,
+                    &src_cid,
+                    TransportError::CONNECTION_REFUSED(""),
+                )),
+            });
+        }
+
         let ch = ConnectionHandle(self.connections.vacant_key());
         let loc_cid = self.new_cid(ch);
         let mut params = TransportParameters::new(
@@ -529,10 +650,23 @@ impl Endpoint {
             self.local_cid_generator.as_ref(),
             loc_cid,
             Some(&server_config),
+            version,
         );
         params.stateless_reset_token = Some(ResetToken::new(&*self.config.reset_key, &loc_cid));
         params.original_dst_cid = Some(orig_dst_cid);
         params.retry_src_cid = retry_src_cid;
+        let mut pref_addr_cid = None;
+        // A server using zero-length connection IDs must not supply a preferred address
+        if server_config.has_preferred_address() && self.local_cid_generator.cid_len() > 0 {
+            let cid = self.new_cid(ch);
+            pref_addr_cid = Some(cid);
+            params.preferred_address = Some(PreferredAddress {
+                address_v4: server_config.preferred_address_v4,
+                address_v6: server_config.preferred_address_v6,
+                connection_id: cid,
+                stateless_reset_token: ResetToken::new(&*self.config.reset_key, &cid),
+            });
+        }
 
         let tls = server_config.crypto.clone().start_session(version, &params);
         let transport_config = server_config.transport.clone();
@@ -541,53 +675,166 @@ impl Endpoint {
             version,
             dst_cid,
             loc_cid,
+            pref_addr_cid,
             src_cid,
             addresses,
             now,
             tls,
             Some(server_config),
+            None,
+            address_validated,
             transport_config,
         );
-        if dst_cid.len() != 0 {
-            self.index.insert_initial(dst_cid, ch);
-        }
-        match conn.handle_first_packet(now, addresses.remote, ecn, packet_number, packet, rest) {
+        self.index.insert_initial(dst_cid, ch);
+        match conn.handle_first_packet(
+            received_at,
+            addresses.remote,
+            ecn,
+            packet_number,
+            packet,
+            rest,
+        ) {
             Ok(()) => {
                 trace!(id = ch.0, icid = %dst_cid, "connection incoming");
-                Some(DatagramEvent::NewConnection(ch, conn))
+                for event in incoming_buffer.datagrams {
+                    conn.handle_event(event);
+                }
+                Ok((ch, conn))
             }
             Err(e) => {
                 debug!("handshake failed: {}", e);
                 self.handle_event(ch, EndpointEvent(EndpointEventInner::Drained));
-                match e {
-                    ConnectionError::TransportError(e) => Some(DatagramEvent::Response(
-                        self.initial_close(version, addresses, crypto, &src_cid, e),
-                    )),
+                let response = match e {
+                    ConnectionError::TransportError(ref e) => {
+                        Some(self.initial_close(version, addresses, &crypto, &src_cid, e.clone()))
+                    }
                     _ => None,
-                }
+                };
+                Err(AcceptError { cause: e, response })
             }
         }
     }
 
+    /// Reject this incoming connection attempt
+    pub fn refuse(&mut self, incoming: Incoming) -> Transmit {
+        self.clean_up_incoming(&incoming);
+        incoming.improper_drop_warner.dismiss();
+        self.initial_close(
+            incoming.version,
+            incoming.addresses,
+            &incoming.crypto,
+            &incoming.src_cid,
+            TransportError::CONNECTION_REFUSED(""),
+        )
+    }
+
+    /// Respond with a retry packet, requiring the client to retry with address validation
+    ///
+    /// Errors if `incoming.may_retry()` is false.
+    pub fn retry(&mut self, incoming: Incoming) -> Result<Transmit, RetryError> {
+        if !incoming.may_retry() {
+            return Err(RetryError(Box::new(incoming)));
+        }
+        self.clean_up_incoming(&incoming);
+        incoming.improper_drop_warner.dismiss();
+        Ok(self.retry_packet(
+            &incoming.server_config,
+            incoming.addresses,
+            incoming.version,
+            incoming.src_cid,
+            incoming.dst_cid,
+            &incoming.crypto,
+        ))
+    }
+
+    /// Ignore this incoming connection attempt, not sending any packet in response
+    ///
+    /// Doing this actively, rather than merely dropping the [`Incoming`], is necessary to prevent
+    /// memory leaks due to state within [`Endpoint`] tracking the incoming connection.
+    pub fn ignore(&mut self, incoming: Incoming) {
+        self.clean_up_incoming(&incoming);
+        incoming.improper_drop_warner.dismiss();
+    }
+
+    /// Clean up endpoint data structures associated with an `Incoming`.
+    fn clean_up_incoming(&mut self, incoming: &Incoming) -> IncomingBuffer {
+        self.index.remove_initial(&incoming.dst_cid);
+        let incoming_buffer = self.incoming_buffers.remove(incoming.incoming_idx);
+        self.all_incoming_buffers_total_bytes -= incoming_buffer.total_bytes;
+        incoming_buffer
+    }
+
+    fn retry_packet(
+        &mut self,
+        server_config: &ServerConfig,
+        addresses: FourTuple,
+        version: u32,
+        src_cid: ConnectionId,
+        dst_cid: ConnectionId,
+        crypto: &Keys,
+    ) -> Transmit {
+        let mut random_bytes = vec![0u8; RetryToken::RANDOM_BYTES_LEN];
+        self.rng.fill_bytes(&mut random_bytes);
+        // The peer will use this as the DCID of its following Initials. Initial DCIDs are
+        // looked up separately from Handshake/Data DCIDs, so there is no risk of collision
+        // with established connections. In the unlikely event that a collision occurs
+        // between two connections in the initial phase, both will fail fast and may be
+        // retried by the application layer.
+        let loc_cid = self.local_cid_generator.generate_cid();
+
+        let token = RetryToken {
+            orig_dst_cid: dst_cid,
+            issued: SystemTime::now(),
+            random_bytes: &random_bytes,
+        }
+        .encode(&*server_config.token_key, &addresses.remote, &loc_cid);
+
+        let header = Header::Retry {
+            src_cid: loc_cid,
+            dst_cid: src_cid,
+            version,
+        };
+
+        let mut buf = BytesMut::new();
+        let encode = header.encode(&mut buf);
+        buf.put_slice(&token);
+        buf.extend_from_slice(&server_config.crypto.retry_tag(version, &dst_cid, &buf));
+        encode.finish(&mut buf, &*crypto.header.local, None);
+
+        Transmit {
+            destination: addresses.remote,
+            ecn: None,
+            contents: buf.freeze(),
+            segment_size: None,
+            src_ip: addresses.local_ip,
+        }
+    }
+
     fn add_connection(
         &mut self,
         ch: ConnectionHandle,
         version: u32,
         init_cid: ConnectionId,
         loc_cid: ConnectionId,
+        pref_addr_cid: Option<ConnectionId>,
         rem_cid: ConnectionId,
         addresses: FourTuple,
         now: Instant,
         tls: Box<dyn crypto::Session>,
         server_config: Option<Arc<ServerConfig>>,
+        handshake_restart: Option<HandshakeRestart>,
+        path_validated: bool,
         transport_config: Arc<TransportConfig>,
     ) -> Connection {
         let conn = Connection::new(
             self.config.clone(),
             server_config,
+            handshake_restart,
+            path_validated,
             transport_config,
             init_cid,
             loc_cid,
+            pref_addr_cid,
             rem_cid,
             addresses.remote,
             addresses.local_ip,
@@ -596,12 +843,16 @@ impl Endpoint {
             now,
             version,
             self.allow_mtud,
+            self.send_rate.clone(),
         );
 
         let id = self.connections.insert(ConnectionMeta {
             init_cid,
-            cids_issued: 0,
-            loc_cids: iter::once((0, loc_cid)).collect(),
+            // The preferred address CID, if any, has sequence number 1
+            cids_issued: pref_addr_cid.is_some() as u64,
+            loc_cids: iter::once((0, loc_cid))
+                .chain(pref_addr_cid.map(|cid| (1, cid)))
+                .collect(),
             addresses,
             reset_token: None,
         });
@@ -673,7 +924,14 @@ impl Endpoint {
     #[cfg(test)]
     pub(crate) fn known_connections(&self) -> usize {
         let x = self.connections.len();
-        debug_assert_eq!(x, self.index.connection_ids_initial.len());
+        debug_assert_eq!(
+            x,
+            self.index
+                .connection_ids_initial
+                .values()
+                .filter(|route| matches!(route, RouteDatagramTo::Connection(_)))
+                .count()
+        );
         // Not all connections have known reset tokens
         debug_assert!(x >= self.index.connection_reset_tokens.0.len());
         // Not all connections have unique remotes, and 0-length CIDs might not be in use.
@@ -707,6 +965,11 @@ impl fmt::Debug for Endpoint {
             .field("connections", &self.connections)
             .field("config", &self.config)
             .field("server_config", &self.server_config)
+            .field("incoming_buffers", &self.incoming_buffers.len())
+            .field(
+                "all_incoming_buffers_total_bytes",
+                &self.all_incoming_buffers_total_bytes,
+            )
             .finish()
     }
 }
@@ -717,7 +980,7 @@ struct ConnectionIndex {
     /// Identifies connections based on the initial DCID the peer utilized
     ///
     /// Uses a standard `HashMap` to protect against hash collision attacks.
-    connection_ids_initial: HashMap<ConnectionId, ConnectionHandle>,
+    connection_ids_initial: HashMap<ConnectionId, RouteDatagramTo>,
     /// Identifies connections based on locally created CIDs
     ///
     /// Uses a cheaper hash function since keys are locally created
@@ -734,9 +997,30 @@ struct ConnectionIndex {
 }
 
 impl ConnectionIndex {
+    /// Associate an incoming connection attempt with its initial destination CID
+    fn insert_initial_incoming(&mut self, dst_cid: ConnectionId, incoming_idx: usize) {
+        if dst_cid.is_empty() {
+            return;
+        }
+        self.connection_ids_initial
+            .insert(dst_cid, RouteDatagramTo::Incoming(incoming_idx));
+    }
+
+    /// Remove an association with an initial destination CID
+    fn remove_initial(&mut self, dst_cid: &ConnectionId) {
+        if dst_cid.is_empty() {
+            return;
+        }
+        self.connection_ids_initial.remove(dst_cid);
+    }
+
     /// Associate a connection with its initial destination CID
     fn insert_initial(&mut self, dst_cid: ConnectionId, connection: ConnectionHandle) {
-        self.connection_ids_initial.insert(dst_cid, connection);
+        if dst_cid.is_empty() {
+            return;
+        }
+        self.connection_ids_initial
+            .insert(dst_cid, RouteDatagramTo::Connection(connection));
     }
 
     /// Associate a connection with its first locally-chosen destination CID if used, or otherwise
@@ -764,9 +1048,7 @@ impl ConnectionIndex {
 
     /// Remove all references to a connection
     fn remove(&mut self, conn: &ConnectionMeta) {
-        if conn.init_cid.len() > 0 {
-            self.connection_ids_initial.remove(&conn.init_cid);
-        }
+        self.remove_initial(&conn.init_cid);
         for cid in conn.loc_cids.values() {
             self.connection_ids.remove(cid);
         }
@@ -776,21 +1058,22 @@ impl ConnectionIndex {
         }
     }
 
-    /// Find the existing connection that `datagram` should be routed to, if any
-    fn get(&self, addresses: &FourTuple, datagram: &PartialDecode) -> Option<ConnectionHandle> {
+    /// Find the existing connection or connection attempt that `datagram` should be routed to, if
+    /// any
+    fn get(&self, addresses: &FourTuple, datagram: &PartialDecode) -> Option<RouteDatagramTo> {
         if datagram.dst_cid().len() != 0 {
             if let Some(&ch) = self.connection_ids.get(datagram.dst_cid()) {
-                return Some(ch);
+                return Some(RouteDatagramTo::Connection(ch));
             }
         }
         if datagram.is_initial() || datagram.is_0rtt() {
-            if let Some(&ch) = self.connection_ids_initial.get(datagram.dst_cid()) {
-                return Some(ch);
+            if let Some(&route) = self.connection_ids_initial.get(datagram.dst_cid()) {
+                return Some(route);
             }
         }
         if datagram.dst_cid().len() == 0 {
             if let Some(&ch) = self.connection_remotes.get(addresses) {
-                return Some(ch);
+                return Some(RouteDatagramTo::Connection(ch));
             }
         }
         let data = datagram.data();
@@ -800,9 +1083,18 @@ impl ConnectionIndex {
         self.connection_reset_tokens
             .get(addresses.remote, &data[data.len() - RESET_TOKEN_SIZE..])
             .cloned()
+            .map(RouteDatagramTo::Connection)
     }
 }
 
+/// Destination of a datagram matched by the [`ConnectionIndex`]
+#[derive(Debug, Copy, Clone)]
+enum RouteDatagramTo {
+    /// A connection attempt the application has yet to accept or reject
+    Incoming(usize),
+    Connection(ConnectionHandle),
+}
+
 #[derive(Debug)]
 pub(crate) struct ConnectionMeta {
     init_cid: ConnectionId,
@@ -847,12 +1139,137 @@ impl IndexMut<ConnectionHandle> for Slab<ConnectionMeta> {
 pub enum DatagramEvent {
     /// The datagram is redirected to its `Connection`
     ConnectionEvent(ConnectionHandle, ConnectionEvent),
-    /// The datagram has resulted in starting a new `Connection`
-    NewConnection(ConnectionHandle, Connection),
+    /// The datagram may result in starting a new `Connection`
+    NewConnection(Incoming),
     /// Response generated directly by the endpoint
     Response(Transmit),
 }
 
+/// An incoming connection for which the server has not yet begun its part of the handshake
+///
+/// Must be passed to exactly one of [`Endpoint::accept`], [`Endpoint::refuse`],
+/// [`Endpoint::retry`] or [`Endpoint::ignore`].
+pub struct Incoming {
+    received_at: Instant,
+    addresses: FourTuple,
+    ecn: Option<EcnCodepoint>,
+    packet: Packet,
+    packet_number: u64,
+    rest: Option<BytesMut>,
+    crypto: Keys,
+    src_cid: ConnectionId,
+    dst_cid: ConnectionId,
+    version: u32,
+    retry_src_cid: Option<ConnectionId>,
+    orig_dst_cid: ConnectionId,
+    address_validated: bool,
+    server_config: Arc<ServerConfig>,
+    incoming_idx: usize,
+    improper_drop_warner: IncomingImproperDropWarner,
+}
+
+impl Incoming {
+    /// The local IP address which was used when the peer established the connection
+    ///
+    /// This has the same behavior as [`Connection::local_ip`]
+    pub fn local_ip(&self) -> Option<IpAddr> {
+        self.addresses.local_ip
+    }
+
+    /// The peer's UDP address
+    pub fn remote_address(&self) -> SocketAddr {
+        self.addresses.remote
+    }
+
+    /// Whether the socket address that is initiating this connection has been validated
+    ///
+    /// This means that the sender of the initial packet has proved that they can receive traffic
+    /// sent to `self.remote_address()`, by presenting a token from a Retry packet or a NEW_TOKEN
+    /// frame.
+    pub fn remote_address_validated(&self) -> bool {
+        self.address_validated
+    }
+
+    /// Whether it is legal to respond with a retry packet
+    ///
+    /// A client may only be sent a single Retry, so this is false if the connection attempt
+    /// already follows one.
+    pub fn may_retry(&self) -> bool {
+        self.retry_src_cid.is_none()
+    }
+
+    /// The original destination connection ID sent by the client
+    pub fn orig_dst_cid(&self) -> &ConnectionId {
+        &self.orig_dst_cid
+    }
+}
+
+impl fmt::Debug for Incoming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Incoming")
+            .field("addresses", &self.addresses)
+            .field("ecn", &self.ecn)
+            .field("src_cid", &self.src_cid)
+            .field("dst_cid", &self.dst_cid)
+            .field("version", &self.version)
+            .field("retry_src_cid", &self.retry_src_cid)
+            .field("orig_dst_cid", &self.orig_dst_cid)
+            .field("address_validated", &self.address_validated)
+            .field("incoming_idx", &self.incoming_idx)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Warns if an [`Incoming`] is dropped without being passed to the [`Endpoint`]
+struct IncomingImproperDropWarner;
+
+impl IncomingImproperDropWarner {
+    fn dismiss(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for IncomingImproperDropWarner {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            return;
+        }
+        warn!(
+            "quinn_proto::Incoming dropped without passing to Endpoint::accept/refuse/retry/ignore \
+             (may cause memory leak and eventual inability to accept new connections)"
+        );
+    }
+}
+
+/// Datagrams received for an [`Incoming`] before the application decided what to do with it
+#[derive(Default)]
+struct IncomingBuffer {
+    datagrams: Vec<ConnectionEvent>,
+    total_bytes: u64,
+}
+
+/// Error type for attempting to accept an [`Incoming`]
+#[derive(Debug)]
+pub struct AcceptError {
+    /// Underlying error describing reason for failure
+    pub cause: ConnectionError,
+    /// Optional response to transmit back
+    pub response: Option<Transmit>,
+}
+
+/// Error for attempting to retry an [`Incoming`] which already bears an address validation token
+/// from a previous retry
+#[derive(Debug, Error)]
+#[error("retry() with validated Incoming")]
+pub struct RetryError(Box<Incoming>);
+
+impl RetryError {
+    /// Get the [`Incoming`]
+    pub fn into_incoming(self) -> Incoming {
+        *self.0
+    }
+}
+
 /// Errors in the parameters being used to create a new connection
 ///
 /// These arise before any I/O has been performed.
@@ -129,7 +129,10 @@ frame_types! {
     CONNECTION_CLOSE = 0x1c,
     APPLICATION_CLOSE = 0x1d,
     HANDSHAKE_DONE = 0x1e,
+    IMMEDIATE_ACK = 0x1f,
+    RESET_STREAM_AT = 0x24,
     // DATAGRAM
+    ACK_FREQUENCY = 0xaf,
 }
 
 const STREAM_TYS: RangeInclusive<u64> = RangeInclusive::new(0x08, 0x0f);
@@ -159,6 +162,9 @@ pub(crate) enum Frame {
     Datagram(Datagram),
     Invalid { ty: Type, reason: &'static str },
     HandshakeDone,
+    AckFrequency(AckFrequency),
+    ImmediateAck,
+    ResetStreamAt(ResetStreamAt),
 }
 
 impl Frame {
@@ -199,6 +205,9 @@ impl Frame {
             Datagram(_) => Type(*DATAGRAM_TYS.start()),
             Invalid { ty, .. } => ty,
             HandshakeDone => Type::HANDSHAKE_DONE,
+            AckFrequency(_) => Type::ACK_FREQUENCY,
+            ImmediateAck => Type::IMMEDIATE_ACK,
+            ResetStreamAt(_) => Type::RESET_STREAM_AT,
         }
     }
 
@@ -684,6 +693,19 @@ impl Iter {
                 token: self.take_len()?,
             },
             Type::HANDSHAKE_DONE => Frame::HandshakeDone,
+            Type::ACK_FREQUENCY => Frame::AckFrequency(AckFrequency {
+                sequence: self.bytes.get()?,
+                ack_eliciting_threshold: self.bytes.get()?,
+                request_max_ack_delay: self.bytes.get()?,
+                reordering_threshold: self.bytes.get()?,
+            }),
+            Type::IMMEDIATE_ACK => Frame::ImmediateAck,
+            Type::RESET_STREAM_AT => Frame::ResetStreamAt(ResetStreamAt {
+                id: self.bytes.get()?,
+                error_code: self.bytes.get()?,
+                final_offset: self.bytes.get()?,
+                reliable_size: self.bytes.get()?,
+            }),
             _ => {
                 if let Some(s) = ty.stream() {
                     Frame::Stream(Stream {
@@ -801,6 +823,32 @@ impl ResetStream {
     }
 }
 
+/// Abandons a stream after delivering a prefix of its data
+///
+/// Defined by draft-ietf-quic-reliable-stream-reset.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct ResetStreamAt {
+    pub(crate) id: StreamId,
+    pub(crate) error_code: VarInt,
+    pub(crate) final_offset: VarInt,
+    /// Amount of data, from the start of the stream, that the sender still delivers
+    pub(crate) reliable_size: VarInt,
+}
+
+impl FrameStruct for ResetStreamAt {
+    const SIZE_BOUND: usize = 1 + 8 + 8 + 8 + 8;
+}
+
+impl ResetStreamAt {
+    pub(crate) fn encode<W: BufMut>(&self, out: &mut W) {
+        out.write(Type::RESET_STREAM_AT); // 1 byte
+        out.write(self.id); // <= 8 bytes
+        out.write(self.error_code); // <= 8 bytes
+        out.write(self.final_offset); // <= 8 bytes
+        out.write(self.reliable_size); // <= 8 bytes
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub(crate) struct StopSending {
     pub(crate) id: StreamId,
@@ -871,9 +919,39 @@ impl Datagram {
     }
 }
 
+/// Requests that the peer adjust how often it sends acknowledgements
+///
+/// Defined by draft-ietf-quic-ack-frequency.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct AckFrequency {
+    /// Distinguishes newer requests from older ones that may arrive out of order
+    pub(crate) sequence: VarInt,
+    /// Number of ack-eliciting packets the peer may receive before it must send an ACK
+    pub(crate) ack_eliciting_threshold: VarInt,
+    /// Requested maximum ACK delay, in microseconds
+    pub(crate) request_max_ack_delay: VarInt,
+    /// How far out of order a packet may arrive before the peer must send an ACK immediately
+    pub(crate) reordering_threshold: VarInt,
+}
+
+impl FrameStruct for AckFrequency {
+    const SIZE_BOUND: usize = 2 + 8 + 8 + 8 + 8;
+}
+
+impl AckFrequency {
+    pub(crate) fn encode<W: BufMut>(&self, buf: &mut W) {
+        buf.write(Type::ACK_FREQUENCY); // 2 bytes
+        buf.write(self.sequence); // <= 8 bytes
+        buf.write(self.ack_eliciting_threshold); // <= 8 bytes
+        buf.write(self.request_max_ack_delay); // <= 8 bytes
+        buf.write(self.reordering_threshold); // <= 8 bytes
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use assert_matches::assert_matches;
 
     #[test]
     #[allow(clippy::range_plus_one)]
@@ -902,4 +980,37 @@ mod test {
             ref x => panic!("incorrect frame {x:?}"),
         }
     }
+
+    #[test]
+    fn ack_frequency_coding() {
+        let original = AckFrequency {
+            sequence: VarInt(42),
+            ack_eliciting_threshold: VarInt(20),
+            request_max_ack_delay: VarInt(50_000),
+            reordering_threshold: VarInt(1),
+        };
+        let mut buf = Vec::new();
+        original.encode(&mut buf);
+        buf.write(Type::IMMEDIATE_ACK);
+        let frames = Iter::new(Bytes::from(buf)).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 2);
+        assert_matches!(frames[0], Frame::AckFrequency(x) if x == original);
+        assert_matches!(frames[1], Frame::ImmediateAck);
+    }
+
+    #[test]
+    fn reset_stream_at_coding() {
+        let original = ResetStreamAt {
+            id: StreamId(4),
+            error_code: VarInt(7),
+            final_offset: VarInt(1200),
+            reliable_size: VarInt(100),
+        };
+        let mut buf = Vec::new();
+        original.encode(&mut buf);
+        assert!(buf.len() <= ResetStreamAt::SIZE_BOUND);
+        let frames = Iter::new(Bytes::from(buf)).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 1);
+        assert_matches!(frames[0], Frame::ResetStreamAt(x) if x == original);
+    }
 }
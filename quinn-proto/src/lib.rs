@@ -43,16 +43,16 @@ pub use varint::{VarInt, VarIntBoundsExceeded};
 
 mod connection;
 pub use crate::connection::{
-    BytesSource, Chunk, Chunks, Connection, ConnectionError, ConnectionStats, Datagrams, Event,
-    FinishError, FrameStats, PathStats, ReadError, ReadableError, RecvStream, RttEstimator,
-    SendDatagramError, SendStream, StreamEvent, Streams, UdpStats, UnknownStream, WriteError,
-    Written,
+    BytesSource, Chunk, Chunks, Connection, ConnectionError, ConnectionStats, DatagramOptions,
+    Datagrams, Event, FinishError, FrameStats, MigrateError, PathEvent, PathStats, RateSample,
+    ReadError, ReadableError, RecvStream, ResetAtError, RttEstimator, SendDatagramError,
+    SendStream, StreamEvent, Streams, UdpStats, UnknownStream, WriteError, Written,
 };
 
 mod config;
 pub use config::{
-    ClientConfig, ConfigError, EndpointConfig, IdleTimeout, MtuDiscoveryConfig, ServerConfig,
-    TransportConfig,
+    AckFrequencyConfig, ClientConfig, ConfigError, DatagramOverflowPolicy, EndpointConfig,
+    IdleTimeout, KeyUpdateConfig, MtuDiscoveryConfig, ServerConfig, TransportConfig,
 };
 
 pub mod crypto;
@@ -62,7 +62,9 @@ use crate::frame::Frame;
 pub use crate::frame::{ApplicationClose, ConnectionClose, Datagram};
 
 mod endpoint;
-pub use crate::endpoint::{ConnectError, ConnectionHandle, DatagramEvent, Endpoint};
+pub use crate::endpoint::{
+    AcceptError, ConnectError, ConnectionHandle, DatagramEvent, Endpoint, Incoming, RetryError,
+};
 
 mod shared;
 pub use crate::shared::{ConnectionEvent, ConnectionId, EcnCodepoint, EndpointEvent};
@@ -72,11 +74,16 @@ pub use crate::transport_error::{Code as TransportErrorCode, Error as TransportE
 
 pub mod congestion;
 
+pub mod scheduler;
+
+pub mod qlog;
+
 mod cid_generator;
 pub use crate::cid_generator::{ConnectionIdGenerator, RandomConnectionIdGenerator};
 
 mod token;
 use token::{ResetToken, RetryToken};
+pub use token::{TokenLog, TokenMemoryCache, TokenMemoryLog, TokenReuseError, TokenStore};
 
 #[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
@@ -84,7 +91,7 @@ use arbitrary::Arbitrary;
 #[doc(hidden)]
 #[cfg(fuzzing)]
 pub mod fuzzing {
-    pub use crate::connection::{Retransmits, State as ConnectionState, StreamsState};
+    pub use crate::connection::{Retransmits, State as ConnectionState, StreamsState, TimerTable};
     pub use crate::frame::ResetStream;
     pub use crate::packet::PartialDecode;
     pub use crate::transport_parameters::TransportParameters;
@@ -132,6 +139,7 @@ pub mod fuzzing {
 /// The QUIC protocol version implemented.
 pub const DEFAULT_SUPPORTED_VERSIONS: &[u32] = &[
     0x00000001,
+    QUIC_V2,
     0xff00_001d,
     0xff00_001e,
     0xff00_001f,
@@ -140,6 +148,12 @@ pub const DEFAULT_SUPPORTED_VERSIONS: &[u32] = &[
     0xff00_0022,
 ];
 
+/// QUIC version 2, as specified in [RFC 9369](https://www.rfc-editor.org/rfc/rfc9369.html)
+///
+/// Supported by default, but only used if selected with [`ClientConfig::version`] or as the result
+/// of version negotiation.
+pub const QUIC_V2: u32 = 0x6b33_43cf;
+
 /// Whether an endpoint was the initiator of a connection
 #[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
@@ -5,7 +5,7 @@ use thiserror::Error;
 
 use crate::{
     coding::{self, BufExt, BufMutExt},
-    crypto, ConnectionId,
+    crypto, ConnectionId, QUIC_V2,
 };
 
 // Due to packet number encryption, it is impossible to fully decode a header
@@ -280,7 +280,7 @@ impl Header {
                 number,
                 version,
             } => {
-                w.write(u8::from(LongHeaderType::Initial) | number.tag());
+                w.write(LongHeaderType::Initial.to_byte(version) | number.tag());
                 w.write(version);
                 dst_cid.encode_long(w);
                 src_cid.encode_long(w);
@@ -301,7 +301,7 @@ impl Header {
                 number,
                 version,
             } => {
-                w.write(u8::from(LongHeaderType::Standard(ty)) | number.tag());
+                w.write(LongHeaderType::Standard(ty).to_byte(version) | number.tag());
                 w.write(version);
                 dst_cid.encode_long(w);
                 src_cid.encode_long(w);
@@ -318,7 +318,7 @@ impl Header {
                 ref src_cid,
                 version,
             } => {
-                w.write(u8::from(LongHeaderType::Retry));
+                w.write(LongHeaderType::Retry.to_byte(version));
                 w.write(version);
                 dst_cid.encode_long(w);
                 src_cid.encode_long(w);
@@ -575,7 +575,7 @@ impl PlainHeader {
                 });
             }
 
-            match LongHeaderType::from_byte(first)? {
+            match LongHeaderType::from_byte(first, version)? {
                 LongHeaderType::Initial => {
                     let token_len = buf.get_var()? as usize;
                     let token_start = buf.position() as usize;
@@ -722,28 +722,46 @@ pub(crate) enum LongHeaderType {
 }
 
 impl LongHeaderType {
-    fn from_byte(b: u8) -> Result<Self, PacketDecodeError> {
+    fn from_byte(b: u8, version: u32) -> Result<Self, PacketDecodeError> {
         use self::{LongHeaderType::*, LongType::*};
         debug_assert!(b & LONG_HEADER_FORM != 0, "not a long packet");
-        Ok(match (b & 0x30) >> 4 {
-            0x0 => Initial,
-            0x1 => Standard(ZeroRtt),
-            0x2 => Standard(Handshake),
-            0x3 => Retry,
-            _ => unreachable!(),
+        let ty = (b & 0x30) >> 4;
+        Ok(match version {
+            // https://www.rfc-editor.org/rfc/rfc9369.html#section-3.2
+            QUIC_V2 => match ty {
+                0x0 => Retry,
+                0x1 => Initial,
+                0x2 => Standard(ZeroRtt),
+                0x3 => Standard(Handshake),
+                _ => unreachable!(),
+            },
+            _ => match ty {
+                0x0 => Initial,
+                0x1 => Standard(ZeroRtt),
+                0x2 => Standard(Handshake),
+                0x3 => Retry,
+                _ => unreachable!(),
+            },
         })
     }
-}
 
-impl From<LongHeaderType> for u8 {
-    fn from(ty: LongHeaderType) -> Self {
+    fn to_byte(self, version: u32) -> u8 {
         use self::{LongHeaderType::*, LongType::*};
-        match ty {
-            Initial => LONG_HEADER_FORM | FIXED_BIT,
-            Standard(ZeroRtt) => LONG_HEADER_FORM | FIXED_BIT | (0x1 << 4),
-            Standard(Handshake) => LONG_HEADER_FORM | FIXED_BIT | (0x2 << 4),
-            Retry => LONG_HEADER_FORM | FIXED_BIT | (0x3 << 4),
-        }
+        let ty = match version {
+            QUIC_V2 => match self {
+                Retry => 0x0,
+                Initial => 0x1,
+                Standard(ZeroRtt) => 0x2,
+                Standard(Handshake) => 0x3,
+            },
+            _ => match self {
+                Initial => 0x0,
+                Standard(ZeroRtt) => 0x1,
+                Standard(Handshake) => 0x2,
+                Retry => 0x3,
+            },
+        };
+        LONG_HEADER_FORM | FIXED_BIT | (ty << 4)
     }
 }
 
@@ -899,4 +917,66 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn v2_header_encoding() {
+        use crate::{crypto::rustls::initial_keys, Side};
+        use rustls::quic::Version;
+
+        let dcid = ConnectionId::new(&hex!("8394c8f03e515708"));
+        let client = initial_keys(Version::V2, &dcid, Side::Client);
+        let mut buf = BytesMut::new();
+        let header = Header::Initial {
+            number: PacketNumber::U8(0),
+            src_cid: ConnectionId::new(&[]),
+            dst_cid: dcid,
+            token: Bytes::new(),
+            version: QUIC_V2,
+        };
+        let encode = header.encode(&mut buf);
+        let header_len = buf.len();
+        buf.resize(header_len + 16 + client.packet.local.tag_len(), 0);
+        encode.finish(
+            &mut buf,
+            &*client.header.local,
+            Some((0, &*client.packet.local)),
+        );
+
+        let server = initial_keys(Version::V2, &dcid, Side::Server);
+        let decode = PartialDecode::new(buf, 0, &[QUIC_V2], false).unwrap().0;
+        let mut packet = decode.finish(Some(&*server.header.remote)).unwrap();
+        // Initial packets use long packet type 0b01 in QUIC v2
+        assert_eq!(
+            packet.header_data[..],
+            hex!("d06b3343cf088394c8f03e5157080000402100")[..]
+        );
+        server
+            .packet
+            .remote
+            .decrypt(0, &packet.header_data, &mut packet.payload)
+            .unwrap();
+        assert_eq!(packet.payload[..], [0; 16]);
+        match packet.header {
+            Header::Initial {
+                number, version, ..
+            } => {
+                assert_eq!(number, PacketNumber::U8(0));
+                assert_eq!(version, QUIC_V2);
+            }
+            _ => panic!("unexpected header type"),
+        }
+    }
+
+    #[test]
+    fn long_header_types() {
+        use self::{LongHeaderType::*, LongType::*};
+        for version in [1, QUIC_V2] {
+            for ty in [Initial, Retry, Standard(ZeroRtt), Standard(Handshake)] {
+                let byte = ty.to_byte(version);
+                assert_eq!(LongHeaderType::from_byte(byte, version).unwrap(), ty);
+            }
+        }
+        assert_eq!(Initial.to_byte(QUIC_V2), 0xd0);
+        assert_eq!(Retry.to_byte(QUIC_V2), 0xc0);
+    }
 }
@@ -0,0 +1,515 @@
+//! Structured event logging in the [qlog] format
+//!
+//! When a [`QlogFactory`] is configured through [`TransportConfig::qlog_factory`], each new
+//! connection asks it for a writer and records its events there as a single JSON-SEQ ([RFC 7464])
+//! trace following the `draft-ietf-quic-qlog-main-schema` and `draft-ietf-quic-qlog-quic-events`
+//! drafts. The output can be loaded directly into tools such as [qvis].
+//!
+//! [qlog]: https://datatracker.ietf.org/doc/draft-ietf-quic-qlog-main-schema/
+//! [RFC 7464]: https://www.rfc-editor.org/rfc/rfc7464
+//! [qvis]: https://qvis.quictools.info/
+//! [`TransportConfig::qlog_factory`]: crate::TransportConfig::qlog_factory
+
+use std::{
+    fmt::{self, Write as _},
+    io,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use tracing::warn;
+
+use crate::{
+    connection::RttEstimator,
+    frame::{self, Close, Frame},
+    packet::SpaceId,
+    ConnectionId, Dir, Side,
+};
+
+/// Constructs qlog writers for new connections
+pub trait QlogFactory: Send + Sync {
+    /// Create the writer that will receive the trace of a new connection
+    ///
+    /// `original_dst_cid` is the destination connection ID of the client's first Initial packet,
+    /// which qlog uses to group the traces of both endpoints of a connection. Returning `None`
+    /// disables logging for this connection.
+    fn create(
+        &self,
+        side: Side,
+        original_dst_cid: &ConnectionId,
+    ) -> Option<Box<dyn io::Write + Send>>;
+}
+
+/// The qlog trace of a single connection
+pub(crate) struct QlogStream {
+    writer: Box<dyn io::Write + Send>,
+    /// Reference time that event timestamps are relative to
+    start: Instant,
+    /// Scratch space for the event being serialized
+    buf: String,
+    /// Most recently logged connection state
+    state: &'static str,
+    /// Most recently logged recovery metrics
+    metrics: Metrics,
+    /// Number of 1-RTT key updates so far
+    key_generation: u64,
+}
+
+impl QlogStream {
+    pub(crate) fn new(
+        mut writer: Box<dyn io::Write + Send>,
+        side: Side,
+        original_dst_cid: &ConnectionId,
+        now: Instant,
+    ) -> Option<Self> {
+        let vantage_point = match side {
+            Side::Client => "client",
+            Side::Server => "server",
+        };
+        let header = format!(
+            "\x1e{{\"qlog_version\":\"0.3\",\"qlog_format\":\"JSON-SEQ\",\"title\":\"quinn\",\
+             \"trace\":{{\"vantage_point\":{{\"name\":\"quinn\",\"type\":\"{vantage_point}\"}},\
+             \"common_fields\":{{\"ODCID\":\"{original_dst_cid}\",\"time_format\":\"relative\",\
+             \"reference_time\":0}}}}}}\n"
+        );
+        if let Err(e) = writer.write_all(header.as_bytes()) {
+            warn!("failed to write qlog header: {}", e);
+            return None;
+        }
+        Some(Self {
+            writer,
+            start: now,
+            buf: String::new(),
+            state: "attempted",
+            metrics: Metrics::default(),
+            key_generation: 0,
+        })
+    }
+
+    pub(crate) fn packet_sent(
+        &mut self,
+        now: Instant,
+        space: SpaceId,
+        short_header: bool,
+        number: u64,
+        length: usize,
+        payload: Bytes,
+        ack_delay_exponent: u64,
+    ) {
+        self.packet(
+            now,
+            "packet_sent",
+            space,
+            short_header,
+            number,
+            length,
+            payload,
+            ack_delay_exponent,
+        );
+    }
+
+    pub(crate) fn packet_received(
+        &mut self,
+        now: Instant,
+        space: SpaceId,
+        short_header: bool,
+        number: u64,
+        length: usize,
+        payload: Bytes,
+        ack_delay_exponent: u64,
+    ) {
+        self.packet(
+            now,
+            "packet_received",
+            space,
+            short_header,
+            number,
+            length,
+            payload,
+            ack_delay_exponent,
+        );
+    }
+
+    fn packet(
+        &mut self,
+        now: Instant,
+        name: &str,
+        space: SpaceId,
+        short_header: bool,
+        number: u64,
+        length: usize,
+        payload: Bytes,
+        ack_delay_exponent: u64,
+    ) {
+        self.begin(now, "transport", name);
+        write_header(&mut self.buf, space, short_header, number);
+        let _ = write!(self.buf, ",\"raw\":{{\"length\":{length}}},\"frames\":[");
+        // Runs of padding bytes are logged as a single frame
+        let mut padding = 0;
+        let mut sep = "";
+        for frame in frame::Iter::new(payload) {
+            if let Frame::Padding = frame {
+                padding += 1;
+                continue;
+            }
+            if padding != 0 {
+                let _ = write!(self.buf, "{sep}{}", Padding(padding));
+                padding = 0;
+                sep = ",";
+            }
+            self.buf.push_str(sep);
+            write_frame(&mut self.buf, &frame, ack_delay_exponent);
+            sep = ",";
+        }
+        if padding != 0 {
+            let _ = write!(self.buf, "{sep}{}", Padding(padding));
+        }
+        self.buf.push(']');
+        self.end();
+    }
+
+    /// `zero_rtt` indicates whether a packet in the Data space was a 0-RTT packet
+    pub(crate) fn packet_lost(
+        &mut self,
+        now: Instant,
+        space: SpaceId,
+        zero_rtt: bool,
+        number: u64,
+    ) {
+        self.begin(now, "recovery", "packet_lost");
+        write_header(&mut self.buf, space, !zero_rtt, number);
+        self.end();
+    }
+
+    /// Log any recovery metrics that changed since they were last logged
+    pub(crate) fn metrics_updated(
+        &mut self,
+        now: Instant,
+        rtt: &RttEstimator,
+        congestion_window: u64,
+        bytes_in_flight: u64,
+    ) {
+        let metrics = Metrics {
+            min_rtt: rtt.min(),
+            smoothed_rtt: rtt.get(),
+            latest_rtt: rtt.latest(),
+            rtt_variance: rtt.var(),
+            congestion_window,
+            bytes_in_flight,
+        };
+        if metrics == self.metrics {
+            return;
+        }
+
+        self.begin(now, "recovery", "metrics_updated");
+        let old = self.metrics;
+        let mut sep = "";
+        let rtts = [
+            ("min_rtt", metrics.min_rtt, old.min_rtt),
+            ("smoothed_rtt", metrics.smoothed_rtt, old.smoothed_rtt),
+            ("latest_rtt", metrics.latest_rtt, old.latest_rtt),
+            ("rtt_variance", metrics.rtt_variance, old.rtt_variance),
+        ];
+        for (name, new, old) in rtts {
+            if new != old {
+                let _ = write!(self.buf, "{sep}\"{name}\":{}", Ms(new));
+                sep = ",";
+            }
+        }
+        let counters = [
+            (
+                "congestion_window",
+                metrics.congestion_window,
+                old.congestion_window,
+            ),
+            (
+                "bytes_in_flight",
+                metrics.bytes_in_flight,
+                old.bytes_in_flight,
+            ),
+        ];
+        for (name, new, old) in counters {
+            if new != old {
+                let _ = write!(self.buf, "{sep}\"{name}\":{new}");
+                sep = ",";
+            }
+        }
+        self.metrics = metrics;
+        self.end();
+    }
+
+    /// Log a 1-RTT key update, initiated locally or by the peer
+    pub(crate) fn key_updated(&mut self, now: Instant, remote: bool) {
+        self.key_generation += 1;
+        let trigger = match remote {
+            true => "remote_update",
+            false => "local_update",
+        };
+        for key_type in ["client_1rtt_secret", "server_1rtt_secret"] {
+            self.begin(now, "security", "key_updated");
+            let _ = write!(
+                self.buf,
+                "\"key_type\":\"{key_type}\",\"trigger\":\"{trigger}\",\"generation\":{}",
+                self.key_generation
+            );
+            self.end();
+        }
+    }
+
+    /// Log a connection state transition, if `state` differs from the last one logged
+    pub(crate) fn state_updated(&mut self, now: Instant, state: &'static str) {
+        if state == self.state {
+            return;
+        }
+        self.begin(now, "connectivity", "connection_state_updated");
+        let _ = write!(self.buf, "\"old\":\"{}\",\"new\":\"{state}\"", self.state);
+        self.state = state;
+        self.end();
+    }
+
+    fn begin(&mut self, now: Instant, category: &str, name: &str) {
+        self.buf.clear();
+        let time = Ms(now.saturating_duration_since(self.start));
+        let _ = write!(
+            self.buf,
+            "\x1e{{\"time\":{time},\"name\":\"{category}:{name}\",\"data\":{{"
+        );
+    }
+
+    fn end(&mut self) {
+        self.buf.push_str("}}\n");
+        if let Err(e) = self.writer.write_all(self.buf.as_bytes()) {
+            warn!("failed to write qlog event: {}", e);
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+struct Metrics {
+    min_rtt: Duration,
+    smoothed_rtt: Duration,
+    latest_rtt: Duration,
+    rtt_variance: Duration,
+    congestion_window: u64,
+    bytes_in_flight: u64,
+}
+
+/// Formats a duration as fractional milliseconds
+struct Ms(Duration);
+
+impl fmt::Display for Ms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.3}", self.0.as_secs_f64() * 1000.0)
+    }
+}
+
+fn write_header(buf: &mut String, space: SpaceId, short_header: bool, number: u64) {
+    let packet_type = match (space, short_header) {
+        (SpaceId::Initial, _) => "initial",
+        (SpaceId::Handshake, _) => "handshake",
+        (SpaceId::Data, false) => "0RTT",
+        (SpaceId::Data, true) => "1RTT",
+    };
+    let _ = write!(
+        buf,
+        "\"header\":{{\"packet_type\":\"{packet_type}\",\"packet_number\":{number}}}"
+    );
+}
+
+/// `ack_delay_exponent` is the exponent used to encode the delay of ACK frames
+fn write_frame(buf: &mut String, frame: &Frame, ack_delay_exponent: u64) {
+    let _ = match *frame {
+        Frame::Padding => write!(buf, "{}", Padding(1)),
+        Frame::Ping => write!(buf, "{{\"frame_type\":\"ping\"}}"),
+        Frame::Ack(ref ack) => {
+            let _ = write!(
+                buf,
+                "{{\"frame_type\":\"ack\",\"ack_delay\":{},\"acked_ranges\":[",
+                Ms(Duration::from_micros(
+                    ack.delay.checked_shl(ack_delay_exponent as u32).unwrap_or(u64::MAX)
+                ))
+            );
+            for (i, range) in ack.iter().enumerate() {
+                if i != 0 {
+                    buf.push(',');
+                }
+                let _ = write!(buf, "[{},{}]", range.start(), range.end());
+            }
+            buf.push(']');
+            if let Some(ecn) = ack.ecn {
+                let _ = write!(
+                    buf,
+                    ",\"ect0\":{},\"ect1\":{},\"ce\":{}",
+                    ecn.ect0, ecn.ect1, ecn.ce
+                );
+            }
+            write!(buf, "}}")
+        }
+        Frame::ResetStream(ref frame) => write!(
+            buf,
+            "{{\"frame_type\":\"reset_stream\",\"stream_id\":{},\"error_code\":{},\"final_size\":{}}}",
+            frame.id.0, frame.error_code, frame.final_offset
+        ),
+        Frame::ResetStreamAt(ref frame) => write!(
+            buf,
+            "{{\"frame_type\":\"reset_stream_at\",\"stream_id\":{},\"error_code\":{},\"final_size\":{},\"reliable_size\":{}}}",
+            frame.id.0, frame.error_code, frame.final_offset, frame.reliable_size
+        ),
+        Frame::StopSending(ref frame) => write!(
+            buf,
+            "{{\"frame_type\":\"stop_sending\",\"stream_id\":{},\"error_code\":{}}}",
+            frame.id.0, frame.error_code
+        ),
+        Frame::Crypto(ref frame) => write!(
+            buf,
+            "{{\"frame_type\":\"crypto\",\"offset\":{},\"length\":{}}}",
+            frame.offset,
+            frame.data.len()
+        ),
+        Frame::NewToken { ref token } => write!(
+            buf,
+            "{{\"frame_type\":\"new_token\",\"token\":{{\"raw\":{{\"length\":{}}}}}}}",
+            token.len()
+        ),
+        Frame::Stream(ref frame) => write!(
+            buf,
+            "{{\"frame_type\":\"stream\",\"stream_id\":{},\"offset\":{},\"length\":{},\"fin\":{}}}",
+            frame.id.0,
+            frame.offset,
+            frame.data.len(),
+            frame.fin
+        ),
+        Frame::MaxData(maximum) => {
+            write!(buf, "{{\"frame_type\":\"max_data\",\"maximum\":{maximum}}}")
+        }
+        Frame::MaxStreamData { id, offset } => write!(
+            buf,
+            "{{\"frame_type\":\"max_stream_data\",\"stream_id\":{},\"maximum\":{offset}}}",
+            id.0
+        ),
+        Frame::MaxStreams { dir, count } => write!(
+            buf,
+            "{{\"frame_type\":\"max_streams\",\"stream_type\":\"{}\",\"maximum\":{count}}}",
+            stream_type(dir)
+        ),
+        Frame::DataBlocked { offset } => {
+            write!(buf, "{{\"frame_type\":\"data_blocked\",\"limit\":{offset}}}")
+        }
+        Frame::StreamDataBlocked { id, offset } => write!(
+            buf,
+            "{{\"frame_type\":\"stream_data_blocked\",\"stream_id\":{},\"limit\":{offset}}}",
+            id.0
+        ),
+        Frame::StreamsBlocked { dir, limit } => write!(
+            buf,
+            "{{\"frame_type\":\"streams_blocked\",\"stream_type\":\"{}\",\"limit\":{limit}}}",
+            stream_type(dir)
+        ),
+        Frame::NewConnectionId(ref frame) => write!(
+            buf,
+            "{{\"frame_type\":\"new_connection_id\",\"sequence_number\":{},\"retire_prior_to\":{},\
+             \"connection_id_length\":{},\"connection_id\":\"{}\"}}",
+            frame.sequence,
+            frame.retire_prior_to,
+            frame.id.len(),
+            frame.id
+        ),
+        Frame::RetireConnectionId { sequence } => write!(
+            buf,
+            "{{\"frame_type\":\"retire_connection_id\",\"sequence_number\":{sequence}}}"
+        ),
+        Frame::PathChallenge(token) => write!(
+            buf,
+            "{{\"frame_type\":\"path_challenge\",\"data\":\"{token:016x}\"}}"
+        ),
+        Frame::PathResponse(token) => write!(
+            buf,
+            "{{\"frame_type\":\"path_response\",\"data\":\"{token:016x}\"}}"
+        ),
+        Frame::Close(Close::Connection(ref frame)) => write!(
+            buf,
+            "{{\"frame_type\":\"connection_close\",\"error_space\":\"transport\",\
+             \"error_code\":{},\"reason\":\"{}\"}}",
+            u64::from(frame.error_code),
+            JsonStr(&String::from_utf8_lossy(&frame.reason))
+        ),
+        Frame::Close(Close::Application(ref frame)) => write!(
+            buf,
+            "{{\"frame_type\":\"connection_close\",\"error_space\":\"application\",\
+             \"error_code\":{},\"reason\":\"{}\"}}",
+            frame.error_code,
+            JsonStr(&String::from_utf8_lossy(&frame.reason))
+        ),
+        Frame::Datagram(ref frame) => write!(
+            buf,
+            "{{\"frame_type\":\"datagram\",\"length\":{}}}",
+            frame.data.len()
+        ),
+        Frame::HandshakeDone => write!(buf, "{{\"frame_type\":\"handshake_done\"}}"),
+        Frame::AckFrequency(ref frame) => write!(
+            buf,
+            "{{\"frame_type\":\"ack_frequency\",\"sequence_number\":{},\
+             \"ack_eliciting_threshold\":{},\"request_max_ack_delay\":{},\
+             \"reordering_threshold\":{}}}",
+            frame.sequence,
+            frame.ack_eliciting_threshold,
+            frame.request_max_ack_delay,
+            frame.reordering_threshold
+        ),
+        Frame::ImmediateAck => write!(buf, "{{\"frame_type\":\"immediate_ack\"}}"),
+        Frame::Invalid { ty, reason } => write!(
+            buf,
+            "{{\"frame_type\":\"unknown\",\"reason\":\"{ty}: {reason}\"}}"
+        ),
+    };
+}
+
+/// A run of padding bytes
+struct Padding(usize);
+
+impl fmt::Display for Padding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{\"frame_type\":\"padding\",\"raw\":{{\"length\":{}}}}}",
+            self.0
+        )
+    }
+}
+
+fn stream_type(dir: Dir) -> &'static str {
+    match dir {
+        Dir::Bi => "bidirectional",
+        Dir::Uni => "unidirectional",
+    }
+}
+
+/// Escapes a string for inclusion in a JSON string literal
+struct JsonStr<'a>(&'a str);
+
+impl fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn escape() {
+        assert_eq!(
+            JsonStr("a\"b\\c\nd").to_string(),
+            "a\\\"b\\\\c\\u000ad".to_string()
+        );
+    }
+}
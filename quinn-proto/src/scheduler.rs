@@ -0,0 +1,64 @@
+//! Logic for deciding which stream's data is transmitted next
+
+use crate::StreamId;
+
+mod extensible;
+mod strict;
+mod weighted;
+
+pub use extensible::{ExtensiblePriorities, ExtensiblePrioritiesConfig};
+pub use strict::{StrictPriority, StrictPriorityConfig};
+pub use weighted::{WeightedFair, WeightedFairConfig};
+
+/// Common interface for different stream schedulers
+///
+/// A scheduler queues the streams which have data to send. When building a packet, the connection
+/// repeatedly takes the next stream from the scheduler, writes as much of its data as fits into a
+/// single STREAM frame, and pushes it back if it has more to send.
+pub trait StreamScheduler: Send {
+    /// Queue a stream which has data to send and isn't queued already
+    ///
+    /// `priority` and `incremental` are the values most recently set on the stream through
+    /// [`SendStream::set_priority`] and [`SendStream::set_incremental`].
+    ///
+    /// [`SendStream::set_priority`]: crate::SendStream::set_priority
+    /// [`SendStream::set_incremental`]: crate::SendStream::set_incremental
+    fn push(&mut self, stream: StreamId, priority: i32, incremental: bool);
+
+    /// The stream which should transmit next, without dequeuing it
+    fn peek(&self) -> Option<StreamId>;
+
+    /// Dequeue the stream returned by [`peek`](Self::peek)
+    fn pop(&mut self) -> Option<StreamId>;
+
+    /// The priority queued stream data is considered to have when ordering it against datagrams
+    ///
+    /// Datagrams are sent ahead of all stream data while this is at most their
+    /// [priority](crate::DatagramOptions::priority), and after it otherwise. Typically the highest
+    /// priority among queued streams, which needn't be that of the stream [`peek`](Self::peek)
+    /// returns. `None` if no streams are queued.
+    fn max_priority(&self) -> Option<i32>;
+
+    /// The stream which was most recently dequeued transmitted `bytes` of data
+    ///
+    /// Called before the stream is pushed again, if it has more data to send.
+    #[allow(unused_variables)]
+    fn on_transmit(&mut self, stream: StreamId, bytes: u64) {}
+
+    /// Dequeue a stream which no longer has data to send, e.g. because it was reset
+    ///
+    /// Must be a no-op for streams which aren't queued.
+    fn remove(&mut self, stream: StreamId);
+
+    /// Whether no streams are queued
+    fn is_empty(&self) -> bool;
+
+    /// Dequeue all streams
+    fn clear(&mut self);
+}
+
+/// Constructs stream schedulers on demand
+pub trait StreamSchedulerFactory {
+    /// Construct a fresh `StreamScheduler`
+    fn build(&self) -> Box<dyn StreamScheduler>;
+}
@@ -0,0 +1,171 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use super::{StreamScheduler, StreamSchedulerFactory};
+use crate::StreamId;
+
+/// Schedules streams like the Extensible Prioritization Scheme for HTTP (RFC 9218)
+///
+/// Streams are served in order of urgency, from 0 (most urgent) to 7. To keep higher priorities
+/// more important, a stream's urgency is 3 minus its priority, clamped to that range: the default
+/// priority 0 corresponds to the default urgency 3, and a priority of 3 or more to urgency 0.
+///
+/// Among streams of equal urgency, non-incremental streams are served first, one at a time in order
+/// of stream ID, so that each is delivered in full before the next starts. Incremental streams
+/// then share the remaining capacity round-robin.
+#[derive(Default)]
+pub struct ExtensiblePriorities {
+    levels: [Urgency; 8],
+}
+
+impl ExtensiblePriorities {
+    fn urgency(priority: i32) -> usize {
+        (3 - i64::from(priority)).clamp(0, 7) as usize
+    }
+}
+
+impl StreamScheduler for ExtensiblePriorities {
+    fn push(&mut self, stream: StreamId, priority: i32, incremental: bool) {
+        let level = &mut self.levels[Self::urgency(priority)];
+        match incremental {
+            true => level.incremental.push_back(stream),
+            false => {
+                level.sequential.insert(stream);
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<StreamId> {
+        self.levels.iter().find_map(|level| {
+            level
+                .sequential
+                .iter()
+                .next()
+                .or_else(|| level.incremental.front())
+                .copied()
+        })
+    }
+
+    /// Streams are compared by urgency alone, so this is the lowest priority mapping to the most
+    /// urgent queued level, and datagrams of the same urgency are sent first
+    fn max_priority(&self) -> Option<i32> {
+        let urgency = self
+            .levels
+            .iter()
+            .position(|x| !x.sequential.is_empty() || !x.incremental.is_empty())?;
+        Some(match urgency {
+            7 => i32::MIN,
+            x => 3 - x as i32,
+        })
+    }
+
+    fn pop(&mut self) -> Option<StreamId> {
+        let stream = self.peek()?;
+        for level in &mut self.levels {
+            if level.sequential.remove(&stream) {
+                break;
+            }
+            if level.incremental.front() == Some(&stream) {
+                level.incremental.pop_front();
+                break;
+            }
+        }
+        Some(stream)
+    }
+
+    fn remove(&mut self, stream: StreamId) {
+        for level in &mut self.levels {
+            level.sequential.remove(&stream);
+            level.incremental.retain(|&x| x != stream);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.levels
+            .iter()
+            .all(|x| x.sequential.is_empty() && x.incremental.is_empty())
+    }
+
+    fn clear(&mut self) {
+        for level in &mut self.levels {
+            level.sequential.clear();
+            level.incremental.clear();
+        }
+    }
+}
+
+/// Builds [`ExtensiblePriorities`] schedulers
+#[derive(Debug, Clone, Default)]
+pub struct ExtensiblePrioritiesConfig {
+    _private: (),
+}
+
+impl StreamSchedulerFactory for ExtensiblePrioritiesConfig {
+    fn build(&self) -> Box<dyn StreamScheduler> {
+        Box::<ExtensiblePriorities>::default()
+    }
+}
+
+#[derive(Default)]
+struct Urgency {
+    /// Non-incremental streams, served in order of stream ID
+    sequential: BTreeSet<StreamId>,
+    /// Incremental streams, served round-robin
+    incremental: VecDeque<StreamId>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dir, Side};
+
+    #[test]
+    fn urgency_and_incremental() {
+        let mut scheduler = ExtensiblePriorities::default();
+        let ids = (0..5)
+            .map(|i| StreamId::new(Side::Server, Dir::Uni, i))
+            .collect::<Vec<_>>();
+        scheduler.push(ids[0], 0, true);
+        scheduler.push(ids[1], 0, true);
+        scheduler.push(ids[3], 0, false);
+        scheduler.push(ids[2], 0, false);
+        scheduler.push(ids[4], 5, true);
+
+        // Most urgent first
+        assert_eq!(scheduler.pop(), Some(ids[4]));
+        // Non-incremental streams are sent to completion in order of stream ID
+        for _ in 0..2 {
+            assert_eq!(scheduler.pop(), Some(ids[2]));
+            scheduler.push(ids[2], 0, false);
+        }
+        scheduler.remove(ids[2]);
+        assert_eq!(scheduler.pop(), Some(ids[3]));
+        // Incremental streams take turns
+        assert_eq!(scheduler.pop(), Some(ids[0]));
+        scheduler.push(ids[0], 0, true);
+        assert_eq!(scheduler.pop(), Some(ids[1]));
+        assert_eq!(scheduler.pop(), Some(ids[0]));
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn urgency_mapping() {
+        assert_eq!(ExtensiblePriorities::urgency(0), 3);
+        assert_eq!(ExtensiblePriorities::urgency(i32::MAX), 0);
+        assert_eq!(ExtensiblePriorities::urgency(-4), 7);
+        assert_eq!(ExtensiblePriorities::urgency(i32::MIN), 7);
+    }
+
+    #[test]
+    fn max_priority() {
+        let mut scheduler = ExtensiblePriorities::default();
+        let a = StreamId::new(Side::Server, Dir::Uni, 0);
+        let b = StreamId::new(Side::Server, Dir::Uni, 1);
+        assert_eq!(scheduler.max_priority(), None);
+        scheduler.push(a, -10, false);
+        assert_eq!(scheduler.max_priority(), Some(i32::MIN));
+        scheduler.push(b, 10, true);
+        assert_eq!(scheduler.max_priority(), Some(3));
+        scheduler.remove(b);
+        assert_eq!(scheduler.max_priority(), Some(i32::MIN));
+    }
+}
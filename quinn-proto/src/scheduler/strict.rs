@@ -0,0 +1,198 @@
+use std::{
+    cell::RefCell,
+    collections::{binary_heap::PeekMut, BinaryHeap, VecDeque},
+    mem,
+};
+
+use super::{StreamScheduler, StreamSchedulerFactory};
+use crate::StreamId;
+
+/// Transmits streams strictly in order of priority, round-robin among streams of equal priority
+///
+/// Streams with higher priority are always served first. Whether a stream is incremental is
+/// ignored: a stream which transmitted a frame is queued behind the other streams of its priority.
+#[derive(Default)]
+pub struct StrictPriority {
+    levels: BinaryHeap<PendingLevel>,
+}
+
+impl StreamScheduler for StrictPriority {
+    fn push(&mut self, stream: StreamId, priority: i32, _incremental: bool) {
+        for level in self.levels.iter() {
+            if priority == level.priority {
+                level.queue.borrow_mut().push_back(stream);
+                return;
+            }
+        }
+
+        // If there is only a single level and it's empty, repurpose it for the
+        // required priority
+        if self.levels.len() == 1 {
+            if let Some(mut first) = self.levels.peek_mut() {
+                let mut queue = first.queue.borrow_mut();
+                if queue.is_empty() {
+                    queue.push_back(stream);
+                    drop(queue);
+                    first.priority = priority;
+                    return;
+                }
+            }
+        }
+
+        let mut queue = VecDeque::new();
+        queue.push_back(stream);
+        self.levels.push(PendingLevel {
+            queue: RefCell::new(queue),
+            priority,
+        });
+    }
+
+    fn peek(&self) -> Option<StreamId> {
+        self.levels.peek()?.queue.borrow().front().copied()
+    }
+
+    fn max_priority(&self) -> Option<i32> {
+        self.levels
+            .peek()
+            .filter(|level| !level.queue.borrow().is_empty())
+            .map(|level| level.priority)
+    }
+
+    fn pop(&mut self) -> Option<StreamId> {
+        let num_levels = self.levels.len();
+        let mut level = self.levels.peek_mut()?;
+        let stream = level.queue.get_mut().pop_front();
+        if level.queue.get_mut().is_empty() && num_levels != 1 {
+            // We keep the last level around even in empty form so that
+            // the next insert doesn't have to reallocate the queue
+            PeekMut::pop(level);
+        }
+        stream
+    }
+
+    fn remove(&mut self, stream: StreamId) {
+        let num_levels = self.levels.len();
+        for level in self.levels.iter() {
+            level.queue.borrow_mut().retain(|&x| x != stream);
+        }
+        if num_levels > 1 {
+            let levels = mem::take(&mut self.levels).into_vec();
+            self.levels = levels
+                .into_iter()
+                .filter(|x| !x.queue.borrow().is_empty())
+                .collect();
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.levels.iter().all(|x| x.queue.borrow().is_empty())
+    }
+
+    fn clear(&mut self) {
+        self.levels.clear();
+    }
+}
+
+/// Builds [`StrictPriority`] schedulers, the default
+#[derive(Debug, Clone, Default)]
+pub struct StrictPriorityConfig {
+    _private: (),
+}
+
+impl StreamSchedulerFactory for StrictPriorityConfig {
+    fn build(&self) -> Box<dyn StreamScheduler> {
+        Box::<StrictPriority>::default()
+    }
+}
+
+struct PendingLevel {
+    // RefCell is needed because BinaryHeap doesn't have an iter_mut()
+    queue: RefCell<VecDeque<StreamId>>,
+    priority: i32,
+}
+
+impl PartialEq for PendingLevel {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority.eq(&other.priority)
+    }
+}
+
+impl PartialOrd for PendingLevel {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Eq for PendingLevel {}
+
+impl Ord for PendingLevel {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority.cmp(&other.priority)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dir, Side};
+
+    #[test]
+    fn priority_order() {
+        let mut scheduler = StrictPriority::default();
+        let ids = (0..4)
+            .map(|i| StreamId::new(Side::Client, Dir::Bi, i))
+            .collect::<Vec<_>>();
+        scheduler.push(ids[0], 0, false);
+        scheduler.push(ids[1], -1, false);
+        scheduler.push(ids[2], 1, false);
+        scheduler.push(ids[3], 0, false);
+        assert_eq!(scheduler.levels.len(), 3);
+
+        assert_eq!(scheduler.peek(), Some(ids[2]));
+        assert_eq!(scheduler.pop(), Some(ids[2]));
+        assert_eq!(scheduler.levels.len(), 2);
+        // Round-robin within a level
+        assert_eq!(scheduler.pop(), Some(ids[0]));
+        scheduler.push(ids[0], 0, false);
+        assert_eq!(scheduler.pop(), Some(ids[3]));
+        assert_eq!(scheduler.pop(), Some(ids[0]));
+        assert_eq!(scheduler.pop(), Some(ids[1]));
+        assert!(scheduler.is_empty());
+        // The last level is kept for reuse
+        assert_eq!(scheduler.levels.len(), 1);
+    }
+
+    #[test]
+    fn requeue_at_new_priority() {
+        let mut scheduler = StrictPriority::default();
+        let mid = StreamId::new(Side::Server, Dir::Bi, 0);
+        let high = StreamId::new(Side::Server, Dir::Bi, 1);
+        scheduler.push(mid, 0, false);
+        assert_eq!(scheduler.levels.len(), 1);
+        scheduler.push(high, 1, false);
+        assert_eq!(scheduler.levels.len(), 2);
+
+        // Requeuing at a lower priority drops the level the stream was queued at, so we end up
+        // with 2 levels - not 3
+        assert_eq!(scheduler.pop(), Some(high));
+        scheduler.push(high, -1, false);
+        assert_eq!(scheduler.levels.len(), 2);
+        assert_eq!(scheduler.pop(), Some(mid));
+        assert_eq!(scheduler.pop(), Some(high));
+        assert_eq!(scheduler.levels.len(), 1);
+    }
+
+    #[test]
+    fn remove() {
+        let mut scheduler = StrictPriority::default();
+        let a = StreamId::new(Side::Client, Dir::Uni, 0);
+        let b = StreamId::new(Side::Client, Dir::Uni, 1);
+        scheduler.push(a, 1, false);
+        scheduler.push(b, 0, false);
+        scheduler.remove(a);
+        assert_eq!(scheduler.levels.len(), 1);
+        assert_eq!(scheduler.peek(), Some(b));
+        scheduler.remove(b);
+        assert!(scheduler.is_empty());
+    }
+}
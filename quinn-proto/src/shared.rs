@@ -1,4 +1,8 @@
-use std::{fmt, net::SocketAddr, time::Instant};
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    time::Instant,
+};
 
 use bytes::{Buf, BufMut, BytesMut};
 
@@ -14,6 +18,7 @@ pub(crate) enum ConnectionEventInner {
     Datagram {
         now: Instant,
         remote: SocketAddr,
+        local_ip: Option<IpAddr>,
         ecn: Option<EcnCodepoint>,
         first_decode: PartialDecode,
         remaining: Option<BytesMut>,
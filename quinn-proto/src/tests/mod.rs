@@ -1,7 +1,7 @@
 use std::{
     convert::TryInto,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
-    sync::Arc,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
@@ -87,6 +87,79 @@ fn version_negotiate_client() {
     );
 }
 
+#[test]
+fn version_negotiate_fallback() {
+    let _guard = subscribe();
+    let mut server_endpoint_config = EndpointConfig::default();
+    server_endpoint_config.supported_versions(vec![0xff00_0020]);
+    let server = Endpoint::new(
+        Arc::new(server_endpoint_config),
+        Some(Arc::new(server_config())),
+        true,
+    );
+    let client = Endpoint::new(Default::default(), None, true);
+    let mut pair = Pair::new_from_endpoint(client, server);
+
+    // The client starts out with version 1, which the server doesn't support
+    let (client_ch, server_ch) = pair.connect();
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+}
+
+#[test]
+fn version_negotiate_downgrade() {
+    let _guard = subscribe();
+    // Configure client to use empty CIDs so we can easily hardcode a server version negotiation
+    // packet
+    let cid_generator_factory: fn() -> Box<dyn ConnectionIdGenerator> =
+        || Box::new(RandomConnectionIdGenerator::new(0));
+    let client = Endpoint::new(
+        Arc::new(EndpointConfig {
+            connection_id_generator_factory: Arc::new(cid_generator_factory),
+            ..Default::default()
+        }),
+        None,
+        true,
+    );
+    let server = Endpoint::new(Default::default(), Some(Arc::new(server_config())), true);
+    let mut pair = Pair::new_from_endpoint(client, server);
+
+    let client_ch = pair.begin_connect(client_config());
+    pair.drive_client();
+    // An attacker drops the client's Initial and forges a Version Negotiation packet that only
+    // lists a version the client likes less than the one it tried
+    pair.server.inbound.clear();
+    pair.client.inbound.push_back((
+        pair.time,
+        None,
+        hex!(
+            "80 00000000 00 04 00000000
+             ff000020"
+        )[..]
+            .into(),
+        None,
+    ));
+    pair.drive();
+
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::ConnectionLost {
+            reason: ConnectionError::TransportError(ref error)
+        }) if error.code == TransportErrorCode::VERSION_NEGOTIATION_ERROR
+    );
+}
+
 #[test]
 fn lifecycle() {
     let _guard = subscribe();
@@ -148,6 +221,56 @@ fn draft_version_compat() {
     assert_eq!(pair.server.known_cids(), 0);
 }
 
+#[test]
+fn quic_v2() {
+    let _guard = subscribe();
+
+    let mut client_config = client_config();
+    client_config.version(QUIC_V2);
+
+    // Exercise v2 Retry integrity protection as well as the regular handshake
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            use_retry: true,
+            ..server_config()
+        },
+    );
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+
+    // Key updates use v2-specific labels
+    pair.client_conn_mut(client_ch).initiate_key_update();
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::KeysUpdated { key_phase: 1, .. })
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+}
+
+#[test]
+fn version_negotiate_v1_to_v2() {
+    let _guard = subscribe();
+    let mut server_endpoint_config = EndpointConfig::default();
+    server_endpoint_config.supported_versions(vec![QUIC_V2]);
+    let server = Endpoint::new(
+        Arc::new(server_endpoint_config),
+        Some(Arc::new(server_config())),
+        true,
+    );
+    let client = Endpoint::new(Default::default(), None, true);
+    let mut pair = Pair::new_from_endpoint(client, server);
+
+    // The client starts out with version 1 and switches to the only version the server supports
+    let (client_ch, _) = pair.connect();
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+}
+
 #[test]
 fn stateless_retry() {
     let _guard = subscribe();
@@ -161,6 +284,116 @@ fn stateless_retry() {
     pair.connect();
 }
 
+#[test]
+fn new_token_skips_retry() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            use_retry: true,
+            ..server_config()
+        },
+    );
+    let client_config = client_config();
+    let (client_ch, _) = pair.connect_with(client_config.clone());
+    pair.drive();
+    assert_eq!(
+        pair.client_conn_mut(client_ch).stats().frame_rx.new_token,
+        2
+    );
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .close(now, VarInt(0), [][..].into());
+    pair.drive();
+
+    // The first Initial of a new connection carries a token, so the server accepts immediately
+    let client_ch = pair.begin_connect(client_config);
+    pair.drive_client();
+    pair.drive_server();
+    let server_ch = pair.server.assert_accept();
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Connected)
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+}
+
+#[test]
+fn new_token_replay() {
+    /// Hands out the same token for every connection, like an attacker replaying it would
+    struct Replay(Mutex<Option<Bytes>>);
+
+    impl TokenStore for Replay {
+        fn insert(&self, _: &str, token: Bytes) {
+            self.0.lock().unwrap().get_or_insert(token);
+        }
+
+        fn take(&self, _: &str) -> Option<Bytes> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            use_retry: true,
+            ..server_config()
+        },
+    );
+    let mut client_config = client_config();
+    client_config.token_store(Some(Arc::new(Replay(Mutex::new(None)))));
+    let (client_ch, _) = pair.connect_with(client_config.clone());
+    pair.drive();
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .close(now, VarInt(0), [][..].into());
+    pair.drive();
+
+    // The token is accepted the first time it's used...
+    let client_ch = pair.begin_connect(client_config.clone());
+    pair.drive_client();
+    pair.drive_server();
+    pair.server.assert_accept();
+    pair.drive();
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .close(now, VarInt(0), [][..].into());
+    pair.drive();
+
+    // ...but replaying it gets a Retry
+    pair.begin_connect(client_config);
+    pair.drive_client();
+    pair.drive_server();
+    pair.server.assert_no_accept();
+    pair.drive();
+    pair.server.assert_accept();
+}
+
+#[test]
+fn stateless_retry_without_token() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            use_retry: true,
+            ..server_config()
+        },
+    );
+    pair.begin_connect(client_config());
+    pair.drive_client();
+    pair.drive_server();
+    pair.server.assert_no_accept();
+}
+
 #[test]
 fn server_stateless_reset() {
     let _guard = subscribe();
@@ -311,6 +544,209 @@ fn reset_stream() {
     assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
 }
 
+#[test]
+fn write_deadline() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    const MSG: &[u8] = b"hello";
+    const ERROR: VarInt = VarInt(42);
+
+    // Acknowledged in time
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let deadline = pair.time + Duration::from_millis(10);
+    assert_eq!(
+        pair.client_send(client_ch, s)
+            .write_with_deadline(MSG, deadline, ERROR),
+        Ok(MSG.len())
+    );
+    pair.drive();
+    pair.time = deadline + Duration::from_millis(1);
+    pair.drive();
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(chunks.next(usize::MAX), Ok(Some(chunk)) if chunk.bytes == MSG);
+    let _ = chunks.finalize();
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+
+    // Lost until the deadline passes
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let deadline = pair.time + Duration::from_millis(1);
+    pair.client_send(client_ch, s)
+        .write_with_deadline(MSG, deadline, ERROR)
+        .unwrap();
+    pair.drive_client();
+    assert!(!pair.server.inbound.is_empty());
+    pair.server.inbound.clear();
+    pair.time = deadline;
+    pair.drive_client();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Expired { id, error_code: ERROR })) if id == s
+    );
+    assert_eq!(
+        pair.client_send(client_ch, s).write(MSG),
+        Err(WriteError::Expired(ERROR))
+    );
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(chunks.next(usize::MAX), Err(ReadError::Reset(ERROR)));
+    let _ = chunks.finalize();
+}
+
+#[test]
+fn write_message_deadline() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport: Arc::new(TransportConfig {
+                stream_receive_window: 2000u32.into(),
+                ..TransportConfig::default()
+            }),
+            ..server_config()
+        },
+    );
+    let (client_ch, server_ch) = pair.connect();
+    const ERROR: VarInt = VarInt(42);
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let deadline = pair.time + Duration::from_millis(1);
+    assert_eq!(
+        pair.client_send(client_ch, s)
+            .write_message_with_deadline(&[0xab; 1500], deadline, ERROR),
+        Ok(())
+    );
+    // Messages are written whole or not at all
+    let later = deadline + Duration::from_secs(10);
+    assert_eq!(
+        pair.client_send(client_ch, s)
+            .write_message_with_deadline(&[0xcd; 1000], later, ERROR),
+        Err(WriteError::Blocked)
+    );
+    // ...which fails outright for messages larger than the peer's window
+    assert_eq!(
+        pair.client_send(client_ch, s)
+            .write_message_with_deadline(&[0xcd; 2500], later, ERROR),
+        Err(WriteError::TooLarge)
+    );
+    assert_eq!(
+        pair.client_send(client_ch, s)
+            .write_message_with_deadline(&[], later, ERROR),
+        Ok(())
+    );
+
+    // Skipped before any of it was sent, releasing its flow control credit
+    pair.time = deadline;
+    pair.client_conn_mut(client_ch).handle_timeout(deadline);
+    assert_eq!(
+        pair.client_send(client_ch, s)
+            .write_message_with_deadline(&[0xcd; 1000], later, ERROR),
+        Ok(())
+    );
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Finished { id })) if id == s
+    );
+
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    let mut received = Vec::new();
+    while let Ok(Some(chunk)) = chunks.next(usize::MAX) {
+        assert_eq!(chunk.offset, received.len() as u64);
+        received.extend_from_slice(&chunk.bytes);
+    }
+    let _ = chunks.finalize();
+    assert_eq!(received, [0xcd; 1000]);
+
+    // Already sent when the deadline passes, so the stream must be reset
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let deadline = pair.time + Duration::from_millis(1);
+    pair.client_send(client_ch, s)
+        .write_message_with_deadline(b"lost", deadline, ERROR)
+        .unwrap();
+    pair.drive_client();
+    assert!(!pair.server.inbound.is_empty());
+    pair.server.inbound.clear();
+    pair.time = deadline;
+    pair.drive_client();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Expired { id, error_code: ERROR })) if id == s
+    );
+}
+
+#[test]
+fn reset_stream_at() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    const HEADER: &[u8] = b"header";
+    const ERROR: VarInt = VarInt(42);
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(HEADER).unwrap();
+    pair.client_send(client_ch, s).write(&[0xab; 4000]).unwrap();
+    assert_eq!(
+        pair.client_send(client_ch, s).reset_at(ERROR, 5000),
+        Err(ResetAtError::ReliableSizeTooLarge)
+    );
+    // Lose everything sent so far
+    pair.drive_client();
+    assert!(!pair.server.inbound.is_empty());
+    pair.server.inbound.clear();
+
+    info!("resetting stream");
+    pair.client_send(client_ch, s)
+        .reset_at(ERROR, HEADER.len() as u64)
+        .unwrap();
+    assert_eq!(
+        pair.client_send(client_ch, s).reset_at(ERROR, 1),
+        Err(ResetAtError::UnknownStream)
+    );
+    pair.drive();
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .stats()
+            .frame_tx
+            .reset_stream_at,
+        1
+    );
+
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Uni), Some(stream) if stream == s);
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    assert_matches!(chunks.next(usize::MAX), Ok(Some(chunk)) if chunk.offset == 0 && chunk.bytes == HEADER);
+    assert_matches!(chunks.next(usize::MAX), Err(ReadError::Reset(ERROR)));
+    let _ = chunks.finalize();
+
+    // The stream was forgotten once the reset and the reliable data were acknowledged
+    assert_eq!(pair.client_streams(client_ch).send_streams(), 0);
+}
+
 #[test]
 fn stop_stream() {
     let _guard = subscribe();
@@ -415,16 +851,192 @@ fn congestion() {
     const TARGET: u64 = 2048;
     assert!(pair.client_conn_mut(client_ch).congestion_window() > TARGET);
     let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
-    // Send data without receiving ACKs until the congestion state falls below target
-    while pair.client_conn_mut(client_ch).congestion_window() > TARGET {
-        let n = pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
-        assert_eq!(n, 1024);
-        pair.drive_client();
-    }
-    // Ensure that the congestion state recovers after receiving the ACKs
+    // Send data without receiving ACKs until the congestion state falls below target
+    while pair.client_conn_mut(client_ch).congestion_window() > TARGET {
+        let n = pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
+        assert_eq!(n, 1024);
+        pair.drive_client();
+    }
+    // Ensure that the congestion state recovers after receiving the ACKs
+    pair.drive();
+    assert!(pair.client_conn_mut(client_ch).congestion_window() >= TARGET);
+    pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
+}
+
+#[test]
+fn ect1_ce_marks() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut transport = TransportConfig::default();
+    transport
+        .send_ect1(true)
+        .congestion_controller_factory(Arc::new(congestion::PragueConfig::default()));
+    let (client_ch, _) = pair.connect_with(ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    });
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s)
+        .write(&[42; 32 * 1024])
+        .unwrap();
+    pair.drive();
+    // The peer's feedback for ECT(1) packets was validated
+    assert!(pair.client_conn_mut(client_ch).using_ecn());
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .stats()
+            .path
+            .congestion_events,
+        0
+    );
+    let window = pair.client_conn_mut(client_ch).congestion_window();
+
+    pair.mark_ce = true;
+    pair.client_send(client_ch, s)
+        .write(&[42; 32 * 1024])
+        .unwrap();
+    pair.drive();
+    assert!(pair.client_conn_mut(client_ch).using_ecn());
+    assert_ne!(
+        pair.client_conn_mut(client_ch)
+            .stats()
+            .path
+            .congestion_events,
+        0
+    );
+    assert!(pair.client_conn_mut(client_ch).congestion_window() < window);
+}
+
+#[test]
+fn rate_samples() {
+    /// Keeps a fixed window, recording the rate samples it receives
+    #[derive(Clone)]
+    struct Recorder(Arc<Mutex<Vec<RateSample>>>);
+
+    impl congestion::Controller for Recorder {
+        fn on_rate_sample(&mut self, _now: Instant, sample: &RateSample) {
+            self.0.lock().unwrap().push(*sample);
+        }
+
+        fn on_congestion_event(&mut self, _: Instant, _: Instant, _: bool, _: u64) {}
+
+        fn on_mtu_update(&mut self, _new_mtu: u16) {}
+
+        fn window(&self) -> u64 {
+            64 * 1024
+        }
+
+        fn clone_box(&self) -> Box<dyn congestion::Controller> {
+            Box::new(self.clone())
+        }
+
+        fn initial_window(&self) -> u64 {
+            self.window()
+        }
+
+        fn into_any(self: Box<Self>) -> Box<dyn std::any::Any> {
+            self
+        }
+    }
+
+    impl congestion::ControllerFactory for Recorder {
+        fn build(&self, _now: Instant, _current_mtu: u16) -> Box<dyn congestion::Controller> {
+            Box::new(self.clone())
+        }
+    }
+
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10);
+    let samples = Arc::new(Mutex::new(Vec::new()));
+    let mut transport = TransportConfig::default();
+    transport.congestion_controller_factory(Recorder(samples.clone()));
+    let (client_ch, _) = pair.connect_with(ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    });
+    samples.lock().unwrap().clear();
+
+    const SIZE: usize = 512 * 1024;
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[42; SIZE]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+
+    let samples = samples.lock().unwrap();
+    let acked = samples.iter().map(|x| x.newly_acked).sum::<u64>();
+    assert!(acked >= SIZE as u64);
+    // The window, rather than the application, limits the rate once the transfer is under way,
+    // to at most one window per round trip
+    let sample = samples.last().unwrap();
+    assert!(!sample.is_app_limited);
+    let rate = sample.delivery_rate.unwrap();
+    assert!(rate <= 64 * 1024 * 1000 / 20, "rate {rate}");
+    assert!(rate >= 64 * 1024 * 1000 / 40, "rate {rate}");
+}
+
+#[allow(clippy::field_reassign_with_default)] // https://github.com/rust-lang/rust-clippy/issues/6527
+#[test]
+fn send_rate_limit() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10);
+    let mut transport = TransportConfig::default();
+    transport.max_send_rate(Some(1_000_000));
+    let (client_ch, _) = pair.connect_with(ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    });
+
+    const SIZE: usize = 500 * 1000;
+    let start = pair.time;
+    let before = pair.client_conn_mut(client_ch).stats();
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[42; SIZE]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    let elapsed = pair.time - start;
+    assert!(elapsed > Duration::from_millis(500), "took {elapsed:?}");
+    assert!(elapsed < Duration::from_millis(600), "took {elapsed:?}");
+    // Time spent held back by the limit is accounted for separately
+    let stats = pair.client_conn_mut(client_ch).stats();
+    assert!(
+        stats.path.rate_limited_time > elapsed * 9 / 10,
+        "{:?}",
+        stats.path.rate_limited_time
+    );
+    let app_limited_time = stats.path.app_limited_time - before.path.app_limited_time;
+    assert!(app_limited_time < elapsed / 10, "{app_limited_time:?}");
+
+    // Lifting the limit speeds things up
+    pair.client_conn_mut(client_ch).set_max_send_rate(None);
+    let start = pair.time;
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[42; SIZE]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+    assert!(pair.time - start < Duration::from_millis(200));
+}
+
+#[allow(clippy::field_reassign_with_default)] // https://github.com/rust-lang/rust-clippy/issues/6527
+#[test]
+fn endpoint_send_rate_limit() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.latency = Duration::from_millis(10);
+    let (client_ch, _) = pair.connect();
+
+    // The limit applies to existing connections
+    pair.client.endpoint.set_max_send_rate(Some(1_000_000));
+    const SIZE: usize = 500 * 1000;
+    let start = pair.time;
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(&[42; SIZE]).unwrap();
+    pair.client_send(client_ch, s).finish().unwrap();
     pair.drive();
-    assert!(pair.client_conn_mut(client_ch).congestion_window() >= TARGET);
-    pair.client_send(client_ch, s).write(&[42; 1024]).unwrap();
+    let elapsed = pair.time - start;
+    assert!(elapsed > Duration::from_millis(500), "took {elapsed:?}");
+    assert!(elapsed < Duration::from_millis(600), "took {elapsed:?}");
 }
 
 #[allow(clippy::field_reassign_with_default)] // https://github.com/rust-lang/rust-clippy/issues/6527
@@ -794,6 +1406,24 @@ fn key_update_simple() {
     pair.client_send(client_ch, s).write(MSG2).unwrap();
     pair.drive();
 
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::KeysUpdated {
+            key_phase: 1,
+            initiator: Side::Client,
+            ..
+        })
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::KeysUpdated {
+            key_phase: 1,
+            initiator: Side::Client,
+            ..
+        })
+    );
+    assert_eq!(pair.client_conn_mut(client_ch).stats().local_key_updates, 1);
+    assert_eq!(pair.server_conn_mut(server_ch).stats().peer_key_updates, 1);
     assert_matches!(pair.server_conn_mut(server_ch).poll(), Some(Event::Stream(StreamEvent::Readable { id })) if id == s);
     assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
     let mut recv = pair.server_recv(server_ch, s);
@@ -808,6 +1438,87 @@ fn key_update_simple() {
     assert_eq!(pair.server_conn_mut(server_ch).lost_packets(), 0);
 }
 
+#[test]
+fn key_update_interval() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut key_updates = KeyUpdateConfig::default();
+    key_updates.interval(Some(Duration::from_secs(1)));
+    let client_config = ClientConfig {
+        transport: Arc::new(TransportConfig {
+            key_update_config: Some(key_updates),
+            ..TransportConfig::default()
+        }),
+        ..client_config()
+    };
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+
+    for i in 1..=3 {
+        pair.time += Duration::from_millis(1500);
+        pair.client_conn_mut(client_ch).ping();
+        pair.drive();
+        assert_matches!(
+            pair.client_conn_mut(client_ch).poll(),
+            Some(Event::KeysUpdated {
+                key_phase,
+                initiator: Side::Client,
+                previous_duration,
+            }) if key_phase == i && previous_duration >= Duration::from_secs(1)
+        );
+        assert_matches!(
+            pair.server_conn_mut(server_ch).poll(),
+            Some(Event::KeysUpdated { key_phase, initiator: Side::Client, .. }) if key_phase == i
+        );
+    }
+    assert_eq!(pair.client_conn_mut(client_ch).stats().local_key_updates, 3);
+    assert_eq!(pair.client_conn_mut(client_ch).stats().peer_key_updates, 0);
+    assert_eq!(pair.server_conn_mut(server_ch).stats().peer_key_updates, 3);
+}
+
+#[test]
+fn key_update_bytes() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut key_updates = KeyUpdateConfig::default();
+    key_updates.bytes(Some(20_000));
+    let client_config = ClientConfig {
+        transport: Arc::new(TransportConfig {
+            key_update_config: Some(key_updates),
+            ..TransportConfig::default()
+        }),
+        ..client_config()
+    };
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    const SIZE: usize = 100_000;
+    let msg = vec![0xAB; SIZE];
+    let mut sent = 0;
+    while sent < SIZE {
+        sent += pair.client_send(client_ch, s).write(&msg[sent..]).unwrap();
+        pair.drive();
+    }
+    pair.client_send(client_ch, s).finish().unwrap();
+    pair.drive();
+
+    // Updates wait for the previous one to be confirmed, so may happen a little late
+    let updates = pair.client_conn_mut(client_ch).stats().local_key_updates;
+    assert!((3..=5).contains(&updates), "{updates} key updates");
+    assert_eq!(
+        pair.server_conn_mut(server_ch).stats().peer_key_updates,
+        updates
+    );
+
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(true).unwrap();
+    let mut received = 0;
+    while let Ok(Some(chunk)) = chunks.next(usize::MAX) {
+        received += chunk.bytes.len();
+    }
+    let _ = chunks.finalize();
+    assert_eq!(received, SIZE);
+}
+
 #[test]
 fn key_update_reordered() {
     let _guard = subscribe();
@@ -838,6 +1549,10 @@ fn key_update_reordered() {
     pair.drive();
 
     assert_eq!(pair.client_conn_mut(client_ch).lost_packets(), 0);
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::KeysUpdated { key_phase: 1, .. })
+    );
     assert_matches!(
         pair.server_conn_mut(server_ch).poll(),
         Some(Event::Stream(StreamEvent::Opened { dir: Dir::Bi }))
@@ -973,96 +1688,427 @@ fn idle_timeout() {
 }
 
 #[test]
-fn connection_close_sends_acks() {
+fn connection_close_sends_acks() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, _server_ch) = pair.connect();
+
+    let client_acks = pair.client_conn_mut(client_ch).stats().frame_rx.acks;
+
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive_client();
+
+    let time = pair.time;
+    pair.server_conn_mut(client_ch)
+        .close(time, VarInt(42), Bytes::new());
+
+    pair.drive();
+
+    let client_acks_2 = pair.client_conn_mut(client_ch).stats().frame_rx.acks;
+    assert!(
+        client_acks_2 > client_acks,
+        "Connection close should send pending ACKs"
+    );
+}
+
+#[test]
+fn concurrent_connections_full() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            concurrent_connections: 0,
+            ..server_config()
+        },
+    );
+    let client_ch = pair.begin_connect(client_config());
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::ConnectionLost {
+            reason: ConnectionError::ConnectionClosed(frame::ConnectionClose {
+                error_code: TransportErrorCode::CONNECTION_REFUSED,
+                ..
+            }),
+        })
+    );
+    assert_eq!(pair.server.connections.len(), 0);
+    assert_eq!(pair.server.known_connections(), 0);
+    assert_eq!(pair.server.known_cids(), 0);
+}
+
+#[test]
+fn server_hs_retransmit() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let client_ch = pair.begin_connect(client_config());
+    pair.step();
+    assert!(!pair.client.inbound.is_empty()); // Initial + Handshakes
+    pair.client.inbound.clear();
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Connected { .. })
+    );
+}
+
+#[test]
+fn migration() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    pair.client.addr = SocketAddr::new(
+        Ipv4Addr::new(127, 0, 0, 1).into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    pair.client_conn_mut(client_ch).ping();
+
+    // Assert that just receiving the ping message is accounted into the servers
+    // anti-amplification budget
+    pair.drive_client();
+    pair.drive_server();
+    assert_ne!(pair.server_conn_mut(server_ch).total_recvd(), 0);
+
+    pair.drive();
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+    assert_eq!(
+        pair.server_conn_mut(server_ch).remote_address(),
+        pair.client.addr
+    );
+}
+
+#[test]
+fn preferred_address() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let preferred = SocketAddrV6::new(
+        Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 2),
+        pair.server.addr.port(),
+        0,
+        0,
+    );
+    let mut server_config = server_config();
+    server_config.preferred_address_v6(Some(preferred));
+    pair.server.set_server_config(Some(Arc::new(server_config)));
+    let (client_ch, server_ch) = pair.connect();
+
+    // The client switched to the preferred address once the handshake was confirmed
+    assert_eq!(
+        pair.client_conn_mut(client_ch).remote_address(),
+        preferred.into()
+    );
+    assert_eq!(
+        pair.server_conn_mut(server_ch).local_ip(),
+        Some((*preferred.ip()).into())
+    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Path(PathEvent::Validated { remote })) if remote == preferred.into()
+    );
+    let old = pair.server.addr;
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Path(PathEvent::RemoteAddressChanged { old: x, new }))
+            if x == old && new == preferred.into()
+    );
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+
+    // Data flows in both directions on the new path
+    let s = pair.client_streams(client_ch).open(Dir::Bi).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Bi }))
+    );
+    assert_matches!(pair.server_streams(server_ch).accept(Dir::Bi), Some(stream) if stream == s);
+    pair.server_send(server_ch, s).write(b"world").unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Stream(StreamEvent::Readable { id })) if id == s
+    );
+    let mut recv = pair.client_recv(client_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(
+        chunks.next(usize::MAX),
+        Ok(Some(chunk)) if chunk.bytes == b"world"[..]
+    );
+    let _ = chunks.finalize();
+}
+
+#[test]
+fn preferred_address_unreachable() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    // Datagrams to another port never reach the server
+    let preferred = SocketAddrV6::new(Ipv6Addr::LOCALHOST, pair.server.addr.port() + 1, 0, 0);
+    let mut server_config = server_config();
+    server_config.preferred_address_v6(Some(preferred));
+    pair.server.set_server_config(Some(Arc::new(server_config)));
+    let (client_ch, server_ch) = pair.connect();
+
+    // Path validation failed, so the client keeps using the original address
+    assert_eq!(
+        pair.client_conn_mut(client_ch).remote_address(),
+        pair.server.addr
+    );
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+}
+
+#[test]
+fn probe_path_then_migrate() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    // The server is reachable on any IP address using its port
+    let new_remote = SocketAddr::new(
+        Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 2).into(),
+        pair.server.addr.port(),
+    );
+
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .probe_path(now, new_remote, None)
+        .unwrap();
+    pair.drive();
+    // Probing alone leaves traffic on the original path
+    assert_eq!(
+        pair.client_conn_mut(client_ch).remote_address(),
+        pair.server.addr
+    );
+    let stats = pair.client_conn_mut(client_ch).stats();
+    assert_eq!(stats.frame_tx.path_challenge, 1);
+    assert_eq!(stats.frame_rx.path_response, 1);
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Path(PathEvent::Validated { remote })) if remote == new_remote
+    );
+
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .migrate(now, new_remote, None)
+        .unwrap();
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    pair.drive();
+    assert_eq!(pair.client_conn_mut(client_ch).remote_address(), new_remote);
+    // The probed path needn't be validated again
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .stats()
+            .frame_tx
+            .path_challenge,
+        1
+    );
+    assert_eq!(
+        pair.server_conn_mut(server_ch).local_ip(),
+        Some(new_remote.ip())
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+}
+
+#[test]
+fn probe_path_cid() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    let new_remote = SocketAddr::new(
+        Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 2).into(),
+        pair.server.addr.port(),
+    );
+
+    // The probe and the active path use different connection IDs
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .probe_path(now, new_remote, None)
+        .unwrap();
+    pair.client_conn_mut(client_ch).ping();
+    pair.client.drive(now, pair.server.addr);
+    let dst_cid = |remote: SocketAddr| {
+        let transmit = pair
+            .client
+            .outbound
+            .iter()
+            .find(|x| x.destination == remote)
+            .unwrap();
+        transmit.contents[1..9].to_vec()
+    };
+    let (active, probe) = (dst_cid(pair.server.addr), dst_cid(new_remote));
+    assert_ne!(active, probe);
+    pair.drive();
+
+    // Each probe consumes a connection ID, and probing fails once none are left
+    let mut probes = 1;
+    loop {
+        let now = pair.time;
+        match pair
+            .client_conn_mut(client_ch)
+            .probe_path(now, new_remote, None)
+        {
+            Ok(()) => probes += 1,
+            Err(e) => {
+                assert_eq!(e, MigrateError::NoConnectionId);
+                break;
+            }
+        }
+    }
+    assert_eq!(probes, 4);
+}
+
+#[test]
+fn probe_path_lost_challenge() {
     let _guard = subscribe();
     let mut pair = Pair::default();
-    let (client_ch, _server_ch) = pair.connect();
-
-    let client_acks = pair.client_conn_mut(client_ch).stats().frame_rx.acks;
-
-    pair.client_conn_mut(client_ch).ping();
-    pair.drive_client();
+    let (client_ch, _) = pair.connect();
+    let new_remote = SocketAddr::new(
+        Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 2).into(),
+        pair.server.addr.port(),
+    );
 
-    let time = pair.time;
-    pair.server_conn_mut(client_ch)
-        .close(time, VarInt(42), Bytes::new());
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .probe_path(now, new_remote, None)
+        .unwrap();
+    pair.client.drive(now, pair.server.addr);
+    pair.client.outbound.retain(|x| x.destination != new_remote);
 
+    // The challenge is resent once a PTO passes without a response
     pair.drive();
-
-    let client_acks_2 = pair.client_conn_mut(client_ch).stats().frame_rx.acks;
-    assert!(
-        client_acks_2 > client_acks,
-        "Connection close should send pending ACKs"
+    let stats = pair.client_conn_mut(client_ch).stats();
+    assert_eq!(stats.frame_tx.path_challenge, 2);
+    assert_eq!(stats.frame_rx.path_response, 1);
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Path(PathEvent::Validated { remote })) if remote == new_remote
     );
 }
 
 #[test]
-fn concurrent_connections_full() {
+fn probe_path_during_validation() {
     let _guard = subscribe();
-    let mut pair = Pair::new(
-        Default::default(),
-        ServerConfig {
-            concurrent_connections: 0,
-            ..server_config()
-        },
+    let mut pair = Pair::default();
+    let (client_ch, _) = pair.connect();
+    let (unreachable, new_remote) = (
+        SocketAddr::new(pair.server.addr.ip(), pair.server.addr.port() + 1),
+        SocketAddr::new(
+            Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 2).into(),
+            pair.server.addr.port(),
+        ),
     );
-    let client_ch = pair.begin_connect(client_config());
+
+    // Validating a probed path doesn't stop validation of the active one from timing out
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .migrate(now, unreachable, None)
+        .unwrap();
+    pair.client_conn_mut(client_ch)
+        .probe_path(now, new_remote, None)
+        .unwrap();
     pair.drive();
-    assert_matches!(
-        pair.client_conn_mut(client_ch).poll(),
-        Some(Event::ConnectionLost {
-            reason: ConnectionError::ConnectionClosed(frame::ConnectionClose {
-                error_code: TransportErrorCode::CONNECTION_REFUSED,
-                ..
-            }),
-        })
-    );
-    assert_eq!(pair.server.connections.len(), 0);
-    assert_eq!(pair.server.known_connections(), 0);
-    assert_eq!(pair.server.known_cids(), 0);
+    let mut events = Vec::new();
+    while let Some(event) = pair.client_conn_mut(client_ch).poll() {
+        events.push(event);
+    }
+    assert!(events.iter().any(|x| matches!(
+        x,
+        Event::Path(PathEvent::Validated { remote }) if *remote == new_remote
+    )));
+    assert!(events.iter().any(|x| matches!(
+        x,
+        Event::Path(PathEvent::ValidationFailed { remote }) if *remote == unreachable
+    )));
 }
 
 #[test]
-fn server_hs_retransmit() {
+fn migrate_unprobed() {
     let _guard = subscribe();
     let mut pair = Pair::default();
-    let client_ch = pair.begin_connect(client_config());
-    pair.step();
-    assert!(!pair.client.inbound.is_empty()); // Initial + Handshakes
-    pair.client.inbound.clear();
+    let (client_ch, server_ch) = pair.connect();
+    let old_addr = pair.client.addr;
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+
+    let now = pair.time;
+    let remote = pair.server.addr;
+    pair.client_conn_mut(client_ch)
+        .migrate(now, remote, None)
+        .unwrap();
     pair.drive();
+    // The server followed the client, and the new path was validated in both directions
+    assert_eq!(
+        pair.server_conn_mut(server_ch).remote_address(),
+        pair.client.addr
+    );
+    let stats = pair.client_conn_mut(client_ch).stats();
+    assert_eq!(stats.frame_tx.path_challenge, 1);
+    assert_eq!(stats.frame_rx.path_response, 1);
+    assert!(stats.frame_rx.path_challenge >= 1);
     assert_matches!(
         pair.client_conn_mut(client_ch).poll(),
-        Some(Event::HandshakeDataReady)
+        Some(Event::Path(PathEvent::Validated { remote: x })) if x == remote
+    );
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+    let (old, new) = (old_addr, pair.client.addr);
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Path(PathEvent::RemoteAddressChanged { old: x, new: y })) if x == old && y == new
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Path(PathEvent::Validated { remote })) if remote == new
     );
+
+    let s = pair.server_streams(server_ch).open(Dir::Uni).unwrap();
+    pair.server_send(server_ch, s).write(b"hello").unwrap();
+    pair.drive();
     assert_matches!(
         pair.client_conn_mut(client_ch).poll(),
-        Some(Event::Connected { .. })
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
     );
 }
 
 #[test]
-fn migration() {
+fn migrate_refused() {
     let _guard = subscribe();
-    let mut pair = Pair::default();
-    let (client_ch, server_ch) = pair.connect();
-    pair.client.addr = SocketAddr::new(
-        Ipv4Addr::new(127, 0, 0, 1).into(),
-        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    let mut server_config = server_config();
+    server_config.migration(false);
+    let mut pair = Pair::new(Default::default(), server_config);
+    let client_ch = pair.begin_connect(client_config());
+    let now = pair.time;
+    let remote = pair.server.addr;
+    assert_eq!(
+        pair.client_conn_mut(client_ch).migrate(now, remote, None),
+        Err(MigrateError::HandshakeNotConfirmed)
     );
-    pair.client_conn_mut(client_ch).ping();
-
-    // Assert that just receiving the ping message is accounted into the servers
-    // anti-amplification budget
-    pair.drive_client();
-    pair.drive_server();
-    assert_ne!(pair.server_conn_mut(server_ch).total_recvd(), 0);
-
     pair.drive();
-    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+    let server_ch = pair.server.assert_accept();
+
+    let client_remote = pair.client.addr;
     assert_eq!(
-        pair.server_conn_mut(server_ch).remote_address(),
-        pair.client.addr
+        pair.server_conn_mut(server_ch)
+            .probe_path(now, client_remote, None),
+        Err(MigrateError::NotClient)
+    );
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .probe_path(now, remote, None),
+        Err(MigrateError::Disabled)
     );
 }
 
@@ -1187,6 +2233,56 @@ fn conn_flow_control() {
     );
 }
 
+/// Measures how long it takes to stream data over a high-latency path with a small initial stream
+/// receive window
+fn window_limited_transfer(max_stream_receive_window: Option<VarInt>) -> Duration {
+    const SIZE: usize = 400_000;
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            transport: Arc::new(TransportConfig {
+                stream_receive_window: 10_000u32.into(),
+                max_stream_receive_window,
+                ..TransportConfig::default()
+            }),
+            ..server_config()
+        },
+    );
+    pair.latency = Duration::from_millis(50);
+    let (client_ch, server_ch) = pair.connect();
+    let start = pair.time;
+
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    let msg = vec![0xAB; SIZE];
+    let (mut sent, mut received) = (0, 0);
+    for _ in 0..100_000 {
+        if let Ok(n) = pair.client_send(client_ch, s).write(&msg[sent..]) {
+            sent += n;
+        }
+        pair.step();
+        let mut recv = pair.server_recv(server_ch, s);
+        if let Ok(mut chunks) = recv.read(true) {
+            while let Ok(Some(chunk)) = chunks.next(usize::MAX) {
+                received += chunk.bytes.len();
+            }
+            let _ = chunks.finalize();
+        }
+        if received == SIZE {
+            return pair.time - start;
+        }
+    }
+    panic!("transfer didn't complete");
+}
+
+#[test]
+fn receive_window_tuning() {
+    let _guard = subscribe();
+    let fixed = window_limited_transfer(None);
+    let tuned = window_limited_transfer(Some(1_000_000u32.into()));
+    info!(?fixed, ?tuned, "transfer time");
+    assert!(tuned * 4 < fixed);
+}
+
 #[test]
 fn stop_opens_bidi() {
     let _guard = subscribe();
@@ -1554,62 +2650,300 @@ fn congested_tail_loss() {
 }
 
 #[test]
-fn datagram_send_recv() {
+fn datagram_send_recv() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
+    assert_matches!(pair.client_datagrams(client_ch).max_size(), Some(x) if x > 0);
+
+    const DATA: &[u8] = b"whee";
+    pair.client_datagrams(client_ch).send(DATA.into()).unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::DatagramReceived)
+    );
+    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), DATA);
+    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
+}
+
+#[test]
+fn datagram_acked_and_lost() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+    assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
+
+    const DATA: &[u8] = b"whee";
+    pair.client_datagrams(client_ch)
+        .send_with_id(DATA.into(), 1)
+        .unwrap();
+    pair.drive();
+    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), DATA);
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::DatagramAcked(1))
+    );
+
+    pair.client_datagrams(client_ch)
+        .send_with_id(DATA.into(), 2)
+        .unwrap();
+    pair.drive_client();
+    assert!(!pair.server.inbound.is_empty());
+    pair.server.inbound.clear(); // Lose it
+    pair.drive();
+    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::DatagramLost(2))
+    );
+    assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+}
+
+#[test]
+fn datagram_recv_buffer_overflow() {
+    let _guard = subscribe();
+    const WINDOW: usize = 100;
+    let server = ServerConfig {
+        transport: Arc::new(TransportConfig {
+            datagram_receive_buffer_size: Some(WINDOW),
+            ..TransportConfig::default()
+        }),
+        ..server_config()
+    };
+    let mut pair = Pair::new(Default::default(), server);
+    let (client_ch, server_ch) = pair.connect();
+    assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
+    assert_eq!(
+        pair.client_conn_mut(client_ch).datagrams().max_size(),
+        Some(WINDOW - Datagram::SIZE_BOUND)
+    );
+
+    const DATA1: &[u8] = &[0xAB; (WINDOW / 3) + 1];
+    const DATA2: &[u8] = &[0xBC; (WINDOW / 3) + 1];
+    const DATA3: &[u8] = &[0xCD; (WINDOW / 3) + 1];
+    pair.client_datagrams(client_ch).send(DATA1.into()).unwrap();
+    pair.client_datagrams(client_ch).send(DATA2.into()).unwrap();
+    pair.client_datagrams(client_ch).send(DATA3.into()).unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::DatagramReceived)
+    );
+    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), DATA2);
+    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), DATA3);
+    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
+
+    pair.client_datagrams(client_ch).send(DATA1.into()).unwrap();
+    pair.drive();
+    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), DATA1);
+    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
+}
+
+#[test]
+fn datagram_send_buffer_overflow() {
+    let _guard = subscribe();
+    const WINDOW: usize = 100;
+    const DATA1: &[u8] = &[0xAB; (WINDOW / 2) + 1];
+    const DATA2: &[u8] = &[0xBC; (WINDOW / 2) + 1];
+
+    for policy in [
+        DatagramOverflowPolicy::DropOldest,
+        DatagramOverflowPolicy::DropNewest,
+        DatagramOverflowPolicy::Reject,
+    ] {
+        let mut config = TransportConfig::default();
+        config
+            .datagram_send_buffer_size(WINDOW)
+            .datagram_overflow_policy(policy);
+        let mut pair = Pair::default();
+        let client_config = ClientConfig {
+            transport: Arc::new(config),
+            ..client_config()
+        };
+        let (client_ch, server_ch) = pair.connect_with(client_config);
+        assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+
+        pair.client_datagrams(client_ch).send(DATA1.into()).unwrap();
+        let result = pair.client_datagrams(client_ch).send(DATA2.into());
+        match policy {
+            DatagramOverflowPolicy::Reject => {
+                assert_eq!(result, Err(SendDatagramError::Blocked(DATA2.into())))
+            }
+            _ => assert_eq!(result, Ok(())),
+        }
+        let dropped = pair.client_conn_mut(client_ch).stats().dropped_datagrams;
+        assert_eq!(dropped, (policy != DatagramOverflowPolicy::Reject) as u64);
+        pair.drive();
+
+        let expected = match policy {
+            DatagramOverflowPolicy::DropOldest => DATA2,
+            _ => DATA1,
+        };
+        assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), expected);
+        assert_matches!(pair.server_datagrams(server_ch).recv(), None);
+        if policy == DatagramOverflowPolicy::Reject {
+            assert_matches!(
+                pair.client_conn_mut(client_ch).poll(),
+                Some(Event::DatagramsUnblocked)
+            );
+        }
+        assert_matches!(pair.client_conn_mut(client_ch).poll(), None);
+    }
+}
+
+#[test]
+fn datagram_deadline() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect();
+
+    const STALE: &[u8] = b"stale";
+    const FRESH: &[u8] = b"fresh";
+    let now = pair.time;
+    pair.client_datagrams(client_ch)
+        .send_with_options(STALE.into(), DatagramOptions::default().deadline(now))
+        .unwrap();
+    pair.client_datagrams(client_ch)
+        .send_with_options(
+            FRESH.into(),
+            DatagramOptions::default().deadline(now + Duration::from_secs(1)),
+        )
+        .unwrap();
+    pair.drive();
+    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), FRESH);
+    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
+    let stats = pair.client_conn_mut(client_ch).stats();
+    assert_eq!(stats.expired_datagrams, 1);
+    assert_eq!(stats.frame_tx.datagram, 1);
+}
+
+#[test]
+fn datagram_deadline_frees_space() {
+    let _guard = subscribe();
+    let mut transport = TransportConfig::default();
+    transport.datagram_send_buffer_size(16);
+    let mut pair = Pair::default();
+    let (client_ch, server_ch) = pair.connect_with(ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    });
+
+    // An expired datagram of any priority stops taking up space once its deadline passes
+    let deadline = pair.time + Duration::from_millis(1);
+    pair.client_datagrams(client_ch)
+        .send_with_options(
+            [0; 12][..].into(),
+            DatagramOptions::default().priority(-1).deadline(deadline),
+        )
+        .unwrap();
+    pair.client_datagrams(client_ch)
+        .send_with_options([1; 4][..].into(), DatagramOptions::default().priority(1))
+        .unwrap();
+    assert_matches!(
+        pair.client_datagrams(client_ch)
+            .try_send([2; 12][..].into()),
+        Err(SendDatagramError::Blocked(_))
+    );
+    pair.time = deadline;
+    pair.client_conn_mut(client_ch).handle_timeout(deadline);
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::DatagramsUnblocked)
+    );
+    pair.client_datagrams(client_ch)
+        .try_send([2; 12][..].into())
+        .unwrap();
+    pair.drive();
+    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), [1; 4][..]);
+    assert_eq!(
+        pair.server_datagrams(server_ch).recv().unwrap(),
+        [2; 12][..]
+    );
+    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
+    assert_eq!(pair.client_conn_mut(client_ch).stats().expired_datagrams, 1);
+}
+
+#[test]
+fn datagram_priority() {
     let _guard = subscribe();
     let mut pair = Pair::default();
     let (client_ch, server_ch) = pair.connect();
-    assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
-    assert_matches!(pair.client_datagrams(client_ch).max_size(), Some(x) if x > 0);
 
-    const DATA: &[u8] = b"whee";
-    pair.client_datagrams(client_ch).send(DATA.into()).unwrap();
+    // Datagrams are sent by priority, then in order
+    for (data, priority) in [(b"a", 0), (b"b", 1), (b"c", 0), (b"d", 1)] {
+        pair.client_datagrams(client_ch)
+            .send_with_options(
+                data[..].into(),
+                DatagramOptions::default().priority(priority),
+            )
+            .unwrap();
+    }
     pair.drive();
-    assert_matches!(
-        pair.server_conn_mut(server_ch).poll(),
-        Some(Event::DatagramReceived)
+    for expected in [b"b", b"d", b"a", b"c"] {
+        assert_eq!(
+            pair.server_datagrams(server_ch).recv().unwrap(),
+            expected[..]
+        );
+    }
+
+    // Urgent stream data goes ahead of less urgent datagrams, filling the first packet
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).set_priority(1).unwrap();
+    pair.client_send(client_ch, s).write(&[0; 4000]).unwrap();
+    for (data, priority) in [(&b"low"[..], 0), (&b"high"[..], 2)] {
+        pair.client_datagrams(client_ch)
+            .send_with_options(
+                data[..].into(),
+                DatagramOptions::default().priority(priority),
+            )
+            .unwrap();
+    }
+    pair.drive_client();
+    assert!(pair.server.inbound.len() > 2);
+    pair.server.inbound.truncate(1);
+    pair.drive_server();
+    assert_eq!(
+        pair.server_datagrams(server_ch).recv().unwrap(),
+        &b"high"[..]
     );
-    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), DATA);
     assert_matches!(pair.server_datagrams(server_ch).recv(), None);
 }
 
 #[test]
-fn datagram_recv_buffer_overflow() {
+fn datagram_priority_weighted_streams() {
     let _guard = subscribe();
-    const WINDOW: usize = 100;
-    let server = ServerConfig {
-        transport: Arc::new(TransportConfig {
-            datagram_receive_buffer_size: Some(WINDOW),
-            ..TransportConfig::default()
-        }),
-        ..server_config()
+    let mut config = TransportConfig::default();
+    config.stream_scheduler_factory(scheduler::WeightedFairConfig::default());
+    let mut pair = Pair::default();
+    let client_config = ClientConfig {
+        transport: Arc::new(config),
+        ..client_config()
     };
-    let mut pair = Pair::new(Default::default(), server);
-    let (client_ch, server_ch) = pair.connect();
-    assert_matches!(pair.server_conn_mut(server_ch).poll(), None);
-    assert_eq!(
-        pair.client_conn_mut(client_ch).datagrams().max_size(),
-        Some(WINDOW - Datagram::SIZE_BOUND)
-    );
+    let (client_ch, server_ch) = pair.connect_with(client_config);
 
-    const DATA1: &[u8] = &[0xAB; (WINDOW / 3) + 1];
-    const DATA2: &[u8] = &[0xBC; (WINDOW / 3) + 1];
-    const DATA3: &[u8] = &[0xCD; (WINDOW / 3) + 1];
-    pair.client_datagrams(client_ch).send(DATA1.into()).unwrap();
-    pair.client_datagrams(client_ch).send(DATA2.into()).unwrap();
-    pair.client_datagrams(client_ch).send(DATA3.into()).unwrap();
-    pair.drive();
+    // The stream scheduled next is less urgent than the datagram, but another queued stream is
+    // more urgent, so stream data fills the first packet
+    for priority in [0, 2] {
+        let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+        pair.client_send(client_ch, s)
+            .set_priority(priority)
+            .unwrap();
+        pair.client_send(client_ch, s).write(&[0; 4000]).unwrap();
+    }
+    pair.client_datagrams(client_ch)
+        .send_with_options(b"mid"[..].into(), DatagramOptions::default().priority(1))
+        .unwrap();
+    pair.drive_client();
+    assert!(pair.server.inbound.len() > 2);
+    pair.server.inbound.truncate(1);
+    pair.drive_server();
+    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
     assert_matches!(
-        pair.server_conn_mut(server_ch).poll(),
-        Some(Event::DatagramReceived)
+        pair.server_streams(server_ch).accept(Dir::Uni),
+        Some(stream) if stream == StreamId::new(Side::Client, Dir::Uni, 0)
     );
-    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), DATA2);
-    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), DATA3);
-    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
-
-    pair.client_datagrams(client_ch).send(DATA1.into()).unwrap();
-    pair.drive();
-    assert_eq!(pair.server_datagrams(server_ch).recv().unwrap(), DATA1);
-    assert_matches!(pair.server_datagrams(server_ch).recv(), None);
 }
 
 #[test]
@@ -1965,6 +3299,23 @@ fn connect_detects_mtu() {
     }
 }
 
+#[test]
+fn mtu_changed_event() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.mtu = 1400;
+    let (client_ch, _) = pair.connect();
+    pair.drive();
+
+    let mut last_mtu = None;
+    while let Some(event) = pair.client_conn_mut(client_ch).poll() {
+        if let Event::Path(PathEvent::MtuChanged { mtu }) = event {
+            last_mtu = Some(mtu);
+        }
+    }
+    assert_eq!(last_mtu, Some(1389));
+}
+
 #[test]
 fn migrate_detects_new_mtu_and_respects_original_peer_max_udp_payload_size() {
     let _guard = subscribe();
@@ -2206,3 +3557,337 @@ fn reject_new_connections() {
     pair.server.assert_no_accept();
     assert!(pair.client.connections.get(&client_ch).unwrap().is_closed());
 }
+
+#[test]
+fn refuse_incoming() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::RejectAll;
+
+    let client_ch = pair.begin_connect(client_config());
+    pair.drive();
+    pair.server.assert_no_accept();
+    assert_eq!(pair.server.known_connections(), 0);
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::ConnectionLost {
+            reason: ConnectionError::ConnectionClosed(frame::ConnectionClose {
+                error_code: TransportErrorCode::CONNECTION_REFUSED,
+                ..
+            })
+        })
+    );
+}
+
+#[test]
+fn retry_incoming() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Validate;
+    let (client_ch, _) = pair.connect();
+    // The ClientHello is sent a second time in response to the Retry
+    assert_eq!(pair.client_conn_mut(client_ch).stats().frame_tx.crypto, 3);
+}
+
+#[test]
+fn retry_after_retry_fails() {
+    let _guard = subscribe();
+    let mut pair = Pair::new(
+        Default::default(),
+        ServerConfig {
+            use_retry: true,
+            ..server_config()
+        },
+    );
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Wait;
+    let client_ch = pair.begin_connect(client_config());
+    pair.drive();
+    let incoming = pair.server.waiting_incoming.pop().unwrap();
+    assert!(pair.server.waiting_incoming.is_empty());
+    assert!(incoming.remote_address_validated());
+    assert!(!incoming.may_retry());
+
+    let incoming = pair
+        .server
+        .endpoint
+        .retry(incoming)
+        .unwrap_err()
+        .into_incoming();
+    let now = pair.time;
+    pair.server.try_accept(incoming, now).unwrap();
+    pair.drive();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::Connected)
+    );
+}
+
+#[test]
+fn ignore_incoming() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Wait;
+    let client_ch = pair.begin_connect(client_config());
+    pair.drive_client();
+    pair.drive_server();
+    let incoming = pair.server.waiting_incoming.pop().unwrap();
+    assert_eq!(incoming.remote_address(), pair.client.addr);
+    pair.server.endpoint.ignore(incoming);
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::AcceptAll;
+
+    // The client's retransmitted Initial is treated as a new connection attempt
+    pair.drive();
+    pair.server.assert_accept();
+    assert_matches!(
+        pair.client_conn_mut(client_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+}
+
+#[test]
+fn accept_incoming_with_buffered_0rtt() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let config = client_config();
+
+    // Establish a connection to obtain a session ticket
+    let client_ch = pair.begin_connect(config.clone());
+    pair.drive();
+    pair.server.assert_accept();
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .close(now, VarInt(0), [][..].into());
+    pair.drive();
+
+    pair.server.incoming_connection_behavior = IncomingConnectionBehavior::Wait;
+    let client_ch = pair.begin_connect(config);
+    assert!(pair.client_conn_mut(client_ch).has_0rtt());
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    const MSG: &[u8] = b"Hello, 0-RTT!";
+    pair.client_send(client_ch, s).write(MSG).unwrap();
+    pair.drive_client();
+    pair.drive_server();
+
+    // 0-RTT data arriving before the connection is accepted is delivered once it is
+    let incoming = pair.server.waiting_incoming.pop().unwrap();
+    let now = pair.time;
+    let server_ch = pair.server.try_accept(incoming, now).unwrap();
+    pair.drive();
+    assert!(pair.client_conn_mut(client_ch).accepted_0rtt());
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::HandshakeDataReady)
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Connected)
+    );
+    assert_matches!(
+        pair.server_conn_mut(server_ch).poll(),
+        Some(Event::Stream(StreamEvent::Opened { dir: Dir::Uni }))
+    );
+    let mut recv = pair.server_recv(server_ch, s);
+    let mut chunks = recv.read(false).unwrap();
+    assert_matches!(
+        chunks.next(usize::MAX),
+        Ok(Some(chunk)) if chunk.offset == 0 && chunk.bytes == MSG
+    );
+    let _ = chunks.finalize();
+}
+
+/// Collects the qlog traces of client connections
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl qlog::QlogFactory for SharedBuf {
+    fn create(
+        &self,
+        side: Side,
+        _original_dst_cid: &ConnectionId,
+    ) -> Option<Box<dyn std::io::Write + Send>> {
+        match side {
+            Side::Client => Some(Box::new(self.clone())),
+            Side::Server => None,
+        }
+    }
+}
+
+#[test]
+fn qlog_trace() {
+    let _guard = subscribe();
+    let log = SharedBuf::default();
+    let mut pair = Pair::default();
+    let mut transport = TransportConfig::default();
+    transport.qlog_factory(Some(Arc::new(log.clone())));
+    let client_config = ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    };
+    let (client_ch, _) = pair.connect_with(client_config);
+    pair.client_conn_mut(client_ch).initiate_key_update();
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive();
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .close(now, VarInt(0), Bytes::new());
+    pair.drive();
+
+    let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+    let records = log
+        .split('\x1e')
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+    assert!(records.iter().all(|x| x.ends_with("}\n")));
+    assert!(records[0].contains("\"qlog_format\":\"JSON-SEQ\""));
+    assert!(records[0].contains("\"type\":\"client\""));
+    for name in [
+        "transport:packet_sent",
+        "transport:packet_received",
+        "recovery:metrics_updated",
+        "security:key_updated",
+        "connectivity:connection_state_updated",
+    ] {
+        assert!(
+            records.iter().any(|x| x.contains(name)),
+            "missing {name} event"
+        );
+    }
+    assert!(log.contains("\"packet_type\":\"initial\""));
+    assert!(log.contains("\"frame_type\":\"crypto\""));
+    assert!(log.contains("\"new\":\"handshake_complete\""));
+    assert!(log.contains("\"new\":\"closing\""));
+    // ACK delays are decoded into milliseconds
+    assert!(log.contains("\"ack_delay\":"));
+    for delay in log.split("\"ack_delay\":").skip(1) {
+        let delay = &delay[..delay.find(',').unwrap()];
+        assert!(
+            delay.parse::<f64>().is_ok() && delay.contains('.'),
+            "{delay}"
+        );
+    }
+}
+
+#[test]
+fn qlog_lost_0rtt() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let config = client_config();
+    let client_ch = pair.begin_connect(config.clone());
+    pair.drive();
+    pair.server.assert_accept();
+    let now = pair.time;
+    pair.client_conn_mut(client_ch)
+        .close(now, VarInt(0), Bytes::new());
+    pair.drive();
+
+    pair.client.addr = SocketAddr::new(
+        Ipv6Addr::LOCALHOST.into(),
+        CLIENT_PORTS.lock().unwrap().next().unwrap(),
+    );
+    let log = SharedBuf::default();
+    let mut transport = TransportConfig::default();
+    transport.qlog_factory(Some(Arc::new(log.clone())));
+    let client_ch = pair.begin_connect(ClientConfig {
+        transport: Arc::new(transport),
+        ..config
+    });
+    assert!(pair.client_conn_mut(client_ch).has_0rtt());
+    let s = pair.client_streams(client_ch).open(Dir::Uni).unwrap();
+    pair.client_send(client_ch, s).write(b"hello").unwrap();
+    pair.drive_client();
+    pair.server.inbound.clear();
+    pair.drive();
+    assert!(pair.client_conn_mut(client_ch).lost_packets() > 0);
+
+    // Lost 0-RTT packets are logged as such, rather than as 1-RTT packets
+    let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+    let lost = log
+        .split('\x1e')
+        .filter(|x| x.contains("recovery:packet_lost"))
+        .collect::<Vec<_>>();
+    assert!(!lost.is_empty());
+    assert!(
+        lost.iter().any(|x| x.contains("\"packet_type\":\"0RTT\"")),
+        "{lost:?}"
+    );
+}
+
+#[test]
+fn ack_frequency() {
+    let _guard = subscribe();
+    let mut pair = Pair::default();
+    let mut transport = TransportConfig::default();
+    let mut ack_frequency = AckFrequencyConfig::default();
+    ack_frequency
+        .ack_eliciting_threshold(VarInt(9))
+        .max_ack_delay(Some(Duration::from_millis(20)));
+    transport.ack_frequency_config(Some(ack_frequency));
+    let client_config = ClientConfig {
+        transport: Arc::new(transport),
+        ..client_config()
+    };
+    let (client_ch, server_ch) = pair.connect_with(client_config);
+    assert_eq!(
+        pair.client_conn_mut(client_ch)
+            .stats()
+            .frame_tx
+            .ack_frequency,
+        1
+    );
+    assert_eq!(
+        pair.server_conn_mut(server_ch)
+            .stats()
+            .frame_rx
+            .ack_frequency,
+        1
+    );
+
+    // A single ack-eliciting packet is not acknowledged right away
+    let acks = pair.server_conn_mut(server_ch).stats().frame_tx.acks;
+    pair.client_conn_mut(client_ch).ping();
+    pair.drive_client();
+    pair.time += pair.latency;
+    pair.drive_server();
+    assert_eq!(pair.server_conn_mut(server_ch).stats().frame_tx.acks, acks);
+
+    // ...but once the requested max_ack_delay elapses
+    let start = pair.time;
+    pair.drive();
+    assert_eq!(
+        pair.server_conn_mut(server_ch).stats().frame_tx.acks,
+        acks + 1
+    );
+    assert!(pair.time - start >= Duration::from_millis(20));
+
+    // IMMEDIATE_ACK overrides the delay
+    pair.client_conn_mut(client_ch).immediate_ack();
+    pair.drive_client();
+    pair.time += pair.latency;
+    pair.drive_server();
+    assert_eq!(
+        pair.server_conn_mut(server_ch).stats().frame_tx.acks,
+        acks + 2
+    );
+    assert_eq!(
+        pair.server_conn_mut(server_ch)
+            .stats()
+            .frame_rx
+            .immediate_ack,
+        1
+    );
+}
@@ -4,7 +4,7 @@ use std::{
     env,
     io::{self, Write},
     mem,
-    net::{Ipv6Addr, SocketAddr, UdpSocket},
+    net::{IpAddr, Ipv6Addr, SocketAddr, UdpSocket},
     ops::RangeFrom,
     str,
     sync::{Arc, Mutex},
@@ -32,6 +32,8 @@ pub(super) struct Pair {
     /// Number of spin bit flips
     pub(super) spins: u64,
     last_spin: bool,
+    /// Whether ECN-capable packets sent by the client are marked as congestion experienced
+    pub(super) mark_ce: bool,
 }
 
 impl Pair {
@@ -52,13 +54,14 @@ impl Pair {
             CLIENT_PORTS.lock().unwrap().next().unwrap(),
         );
         Self {
-            server: TestEndpoint::new(server, server_addr),
-            client: TestEndpoint::new(client, client_addr),
+            server: TestEndpoint::new(server, server_addr, Side::Server),
+            client: TestEndpoint::new(client, client_addr, Side::Client),
             time: Instant::now(),
             mtu: DEFAULT_MTU,
             latency: Duration::new(0, 0),
             spins: 0,
             last_spin: false,
+            mark_ce: false,
         }
     }
 
@@ -131,11 +134,24 @@ impl Pair {
             if let Some(ref socket) = self.client.socket {
                 socket.send_to(&x.contents, x.destination).unwrap();
             }
+            let ecn = match self.mark_ce {
+                true => x.ecn.map(|_| EcnCodepoint::Ce),
+                false => x.ecn,
+            };
             if self.server.addr == x.destination {
                 self.server.inbound.push_back((
                     self.time + self.latency,
-                    x.ecn,
+                    ecn,
+                    x.contents.as_ref().into(),
+                    None,
+                ));
+            } else if self.server.addr.port() == x.destination.port() {
+                // The server is reachable on any IP address using its port
+                self.server.inbound.push_back((
+                    self.time + self.latency,
+                    ecn,
                     x.contents.as_ref().into(),
+                    Some(x.destination.ip()),
                 ));
             }
         }
@@ -157,10 +173,12 @@ impl Pair {
                 socket.send_to(&x.contents, x.destination).unwrap();
             }
             if self.client.addr == x.destination {
+                let server_ip = self.server.addr.ip();
                 self.client.inbound.push_back((
                     self.time + self.latency,
                     x.ecn,
                     x.contents.as_ref().into(),
+                    x.src_ip.filter(|&ip| ip != server_ip),
                 ));
             }
         }
@@ -267,14 +285,31 @@ pub(super) struct TestEndpoint {
     timeout: Option<Instant>,
     pub(super) outbound: VecDeque<Transmit>,
     delayed: VecDeque<Transmit>,
-    pub(super) inbound: VecDeque<(Instant, Option<EcnCodepoint>, BytesMut)>,
+    side: Side,
+    /// Datagrams to be received, with the IP address of the server's end of the path if it isn't
+    /// the server's primary address
+    pub(super) inbound: VecDeque<(Instant, Option<EcnCodepoint>, BytesMut, Option<IpAddr>)>,
     accepted: Option<ConnectionHandle>,
     pub(super) connections: HashMap<ConnectionHandle, Connection>,
     conn_events: HashMap<ConnectionHandle, VecDeque<ConnectionEvent>>,
+    pub(super) incoming_connection_behavior: IncomingConnectionBehavior,
+    /// Connection attempts held back by [`IncomingConnectionBehavior::Wait`]
+    pub(super) waiting_incoming: Vec<Incoming>,
+}
+
+/// How a [`TestEndpoint`] handles incoming connection attempts
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum IncomingConnectionBehavior {
+    AcceptAll,
+    RejectAll,
+    /// Retry unless the client's address is already validated, then accept
+    Validate,
+    /// Store them in `waiting_incoming` for the test to handle
+    Wait,
 }
 
 impl TestEndpoint {
-    fn new(endpoint: Endpoint, addr: SocketAddr) -> Self {
+    fn new(endpoint: Endpoint, addr: SocketAddr, side: Side) -> Self {
         let socket = if env::var_os("SSLKEYLOGFILE").is_some() {
             let socket = UdpSocket::bind(addr).expect("failed to bind UDP socket");
             socket
@@ -291,10 +326,13 @@ impl TestEndpoint {
             timeout: None,
             outbound: VecDeque::new(),
             delayed: VecDeque::new(),
+            side,
             inbound: VecDeque::new(),
             accepted: None,
             connections: HashMap::default(),
             conn_events: HashMap::default(),
+            incoming_connection_behavior: IncomingConnectionBehavior::AcceptAll,
+            waiting_incoming: Vec::new(),
         }
     }
 
@@ -309,12 +347,35 @@ impl TestEndpoint {
         }
 
         while self.inbound.front().map_or(false, |x| x.0 <= now) {
-            let (recv_time, ecn, packet) = self.inbound.pop_front().unwrap();
-            if let Some(event) = self.endpoint.handle(recv_time, remote, None, ecn, packet) {
+            let (recv_time, ecn, packet, server_ip) = self.inbound.pop_front().unwrap();
+            let (remote, local_ip) = match (self.side, server_ip) {
+                (Side::Client, Some(ip)) => (SocketAddr::new(ip, remote.port()), None),
+                _ => (remote, server_ip),
+            };
+            if let Some(event) = self
+                .endpoint
+                .handle(recv_time, remote, local_ip, ecn, packet)
+            {
                 match event {
-                    DatagramEvent::NewConnection(ch, conn) => {
-                        self.connections.insert(ch, conn);
-                        self.accepted = Some(ch);
+                    DatagramEvent::NewConnection(incoming) => {
+                        match self.incoming_connection_behavior {
+                            IncomingConnectionBehavior::AcceptAll => {
+                                let _ = self.try_accept(incoming, recv_time);
+                            }
+                            IncomingConnectionBehavior::RejectAll => {
+                                self.reject(incoming);
+                            }
+                            IncomingConnectionBehavior::Validate => {
+                                if incoming.remote_address_validated() {
+                                    let _ = self.try_accept(incoming, recv_time);
+                                } else {
+                                    self.retry(incoming);
+                                }
+                            }
+                            IncomingConnectionBehavior::Wait => {
+                                self.waiting_incoming.push(incoming);
+                            }
+                        }
                     }
                     DatagramEvent::ConnectionEvent(ch, event) => {
                         self.conn_events
@@ -367,6 +428,36 @@ impl TestEndpoint {
         }
     }
 
+    pub(super) fn try_accept(
+        &mut self,
+        incoming: Incoming,
+        now: Instant,
+    ) -> Result<ConnectionHandle, ConnectionError> {
+        match self.endpoint.accept(incoming, now) {
+            Ok((ch, conn)) => {
+                self.connections.insert(ch, conn);
+                self.accepted = Some(ch);
+                Ok(ch)
+            }
+            Err(error) => {
+                if let Some(transmit) = error.response {
+                    self.outbound.extend(split_transmit(transmit));
+                }
+                Err(error.cause)
+            }
+        }
+    }
+
+    pub(super) fn reject(&mut self, incoming: Incoming) {
+        let transmit = self.endpoint.refuse(incoming);
+        self.outbound.extend(split_transmit(transmit));
+    }
+
+    pub(super) fn retry(&mut self, incoming: Incoming) {
+        let transmit = self.endpoint.retry(incoming).unwrap();
+        self.outbound.extend(split_transmit(transmit));
+    }
+
     pub(super) fn next_wakeup(&self) -> Option<Instant> {
         let next_inbound = self.inbound.front().map(|x| x.0);
         min_opt(self.timeout, next_inbound)
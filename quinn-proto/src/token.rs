@@ -1,10 +1,13 @@
 use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
     fmt, io,
     net::{IpAddr, SocketAddr},
+    sync::Mutex,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use bytes::BufMut;
+use bytes::{BufMut, Bytes};
+use thiserror::Error;
 
 use crate::{
     coding::{BufExt, BufMutExt},
@@ -46,6 +49,7 @@ impl<'a> RetryToken<'a> {
         aead_key.seal(&mut buf, additional_data).unwrap();
 
         let mut token = Vec::new();
+        token.put_u8(TokenKind::Retry as u8);
         token.put_slice(self.random_bytes);
         token.put_slice(&buf);
         token
@@ -57,14 +61,16 @@ impl<'a> RetryToken<'a> {
         retry_src_cid: &ConnectionId,
         raw_token_bytes: &'a [u8],
     ) -> Result<Self, CryptoError> {
-        if raw_token_bytes.len() < Self::RANDOM_BYTES_LEN {
-            // Invalid length
+        if TokenKind::from_token(raw_token_bytes) != Some(TokenKind::Retry)
+            || raw_token_bytes.len() < 1 + Self::RANDOM_BYTES_LEN
+        {
+            // Invalid kind or length
             return Err(CryptoError);
         }
 
-        let random_bytes = &raw_token_bytes[..Self::RANDOM_BYTES_LEN];
+        let random_bytes = &raw_token_bytes[1..1 + Self::RANDOM_BYTES_LEN];
         let aead_key = key.aead_from_hkdf(random_bytes);
-        let mut sealed_token = raw_token_bytes[Self::RANDOM_BYTES_LEN..].to_vec();
+        let mut sealed_token = raw_token_bytes[1 + Self::RANDOM_BYTES_LEN..].to_vec();
 
         let mut additional_data = [0u8; Self::MAX_ADDITIONAL_DATA_SIZE];
         let additional_data =
@@ -103,6 +109,255 @@ impl<'a> RetryToken<'a> {
     pub(crate) const RANDOM_BYTES_LEN: usize = 32;
 }
 
+/// A token sent to the client in a NEW_TOKEN frame
+///
+/// Lets the client prove ownership of its address on future connections, so that it needn't be
+/// sent a Retry packet.
+pub(crate) struct ValidationToken<'a> {
+    /// The time at which this token was issued
+    pub(crate) issued: SystemTime,
+    /// Random bytes for deriving AEAD key
+    pub(crate) random_bytes: &'a [u8],
+}
+
+impl<'a> ValidationToken<'a> {
+    pub(crate) fn encode(&self, key: &dyn HandshakeTokenKey, address: &IpAddr) -> Vec<u8> {
+        let aead_key = key.aead_from_hkdf(self.random_bytes);
+
+        let mut buf = Vec::new();
+        buf.write::<u64>(
+            self.issued
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or(0),
+        );
+        aead_key
+            .seal(&mut buf, &Self::additional_data(address))
+            .unwrap();
+
+        let mut token = Vec::new();
+        token.put_u8(TokenKind::Validation as u8);
+        token.put_slice(self.random_bytes);
+        token.put_slice(&buf);
+        token
+    }
+
+    /// Uniquely identifies the token, for detecting reuse
+    pub(crate) fn nonce(&self) -> u128 {
+        let mut nonce = [0; 16];
+        nonce.copy_from_slice(&self.random_bytes[..16]);
+        u128::from_le_bytes(nonce)
+    }
+
+    pub(crate) fn from_bytes(
+        key: &dyn HandshakeTokenKey,
+        address: &IpAddr,
+        raw_token_bytes: &'a [u8],
+    ) -> Result<Self, CryptoError> {
+        if TokenKind::from_token(raw_token_bytes) != Some(TokenKind::Validation)
+            || raw_token_bytes.len() < 1 + RetryToken::RANDOM_BYTES_LEN
+        {
+            // Invalid kind or length
+            return Err(CryptoError);
+        }
+
+        let random_bytes = &raw_token_bytes[1..1 + RetryToken::RANDOM_BYTES_LEN];
+        let aead_key = key.aead_from_hkdf(random_bytes);
+        let mut sealed_token = raw_token_bytes[1 + RetryToken::RANDOM_BYTES_LEN..].to_vec();
+        let data = aead_key.open(&mut sealed_token, &Self::additional_data(address))?;
+
+        let mut reader = io::Cursor::new(data);
+        let issued = UNIX_EPOCH + Duration::new(reader.get::<u64>().map_err(|_| CryptoError)?, 0);
+
+        Ok(Self {
+            issued,
+            random_bytes,
+        })
+    }
+
+    /// Binds the token to the client's IP address only, since its port is likely to change
+    /// between connections
+    fn additional_data(address: &IpAddr) -> Vec<u8> {
+        match address {
+            IpAddr::V4(x) => x.octets().to_vec(),
+            IpAddr::V6(x) => x.octets().to_vec(),
+        }
+    }
+}
+
+/// The kinds of token a client may present in its Initial packets
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum TokenKind {
+    /// Issued in a Retry packet, valid for a single connection attempt
+    Retry = 0,
+    /// Issued in a NEW_TOKEN frame, valid for future connections
+    Validation = 1,
+}
+
+impl TokenKind {
+    pub(crate) fn from_token(token: &[u8]) -> Option<Self> {
+        match token.first()? {
+            0 => Some(Self::Retry),
+            1 => Some(Self::Validation),
+            _ => None,
+        }
+    }
+}
+
+/// Responsible for storing address validation tokens received from servers and retrieving them
+/// for use in subsequent connections
+///
+/// Servers send tokens in NEW_TOKEN frames. Presenting one when connecting to the same server again
+/// allows it to skip address validation, saving a round trip if it's configured to use Retry
+/// packets.
+pub trait TokenStore: Send + Sync {
+    /// Store a token received from the server called `server_name`
+    fn insert(&self, server_name: &str, token: Bytes);
+
+    /// Take a token previously stored for `server_name`, if any
+    ///
+    /// A token should not be returned more than once, since that would allow observers to link
+    /// the connections using it.
+    fn take(&self, server_name: &str) -> Option<Bytes>;
+}
+
+/// A [`TokenStore`] keeping a bounded number of tokens in memory
+#[derive(Debug)]
+pub struct TokenMemoryCache {
+    max_server_names: usize,
+    max_tokens_per_server: usize,
+    state: Mutex<TokenMemoryCacheState>,
+}
+
+#[derive(Debug, Default)]
+struct TokenMemoryCacheState {
+    tokens: HashMap<String, VecDeque<Bytes>>,
+    /// Server names in the order they were first stored, to evict the oldest
+    order: VecDeque<String>,
+}
+
+impl TokenMemoryCache {
+    /// Construct a cache holding tokens for up to `max_server_names` servers, and at most
+    /// `max_tokens_per_server` tokens for each of them
+    pub fn new(max_server_names: usize, max_tokens_per_server: usize) -> Self {
+        Self {
+            max_server_names,
+            max_tokens_per_server,
+            state: Mutex::new(TokenMemoryCacheState::default()),
+        }
+    }
+}
+
+impl Default for TokenMemoryCache {
+    fn default() -> Self {
+        Self::new(256, 2)
+    }
+}
+
+impl TokenStore for TokenMemoryCache {
+    fn insert(&self, server_name: &str, token: Bytes) {
+        if self.max_server_names == 0 || self.max_tokens_per_server == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if !state.tokens.contains_key(server_name) {
+            if state.order.len() == self.max_server_names {
+                let oldest = state.order.pop_front().unwrap();
+                state.tokens.remove(&oldest);
+            }
+            state.order.push_back(server_name.into());
+        }
+        let tokens = state.tokens.entry(server_name.into()).or_default();
+        if tokens.len() == self.max_tokens_per_server {
+            tokens.pop_front();
+        }
+        tokens.push_back(token);
+    }
+
+    fn take(&self, server_name: &str) -> Option<Bytes> {
+        let mut state = self.state.lock().unwrap();
+        let tokens = state.tokens.get_mut(server_name)?;
+        // Newer tokens are less likely to have expired
+        let token = tokens.pop_back();
+        if tokens.is_empty() {
+            state.tokens.remove(server_name);
+            state.order.retain(|x| x != server_name);
+        }
+        token
+    }
+}
+
+/// Responsible for detecting reuse of address validation tokens sent in NEW_TOKEN frames
+///
+/// A token which can be used any number of times would let anyone who observed it skip address
+/// validation from the client's IP address until it expires, so servers should accept each token
+/// only once. The log is only consulted for tokens which are otherwise valid, so it need only
+/// remember tokens until they expire.
+pub trait TokenLog: Send + Sync {
+    /// Record the use of the token identified by `nonce`, failing if it was used before
+    ///
+    /// The token was issued at `issued`, and is valid for `lifetime` afterwards. Implementations
+    /// may also fail if they can't keep track of any more tokens, in which case the client is
+    /// simply treated as if it had presented no token.
+    fn check_and_insert(
+        &self,
+        nonce: u128,
+        issued: SystemTime,
+        lifetime: Duration,
+    ) -> Result<(), TokenReuseError>;
+}
+
+/// Error for when a validation token may have been reused
+#[derive(Debug, Copy, Clone, Error)]
+#[error("validation token was already used")]
+pub struct TokenReuseError;
+
+/// A [`TokenLog`] keeping the tokens used before they expire in memory
+///
+/// Once `capacity` unexpired tokens have been used, further tokens are refused until some of them
+/// expire.
+#[derive(Debug)]
+pub struct TokenMemoryLog {
+    capacity: usize,
+    /// Issue time and nonce of each token used
+    used: Mutex<BTreeSet<(SystemTime, u128)>>,
+}
+
+impl TokenMemoryLog {
+    /// Construct a log remembering up to `capacity` tokens
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            used: Mutex::new(BTreeSet::new()),
+        }
+    }
+}
+
+impl Default for TokenMemoryLog {
+    fn default() -> Self {
+        Self::new(100_000)
+    }
+}
+
+impl TokenLog for TokenMemoryLog {
+    fn check_and_insert(
+        &self,
+        nonce: u128,
+        issued: SystemTime,
+        lifetime: Duration,
+    ) -> Result<(), TokenReuseError> {
+        let mut used = self.used.lock().unwrap();
+        // Forget tokens which have expired, since they'll be refused regardless
+        if let Some(oldest) = SystemTime::now().checked_sub(lifetime) {
+            *used = used.split_off(&(oldest, 0));
+        }
+        if used.len() >= self.capacity || !used.insert((issued, nonce)) {
+            return Err(TokenReuseError);
+        }
+        Ok(())
+    }
+}
+
 /// Stateless reset token
 ///
 /// Used for an endpoint to securely communicate that it has lost state for a connection.
@@ -232,4 +487,83 @@ mod test {
         // Assert: completely invalid retry token returns error
         assert!(RetryToken::from_bytes(&prk, &addr, &retry_src_cid, &invalid_token).is_err());
     }
+    #[cfg(feature = "ring")]
+    #[test]
+    fn validation_token_sanity() {
+        use super::*;
+        use rand::RngCore;
+        use std::{
+            net::{Ipv4Addr, Ipv6Addr},
+            time::{Duration, UNIX_EPOCH},
+        };
+
+        let rng = &mut rand::thread_rng();
+
+        let mut master_key = [0; 64];
+        rng.fill_bytes(&mut master_key);
+
+        let mut random_bytes = [0; 32];
+        rng.fill_bytes(&mut random_bytes);
+
+        let prk = ring::hkdf::Salt::new(ring::hkdf::HKDF_SHA256, &[]).extract(&master_key);
+
+        let ip = IpAddr::from(Ipv6Addr::LOCALHOST);
+        let token = ValidationToken {
+            issued: UNIX_EPOCH + Duration::new(42, 0),
+            random_bytes: &random_bytes,
+        };
+        let encoded = token.encode(&prk, &ip);
+        assert_eq!(TokenKind::from_token(&encoded), Some(TokenKind::Validation));
+
+        let decoded =
+            ValidationToken::from_bytes(&prk, &ip, &encoded).expect("token didn't validate");
+        assert_eq!(token.issued, decoded.issued);
+
+        // Tokens are bound to the client's IP address
+        let other = IpAddr::from(Ipv4Addr::LOCALHOST);
+        assert!(ValidationToken::from_bytes(&prk, &other, &encoded).is_err());
+    }
+
+    #[test]
+    fn token_memory_log() {
+        use super::*;
+
+        let log = TokenMemoryLog::new(2);
+        let lifetime = Duration::from_secs(60);
+        let now = SystemTime::now();
+        log.check_and_insert(1, now, lifetime).unwrap();
+        // Tokens are accepted only once
+        assert!(log.check_and_insert(1, now, lifetime).is_err());
+        log.check_and_insert(2, now, lifetime).unwrap();
+        // The log is full
+        assert!(log.check_and_insert(3, now, lifetime).is_err());
+
+        // Expired tokens are forgotten
+        let log = TokenMemoryLog::new(1);
+        log.check_and_insert(1, now - 2 * lifetime, lifetime)
+            .unwrap();
+        log.check_and_insert(2, now, lifetime).unwrap();
+    }
+
+    #[test]
+    fn token_memory_cache() {
+        use super::*;
+
+        let cache = TokenMemoryCache::new(2, 2);
+        cache.insert("a", Bytes::from_static(b"a1"));
+        cache.insert("a", Bytes::from_static(b"a2"));
+        cache.insert("a", Bytes::from_static(b"a3"));
+        // Newest tokens are used first, and the oldest are dropped
+        assert_eq!(cache.take("a").as_deref(), Some(&b"a3"[..]));
+        assert_eq!(cache.take("a").as_deref(), Some(&b"a2"[..]));
+        assert_eq!(cache.take("a"), None);
+
+        cache.insert("a", Bytes::from_static(b"a"));
+        cache.insert("b", Bytes::from_static(b"b"));
+        cache.insert("c", Bytes::from_static(b"c"));
+        // Least recently inserted server name is evicted
+        assert_eq!(cache.take("a"), None);
+        assert_eq!(cache.take("b").as_deref(), Some(&b"b"[..]));
+        assert_eq!(cache.take("c").as_deref(), Some(&b"c"[..]));
+    }
 }
@@ -21,9 +21,14 @@ use crate::{
     config::{EndpointConfig, ServerConfig, TransportConfig},
     shared::ConnectionId,
     ResetToken, Side, TransportError, VarInt, LOC_CID_COUNT, MAX_CID_SIZE, MAX_STREAM_COUNT,
-    RESET_TOKEN_SIZE,
+    RESET_TOKEN_SIZE, TIMER_GRANULARITY,
 };
 
+/// Exponent used to encode the delay of ACK frames we send
+///
+/// Always advertised as our `ack_delay_exponent`, since it isn't configurable.
+pub(crate) const ACK_DELAY_EXPONENT: u32 = 3;
+
 // Apply a given macro to a list of all the transport parameters having integer types, along with
 // their codes and default values. Using this helps us avoid error-prone duplication of the
 // contained information across decoding, encoding, and the `Default` impl. Whenever we want to do
@@ -53,7 +58,7 @@ macro_rules! apply_params {
             initial_max_streams_uni(0x0009) = 0,
 
             /// Exponent used to decode the ACK Delay field in the ACK frame
-            ack_delay_exponent(0x000a) = 3,
+            ack_delay_exponent(0x000a) = ACK_DELAY_EXPONENT,
             /// Maximum amount of time in milliseconds by which the endpoint will delay sending
             /// acknowledgments
             max_ack_delay(0x000b) = 25,
@@ -66,7 +71,7 @@ macro_rules! apply_params {
 macro_rules! make_struct {
     {$($(#[$doc:meta])* $name:ident ($code:expr) = $default:expr,)*} => {
         /// Transport parameters used to negotiate connection-level preferences between peers
-        #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+        #[derive(Debug, Clone, Eq, PartialEq)]
         pub struct TransportParameters {
             $($(#[$doc])* pub(crate) $name : VarInt,)*
 
@@ -80,6 +85,19 @@ macro_rules! make_struct {
             /// The endpoint is willing to receive QUIC packets containing any value for the fixed
             /// bit
             pub(crate) grease_quic_bit: bool,
+            /// Minimum amount of time in microseconds by which the endpoint is able to delay
+            /// sending acknowledgments
+            ///
+            /// If a value is provided, it implies that the endpoint supports QUIC Acknowledgement
+            /// Frequency
+            pub(crate) min_ack_delay: Option<VarInt>,
+            /// The version used for the connection and the versions the endpoint supports, used to
+            /// detect downgrade attacks on version negotiation
+            pub(crate) version_information: Option<VersionInformation>,
+            /// The endpoint supports the RESET_STREAM_AT frame
+            ///
+            /// Defined by draft-ietf-quic-reliable-stream-reset.
+            pub(crate) reset_stream_at: bool,
 
             // Server-only
             /// The value of the Destination Connection ID field from the first Initial packet sent
@@ -104,6 +122,9 @@ macro_rules! make_struct {
                     max_datagram_frame_size: None,
                     initial_src_cid: None,
                     grease_quic_bit: false,
+                    min_ack_delay: None,
+                    version_information: None,
+                    reset_stream_at: false,
 
                     original_dst_cid: None,
                     retry_src_cid: None,
@@ -124,6 +145,7 @@ impl TransportParameters {
         cid_gen: &dyn ConnectionIdGenerator,
         initial_src_cid: ConnectionId,
         server_config: Option<&ServerConfig>,
+        version: u32,
     ) -> Self {
         Self {
             initial_src_cid: Some(initial_src_cid),
@@ -146,6 +168,14 @@ impl TransportParameters {
                 .datagram_receive_buffer_size
                 .map(|x| (x.min(u16::max_value().into()) as u16).into()),
             grease_quic_bit: endpoint_config.grease_quic_bit,
+            min_ack_delay: Some(
+                VarInt::from_u64(u64::try_from(TIMER_GRANULARITY.as_micros()).unwrap()).unwrap(),
+            ),
+            version_information: Some(VersionInformation {
+                chosen_version: version,
+                available_versions: endpoint_config.supported_versions.clone(),
+            }),
+            reset_stream_at: true,
             ..Self::default()
         }
     }
@@ -162,6 +192,7 @@ impl TransportParameters {
             || cached.initial_max_streams_uni > self.initial_max_streams_uni
             || cached.max_datagram_frame_size > self.max_datagram_frame_size
             || cached.grease_quic_bit && !self.grease_quic_bit
+            || cached.reset_stream_at && !self.reset_stream_at
         {
             return Err(TransportError::PROTOCOL_VIOLATION(
                 "0-RTT accepted with incompatible transport parameters",
@@ -179,6 +210,48 @@ impl TransportParameters {
     }
 }
 
+/// The Version Information transport parameter
+///
+/// See [RFC 9368](https://www.rfc-editor.org/rfc/rfc9368.html#section-3).
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct VersionInformation {
+    /// The version of the packets carrying the transport parameters
+    pub(crate) chosen_version: u32,
+    /// The versions the endpoint supports, in order of preference
+    pub(crate) available_versions: Vec<u32>,
+}
+
+impl VersionInformation {
+    fn wire_size(&self) -> usize {
+        4 * (1 + self.available_versions.len())
+    }
+
+    fn write<W: BufMut>(&self, w: &mut W) {
+        w.write(self.chosen_version);
+        for &version in &self.available_versions {
+            w.write(version);
+        }
+    }
+
+    fn read<R: Buf>(r: &mut R) -> Result<Self, Error> {
+        if r.remaining() % 4 != 0 {
+            return Err(Error::Malformed);
+        }
+        let chosen_version = r.get::<u32>()?;
+        let mut available_versions = Vec::with_capacity(r.remaining() / 4);
+        while r.has_remaining() {
+            available_versions.push(r.get::<u32>()?);
+        }
+        if chosen_version == 0 || available_versions.contains(&0) {
+            return Err(Error::IllegalValue);
+        }
+        Ok(Self {
+            chosen_version,
+            available_versions,
+        })
+    }
+}
+
 /// A server's preferred address
 ///
 /// This is communicated as a transport parameter during TLS session establishment.
@@ -276,7 +349,7 @@ impl TransportParameters {
         macro_rules! write_params {
             {$($(#[$doc:meta])* $name:ident ($code:expr) = $default:expr,)*} => {
                 $(
-                    if self.$name.0 != $default {
+                    if self.$name != VarInt::from_u32($default) {
                         w.write_var($code);
                         w.write(VarInt::try_from(self.$name.size()).unwrap());
                         w.write(self.$name);
@@ -329,6 +402,23 @@ impl TransportParameters {
             w.write_var(0x2ab2);
             w.write_var(0);
         }
+
+        if let Some(x) = self.min_ack_delay {
+            w.write_var(0xff04de1a);
+            w.write_var(x.size() as u64);
+            w.write(x);
+        }
+
+        if let Some(ref x) = self.version_information {
+            w.write_var(0x11);
+            w.write_var(x.wire_size() as u64);
+            x.write(w);
+        }
+
+        if self.reset_stream_at {
+            w.write_var(0x17f7586d2cb571);
+            w.write_var(0);
+        }
     }
 
     /// Decode `TransportParameters` from buffer
@@ -382,6 +472,12 @@ impl TransportParameters {
                 }
                 0x0f => decode_cid(len, &mut params.initial_src_cid, r)?,
                 0x10 => decode_cid(len, &mut params.retry_src_cid, r)?,
+                0x11 => {
+                    if params.version_information.is_some() {
+                        return Err(Error::Malformed);
+                    }
+                    params.version_information = Some(VersionInformation::read(&mut r.take(len))?);
+                }
                 0x20 => {
                     if len > 8 || params.max_datagram_frame_size.is_some() {
                         return Err(Error::Malformed);
@@ -392,6 +488,19 @@ impl TransportParameters {
                     0 => params.grease_quic_bit = true,
                     _ => return Err(Error::Malformed),
                 },
+                0xff04de1a => {
+                    let value = r.get::<VarInt>()?;
+                    if len != value.size() || params.min_ack_delay.is_some() {
+                        return Err(Error::Malformed);
+                    }
+                    params.min_ack_delay = Some(value);
+                }
+                0x17f7586d2cb571 => {
+                    if len != 0 || params.reset_stream_at {
+                        return Err(Error::Malformed);
+                    }
+                    params.reset_stream_at = true;
+                }
                 _ => {
                     macro_rules! parse {
                         {$($(#[$doc:meta])* $name:ident ($code:expr) = $default:expr,)*} => {
@@ -414,12 +523,18 @@ impl TransportParameters {
         // Semantic validation
         if params.ack_delay_exponent.0 > 20
             || params.max_ack_delay.0 >= 1 << 14
+            || params
+                .min_ack_delay
+                .map_or(false, |x| x.0 > params.max_ack_delay.0 * 1_000)
             || params.active_connection_id_limit.0 < 2
             || params.max_udp_payload_size.0 < 1200
             || params.initial_max_streams_bidi.0 > MAX_STREAM_COUNT
             || params.initial_max_streams_uni.0 > MAX_STREAM_COUNT
             || (side.is_server()
                 && (params.stateless_reset_token.is_some() || params.preferred_address.is_some()))
+            || params
+                .preferred_address
+                .map_or(false, |x| x.connection_id.is_empty())
         {
             return Err(Error::IllegalValue);
         }
@@ -454,10 +569,16 @@ mod test {
             preferred_address: Some(PreferredAddress {
                 address_v4: Some(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 42)),
                 address_v6: None,
-                connection_id: ConnectionId::new(&[]),
+                connection_id: ConnectionId::new(&[0x42]),
                 stateless_reset_token: [0xab; RESET_TOKEN_SIZE].into(),
             }),
             grease_quic_bit: true,
+            min_ack_delay: Some(2_000u32.into()),
+            version_information: Some(VersionInformation {
+                chosen_version: 0x1,
+                available_versions: vec![0x1, 0xff00_001d],
+            }),
+            reset_stream_at: true,
             ..TransportParameters::default()
         };
         params.write(&mut buf);
@@ -116,6 +116,8 @@ impl Context {
                         .accept()
                         .await
                         .expect("accept")
+                        .accept()
+                        .expect("accept")
                         .await
                         .expect("connect");
 
@@ -13,7 +13,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let endpoint2 = endpoint.clone();
     tokio::spawn(async move {
         let incoming_conn = endpoint2.accept().await.unwrap();
-        let conn = incoming_conn.await.unwrap();
+        let conn = incoming_conn.accept().unwrap().await.unwrap();
         println!(
             "[server] connection accepted: addr={}",
             conn.remote_address()
@@ -23,7 +23,7 @@ async fn run_server(addr: SocketAddr) {
     let (endpoint, _server_cert) = make_server_endpoint(addr).unwrap();
     // accept a single connection
     let incoming_conn = endpoint.accept().await.unwrap();
-    let conn = incoming_conn.await.unwrap();
+    let conn = incoming_conn.accept().unwrap().await.unwrap();
     println!(
         "[server] connection accepted: addr={}",
         conn.remote_address()
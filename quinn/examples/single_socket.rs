@@ -41,7 +41,14 @@ fn run_server(addr: SocketAddr) -> Result<Vec<u8>, Box<dyn Error>> {
     let (endpoint, server_cert) = make_server_endpoint(addr)?;
     // accept a single connection
     tokio::spawn(async move {
-        let connection = endpoint.accept().await.unwrap().await.unwrap();
+        let connection = endpoint
+            .accept()
+            .await
+            .unwrap()
+            .accept()
+            .unwrap()
+            .await
+            .unwrap();
         println!(
             "[server] incoming connection: addr={}",
             connection.remote_address()
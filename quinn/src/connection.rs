@@ -1,7 +1,9 @@
 use std::{
     any::Any,
+    collections::VecDeque,
     fmt,
     future::Future,
+    io,
     net::{IpAddr, SocketAddr},
     pin::Pin,
     sync::Arc,
@@ -12,10 +14,13 @@ use std::{
 use crate::runtime::{AsyncTimer, Runtime};
 use bytes::Bytes;
 use pin_project_lite::pin_project;
-use proto::{ConnectionError, ConnectionHandle, ConnectionStats, Dir, StreamEvent, StreamId};
+use proto::{
+    ConnectionError, ConnectionHandle, ConnectionStats, DatagramOptions, Dir, PathEvent, Side,
+    StreamEvent, StreamId,
+};
 use rustc_hash::FxHashMap;
 use thiserror::Error;
-use tokio::sync::{futures::Notified, mpsc, oneshot, Notify};
+use tokio::sync::{broadcast, futures::Notified, mpsc, oneshot, Notify};
 use tracing::debug_span;
 use udp::UdpState;
 
@@ -313,6 +318,30 @@ impl Connection {
         }
     }
 
+    /// Learn whether a datagram sent with
+    /// [`send_datagram_with_id()`](Self::send_datagram_with_id) was delivered
+    ///
+    /// Outcomes are yielded in the order they become known, which needn't be the order the
+    /// datagrams were sent in. Only the most recent few thousand outcomes are buffered; older ones
+    /// are discarded if they aren't read in time, and counted by
+    /// [`missed_datagram_outcomes()`](Self::missed_datagram_outcomes).
+    pub fn read_datagram_outcome(&self) -> ReadDatagramOutcome<'_> {
+        ReadDatagramOutcome {
+            conn: &self.0,
+            notify: self.0.shared.datagram_outcomes.notified(),
+        }
+    }
+
+    /// Number of datagram outcomes discarded so far because they weren't read in time
+    ///
+    /// See [`read_datagram_outcome()`](Self::read_datagram_outcome).
+    pub fn missed_datagram_outcomes(&self) -> u64 {
+        self.0
+            .state
+            .lock("missed_datagram_outcomes")
+            .missed_datagram_outcomes
+    }
+
     /// Wait for the connection to be closed for any reason
     ///
     /// Despite the return type's name, closed connections are often not an error condition at the
@@ -372,21 +401,52 @@ impl Connection {
     /// and `data` must both fit inside a single QUIC packet and be smaller than the maximum
     /// dictated by the peer.
     pub fn send_datagram(&self, data: Bytes) -> Result<(), SendDatagramError> {
+        self.send_datagram_with_options(data, &DatagramOptions::default())
+    }
+
+    /// Transmit `data` as an unreliable, unordered application datagram, tracking its delivery
+    ///
+    /// Like [`send_datagram()`](Self::send_datagram), but once the datagram is acknowledged by the
+    /// peer or declared lost, a [`DatagramOutcome`] carrying `id` can be obtained from
+    /// [`read_datagram_outcome()`](Self::read_datagram_outcome). A bounded number of outcomes is
+    /// buffered until they're read.
+    pub fn send_datagram_with_id(&self, data: Bytes, id: u64) -> Result<(), SendDatagramError> {
+        self.send_datagram_with_options(data, DatagramOptions::default().id(id))
+    }
+
+    /// Transmit `data` as an unreliable, unordered application datagram with an ID, priority
+    /// and/or deadline
+    ///
+    /// Otherwise behaves like [`send_datagram()`](Self::send_datagram).
+    pub fn send_datagram_with_options(
+        &self,
+        data: Bytes,
+        options: &DatagramOptions,
+    ) -> Result<(), SendDatagramError> {
         let conn = &mut *self.0.state.lock("send_datagram");
         if let Some(ref x) = conn.error {
             return Err(SendDatagramError::ConnectionLost(x.clone()));
         }
-        use proto::SendDatagramError::*;
-        match conn.inner.datagrams().send(data) {
+        match conn.inner.datagrams().send_with_options(data, options) {
             Ok(()) => {
                 conn.wake();
                 Ok(())
             }
-            Err(e) => Err(match e {
-                UnsupportedByPeer => SendDatagramError::UnsupportedByPeer,
-                Disabled => SendDatagramError::Disabled,
-                TooLarge => SendDatagramError::TooLarge,
-            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Transmit `data` as an unreliable, unordered application datagram, waiting for buffer space
+    ///
+    /// Like [`send_datagram()`](Self::send_datagram), but rather than dropping or refusing a
+    /// datagram when the outgoing datagram buffer is full, regardless of the configured
+    /// [`DatagramOverflowPolicy`](crate::DatagramOverflowPolicy), resolves only once it has been
+    /// queued.
+    pub fn send_datagram_wait(&self, data: Bytes) -> SendDatagram<'_> {
+        SendDatagram {
+            conn: &self.0,
+            data: Some(data),
+            notify: self.0.shared.datagrams_unblocked.notified(),
         }
     }
 
@@ -431,6 +491,52 @@ impl Connection {
         self.0.state.lock("remote_address").inner.remote_address()
     }
 
+    /// Move this connection onto a UDP socket of its own
+    ///
+    /// Unlike [`Endpoint::rebind`](crate::Endpoint::rebind), which switches the socket for every
+    /// connection at once, only this connection's traffic moves to `socket`. The new path is
+    /// validated as it's used and the peer follows the connection to it, while the endpoint's
+    /// other connections stay where they are. The endpoint driver reads from `socket` until the
+    /// connection is drained.
+    ///
+    /// `socket` must use the same address family as the endpoint's socket. Fails with a
+    /// [`MigrateError`](crate::MigrateError) if the connection may not migrate, e.g. because it
+    /// was accepted rather than initiated by this endpoint, or the peer disabled active migration.
+    pub fn rebind(&self, socket: std::net::UdpSocket) -> io::Result<()> {
+        let conn = &mut *self.0.state.lock("rebind");
+        let socket = conn.runtime.wrap_udp_socket(socket)?;
+        let remote = conn.inner.remote_address();
+        conn.inner
+            .migrate(Instant::now(), remote, None)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        // If the endpoint driver is gone, noop.
+        let _ = conn
+            .endpoint_events
+            .send((conn.handle, EndpointEvent::Rebind(socket)));
+        conn.wake();
+        Ok(())
+    }
+
+    /// Subscribe to events about the connection's network paths
+    ///
+    /// Yields path validation results, changes of the peer's address, e.g. due to NAT rebinding,
+    /// and path MTU changes that occur after the call. See [`Subscription`] for what happens when
+    /// events aren't received promptly.
+    pub fn path_events(&self) -> PathEvents {
+        let state = self.0.state.lock("path_events");
+        Subscription::new(state.path_events.as_ref())
+    }
+
+    /// Subscribe to updates of the keys protecting 1-RTT packets
+    ///
+    /// Yields the key updates initiated by either peer after the call, whether requested through
+    /// [`TransportConfig::key_update_config`](crate::TransportConfig::key_update_config) or by the
+    /// peer. See [`Subscription`] for what happens when events aren't received promptly.
+    pub fn key_updates(&self) -> KeyUpdates {
+        let state = self.0.state.lock("key_updates");
+        Subscription::new(state.key_updates.as_ref())
+    }
+
     /// The local IP address which was used when the peer established
     /// the connection
     ///
@@ -507,14 +613,13 @@ impl Connection {
         self.0.stable_id()
     }
 
-    // Update traffic keys spontaneously for testing purposes.
+    // Update traffic keys spontaneously for testing purposes. The update takes effect with the
+    // next 1-RTT packet the connection sends.
     #[doc(hidden)]
     pub fn force_key_update(&self) {
-        self.0
-            .state
-            .lock("force_key_update")
-            .inner
-            .initiate_key_update()
+        let mut state = self.0.state.lock("force_key_update");
+        state.inner.initiate_key_update();
+        state.wake();
     }
 
     /// Derive keying material from this connection's TLS session secrets.
@@ -550,6 +655,14 @@ impl Connection {
         conn.wake();
     }
 
+    /// See [`proto::TransportConfig::max_send_rate()`]
+    pub fn set_max_send_rate(&self, rate: Option<u64>) {
+        let mut conn = self.0.state.lock("set_max_send_rate");
+        conn.inner.set_max_send_rate(rate);
+        // May be able to send right away if the limit was raised
+        conn.wake();
+    }
+
     /// See [`proto::TransportConfig::receive_window()`]
     pub fn set_receive_window(&self, receive_window: VarInt) {
         let mut conn = self.0.state.lock("set_receive_window");
@@ -735,6 +848,161 @@ impl Future for ReadDatagram<'_> {
     }
 }
 
+pin_project! {
+    /// Future produced by [`Connection::read_datagram_outcome`]
+    pub struct ReadDatagramOutcome<'a> {
+        conn: &'a ConnectionRef,
+        #[pin]
+        notify: Notified<'a>,
+    }
+}
+
+impl Future for ReadDatagramOutcome<'_> {
+    type Output = Result<DatagramOutcome, ConnectionError>;
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        let mut state = this.conn.state.lock("ReadDatagramOutcome::poll");
+        if let Some(x) = state.datagram_outcomes.pop_front() {
+            return Poll::Ready(Ok(x));
+        } else if let Some(ref e) = state.error {
+            return Poll::Ready(Err(e.clone()));
+        }
+        loop {
+            match this.notify.as_mut().poll(ctx) {
+                // `state` lock ensures we didn't race with readiness
+                Poll::Pending => return Poll::Pending,
+                // Spurious wakeup, get a new future
+                Poll::Ready(()) => this
+                    .notify
+                    .set(this.conn.shared.datagram_outcomes.notified()),
+            }
+        }
+    }
+}
+
+/// A subscription to events of a connection, such as [`PathEvents`] or [`KeyUpdates`]
+///
+/// Each subscription buffers a few dozen events. If more occur before they're received, the oldest
+/// are discarded, and counted by [`missed`](Self::missed).
+#[derive(Debug)]
+pub struct Subscription<T> {
+    events: broadcast::Receiver<T>,
+    missed: u64,
+}
+
+/// Events about a connection's network paths, produced by [`Connection::path_events`]
+pub type PathEvents = Subscription<PathEvent>;
+
+/// Updates of a connection's 1-RTT keys, produced by [`Connection::key_updates`]
+pub type KeyUpdates = Subscription<KeyUpdate>;
+
+impl<T: Clone> Subscription<T> {
+    fn new(sender: Option<&broadcast::Sender<T>>) -> Self {
+        Self {
+            events: match sender {
+                Some(x) => x.subscribe(),
+                // The connection is closed, so no further events will occur
+                None => broadcast::channel(1).1,
+            },
+            missed: 0,
+        }
+    }
+
+    /// Wait for the next event
+    ///
+    /// Returns `None` once the connection is closed and all buffered events have been received.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.events.recv().await {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::RecvError::Lagged(n)) => self.missed += n,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Get the next event if one is buffered, without waiting
+    pub fn try_recv(&mut self) -> Option<T> {
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::TryRecvError::Lagged(n)) => self.missed += n,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    /// Number of events discarded so far because they weren't received in time
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+pin_project! {
+    /// Future produced by [`Connection::send_datagram_wait`]
+    pub struct SendDatagram<'a> {
+        conn: &'a ConnectionRef,
+        data: Option<Bytes>,
+        #[pin]
+        notify: Notified<'a>,
+    }
+}
+
+impl Future for SendDatagram<'_> {
+    type Output = Result<(), SendDatagramError>;
+    fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        let mut state = this.conn.state.lock("SendDatagram::poll");
+        if let Some(ref e) = state.error {
+            return Poll::Ready(Err(SendDatagramError::ConnectionLost(e.clone())));
+        }
+        let data = this
+            .data
+            .take()
+            .expect("SendDatagram polled after completion");
+        match state.inner.datagrams().try_send(data) {
+            Ok(()) => {
+                state.wake();
+                Poll::Ready(Ok(()))
+            }
+            Err(proto::SendDatagramError::Blocked(data)) => {
+                *this.data = Some(data);
+                loop {
+                    match this.notify.as_mut().poll(ctx) {
+                        // `state` lock ensures we didn't race with readiness
+                        Poll::Pending => return Poll::Pending,
+                        // Spurious wakeup, get a new future
+                        Poll::Ready(()) => this
+                            .notify
+                            .set(this.conn.shared.datagrams_unblocked.notified()),
+                    }
+                }
+            }
+            Err(e) => Poll::Ready(Err(e.into())),
+        }
+    }
+}
+
+/// An update of the keys protecting a connection's 1-RTT packets
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeyUpdate {
+    /// Number of key updates so far, the first 1-RTT keys being key phase 0
+    pub key_phase: u64,
+    /// The side which initiated the key update
+    pub initiator: Side,
+    /// How long the previous keys were in use for
+    pub previous_duration: Duration,
+}
+
+/// Whether a datagram sent with [`Connection::send_datagram_with_id`] was delivered
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DatagramOutcome {
+    /// The packet carrying the datagram with this ID was acknowledged by the peer
+    Acked(u64),
+    /// The datagram with this ID was lost, and will not be retransmitted
+    Lost(u64),
+}
+
 #[derive(Debug)]
 pub(crate) struct ConnectionRef(Arc<ConnectionInner>);
 
@@ -767,6 +1035,10 @@ impl ConnectionRef {
                 finishing: FxHashMap::default(),
                 stopped: FxHashMap::default(),
                 error: None,
+                path_events: Some(broadcast::channel(SUBSCRIPTION_CAPACITY).0),
+                key_updates: Some(broadcast::channel(SUBSCRIPTION_CAPACITY).0),
+                datagram_outcomes: VecDeque::new(),
+                missed_datagram_outcomes: 0,
                 ref_count: 0,
                 udp_state,
                 runtime,
@@ -824,6 +1096,10 @@ pub(crate) struct Shared {
     /// Notified when the peer has initiated a new stream
     stream_incoming: [Notify; 2],
     datagrams: Notify,
+    /// Notified when the delivery of a tracked datagram has been determined
+    datagram_outcomes: Notify,
+    /// Notified when space frees up in the outgoing datagram buffer after a datagram was refused
+    datagrams_unblocked: Notify,
     closed: Notify,
 }
 
@@ -844,6 +1120,13 @@ pub(crate) struct State {
     pub(crate) stopped: FxHashMap<StreamId, Waker>,
     /// Always set to Some before the connection becomes drained
     pub(crate) error: Option<ConnectionError>,
+    /// Dropped once the connection is closed, to let `PathEvents` know no more events will occur
+    path_events: Option<broadcast::Sender<PathEvent>>,
+    /// Dropped once the connection is closed, like `path_events`
+    key_updates: Option<broadcast::Sender<KeyUpdate>>,
+    datagram_outcomes: VecDeque<DatagramOutcome>,
+    /// Number of outcomes discarded from `datagram_outcomes` because it was full
+    missed_datagram_outcomes: u64,
     /// Number of live handles that can be used to initiate or handle I/O; excludes the driver
     ref_count: usize,
     udp_state: Arc<UdpState>,
@@ -899,6 +1182,8 @@ impl State {
                 Poll::Ready(Some(ConnectionEvent::Ping)) => {
                     self.inner.ping();
                 }
+                // Being polled is enough for the driver to try transmitting again
+                Poll::Ready(Some(ConnectionEvent::Wake)) => {}
                 Poll::Ready(Some(ConnectionEvent::Proto(event))) => {
                     self.inner.handle_event(event);
                 }
@@ -952,6 +1237,37 @@ impl State {
                 DatagramReceived => {
                     shared.datagrams.notify_waiters();
                 }
+                DatagramAcked(id) => {
+                    self.push_datagram_outcome(DatagramOutcome::Acked(id));
+                    shared.datagram_outcomes.notify_waiters();
+                }
+                DatagramLost(id) => {
+                    self.push_datagram_outcome(DatagramOutcome::Lost(id));
+                    shared.datagram_outcomes.notify_waiters();
+                }
+                DatagramsUnblocked => {
+                    shared.datagrams_unblocked.notify_waiters();
+                }
+                Path(event) => {
+                    if let Some(ref x) = self.path_events {
+                        // Nobody may be listening
+                        let _ = x.send(event);
+                    }
+                }
+                KeysUpdated {
+                    key_phase,
+                    initiator,
+                    previous_duration,
+                } => {
+                    if let Some(ref x) = self.key_updates {
+                        // Nobody may be listening
+                        let _ = x.send(KeyUpdate {
+                            key_phase,
+                            initiator,
+                            previous_duration,
+                        });
+                    }
+                }
                 Stream(StreamEvent::Readable { id }) => {
                     if let Some(reader) = self.blocked_readers.remove(&id) {
                         reader.wake();
@@ -970,6 +1286,14 @@ impl State {
                         stopped.wake();
                     }
                 }
+                Stream(StreamEvent::Expired { id, error_code }) => {
+                    if let Some(finishing) = self.finishing.remove(&id) {
+                        let _ = finishing.send(Some(WriteError::Expired(error_code)));
+                    }
+                    if let Some(writer) = self.blocked_writers.remove(&id) {
+                        writer.wake();
+                    }
+                }
                 Stream(StreamEvent::Stopped { id, error_code }) => {
                     if let Some(stopped) = self.stopped.remove(&id) {
                         stopped.wake();
@@ -1059,6 +1383,7 @@ impl State {
         shared.stream_incoming[Dir::Uni as usize].notify_waiters();
         shared.stream_incoming[Dir::Bi as usize].notify_waiters();
         shared.datagrams.notify_waiters();
+        shared.datagram_outcomes.notify_waiters();
         for (_, x) in self.finishing.drain() {
             let _ = x.send(Some(WriteError::ConnectionLost(reason.clone())));
         }
@@ -1068,9 +1393,21 @@ impl State {
         for (_, waker) in self.stopped.drain() {
             waker.wake();
         }
+        self.path_events = None;
+        self.key_updates = None;
         shared.closed.notify_waiters();
     }
 
+    /// Buffer an outcome for `read_datagram_outcome`, discarding the oldest if the application
+    /// isn't keeping up
+    fn push_datagram_outcome(&mut self, outcome: DatagramOutcome) {
+        if self.datagram_outcomes.len() == DATAGRAM_OUTCOME_CAPACITY {
+            self.datagram_outcomes.pop_front();
+            self.missed_datagram_outcomes += 1;
+        }
+        self.datagram_outcomes.push_back(outcome);
+    }
+
     fn close(&mut self, error_code: VarInt, reason: Bytes, shared: &Shared) {
         self.inner.close(Instant::now(), error_code, reason);
         self.terminate(ConnectionError::LocallyClosed, shared);
@@ -1127,17 +1464,43 @@ pub enum SendDatagramError {
     /// exceeded.
     #[error("datagram too large")]
     TooLarge,
+    /// The outgoing datagram buffer is full
+    ///
+    /// Returned under [`DatagramOverflowPolicy::Reject`](crate::DatagramOverflowPolicy::Reject),
+    /// carrying back the refused datagram. [`Connection::send_datagram_wait()`] waits for space
+    /// instead.
+    #[error("datagram send buffer full")]
+    Blocked(Bytes),
     /// The connection was lost
     #[error("connection lost")]
     ConnectionLost(#[from] ConnectionError),
 }
 
+impl From<proto::SendDatagramError> for SendDatagramError {
+    fn from(x: proto::SendDatagramError) -> Self {
+        use proto::SendDatagramError::*;
+        match x {
+            UnsupportedByPeer => Self::UnsupportedByPeer,
+            Disabled => Self::Disabled,
+            TooLarge => Self::TooLarge,
+            Blocked(data) => Self::Blocked(data),
+        }
+    }
+}
+
 /// The maximum amount of datagrams which will be produced in a single `drive_transmit` call
 ///
 /// This limits the amount of CPU resources consumed by datagram generation,
 /// and allows other tasks (like receiving ACKs) to run in between.
 const MAX_TRANSMIT_DATAGRAMS: usize = 20;
 
+/// The number of events buffered for each `path_events` or `key_updates` subscription before the
+/// oldest are lost
+const SUBSCRIPTION_CAPACITY: usize = 32;
+
+/// The number of datagram outcomes buffered for `read_datagram_outcome` before the oldest are lost
+const DATAGRAM_OUTCOME_CAPACITY: usize = 4096;
+
 /// Error indicating that a stream has already been finished or reset
 #[derive(Debug, Error, Clone, PartialEq, Eq)]
 #[error("unknown stream")]
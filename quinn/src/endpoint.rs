@@ -3,6 +3,7 @@ use std::{
     future::Future,
     io,
     io::IoSliceMut,
+    iter,
     mem::MaybeUninit,
     net::{SocketAddr, SocketAddrV6},
     pin::Pin,
@@ -16,15 +17,16 @@ use crate::runtime::{default_runtime, AsyncUdpSocket, Runtime};
 use bytes::{Bytes, BytesMut};
 use pin_project_lite::pin_project;
 use proto::{
-    self as proto, ClientConfig, ConnectError, ConnectionHandle, DatagramEvent, ServerConfig,
+    self as proto, ClientConfig, ConnectError, ConnectionError, ConnectionHandle, DatagramEvent,
+    ServerConfig,
 };
 use rustc_hash::FxHashMap;
 use tokio::sync::{futures::Notified, mpsc, Notify};
 use udp::{RecvMeta, UdpState, BATCH_SIZE};
 
 use crate::{
-    connection::Connecting, work_limiter::WorkLimiter, ConnectionEvent, EndpointConfig,
-    EndpointEvent, VarInt, IO_LOOP_BOUND, RECV_TIME_BOUND, SEND_TIME_BOUND,
+    connection::Connecting, incoming::Incoming, work_limiter::WorkLimiter, ConnectionEvent,
+    EndpointConfig, EndpointEvent, VarInt, IO_LOOP_BOUND, RECV_TIME_BOUND, SEND_TIME_BOUND,
 };
 
 /// A QUIC endpoint.
@@ -136,8 +138,10 @@ impl Endpoint {
 
     /// Get the next incoming connection attempt from a client
     ///
-    /// Yields [`Connecting`] futures that must be `await`ed to obtain the final `Connection`, or
-    /// `None` if the endpoint is [`close`](Self::close)d.
+    /// Yields [`Incoming`]s, or `None` if the endpoint is [`close`](Self::close)d. Each must be
+    /// accepted, refused, retried or ignored before the server commits any resources to it;
+    /// [`Incoming::accept`] yields a [`Connecting`] future that must be `await`ed to obtain the
+    /// final `Connection`.
     pub fn accept(&self) -> Accept<'_> {
         Accept {
             endpoint: self,
@@ -231,6 +235,21 @@ impl Endpoint {
             .set_server_config(server_config.map(Arc::new))
     }
 
+    /// Limit the rate at which all of the endpoint's connections together send data, in bytes per
+    /// second
+    ///
+    /// See [`proto::Endpoint::set_max_send_rate()`].
+    pub fn set_max_send_rate(&self, rate: Option<u64>) {
+        let mut inner = self.inner.state.lock().unwrap();
+        inner.inner.set_max_send_rate(rate);
+
+        // Let connections held back by the previous limit send
+        for sender in inner.connections.senders.values() {
+            // Ignoring errors from dropped connections
+            let _ = sender.send(ConnectionEvent::Wake);
+        }
+    }
+
     /// Get the local `SocketAddr` the underlying socket is bound to
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
         self.inner.state.lock().unwrap().socket.local_addr()
@@ -260,6 +279,12 @@ impl Endpoint {
         let reason = Bytes::copy_from_slice(reason);
         let mut endpoint = self.inner.state.lock().unwrap();
         endpoint.connections.close = Some((error_code, reason.clone()));
+        let endpoint = &mut *endpoint;
+        for incoming in endpoint.incoming.drain(..) {
+            let transmit = endpoint.inner.refuse(incoming);
+            endpoint.outgoing.push_back(udp_transmit(transmit));
+        }
+        endpoint.wake();
         for sender in endpoint.connections.senders.values() {
             // Ignoring errors from dropped connections
             let _ = sender.send(ConnectionEvent::Close {
@@ -361,13 +386,54 @@ pub(crate) struct EndpointInner {
     pub(crate) shared: Shared,
 }
 
+impl EndpointInner {
+    pub(crate) fn accept(&self, incoming: proto::Incoming) -> Result<Connecting, ConnectionError> {
+        let mut state = self.state.lock().unwrap();
+        match state.inner.accept(incoming, Instant::now()) {
+            Ok((handle, conn)) => {
+                let udp_state = state.udp_state.clone();
+                let runtime = state.runtime.clone();
+                Ok(state.connections.insert(handle, conn, udp_state, runtime))
+            }
+            Err(error) => {
+                if let Some(transmit) = error.response {
+                    state.outgoing.push_back(udp_transmit(transmit));
+                    state.wake();
+                }
+                Err(error.cause)
+            }
+        }
+    }
+
+    pub(crate) fn refuse(&self, incoming: proto::Incoming) {
+        let mut state = self.state.lock().unwrap();
+        let transmit = state.inner.refuse(incoming);
+        state.outgoing.push_back(udp_transmit(transmit));
+        state.wake();
+    }
+
+    pub(crate) fn retry(&self, incoming: proto::Incoming) -> Result<(), proto::RetryError> {
+        let mut state = self.state.lock().unwrap();
+        let transmit = state.inner.retry(incoming)?;
+        state.outgoing.push_back(udp_transmit(transmit));
+        state.wake();
+        Ok(())
+    }
+
+    pub(crate) fn ignore(&self, incoming: proto::Incoming) {
+        self.state.lock().unwrap().inner.ignore(incoming);
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct State {
     socket: Box<dyn AsyncUdpSocket>,
     udp_state: Arc<UdpState>,
     inner: proto::Endpoint,
     outgoing: VecDeque<udp::Transmit>,
-    incoming: VecDeque<Connecting>,
+    /// Sockets of connections that moved off of `socket` with `Connection::rebind`
+    connection_sockets: FxHashMap<ConnectionHandle, ConnectionSocket>,
+    incoming: VecDeque<proto::Incoming>,
     driver: Option<Waker>,
     ipv6: bool,
     connections: ConnectionSet,
@@ -381,6 +447,21 @@ pub(crate) struct State {
     runtime: Arc<dyn Runtime>,
 }
 
+impl Drop for State {
+    fn drop(&mut self) {
+        for incoming in self.incoming.drain(..) {
+            self.inner.ignore(incoming);
+        }
+    }
+}
+
+/// A UDP socket used by a single connection rather than the whole endpoint
+#[derive(Debug)]
+struct ConnectionSocket {
+    socket: Box<dyn AsyncUdpSocket>,
+    outgoing: VecDeque<udp::Transmit>,
+}
+
 #[derive(Debug)]
 pub(crate) struct Shared {
     incoming: Notify,
@@ -388,6 +469,13 @@ pub(crate) struct Shared {
 }
 
 impl State {
+    /// Wake up the driver, e.g. to send a newly queued transmit
+    fn wake(&self) {
+        if let Some(ref driver) = self.driver {
+            driver.wake_by_ref();
+        }
+    }
+
     fn drive_recv<'a>(&'a mut self, cx: &mut Context, now: Instant) -> Result<bool, io::Error> {
         self.recv_limiter.start_cycle();
         let mut metas = [RecvMeta::default(); BATCH_SIZE];
@@ -402,63 +490,73 @@ impl State {
                     .write(IoSliceMut::<'a>::new(buf));
             });
         let mut iovs = unsafe { iovs.assume_init() };
-        loop {
-            match self.socket.poll_recv(cx, &mut iovs, &mut metas) {
-                Poll::Ready(Ok(msgs)) => {
-                    self.recv_limiter.record_work(msgs);
-                    for (meta, buf) in metas.iter().zip(iovs.iter()).take(msgs) {
-                        let mut data: BytesMut = buf[0..meta.len].into();
-                        while !data.is_empty() {
-                            let buf = data.split_to(meta.stride.min(data.len()));
-                            match self.inner.handle(
-                                now,
-                                meta.addr,
-                                meta.dst_ip,
-                                meta.ecn.map(proto_ecn),
-                                buf,
-                            ) {
-                                Some(DatagramEvent::NewConnection(handle, conn)) => {
-                                    let conn = self.connections.insert(
-                                        handle,
-                                        conn,
-                                        self.udp_state.clone(),
-                                        self.runtime.clone(),
-                                    );
-                                    self.incoming.push_back(conn);
-                                }
-                                Some(DatagramEvent::ConnectionEvent(handle, event)) => {
-                                    // Ignoring errors from dropped connections that haven't yet been cleaned up
-                                    let _ = self
-                                        .connections
-                                        .senders
-                                        .get_mut(&handle)
-                                        .unwrap()
-                                        .send(ConnectionEvent::Proto(event));
-                                }
-                                Some(DatagramEvent::Response(t)) => {
-                                    self.outgoing.push_back(udp_transmit(t));
+        let handles = self.connection_sockets.keys().copied().collect::<Vec<_>>();
+        for handle in iter::once(None).chain(handles.into_iter().map(Some)) {
+            // Responses to datagrams go out on the socket they were received on
+            let (socket, outgoing) = match handle {
+                None => (&mut self.socket, &mut self.outgoing),
+                Some(handle) => {
+                    let x = self.connection_sockets.get_mut(&handle).unwrap();
+                    (&mut x.socket, &mut x.outgoing)
+                }
+            };
+            loop {
+                match socket.poll_recv(cx, &mut iovs, &mut metas) {
+                    Poll::Ready(Ok(msgs)) => {
+                        self.recv_limiter.record_work(msgs);
+                        for (meta, buf) in metas.iter().zip(iovs.iter()).take(msgs) {
+                            let mut data: BytesMut = buf[0..meta.len].into();
+                            while !data.is_empty() {
+                                let buf = data.split_to(meta.stride.min(data.len()));
+                                match self.inner.handle(
+                                    now,
+                                    meta.addr,
+                                    meta.dst_ip,
+                                    meta.ecn.map(proto_ecn),
+                                    buf,
+                                ) {
+                                    Some(DatagramEvent::NewConnection(incoming)) => {
+                                        if self.connections.close.is_none() {
+                                            self.incoming.push_back(incoming);
+                                        } else {
+                                            let transmit = self.inner.refuse(incoming);
+                                            outgoing.push_back(udp_transmit(transmit));
+                                        }
+                                    }
+                                    Some(DatagramEvent::ConnectionEvent(handle, event)) => {
+                                        // Ignoring errors from dropped connections that haven't yet been cleaned up
+                                        let _ = self
+                                            .connections
+                                            .senders
+                                            .get_mut(&handle)
+                                            .unwrap()
+                                            .send(ConnectionEvent::Proto(event));
+                                    }
+                                    Some(DatagramEvent::Response(t)) => {
+                                        outgoing.push_back(udp_transmit(t));
+                                    }
+                                    None => {}
                                 }
-                                None => {}
                             }
                         }
                     }
+                    Poll::Pending => {
+                        break;
+                    }
+                    // Ignore ECONNRESET as it's undefined in QUIC and may be injected by an
+                    // attacker
+                    Poll::Ready(Err(ref e)) if e.kind() == io::ErrorKind::ConnectionReset => {
+                        continue;
+                    }
+                    Poll::Ready(Err(e)) => {
+                        return Err(e);
+                    }
                 }
-                Poll::Pending => {
-                    break;
-                }
-                // Ignore ECONNRESET as it's undefined in QUIC and may be injected by an
-                // attacker
-                Poll::Ready(Err(ref e)) if e.kind() == io::ErrorKind::ConnectionReset => {
-                    continue;
-                }
-                Poll::Ready(Err(e)) => {
-                    return Err(e);
+                if !self.recv_limiter.allow_work() {
+                    self.recv_limiter.finish_cycle();
+                    return Ok(true);
                 }
             }
-            if !self.recv_limiter.allow_work() {
-                self.recv_limiter.finish_cycle();
-                return Ok(true);
-            }
         }
 
         self.recv_limiter.finish_cycle();
@@ -468,33 +566,25 @@ impl State {
     fn drive_send(&mut self, cx: &mut Context) -> Result<bool, io::Error> {
         self.send_limiter.start_cycle();
 
-        let result = loop {
-            if self.outgoing.is_empty() {
-                break Ok(false);
-            }
-
-            if !self.send_limiter.allow_work() {
-                break Ok(true);
-            }
-
-            match self
-                .socket
-                .poll_send(&self.udp_state, cx, self.outgoing.as_slices().0)
-            {
-                Poll::Ready(Ok(n)) => {
-                    self.outgoing.drain(..n);
-                    // We count transmits instead of `poll_send` calls since the cost
-                    // of a `sendmmsg` still linearly increases with number of packets.
-                    self.send_limiter.record_work(n);
-                }
-                Poll::Pending => {
-                    break Ok(false);
-                }
-                Poll::Ready(Err(e)) => {
-                    break Err(e);
-                }
+        let mut result = send_queue(
+            &mut *self.socket,
+            &self.udp_state,
+            &mut self.outgoing,
+            &mut self.send_limiter,
+            cx,
+        );
+        for x in self.connection_sockets.values_mut() {
+            if !matches!(result, Ok(false)) {
+                break;
             }
-        };
+            result = send_queue(
+                &mut *x.socket,
+                &self.udp_state,
+                &mut x.outgoing,
+                &mut self.send_limiter,
+                cx,
+            );
+        }
 
         self.send_limiter.finish_cycle();
         result
@@ -509,6 +599,7 @@ impl State {
                     Proto(e) => {
                         if e.is_drained() {
                             self.connections.senders.remove(&ch);
+                            self.connection_sockets.remove(&ch);
                             if self.connections.is_empty() {
                                 shared.idle.notify_waiters();
                             }
@@ -523,7 +614,21 @@ impl State {
                                 .send(ConnectionEvent::Proto(event));
                         }
                     }
-                    Transmit(t) => self.outgoing.push_back(udp_transmit(t)),
+                    Transmit(t) => match self.connection_sockets.get_mut(&ch) {
+                        Some(x) => x.outgoing.push_back(udp_transmit(t)),
+                        None => self.outgoing.push_back(udp_transmit(t)),
+                    },
+                    Rebind(socket) => {
+                        self.connection_sockets.insert(
+                            ch,
+                            ConnectionSocket {
+                                socket,
+                                outgoing: VecDeque::new(),
+                            },
+                        );
+                        // Make sure the new socket gets polled for incoming datagrams
+                        cx.waker().wake_by_ref();
+                    }
                 },
                 Poll::Ready(None) => unreachable!("EndpointInner owns one sender"),
                 Poll::Pending => {
@@ -536,6 +641,42 @@ impl State {
     }
 }
 
+/// Send as much of `outgoing` on `socket` as possible
+///
+/// Returns whether there's more work to do immediately.
+fn send_queue(
+    socket: &mut dyn AsyncUdpSocket,
+    udp_state: &UdpState,
+    outgoing: &mut VecDeque<udp::Transmit>,
+    limiter: &mut WorkLimiter,
+    cx: &mut Context,
+) -> Result<bool, io::Error> {
+    loop {
+        if outgoing.is_empty() {
+            return Ok(false);
+        }
+
+        if !limiter.allow_work() {
+            return Ok(true);
+        }
+
+        match socket.poll_send(udp_state, cx, outgoing.as_slices().0) {
+            Poll::Ready(Ok(n)) => {
+                outgoing.drain(..n);
+                // We count transmits instead of `poll_send` calls since the cost
+                // of a `sendmmsg` still linearly increases with number of packets.
+                limiter.record_work(n);
+            }
+            Poll::Pending => {
+                return Ok(false);
+            }
+            Poll::Ready(Err(e)) => {
+                return Err(e);
+            }
+        }
+    }
+}
+
 #[inline]
 fn udp_transmit(t: proto::Transmit) -> udp::Transmit {
     udp::Transmit {
@@ -617,15 +758,19 @@ pin_project! {
 }
 
 impl<'a> Future for Accept<'a> {
-    type Output = Option<Connecting>;
+    type Output = Option<Incoming>;
     fn poll(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Self::Output> {
         let mut this = self.project();
         let endpoint = &mut *this.endpoint.inner.state.lock().unwrap();
         if endpoint.driver_lost {
             return Poll::Ready(None);
         }
-        if let Some(conn) = endpoint.incoming.pop_front() {
-            return Poll::Ready(Some(conn));
+        if let Some(incoming) = endpoint.incoming.pop_front() {
+            endpoint.ref_count += 1;
+            return Poll::Ready(Some(Incoming::new(
+                incoming,
+                EndpointRef(this.endpoint.inner.0.clone()),
+            )));
         }
         if endpoint.connections.close.is_some() {
             return Poll::Ready(None);
@@ -673,6 +818,7 @@ impl EndpointRef {
                 ipv6,
                 events,
                 outgoing: VecDeque::new(),
+                connection_sockets: FxHashMap::default(),
                 incoming: VecDeque::new(),
                 driver: None,
                 connections: ConnectionSet {
@@ -0,0 +1,103 @@
+use std::net::{IpAddr, SocketAddr};
+
+use proto::{ConnectionError, ConnectionId};
+use thiserror::Error;
+
+use crate::{connection::Connecting, endpoint::EndpointRef};
+
+/// An incoming connection for which the server has not yet begun its part of the handshake
+///
+/// Dropping an `Incoming` refuses the connection.
+#[derive(Debug)]
+pub struct Incoming(Option<State>);
+
+impl Incoming {
+    pub(crate) fn new(inner: proto::Incoming, endpoint: EndpointRef) -> Self {
+        Self(Some(State { inner, endpoint }))
+    }
+
+    /// Attempt to accept this incoming connection (an error may still occur)
+    pub fn accept(mut self) -> Result<Connecting, ConnectionError> {
+        let state = self.0.take().unwrap();
+        state.endpoint.accept(state.inner)
+    }
+
+    /// Reject this incoming connection attempt
+    pub fn refuse(mut self) {
+        let state = self.0.take().unwrap();
+        state.endpoint.refuse(state.inner);
+    }
+
+    /// Respond with a retry packet, requiring the client to retry with address validation
+    ///
+    /// Errors if `may_retry()` is false.
+    pub fn retry(mut self) -> Result<(), RetryError> {
+        let state = self.0.take().unwrap();
+        state
+            .endpoint
+            .retry(state.inner)
+            .map_err(|e| RetryError(Box::new(Self::new(e.into_incoming(), state.endpoint))))
+    }
+
+    /// Ignore this incoming connection attempt, not sending any packet in response
+    pub fn ignore(mut self) {
+        let state = self.0.take().unwrap();
+        state.endpoint.ignore(state.inner);
+    }
+
+    /// The local IP address which was used when the peer established the connection
+    pub fn local_ip(&self) -> Option<IpAddr> {
+        self.0.as_ref().unwrap().inner.local_ip()
+    }
+
+    /// The peer's UDP address
+    pub fn remote_address(&self) -> SocketAddr {
+        self.0.as_ref().unwrap().inner.remote_address()
+    }
+
+    /// Whether the socket address that is initiating this connection has been validated
+    ///
+    /// This means that the sender of the initial packet has proved that they can receive traffic
+    /// sent to `self.remote_address()`.
+    pub fn remote_address_validated(&self) -> bool {
+        self.0.as_ref().unwrap().inner.remote_address_validated()
+    }
+
+    /// Whether it is legal to respond with a retry packet
+    pub fn may_retry(&self) -> bool {
+        self.0.as_ref().unwrap().inner.may_retry()
+    }
+
+    /// The original destination connection ID sent by the client
+    pub fn orig_dst_cid(&self) -> ConnectionId {
+        *self.0.as_ref().unwrap().inner.orig_dst_cid()
+    }
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        // Implicit dropping is equivalent to refusing
+        if let Some(state) = self.0.take() {
+            state.endpoint.refuse(state.inner);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    inner: proto::Incoming,
+    endpoint: EndpointRef,
+}
+
+/// Error for attempting to retry an [`Incoming`] which already bears an address validation token
+/// from a previous retry
+#[derive(Debug, Error)]
+#[error("retry() with validated Incoming")]
+pub struct RetryError(Box<Incoming>);
+
+impl RetryError {
+    /// Get the [`Incoming`]
+    pub fn into_incoming(self) -> Incoming {
+        *self.0
+    }
+}
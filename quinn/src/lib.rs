@@ -54,6 +54,7 @@ macro_rules! ready {
 
 mod connection;
 mod endpoint;
+mod incoming;
 mod mutex;
 mod recv_stream;
 mod runtime;
@@ -61,17 +62,22 @@ mod send_stream;
 mod work_limiter;
 
 pub use proto::{
-    congestion, crypto, ApplicationClose, Chunk, ClientConfig, ConfigError, ConnectError,
-    ConnectionClose, ConnectionError, EndpointConfig, IdleTimeout, MtuDiscoveryConfig,
-    ServerConfig, StreamId, Transmit, TransportConfig, VarInt,
+    congestion, crypto, scheduler, AckFrequencyConfig, ApplicationClose, Chunk, ClientConfig,
+    ConfigError, ConnectError, ConnectionClose, ConnectionError, DatagramOptions,
+    DatagramOverflowPolicy, EndpointConfig, IdleTimeout, KeyUpdateConfig, MigrateError,
+    MtuDiscoveryConfig, PathEvent, ResetAtError, ServerConfig, Side, StreamId, TokenLog,
+    TokenMemoryCache, TokenMemoryLog, TokenReuseError, TokenStore, Transmit, TransportConfig,
+    VarInt,
 };
 pub use udp;
 
 pub use crate::connection::{
-    AcceptBi, AcceptUni, Connecting, Connection, OpenBi, OpenUni, ReadDatagram, SendDatagramError,
-    UnknownStream, ZeroRttAccepted,
+    AcceptBi, AcceptUni, Connecting, Connection, DatagramOutcome, KeyUpdate, KeyUpdates, OpenBi,
+    OpenUni, PathEvents, ReadDatagram, ReadDatagramOutcome, SendDatagram, SendDatagramError,
+    Subscription, UnknownStream, ZeroRttAccepted,
 };
 pub use crate::endpoint::{Accept, Endpoint};
+pub use crate::incoming::{Incoming, RetryError};
 pub use crate::recv_stream::{ReadError, ReadExactError, ReadToEndError, RecvStream};
 #[cfg(feature = "runtime-async-std")]
 pub use crate::runtime::AsyncStdRuntime;
@@ -91,12 +97,16 @@ enum ConnectionEvent {
     },
     Proto(proto::ConnectionEvent),
     Ping,
+    /// Check whether more data can be sent, e.g. after an endpoint-wide limit changed
+    Wake,
 }
 
 #[derive(Debug)]
 enum EndpointEvent {
     Proto(proto::EndpointEvent),
     Transmit(proto::Transmit),
+    /// Send and receive the connection's datagrams on a socket of its own from now on
+    Rebind(Box<dyn AsyncUdpSocket>),
 }
 
 /// Maximum number of datagrams processed in send/recv calls to make before moving on to other processing
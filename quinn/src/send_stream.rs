@@ -3,10 +3,11 @@ use std::{
     io,
     pin::Pin,
     task::{Context, Poll},
+    time::Instant,
 };
 
 use bytes::Bytes;
-use proto::{ConnectionError, FinishError, StreamId, Written};
+use proto::{ConnectionError, FinishError, ResetAtError, StreamId, Written};
 use thiserror::Error;
 use tokio::sync::oneshot;
 
@@ -47,6 +48,50 @@ impl SendStream {
         Write { stream: self, buf }.await
     }
 
+    /// Write bytes to the stream, giving up on them if they aren't acknowledged by `deadline`
+    ///
+    /// Like [`write()`](Self::write), but if the peer hasn't acknowledged all of the written bytes
+    /// by `deadline`, the stream is reset with `error_code`. Outstanding writes, and further
+    /// writes until the peer acknowledges the reset, then fail with [`WriteError::Expired`].
+    /// Expired data is never retransmitted.
+    pub async fn write_with_deadline(
+        &mut self,
+        buf: &[u8],
+        deadline: Instant,
+        error_code: VarInt,
+    ) -> Result<usize, WriteError> {
+        WriteWithDeadline {
+            stream: self,
+            buf,
+            deadline,
+            error_code,
+        }
+        .await
+    }
+
+    /// Write a message to the stream, skipping it if it isn't sent by `deadline`
+    ///
+    /// The whole of `buf` is written at once, waiting for flow control to leave room for it. Fails
+    /// with [`WriteError::TooLarge`] if it never could, because `buf` is larger than the configured
+    /// send window or the flow control windows granted by the peer. If none of the message has been transmitted when `deadline` passes, it's dropped and the peer
+    /// reads the next message in its place. A message that was partly or entirely transmitted can't
+    /// be skipped, so if it isn't acknowledged by `deadline` the stream is reset with `error_code`
+    /// as by [`write_with_deadline()`](Self::write_with_deadline).
+    pub async fn write_message_with_deadline(
+        &mut self,
+        buf: &[u8],
+        deadline: Instant,
+        error_code: VarInt,
+    ) -> Result<(), WriteError> {
+        WriteMessageWithDeadline {
+            stream: self,
+            buf,
+            deadline,
+            error_code,
+        }
+        .await
+    }
+
     /// Convenience method to write an entire buffer to the stream
     pub async fn write_all(&mut self, buf: &[u8]) -> Result<(), WriteError> {
         WriteAll { stream: self, buf }.await
@@ -103,9 +148,15 @@ impl SendStream {
             Err(Stopped(error_code)) => {
                 return Poll::Ready(Err(WriteError::Stopped(error_code)));
             }
+            Err(Expired(error_code)) => {
+                return Poll::Ready(Err(WriteError::Expired(error_code)));
+            }
             Err(UnknownStream) => {
                 return Poll::Ready(Err(WriteError::UnknownStream));
             }
+            Err(TooLarge) => {
+                return Poll::Ready(Err(WriteError::TooLarge));
+            }
         };
 
         conn.wake();
@@ -177,6 +228,24 @@ impl SendStream {
         Ok(())
     }
 
+    /// Close the send stream immediately, except for its first `reliable_size` bytes
+    ///
+    /// Like [`reset()`](Self::reset), but data written before `reliable_size` is still delivered
+    /// to the peer, which can read it before learning of the reset. Requires the peer to support
+    /// reliable stream resets (draft-ietf-quic-reliable-stream-reset) unless `reliable_size` is
+    /// zero.
+    pub fn reset_at(&mut self, error_code: VarInt, reliable_size: u64) -> Result<(), ResetAtError> {
+        let mut conn = self.conn.state.lock("SendStream::reset_at");
+        if self.is_0rtt && conn.check_0rtt().is_err() {
+            return Ok(());
+        }
+        conn.inner
+            .send_stream(self.stream)
+            .reset_at(error_code, reliable_size)?;
+        conn.wake();
+        Ok(())
+    }
+
     /// Set the priority of the send stream
     ///
     /// Every send stream has an initial priority of 0. Locally buffered data from streams with
@@ -196,6 +265,25 @@ impl SendStream {
         Ok(conn.inner.send_stream(self.stream).priority()?)
     }
 
+    /// Set whether the send stream is incremental
+    ///
+    /// Incremental streams share transmission capacity with other incremental streams of the same
+    /// priority, rather than being sent one after another, under schedulers which support it, such
+    /// as [`ExtensiblePriorities`](crate::scheduler::ExtensiblePriorities).
+    pub fn set_incremental(&self, incremental: bool) -> Result<(), UnknownStream> {
+        let mut conn = self.conn.state.lock("SendStream::set_incremental");
+        conn.inner
+            .send_stream(self.stream)
+            .set_incremental(incremental)?;
+        Ok(())
+    }
+
+    /// Get whether the send stream is incremental
+    pub fn incremental(&self) -> Result<bool, UnknownStream> {
+        let mut conn = self.conn.state.lock("SendStream::incremental");
+        Ok(conn.inner.send_stream(self.stream).incremental()?)
+    }
+
     /// Completes if/when the peer stops the stream, yielding the error code
     pub async fn stopped(&mut self) -> Result<VarInt, StoppedError> {
         Stopped { stream: self }.await
@@ -333,6 +421,49 @@ impl<'a> Future for Write<'a> {
     }
 }
 
+/// Future produced by [`SendStream::write_with_deadline()`].
+///
+/// [`SendStream::write_with_deadline()`]: crate::SendStream::write_with_deadline
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+struct WriteWithDeadline<'a> {
+    stream: &'a mut SendStream,
+    buf: &'a [u8],
+    deadline: Instant,
+    error_code: VarInt,
+}
+
+impl<'a> Future for WriteWithDeadline<'a> {
+    type Output = Result<usize, WriteError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let (buf, deadline, error_code) = (this.buf, this.deadline, this.error_code);
+        this.stream
+            .execute_poll(cx, |s| s.write_with_deadline(buf, deadline, error_code))
+    }
+}
+
+/// Future produced by [`SendStream::write_message_with_deadline()`].
+///
+/// [`SendStream::write_message_with_deadline()`]: crate::SendStream::write_message_with_deadline
+#[must_use = "futures/streams/sinks do nothing unless you `.await` or poll them"]
+struct WriteMessageWithDeadline<'a> {
+    stream: &'a mut SendStream,
+    buf: &'a [u8],
+    deadline: Instant,
+    error_code: VarInt,
+}
+
+impl<'a> Future for WriteMessageWithDeadline<'a> {
+    type Output = Result<(), WriteError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let (buf, deadline, error_code) = (this.buf, this.deadline, this.error_code);
+        this.stream.execute_poll(cx, |s| {
+            s.write_message_with_deadline(buf, deadline, error_code)
+        })
+    }
+}
+
 /// Future produced by [`SendStream::write_all()`].
 ///
 /// [`SendStream::write_all()`]: crate::SendStream::write_all
@@ -431,6 +562,12 @@ pub enum WriteError {
     /// Carries an application-defined error code.
     #[error("sending stopped by peer: error {0}")]
     Stopped(VarInt),
+    /// Data written with [`SendStream::write_with_deadline()`] wasn't acknowledged in time, so the
+    /// stream was reset
+    ///
+    /// Carries the error code the stream was reset with.
+    #[error("write deadline expired: error {0}")]
+    Expired(VarInt),
     /// The connection was lost
     #[error("connection lost")]
     ConnectionLost(#[from] ConnectionError),
@@ -445,6 +582,10 @@ pub enum WriteError {
     /// [`Connecting::into_0rtt()`]: crate::Connecting::into_0rtt()
     #[error("0-RTT rejected")]
     ZeroRttRejected,
+    /// A message written with [`SendStream::write_message_with_deadline()`] is larger than the
+    /// configured send window or the flow control windows granted by the peer
+    #[error("message too large")]
+    TooLarge,
 }
 
 /// Errors that arise while monitoring for a send stream stop from the peer
@@ -471,7 +612,9 @@ impl From<WriteError> for io::Error {
         use self::WriteError::*;
         let kind = match x {
             Stopped(_) | ZeroRttRejected => io::ErrorKind::ConnectionReset,
+            Expired(_) => io::ErrorKind::TimedOut,
             ConnectionLost(_) | UnknownStream => io::ErrorKind::NotConnected,
+            TooLarge => io::ErrorKind::InvalidInput,
         };
         Self::new(kind, x)
     }
@@ -19,7 +19,10 @@ use tracing::{info, info_span};
 use tracing_futures::Instrument as _;
 use tracing_subscriber::EnvFilter;
 
-use super::{ClientConfig, Endpoint, RecvStream, SendStream, TransportConfig};
+use super::{
+    ClientConfig, DatagramOutcome, DatagramOverflowPolicy, Endpoint, PathEvent, RecvStream,
+    SendStream, Side, TransportConfig,
+};
 
 #[test]
 fn handshake_timeout() {
@@ -127,6 +130,8 @@ fn read_after_close() {
             .accept()
             .await
             .expect("endpoint")
+            .accept()
+            .expect("accept")
             .await
             .expect("connection");
         let mut s = new_conn.open_uni().await.unwrap();
@@ -159,17 +164,24 @@ fn export_keying_material() {
     };
 
     runtime.block_on(async move {
-        let outgoing_conn = endpoint
-            .connect(endpoint.local_addr().unwrap(), "localhost")
-            .unwrap()
-            .await
-            .expect("connect");
-        let incoming_conn = endpoint
-            .accept()
-            .await
-            .expect("endpoint")
-            .await
-            .expect("connection");
+        let outgoing_conn_fut = async {
+            endpoint
+                .connect(endpoint.local_addr().unwrap(), "localhost")
+                .unwrap()
+                .await
+                .expect("connect")
+        };
+        let incoming_conn_fut = async {
+            endpoint
+                .accept()
+                .await
+                .expect("endpoint")
+                .accept()
+                .expect("accept")
+                .await
+                .expect("connection")
+        };
+        let (outgoing_conn, incoming_conn) = tokio::join!(outgoing_conn_fut, incoming_conn_fut);
         let mut i_buf = [0u8; 64];
         incoming_conn
             .export_keying_material(&mut i_buf, b"asdf", b"qwer")
@@ -189,11 +201,16 @@ async fn accept_after_close() {
 
     const MSG: &[u8] = b"goodbye!";
 
-    let sender = endpoint
+    let connecting = endpoint
         .connect(endpoint.local_addr().unwrap(), "localhost")
-        .unwrap()
+        .unwrap();
+    let receiving = endpoint
+        .accept()
         .await
-        .expect("connect");
+        .expect("endpoint")
+        .accept()
+        .expect("accept");
+    let sender = connecting.await.expect("connect");
     let mut s = sender.open_uni().await.unwrap();
     s.write_all(MSG).await.unwrap();
     s.finish().await.unwrap();
@@ -203,12 +220,7 @@ async fn accept_after_close() {
     tokio::time::sleep(Duration::from_millis(100)).await;
 
     // Despite the connection having closed, we should be able to accept it...
-    let receiver = endpoint
-        .accept()
-        .await
-        .expect("endpoint")
-        .await
-        .expect("connection");
+    let receiver = receiving.await.expect("connection");
 
     // ...and read what was sent.
     let mut stream = receiver.accept_uni().await.expect("incoming streams");
@@ -222,6 +234,57 @@ async fn accept_after_close() {
     assert!(receiver.open_uni().await.is_err());
 }
 
+#[tokio::test]
+async fn refuse_incoming() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let connecting = endpoint
+        .connect(endpoint.local_addr().unwrap(), "localhost")
+        .unwrap();
+    let incoming = endpoint.accept().await.expect("endpoint");
+    assert_eq!(incoming.remote_address(), endpoint.local_addr().unwrap());
+    assert!(!incoming.remote_address_validated());
+    incoming.refuse();
+
+    match connecting.await {
+        Err(crate::ConnectionError::ConnectionClosed(close))
+            if close.error_code == proto::TransportErrorCode::CONNECTION_REFUSED => {}
+        Err(e) => panic!("unexpected error: {e:?}"),
+        Ok(_) => panic!("unexpected success"),
+    }
+}
+
+#[tokio::test]
+async fn retry_incoming() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let endpoint2 = endpoint.clone();
+    let server = tokio::spawn(async move {
+        let incoming = endpoint2.accept().await.expect("endpoint");
+        assert!(incoming.may_retry());
+        incoming.retry().unwrap();
+
+        // The client responds to the Retry with a new, validated connection attempt
+        let incoming = endpoint2.accept().await.expect("endpoint");
+        assert!(incoming.remote_address_validated());
+        let incoming = incoming.retry().unwrap_err().into_incoming();
+        incoming
+            .accept()
+            .expect("accept")
+            .await
+            .expect("connection")
+    });
+
+    endpoint
+        .connect(endpoint.local_addr().unwrap(), "localhost")
+        .unwrap()
+        .await
+        .expect("connect");
+    server.await.unwrap();
+}
+
 /// Construct an endpoint suitable for connecting to itself
 fn endpoint() -> Endpoint {
     endpoint_with_config(TransportConfig::default())
@@ -260,7 +323,11 @@ async fn zero_rtt() {
     tokio::spawn(async move {
         for _ in 0..2 {
             let incoming = endpoint2.accept().await.unwrap();
-            let (connection, established) = incoming.into_0rtt().unwrap_or_else(|_| unreachable!());
+            let (connection, established) = incoming
+                .accept()
+                .unwrap()
+                .into_0rtt()
+                .unwrap_or_else(|_| unreachable!());
             let c = connection.clone();
             tokio::spawn(async move {
                 while let Ok(mut x) = c.accept_uni().await {
@@ -485,7 +552,12 @@ fn run_echo(args: EchoArgs) {
                 assert_eq!(None, incoming.local_ip());
             }
 
-            let new_conn = incoming.instrument(info_span!("server")).await.unwrap();
+            let new_conn = incoming
+                .accept()
+                .unwrap()
+                .instrument(info_span!("server"))
+                .await
+                .unwrap();
             tokio::spawn(async move {
                 while let Ok(stream) = new_conn.accept_bi().await {
                     tokio::spawn(echo(stream));
@@ -644,7 +716,14 @@ async fn rebind_recv() {
     let connected_send = Arc::new(tokio::sync::Notify::new());
     let connected_recv = connected_send.clone();
     let server = tokio::spawn(async move {
-        let connection = server.accept().await.unwrap().await.unwrap();
+        let connection = server
+            .accept()
+            .await
+            .unwrap()
+            .accept()
+            .unwrap()
+            .await
+            .unwrap();
         info!("got conn");
         connected_send.notify_one();
         write_recv.notified().await;
@@ -670,6 +749,60 @@ async fn rebind_recv() {
     server.await.unwrap();
 }
 
+#[tokio::test]
+async fn rebind_connection() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+    let endpoint_addr = endpoint.local_addr().unwrap();
+
+    const MSG: &[u8; 5] = b"hello";
+    let mut pairs = Vec::new();
+    for _ in 0..2 {
+        let (client, server) = tokio::join!(
+            endpoint.connect(endpoint_addr, "localhost").unwrap(),
+            async { endpoint.accept().await.unwrap().accept().unwrap().await }
+        );
+        let (client, server) = (client.unwrap(), server.unwrap());
+        // Make sure the handshake is confirmed, so the client may migrate
+        let mut stream = server.open_uni().await.unwrap();
+        stream.write_all(MSG).await.unwrap();
+        stream.finish().await.unwrap();
+        let mut stream = client.accept_uni().await.unwrap();
+        assert_eq!(stream.read_to_end(MSG.len()).await.unwrap(), MSG);
+        pairs.push((client, server));
+    }
+
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0)).unwrap();
+    let socket_addr = socket.local_addr().unwrap();
+    let mut path_events = pairs[0].1.path_events();
+    pairs[0].0.rebind(socket).unwrap();
+    info!("rebound");
+
+    for (client, server) in &pairs {
+        let mut stream = client.open_uni().await.unwrap();
+        stream.write_all(MSG).await.unwrap();
+        stream.finish().await.unwrap();
+        let mut stream = server.accept_uni().await.unwrap();
+        assert_eq!(stream.read_to_end(MSG.len()).await.unwrap(), MSG);
+    }
+    // Only the rebound connection moved to the new socket
+    assert_eq!(pairs[0].1.remote_address(), socket_addr);
+    assert_eq!(pairs[1].1.remote_address(), endpoint_addr);
+    let mut events = Vec::new();
+    while let Some(event) = path_events.try_recv() {
+        events.push(event);
+    }
+    assert!(events.contains(&PathEvent::RemoteAddressChanged {
+        old: endpoint_addr,
+        new: socket_addr
+    }));
+    assert_eq!(path_events.missed(), 0);
+
+    // No more events occur once the connection is closed
+    pairs[0].1.close(0u32.into(), b"done");
+    assert_eq!(path_events.recv().await, None);
+}
+
 #[tokio::test]
 async fn stream_id_flow_control() {
     let _guard = subscribe();
@@ -681,7 +814,7 @@ async fn stream_id_flow_control() {
         endpoint
             .connect(endpoint.local_addr().unwrap(), "localhost")
             .unwrap(),
-        async { endpoint.accept().await.unwrap().await }
+        async { endpoint.accept().await.unwrap().accept().unwrap().await }
     );
     let client = client.unwrap();
     let server = server.unwrap();
@@ -713,7 +846,7 @@ async fn two_datagram_readers() {
         endpoint
             .connect(endpoint.local_addr().unwrap(), "localhost")
             .unwrap(),
-        async { endpoint.accept().await.unwrap().await }
+        async { endpoint.accept().await.unwrap().accept().unwrap().await }
     );
     let client = client.unwrap();
     let server = server.unwrap();
@@ -739,3 +872,135 @@ async fn two_datagram_readers() {
     assert!(*a == *b"one" || *b == *b"one");
     assert!(*a == *b"two" || *b == *b"two");
 }
+
+#[tokio::test]
+async fn datagram_outcome() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().accept().unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    client
+        .send_datagram_with_id(b"hello"[..].into(), 42)
+        .unwrap();
+    assert_eq!(*server.read_datagram().await.unwrap(), *b"hello");
+    assert_eq!(
+        client.read_datagram_outcome().await.unwrap(),
+        DatagramOutcome::Acked(42)
+    );
+}
+
+#[tokio::test]
+async fn datagram_outcomes_missed() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().accept().unwrap().await }
+    );
+    let client = client.unwrap();
+    let _server = server.unwrap();
+
+    // Outcomes beyond what's buffered displace the oldest ones, which are counted
+    const EXTRA: u64 = 16;
+    for id in 0..4096 + EXTRA {
+        client
+            .send_datagram_with_id(b"hello"[..].into(), id)
+            .unwrap();
+        if id % 64 == 0 {
+            tokio::task::yield_now().await;
+        }
+    }
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while client.missed_datagram_outcomes() < EXTRA {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(client.missed_datagram_outcomes(), EXTRA);
+}
+
+#[tokio::test]
+async fn key_updates() {
+    let _guard = subscribe();
+    let endpoint = endpoint();
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().accept().unwrap().await }
+    );
+    let (client, server) = (client.unwrap(), server.unwrap());
+    const MSG: &[u8] = b"hello";
+    // Make sure the handshake is confirmed, so keys may be updated
+    let mut stream = server.open_uni().await.unwrap();
+    stream.write_all(MSG).await.unwrap();
+    stream.finish().await.unwrap();
+    let mut stream = client.accept_uni().await.unwrap();
+    assert_eq!(stream.read_to_end(MSG.len()).await.unwrap(), MSG);
+
+    let mut client_updates = client.key_updates();
+    let mut server_updates = server.key_updates();
+    client.force_key_update();
+    let mut stream = client.open_uni().await.unwrap();
+    stream.write_all(MSG).await.unwrap();
+    stream.finish().await.unwrap();
+    let mut stream = server.accept_uni().await.unwrap();
+    assert_eq!(stream.read_to_end(MSG.len()).await.unwrap(), MSG);
+    for updates in [&mut client_updates, &mut server_updates] {
+        let update = updates.recv().await.unwrap();
+        assert_eq!(update.key_phase, 1);
+        assert_eq!(update.initiator, Side::Client);
+        assert_eq!(updates.missed(), 0);
+    }
+
+    // No more events occur once the connection is closed
+    client.close(0u32.into(), b"done");
+    assert_eq!(client_updates.recv().await, None);
+}
+
+#[tokio::test]
+async fn datagram_send_wait() {
+    let _guard = subscribe();
+    let mut transport_config = TransportConfig::default();
+    transport_config
+        .datagram_send_buffer_size(100)
+        .datagram_overflow_policy(DatagramOverflowPolicy::Reject);
+    let endpoint = endpoint_with_config(transport_config);
+
+    let (client, server) = tokio::join!(
+        endpoint
+            .connect(endpoint.local_addr().unwrap(), "localhost")
+            .unwrap(),
+        async { endpoint.accept().await.unwrap().accept().unwrap().await }
+    );
+    let client = client.unwrap();
+    let server = server.unwrap();
+
+    const COUNT: u8 = 8;
+    tokio::join!(
+        async {
+            for i in 0..COUNT {
+                client.send_datagram_wait(vec![i; 60].into()).await.unwrap();
+            }
+        },
+        async {
+            for i in 0..COUNT {
+                assert_eq!(*server.read_datagram().await.unwrap(), [i; 60]);
+            }
+        }
+    );
+    assert_eq!(client.stats().dropped_datagrams, 0);
+}
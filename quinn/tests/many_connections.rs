@@ -39,7 +39,14 @@ fn connect_n_nodes_to_1_and_send_1mb_data() {
     let endpoint2 = endpoint.clone();
     let read_incoming_data = async move {
         for _ in 0..expected_messages {
-            let conn = endpoint2.accept().await.unwrap().await.unwrap();
+            let conn = endpoint2
+                .accept()
+                .await
+                .unwrap()
+                .accept()
+                .unwrap()
+                .await
+                .unwrap();
 
             let shared = shared2.clone();
             let task = async move {